@@ -0,0 +1,491 @@
+//! Alert rules from the config file's `alerts`, evaluated on every
+//! published snapshot, with firing and resolved notifications POSTed to a
+//! webhook.
+//!
+//! A rule compares the samples of one metric with a threshold, such as
+//! `kafka_connect_up == 0`. A series whose sample meets it is pending, and
+//! fires once it has met it for `for_secs`; it resolves as soon as it no
+//! longer does or is gone. Only firing and resolving are notified, through
+//! the same queue and delivery task as the event webhook.
+
+use crate::registry::{self, Labels};
+use crate::webhook::{self, Deliveries};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::atomic::Ordering,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+/// `alerts` of the config file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertsConfig {
+    webhook_url: String,
+    /// Retries per notification on network errors, 5xx and 429.
+    #[serde(default = "default_max_retries")]
+    max_retries: u32,
+    #[serde(default)]
+    rules: Vec<RuleConfig>,
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleConfig {
+    name: String,
+    /// `metric{label="value",...} <op> <number>`.
+    expr: String,
+    /// How long the condition must hold before the alert fires.
+    #[serde(default)]
+    for_secs: u64,
+    /// Added to the series labels of every notification.
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    summary: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl Op {
+    fn holds(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Op::Eq => value == threshold,
+            Op::Ne => value != threshold,
+            Op::Gt => value > threshold,
+            Op::Ge => value >= threshold,
+            Op::Lt => value < threshold,
+            Op::Le => value <= threshold,
+        }
+    }
+}
+
+/// `label="value"` or `label!="value"` of a rule's selector.
+#[derive(Clone, Debug)]
+struct Matcher {
+    label: String,
+    equal: bool,
+    value: String,
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    name: String,
+    metric: String,
+    matchers: Vec<Matcher>,
+    op: Op,
+    threshold: f64,
+    for_: Duration,
+    labels: BTreeMap<String, String>,
+    summary: Option<String>,
+}
+
+impl Rule {
+    /// `labels` has every `=` matcher's value and none of the `!=` ones'.
+    /// A label the series lacks counts as empty.
+    fn selects(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.matchers
+            .iter()
+            .all(|m| (labels.get(&m.label).map_or("", String::as_str) == m.value) == m.equal)
+    }
+}
+
+/// The compiled `alerts` of the config file.
+pub struct Alerts {
+    pub webhook: webhook::WebhookConfig,
+    rules: Vec<Rule>,
+}
+
+/// The label matchers after a `{`, up to and including the `}`, and what
+/// follows it. Values are unescaped as in the text format.
+fn parse_labels(mut s: &str) -> Result<(Vec<Matcher>, &str), String> {
+    let mut matchers = Vec::new();
+    loop {
+        s = s.trim_start();
+        if let Some(rest) = s.strip_prefix('}') {
+            return Ok((matchers, rest));
+        }
+        let end = s.find(['=', '!']).ok_or("expected `=` or `!=` after a label name")?;
+        let label = s[..end].trim();
+        if label.is_empty() {
+            return Err("expected a label name".into());
+        }
+        let (equal, rest) = match s[end..].strip_prefix("!=") {
+            Some(rest) => (false, rest),
+            None => (true, &s[end + 1..]),
+        };
+        let rest = rest.trim_start().strip_prefix('"').ok_or("expected a quoted label value")?;
+        let mut value = String::new();
+        let mut chars = rest.char_indices();
+        let close = loop {
+            match chars.next() {
+                Some((i, '"')) => break i,
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, c)) => value.push(c),
+                    None => return Err("unclosed label value".into()),
+                },
+                Some((_, c)) => value.push(c),
+                None => return Err("unclosed label value".into()),
+            }
+        };
+        matchers.push(Matcher { label: label.to_owned(), equal, value });
+        s = rest[close + 1..].trim_start();
+        s = s.strip_prefix(',').unwrap_or(s);
+    }
+}
+
+/// Selector, comparison and threshold of `metric{label="value"} > 0`.
+fn parse_expr(expr: &str) -> Result<(String, Vec<Matcher>, Op, f64), String> {
+    let expr = expr.trim();
+    let name_end = expr
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+        .unwrap_or(expr.len());
+    if name_end == 0 {
+        return Err("expected a metric name".into());
+    }
+    let mut rest = &expr[name_end..];
+    let mut matchers = Vec::new();
+    if let Some(body) = rest.trim_start().strip_prefix('{') {
+        (matchers, rest) = parse_labels(body)?;
+    }
+    let rest = rest.trim_start();
+    let (op, threshold) = [("==", Op::Eq), ("!=", Op::Ne), (">=", Op::Ge), ("<=", Op::Le), (">", Op::Gt), ("<", Op::Lt)]
+        .into_iter()
+        .find_map(|(token, op)| Some((op, rest.strip_prefix(token)?)))
+        .ok_or("expected ==, !=, >, >=, < or <= after the selector")?;
+    let threshold = threshold.trim().parse().map_err(|_| format!("{}: not a number", threshold.trim()))?;
+    Ok((expr[..name_end].to_owned(), matchers, op, threshold))
+}
+
+impl Alerts {
+    /// `config` with every rule checked; errors name the offending key,
+    /// such as `rules[1].expr`.
+    pub fn compile(config: AlertsConfig) -> Result<Self, String> {
+        if config.webhook_url.trim().is_empty() {
+            return Err("webhook_url: must not be empty".into());
+        }
+        let mut rules: Vec<Rule> = Vec::with_capacity(config.rules.len());
+        for (i, rule) in config.rules.into_iter().enumerate() {
+            let name = rule.name.trim().to_owned();
+            if name.is_empty() {
+                return Err(format!("rules[{i}].name: must not be empty"));
+            }
+            if rules.iter().any(|r| r.name == name) {
+                return Err(format!("rules[{i}].name: {} is used by another rule", name));
+            }
+            let (metric, matchers, op, threshold) =
+                parse_expr(&rule.expr).map_err(|e| format!("rules[{i}].expr: {}", e))?;
+            if let Some(label) = rule.labels.keys().find(|l| !crate::is_valid_label_name(l)) {
+                return Err(format!("rules[{i}].labels: invalid label name {}", label));
+            }
+            rules.push(Rule {
+                name,
+                metric,
+                matchers,
+                op,
+                threshold,
+                for_: Duration::from_secs(rule.for_secs),
+                labels: rule.labels,
+                summary: rule.summary,
+            });
+        }
+        Ok(Self {
+            webhook: webhook::WebhookConfig { url: config.webhook_url, max_retries: config.max_retries },
+            rules,
+        })
+    }
+
+    /// Names of the rules, for the startup log.
+    pub fn names(&self) -> Vec<&str> {
+        self.rules.iter().map(|r| r.name.as_str()).collect()
+    }
+}
+
+/// JSON body of one alert notification.
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    /// `firing` or `resolved`.
+    status: &'static str,
+    alert: String,
+    /// Labels of the series, then the rule's `labels`.
+    labels: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    /// Latest sample value that met the condition.
+    value: f64,
+    /// Unix time in milliseconds the condition was first met.
+    starts_at: i64,
+    /// Unix time in milliseconds the alert resolved.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ends_at: Option<i64>,
+}
+
+fn unix_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// Index of a rule and labels of one of its series.
+type SeriesKey = (usize, BTreeMap<String, String>);
+
+/// A series of a rule whose condition holds.
+struct Active {
+    since: Instant,
+    starts_at: i64,
+    value: f64,
+    firing: bool,
+}
+
+/// Rule state across snapshots, and the queue to the delivery task.
+pub struct Engine {
+    rules: Vec<Rule>,
+    active: Mutex<HashMap<SeriesKey, Active>>,
+    tx: mpsc::Sender<Notification>,
+    deliveries: Arc<Deliveries>,
+}
+
+impl Engine {
+    /// The rules of `alerts`, notifying through `tx`.
+    pub fn new(alerts: &Alerts, tx: mpsc::Sender<Notification>, deliveries: Arc<Deliveries>) -> Self {
+        Self { rules: alerts.rules.clone(), active: Mutex::default(), tx, deliveries }
+    }
+
+    fn notify(&self, rule: &Rule, status: &'static str, labels: &BTreeMap<String, String>, active: &Active) {
+        let mut all = labels.clone();
+        all.extend(rule.labels.iter().map(|(k, v)| (k.clone(), v.clone())));
+        info!("Alert {} {}: {:?}", rule.name, status, labels);
+        let notification = Notification {
+            status,
+            alert: rule.name.clone(),
+            labels: all,
+            summary: rule.summary.clone(),
+            value: active.value,
+            starts_at: active.starts_at,
+            ends_at: (status == "resolved").then(unix_ms),
+        };
+        if self.tx.try_send(notification).is_err() {
+            warn!("Alert notification queue full, dropping {} {}", rule.name, status);
+            self.deliveries.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Check every rule against `samples` and notify the alerts that fire
+    /// or resolve.
+    pub fn evaluate(&self, samples: &[registry::Sample]) {
+        self.evaluate_at(samples, Instant::now());
+    }
+
+    fn evaluate_at(&self, samples: &[registry::Sample], now: Instant) {
+        let samples: Vec<(String, BTreeMap<String, String>, f64)> =
+            samples.iter().map(|s| (s.name(), s.labels.pairs().iter().cloned().collect(), s.value)).collect();
+        let mut active = self.active.lock().unwrap();
+        let mut holding = HashSet::new();
+        for (i, rule) in self.rules.iter().enumerate() {
+            for (name, labels, value) in &samples {
+                if *name != rule.metric || !rule.selects(labels) || !rule.op.holds(*value, rule.threshold) {
+                    continue;
+                }
+                let key = (i, labels.clone());
+                let entry = active
+                    .entry(key.clone())
+                    .or_insert_with(|| Active { since: now, starts_at: unix_ms(), value: *value, firing: false });
+                entry.value = *value;
+                if !entry.firing && now.duration_since(entry.since) >= rule.for_ {
+                    entry.firing = true;
+                    self.notify(rule, "firing", labels, entry);
+                }
+                holding.insert(key);
+            }
+        }
+        active.retain(|key, entry| {
+            if holding.contains(key) {
+                return true;
+            }
+            if entry.firing {
+                self.notify(&self.rules[key.0], "resolved", &key.1, entry);
+            }
+            false
+        });
+    }
+
+    /// `kafka_connect_alerts_firing` per rule and the delivery outcomes.
+    pub fn lines(&self) -> Vec<registry::Sample> {
+        let active = self.active.lock().unwrap();
+        let mut lines: Vec<registry::Sample> = self
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                let firing = active.iter().filter(|((r, _), a)| *r == i && a.firing).count();
+                registry::gauge("kafka_connect_alerts_firing").label("alert", &rule.name).set(firing)
+            })
+            .collect();
+        lines.extend(self.deliveries.lines("kafka_connect_alert_notifications_total", &Labels::default()));
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(rules: serde_json::Value) -> AlertsConfig {
+        serde_json::from_value(serde_json::json!({ "webhook_url": "http://hooks/alerts", "rules": rules })).unwrap()
+    }
+
+    fn compile(rules: serde_json::Value) -> Result<Alerts, String> {
+        Alerts::compile(config(rules))
+    }
+
+    #[test]
+    fn expressions_parse_into_selector_and_threshold() {
+        let (metric, matchers, op, threshold) =
+            parse_expr(r#" kafka_connect_connector_state{ connector="a\"b", state != "running" } >= 1.5 "#).unwrap();
+        assert_eq!((metric.as_str(), threshold), ("kafka_connect_connector_state", 1.5));
+        assert!(matches!(op, Op::Ge));
+        let matchers: Vec<_> = matchers.iter().map(|m| (m.label.as_str(), m.equal, m.value.as_str())).collect();
+        assert_eq!(matchers, [("connector", true, "a\"b"), ("state", false, "running")]);
+        for (expr, holds) in [
+            ("m == 1", [false, true, false]),
+            ("m != 1", [true, false, true]),
+            ("m < 1", [true, false, false]),
+            ("m <= 1", [true, true, false]),
+            ("m > 1", [false, false, true]),
+            ("m>=1", [false, true, true]),
+        ] {
+            let (_, _, op, threshold) = parse_expr(expr).unwrap();
+            assert_eq!([0.0, 1.0, 2.0].map(|v| op.holds(v, threshold)), holds, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn expression_errors_say_what_was_expected() {
+        let err = |expr| parse_expr(expr).unwrap_err();
+        assert_eq!(err("> 1"), "expected a metric name");
+        assert_eq!(err("kafka_connect_up"), "expected ==, !=, >, >=, < or <= after the selector");
+        assert_eq!(err("kafka_connect_up == one"), "one: not a number");
+        assert_eq!(err(r#"m{state~"x"} > 0"#), "expected `=` or `!=` after a label name");
+        assert_eq!(err(r#"m{="x"} > 0"#), "expected a label name");
+        assert_eq!(err("m{state=running} > 0"), "expected a quoted label value");
+        assert_eq!(err(r#"m{state="running} > 0"#), "unclosed label value");
+    }
+
+    #[test]
+    fn compile_names_the_offending_key() {
+        let rule = |name: &str, expr: &str| serde_json::json!({ "name": name, "expr": expr });
+        assert_eq!(compile(serde_json::json!([rule(" ", "m > 0")])).err().unwrap(), "rules[0].name: must not be empty");
+        assert_eq!(
+            compile(serde_json::json!([rule("down", "m > 0"), rule("down ", "m > 1")])).err().unwrap(),
+            "rules[1].name: down is used by another rule"
+        );
+        let expr = compile(serde_json::json!([rule("down", "m")])).err().unwrap();
+        assert_eq!(expr, "rules[0].expr: expected ==, !=, >, >=, < or <= after the selector");
+        let labeled = serde_json::json!([{ "name": "down", "expr": "m > 0", "labels": { "bad-name": "x" } }]);
+        assert_eq!(compile(labeled).err().unwrap(), "rules[0].labels: invalid label name bad-name");
+        let mut unset = config(serde_json::json!([]));
+        unset.webhook_url = "  ".into();
+        assert_eq!(Alerts::compile(unset).err().unwrap(), "webhook_url: must not be empty");
+        let alerts = compile(serde_json::json!([rule("down", "m > 0"), rule("failed", "m > 1")])).unwrap();
+        assert_eq!(alerts.names(), ["down", "failed"]);
+        assert_eq!(alerts.webhook.max_retries, 3);
+    }
+
+    #[test]
+    fn matchers_treat_a_missing_label_as_empty() {
+        let alerts = compile(serde_json::json!([{ "name": "r", "expr": r#"m{cluster="", state!="running"} > 0"# }]));
+        let rule = &alerts.unwrap().rules[0];
+        let labels = |pairs: &[(&str, &str)]| pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        assert!(rule.selects(&labels(&[("state", "failed")])));
+        assert!(rule.selects(&labels(&[])));
+        assert!(!rule.selects(&labels(&[("state", "running")])));
+        assert!(!rule.selects(&labels(&[("cluster", "prod"), ("state", "failed")])));
+    }
+
+    fn up(instance: &str, value: bool) -> registry::Sample {
+        registry::gauge("kafka_connect_up").label("instance", instance).set(value)
+    }
+
+    fn firing(engine: &Engine) -> f64 {
+        engine.lines()[0].value
+    }
+
+    #[test]
+    fn series_fire_after_for_secs_and_resolve_when_gone() {
+        let alerts = compile(serde_json::json!([{
+            "name": "ConnectDown",
+            "expr": "kafka_connect_up == 0",
+            "for_secs": 60,
+            "labels": { "severity": "page" },
+            "summary": "Connect is down",
+        }]))
+        .unwrap();
+        let (tx, mut rx) = mpsc::channel(8);
+        let engine = Engine::new(&alerts, tx, Arc::default());
+        let t0 = Instant::now();
+        let at = |secs| t0 + Duration::from_secs(secs);
+
+        // Pending until the condition has held for 60s
+        engine.evaluate_at(&[up("a", false), up("b", true)], at(0));
+        engine.evaluate_at(&[up("a", false), up("b", true)], at(59));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(firing(&engine), 0.0);
+
+        engine.evaluate_at(&[up("a", false), up("b", true)], at(60));
+        let fired = rx.try_recv().unwrap();
+        assert_eq!((fired.status, fired.alert.as_str(), fired.value), ("firing", "ConnectDown", 0.0));
+        assert_eq!(fired.labels.get("instance").map(String::as_str), Some("a"));
+        assert_eq!(fired.labels.get("severity").map(String::as_str), Some("page"));
+        assert_eq!(fired.summary.as_deref(), Some("Connect is down"));
+        assert_eq!(fired.ends_at, None);
+        assert_eq!(firing(&engine), 1.0);
+
+        // Still firing: no second notification
+        engine.evaluate_at(&[up("a", false)], at(90));
+        assert!(rx.try_recv().is_err());
+
+        // A series that recovers resolves at once; one that was only pending just stops
+        engine.evaluate_at(&[up("a", true), up("b", false)], at(100));
+        let resolved = rx.try_recv().unwrap();
+        assert_eq!((resolved.status, resolved.starts_at), ("resolved", fired.starts_at));
+        assert!(resolved.ends_at.is_some());
+        engine.evaluate_at(&[up("b", true)], at(200));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(firing(&engine), 0.0);
+
+        // Pending starts over after the condition broke
+        engine.evaluate_at(&[up("a", false)], at(300));
+        engine.evaluate_at(&[up("a", false)], at(330));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn a_full_queue_counts_dropped_notifications() {
+        let alerts = compile(serde_json::json!([{ "name": "ConnectDown", "expr": "kafka_connect_up == 0" }])).unwrap();
+        let (tx, mut rx) = mpsc::channel(1);
+        let deliveries = Arc::new(Deliveries::default());
+        let engine = Engine::new(&alerts, tx, deliveries.clone());
+        // With no for_secs the alert fires on the first snapshot
+        engine.evaluate(&[up("a", false), up("b", false)]);
+        assert_eq!(rx.try_recv().unwrap().status, "firing");
+        assert!(rx.try_recv().is_err());
+        assert_eq!(deliveries.dropped.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,263 @@
+//! Slack and Microsoft Teams channels from the config file's `channels`,
+//! sent the connector FAILED transitions the event webhook gets.
+//!
+//! Each channel formats an event from its `template` and links it to the
+//! connector. An event goes to every channel whose `match` it meets, in the
+//! order they are listed. Messages share one queue and delivery task, so a
+//! slow channel holds up the others but never scraping.
+
+use crate::registry::{Labels, Sample};
+use crate::webhook::{self, Deliveries, Event};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::atomic::Ordering,
+    sync::Arc,
+};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+const SLACK_TEMPLATE: &str = "Connector *{connector}* on {cluster} went from {old_state} to {new_state} (<{link}|status>)";
+const TEAMS_TEMPLATE: &str = "Connector **{connector}** on {cluster} went from {old_state} to {new_state}";
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    Slack,
+    Teams,
+}
+
+/// One entry of `channels` in the config file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelConfig {
+    name: String,
+    #[serde(rename = "type")]
+    kind: Kind,
+    /// Incoming webhook URL of the Slack app or Teams connector.
+    webhook_url: String,
+    /// Fields the event must have, by exact value; none matches everything.
+    #[serde(default, rename = "match")]
+    matchers: BTreeMap<String, String>,
+    /// Message text, with `{field}` placeholders.
+    #[serde(default)]
+    template: Option<String>,
+    /// Link to the connector, with `{field}` placeholders; its status on the
+    /// Connect REST API by default.
+    #[serde(default)]
+    link: Option<String>,
+    /// Retries per message on network errors, 5xx and 429.
+    #[serde(default = "default_max_retries")]
+    max_retries: u32,
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+/// A compiled entry of `channels`, with its delivery counters.
+pub struct Channel {
+    name: String,
+    kind: Kind,
+    webhook_url: String,
+    max_retries: u32,
+    matchers: BTreeMap<String, String>,
+    template: String,
+    link: Option<String>,
+    deliveries: Deliveries,
+}
+
+/// Check and compile `channels`; errors name the offending entry.
+pub fn compile(configs: Vec<ChannelConfig>) -> Result<Vec<Channel>, String> {
+    let mut names = HashSet::new();
+    configs
+        .into_iter()
+        .enumerate()
+        .map(|(i, config)| {
+            if config.name.is_empty() {
+                return Err(format!("[{}].name: must not be empty", i));
+            }
+            if !names.insert(config.name.clone()) {
+                return Err(format!("[{}].name: {} is listed twice", i, config.name));
+            }
+            if !config.webhook_url.starts_with("http://") && !config.webhook_url.starts_with("https://") {
+                return Err(format!("[{}].webhook_url: expected an http or https URL", i));
+            }
+            let default = match config.kind {
+                Kind::Slack => SLACK_TEMPLATE,
+                Kind::Teams => TEAMS_TEMPLATE,
+            };
+            Ok(Channel {
+                name: config.name,
+                kind: config.kind,
+                webhook_url: config.webhook_url,
+                max_retries: config.max_retries,
+                matchers: config.matchers,
+                template: config.template.unwrap_or_else(|| default.into()),
+                link: config.link,
+                deliveries: Deliveries::default(),
+            })
+        })
+        .collect()
+}
+
+/// The fields templates and `match` can use: the instance's labels, then
+/// the event's own, which win.
+fn fields(event: &Event) -> BTreeMap<&str, String> {
+    let mut fields: BTreeMap<&str, String> = event.labels.iter().map(|(k, v)| (k.as_str(), v.clone())).collect();
+    fields.insert("connector", event.connector.clone());
+    fields.insert("instance", event.instance.clone());
+    fields.insert("cluster", event.cluster.clone().unwrap_or_else(|| event.instance.clone()));
+    fields.insert("old_state", event.old_state.clone());
+    fields.insert("new_state", event.new_state.clone());
+    fields.insert("url", event.url.clone());
+    fields
+}
+
+/// `template` with each `{field}` replaced; unknown fields are left empty.
+fn render(template: &str, fields: &BTreeMap<&str, String>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after.find('}').filter(|&end| after[..end].chars().all(|c| c.is_ascii_alphanumeric() || c == '_')) {
+            Some(end) => {
+                out.push_str(fields.get(&after[..end]).map_or("", String::as_str));
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+impl Channel {
+    fn matches(&self, fields: &BTreeMap<&str, String>) -> bool {
+        self.matchers.iter().all(|(k, v)| fields.get(k.as_str()) == Some(v))
+    }
+
+    /// Request body of the message for `event`.
+    fn message(&self, event: &Event, fields: &mut BTreeMap<&str, String>) -> Value {
+        fields.remove("link");
+        let link = match &self.link {
+            Some(link) => render(link, fields),
+            None => crate::connector_url(&event.url, &event.connector, "status"),
+        };
+        fields.insert("link", link.clone());
+        let text = render(&self.template, fields);
+        match self.kind {
+            Kind::Slack => json!({ "text": text }),
+            Kind::Teams => json!({
+                "@type": "MessageCard",
+                "@context": "https://schema.org/extensions",
+                "summary": text,
+                "themeColor": if event.new_state == "FAILED" { "D13438" } else { "2EB886" },
+                "title": format!("{}: {}", event.connector, event.new_state),
+                "text": text,
+                "potentialAction": [{
+                    "@type": "OpenUri",
+                    "name": "Open connector",
+                    "targets": [{ "os": "default", "uri": link }],
+                }],
+            }),
+        }
+    }
+}
+
+/// Sending half handed to the scrapers, through [`webhook::EventSink`].
+#[derive(Clone)]
+pub struct Sink {
+    channels: Arc<Vec<Channel>>,
+    tx: mpsc::Sender<(usize, Value)>,
+}
+
+impl Sink {
+    /// Start the delivery task of `channels`.
+    pub fn new(client: reqwest::Client, channels: Arc<Vec<Channel>>) -> Self {
+        let (tx, rx) = mpsc::channel(webhook::QUEUE_CAPACITY);
+        tokio::spawn(run(client, channels.clone(), rx));
+        Self { channels, tx }
+    }
+
+    /// Names of the channels, for the startup log.
+    pub fn names(&self) -> Vec<&str> {
+        self.channels.iter().map(|c| c.name.as_str()).collect()
+    }
+
+    /// Queue a message for every channel `event` matches, without waiting.
+    pub fn emit(&self, event: &Event) {
+        let mut fields = fields(event);
+        for (i, channel) in self.channels.iter().enumerate() {
+            if !channel.matches(&fields) {
+                continue;
+            }
+            if self.tx.try_send((i, channel.message(event, &mut fields))).is_err() {
+                warn!("Channel queue full, dropping connector event for {}", channel.name);
+                channel.deliveries.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// kafka_connect_channel_notifications_total{channel,result}.
+    pub fn lines(&self) -> Vec<Sample> {
+        let mut lines = Vec::new();
+        for channel in self.channels.iter() {
+            let labels = Labels::default().with("channel", &channel.name);
+            lines.extend(channel.deliveries.lines("kafka_connect_channel_notifications_total", &labels));
+        }
+        lines
+    }
+}
+
+/// Deliver queued messages one at a time. Logs name the channel, as the
+/// webhook URL is its secret.
+async fn run(client: reqwest::Client, channels: Arc<Vec<Channel>>, mut rx: mpsc::Receiver<(usize, Value)>) {
+    while let Some((i, body)) = rx.recv().await {
+        let channel = &channels[i];
+        let target = format!("channel {}", channel.name);
+        match webhook::deliver(&client, &channel.webhook_url, channel.max_retries, &body, &target).await {
+            Ok(()) => {
+                debug!("channels: delivered to {}", channel.name);
+                channel.deliveries.success.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                warn!("Delivery to channel {} failed: {}", channel.name, e);
+                channel.deliveries.failure.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webhook::tests::{capture_logs, refused_url};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn failed_delivery_logs_the_channel_not_its_url() {
+        let (logs, _guard) = capture_logs();
+        let url = refused_url("/services/T000/B000/secret-token").await;
+        let config = serde_json::json!({ "name": "ops", "type": "slack", "webhook_url": url, "max_retries": 0 });
+        let channels = Arc::new(compile(vec![serde_json::from_value(config).unwrap()]).unwrap());
+        let sink = Sink::new(reqwest::Client::new(), channels.clone());
+        sink.emit(&Event::new("sink", "connect-1:8083", &BTreeMap::new(), "running", "failed"));
+        let failed = || channels[0].deliveries.failure.load(Ordering::Relaxed);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while failed() == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let logs = String::from_utf8(logs.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("Delivery to channel ops failed"), "{}", logs);
+        assert!(!logs.contains("secret-token"), "{}", logs);
+    }
+}
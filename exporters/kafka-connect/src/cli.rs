@@ -0,0 +1,99 @@
+//! Command-line flags. Each one overrides its env variable, which in turn
+//! overrides the config file where both apply.
+//!
+//! Unknown flags and missing values are usage errors (exit status 2), so a
+//! typo fails loudly instead of leaving a default in place.
+
+use std::time::Duration;
+
+const USAGE: &str = "\
+Prometheus exporter for Kafka Connect
+
+Usage: kafka-connect-exporter [OPTIONS]
+
+Options:
+      --connect-url <URL>  Kafka Connect REST URL; repeat or comma-separate for several.
+                           Replaces KAFKA_CONNECT_URLS and the config file's instances
+      --bind <ADDR>        Listen address for /metrics [env: METRICS_BIND_ADDR, BIND_ADDR]
+                           [default: 0.0.0.0:9407]
+      --interval <SECS>    Background scrape interval in seconds [env: SCRAPE_INTERVAL_SECS]
+                           [default: 30]
+      --config <PATH>      JSON, TOML or YAML config file [env: CONFIG_FILE]
+      --log-level <FILTER> Log filter, e.g. `debug` or `kafka_connect_exporter=debug,warn`
+                           [env: RUST_LOG] [default: info]
+  -h, --help               Print help
+  -V, --version            Print version
+
+Every other setting is an env variable; see the README.
+";
+
+#[derive(Debug, Default)]
+pub struct Args {
+    pub connect_urls: Vec<String>,
+    pub bind: Option<String>,
+    pub interval: Option<Duration>,
+    pub config: Option<String>,
+    pub log_level: Option<String>,
+}
+
+enum Parsed {
+    Run(Args),
+    Help,
+    Version,
+}
+
+fn parse(mut args: impl Iterator<Item = String>) -> Result<Parsed, String> {
+    let mut out = Args::default();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_owned(), Some(value.to_owned())),
+            _ => (arg, None),
+        };
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Parsed::Help),
+            "-V" | "--version" => return Ok(Parsed::Version),
+            "--connect-url" | "--bind" | "--interval" | "--config" | "--log-level" => {}
+            _ => return Err(format!("unexpected argument '{}'", flag)),
+        }
+        let value = match inline.or_else(|| args.next()) {
+            Some(v) if !v.is_empty() => v,
+            _ => return Err(format!("a value is required for '{}'", flag)),
+        };
+        match flag.as_str() {
+            "--connect-url" => out.connect_urls.extend(
+                value
+                    .split(',')
+                    .map(|u| u.trim().trim_end_matches('/').to_owned())
+                    .filter(|u| !u.is_empty()),
+            ),
+            "--bind" => out.bind = Some(value),
+            "--interval" => match value.parse::<u64>() {
+                Ok(secs) if secs > 0 => out.interval = Some(Duration::from_secs(secs)),
+                _ => return Err(format!("invalid value '{}' for '--interval': expected whole seconds > 0", value)),
+            },
+            "--config" => out.config = Some(value),
+            _ => out.log_level = Some(value),
+        }
+    }
+    Ok(Parsed::Run(out))
+}
+
+/// Flags of this process. Prints help or the version and exits when asked
+/// to, and exits with status 2 on a usage error.
+pub fn args() -> Args {
+    match parse(std::env::args().skip(1)) {
+        Ok(Parsed::Run(args)) => args,
+        Ok(Parsed::Help) => {
+            print!("{USAGE}");
+            std::process::exit(0);
+        }
+        Ok(Parsed::Version) => {
+            println!("kafka-connect-exporter {}", env!("CARGO_PKG_VERSION"));
+            std::process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("error: {e}\n\nFor more information, try '--help'.");
+            std::process::exit(2);
+        }
+    }
+}
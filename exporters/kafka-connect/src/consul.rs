@@ -0,0 +1,315 @@
+//! Consul catalog discovery of Connect workers (CONSUL_DISCOVERY_SERVICE).
+//!
+//! The healthy instances of a service are read from the agent's health
+//! endpoint with a blocking query: the request returns as soon as the
+//! service changes, or after [`WATCH_WAIT`] with the same list, and the
+//! next one waits from the index the last answer carried.
+
+use crate::Discovered;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use tracing::debug;
+
+/// Longest Consul holds a blocking query before answering anyway.
+const WATCH_WAIT: Duration = Duration::from_secs(60);
+/// Pause before a failed query is retried.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Settings from CONSUL_*.
+#[derive(Debug)]
+pub struct Discovery {
+    /// Agent base URL, without a trailing slash.
+    addr: String,
+    service: String,
+    /// Tags every instance must carry.
+    tags: Vec<String>,
+    datacenter: Option<String>,
+    token: Option<String>,
+    /// `http` or `https`, for the instance URLs.
+    scheme: String,
+    /// Service meta key whose value is the instance's `cluster`.
+    cluster_meta: Option<String>,
+    /// `cluster` of instances without that meta key.
+    cluster: Option<String>,
+    /// X-Consul-Index of the last answer; 0 asks without blocking.
+    index: AtomicU64,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Entry {
+    node: Node,
+    service: Service,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Node {
+    #[serde(default)]
+    address: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Service {
+    #[serde(default)]
+    address: String,
+    port: u16,
+    #[serde(default)]
+    meta: Option<HashMap<String, String>>,
+}
+
+impl Discovery {
+    /// Settings from CONSUL_*, when CONSUL_DISCOVERY_SERVICE is set. `var`
+    /// reads one env variable; `timeout` is added to the blocking wait.
+    pub fn from_env(var: impl Fn(&str) -> Option<String>, timeout: Duration) -> Result<Option<Self>, String> {
+        let nonempty = |key| var(key).map(|v| v.trim().to_owned()).filter(|v| !v.is_empty());
+        let Some(service) = nonempty("CONSUL_DISCOVERY_SERVICE") else {
+            return Ok(None);
+        };
+        let addr = nonempty("CONSUL_HTTP_ADDR").unwrap_or_else(|| "127.0.0.1:8500".into());
+        let addr = if addr.contains("://") { addr } else { format!("http://{}", addr) };
+        let scheme = nonempty("CONSUL_DISCOVERY_SCHEME").unwrap_or_else(|| "http".into());
+        if scheme != "http" && scheme != "https" {
+            return Err(format!("CONSUL_DISCOVERY_SCHEME {}: expected http or https", scheme));
+        }
+        let token = match (nonempty("CONSUL_HTTP_TOKEN"), nonempty("CONSUL_HTTP_TOKEN_FILE")) {
+            (Some(token), _) => Some(token),
+            (None, Some(path)) => Some(
+                std::fs::read_to_string(&path)
+                    .map(|t| t.trim().to_owned())
+                    .map_err(|e| format!("{}: {}", path, e))?,
+            ),
+            (None, None) => None,
+        };
+
+        // The client waits out a blocking query, plus the jitter Consul adds to it
+        let mut builder = reqwest::Client::builder().timeout(WATCH_WAIT + WATCH_WAIT / 16 + timeout);
+        if let Some(path) = nonempty("CONSUL_CACERT") {
+            for cert in crate::tls::ca_certificates(&path)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        let client = builder.build().map_err(|e| e.to_string())?;
+        Ok(Some(Self {
+            addr: addr.trim_end_matches('/').to_owned(),
+            service,
+            tags: nonempty("CONSUL_DISCOVERY_TAGS").map(|t| crate::split_list(&t)).unwrap_or_default(),
+            datacenter: nonempty("CONSUL_DISCOVERY_DATACENTER"),
+            token,
+            scheme,
+            cluster_meta: nonempty("CONSUL_DISCOVERY_CLUSTER_META"),
+            cluster: nonempty("CONSUL_DISCOVERY_CLUSTER"),
+            index: AtomicU64::new(0),
+            client,
+        }))
+    }
+
+    /// What is discovered, for the startup log.
+    pub fn describe(&self) -> String {
+        let mut what = format!("service {}", self.service);
+        if !self.tags.is_empty() {
+            what.push_str(&format!(" tagged {}", self.tags.join(", ")));
+        }
+        if let Some(dc) = &self.datacenter {
+            what.push_str(&format!(" in datacenter {}", dc));
+        }
+        format!("{} via {}", what, self.addr)
+    }
+
+    /// How long to wait after a failed query.
+    pub fn retry_delay(&self) -> Duration {
+        RETRY_DELAY
+    }
+
+    /// The passing instances of the service. After the first call this
+    /// blocks until the service changes or [`WATCH_WAIT`] passes; a
+    /// failure makes the next call ask afresh.
+    pub async fn targets(&self) -> Result<Vec<Discovered>, String> {
+        let result = self.fetch().await;
+        if result.is_err() {
+            self.index.store(0, Ordering::Relaxed);
+        }
+        result
+    }
+
+    async fn fetch(&self) -> Result<Vec<Discovered>, String> {
+        let url = format!("{}/v1/health/service/{}", self.addr, self.service);
+        let index = self.index.load(Ordering::Relaxed);
+        let mut query = vec![("passing", "true".to_owned())];
+        query.extend(self.tags.iter().map(|tag| ("tag", tag.clone())));
+        if let Some(dc) = &self.datacenter {
+            query.push(("dc", dc.clone()));
+        }
+        if index > 0 {
+            query.push(("index", index.to_string()));
+            query.push(("wait", format!("{}s", WATCH_WAIT.as_secs())));
+        }
+        let mut req = self.client.get(&url).query(&query);
+        if let Some(token) = &self.token {
+            req = req.header("X-Consul-Token", token);
+        }
+        let resp = req.send().await.map_err(|e| e.to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("{} answered HTTP {}", url, resp.status()));
+        }
+        let next = resp
+            .headers()
+            .get("X-Consul-Index")
+            .and_then(|v| v.to_str().ok()?.parse::<u64>().ok())
+            .unwrap_or(0);
+        let entries: Vec<Entry> = resp.json().await.map_err(|e| e.to_string())?;
+        // An index that goes back means the catalog was restored: start over
+        self.index.store(if next < index { 0 } else { next }, Ordering::Relaxed);
+
+        let mut targets: Vec<Discovered> = entries
+            .into_iter()
+            .map(|entry| {
+                let host = if entry.service.address.is_empty() { entry.node.address } else { entry.service.address };
+                let host = if host.contains(':') { format!("[{}]", host) } else { host };
+                let cluster = self
+                    .cluster_meta
+                    .as_ref()
+                    .and_then(|key| entry.service.meta.as_ref()?.get(key).filter(|c| !c.is_empty()).cloned())
+                    .or_else(|| self.cluster.clone());
+                Discovered { name: None, url: format!("{}://{}:{}", self.scheme, host, entry.service.port), cluster }
+            })
+            .collect();
+        targets.sort_by(|a, b| a.url.cmp(&b.url));
+        targets.dedup_by(|a, b| a.url == b.url);
+        debug!("consul: {} has {} passing instances (index {})", self.service, targets.len(), next);
+        Ok(targets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, response::IntoResponse};
+    use std::sync::{Arc, Mutex};
+
+    fn env(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let pairs: Vec<(String, String)> = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |key| pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone())
+    }
+
+    #[test]
+    fn settings_come_from_the_env() {
+        assert!(Discovery::from_env(env(&[]), Duration::from_secs(1)).unwrap().is_none());
+        let found = Discovery::from_env(
+            env(&[
+                ("CONSUL_DISCOVERY_SERVICE", "connect"),
+                ("CONSUL_DISCOVERY_TAGS", "prod, eu"),
+                ("CONSUL_DISCOVERY_DATACENTER", "dc1"),
+            ]),
+            Duration::from_secs(1),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(found.describe(), "service connect tagged prod, eu in datacenter dc1 via http://127.0.0.1:8500");
+
+        let err = |pairs: &[(&str, &str)]| Discovery::from_env(env(pairs), Duration::from_secs(1)).unwrap_err();
+        let scheme = err(&[("CONSUL_DISCOVERY_SERVICE", "connect"), ("CONSUL_DISCOVERY_SCHEME", "tcp")]);
+        assert_eq!(scheme, "CONSUL_DISCOVERY_SCHEME tcp: expected http or https");
+        let token = err(&[("CONSUL_DISCOVERY_SERVICE", "connect"), ("CONSUL_HTTP_TOKEN_FILE", "/nonexistent/token")]);
+        assert!(token.starts_with("/nonexistent/token: "), "{}", token);
+    }
+
+    /// Query strings and X-Consul-Token of the health calls.
+    type Calls = Arc<Mutex<Vec<(String, Option<String>)>>>;
+
+    /// An agent answering with `replies` in turn: its status, the
+    /// X-Consul-Index and the entries.
+    async fn agent(replies: Vec<(StatusCode, u64, serde_json::Value)>) -> (String, Calls) {
+        let calls: Calls = Arc::default();
+        let replies = Arc::new(Mutex::new(replies));
+        let seen = calls.clone();
+        let app = axum::Router::new().route(
+            "/v1/health/service/connect",
+            axum::routing::get(move |req: axum::extract::Request| {
+                let token = req.headers().get("x-consul-token").map(|v| v.to_str().unwrap().to_owned());
+                seen.lock().unwrap().push((req.uri().query().unwrap_or_default().to_owned(), token));
+                let (status, index, body) = replies.lock().unwrap().remove(0);
+                async move { (status, [("X-Consul-Index", index.to_string())], axum::Json(body)).into_response() }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (addr, calls)
+    }
+
+    fn entry(node: &str, address: &str, port: u16, meta: serde_json::Value) -> serde_json::Value {
+        serde_json::json!({
+            "Node": { "Node": "n", "Address": node },
+            "Service": { "Service": "connect", "Address": address, "Port": port, "Meta": meta },
+        })
+    }
+
+    #[tokio::test]
+    async fn targets_follow_the_blocking_index() {
+        let entries = serde_json::json!([
+            entry("10.0.0.2", "", 8083, serde_json::Value::Null),
+            entry("10.0.0.9", "10.0.0.1", 8083, serde_json::json!({ "cluster": "billing" })),
+            entry("10.0.0.9", "fd00::3", 8083, serde_json::json!({ "cluster": "" })),
+            entry("10.0.0.2", "", 8083, serde_json::json!({})),
+        ]);
+        let (addr, calls) = agent(vec![
+            (StatusCode::OK, 42, entries.clone()),
+            (StatusCode::OK, 43, serde_json::json!([])),
+            (StatusCode::OK, 7, serde_json::json!([])),
+            (StatusCode::INTERNAL_SERVER_ERROR, 0, serde_json::json!([])),
+            (StatusCode::OK, 50, entries),
+        ])
+        .await;
+        let found = Discovery::from_env(
+            env(&[
+                ("CONSUL_DISCOVERY_SERVICE", "connect"),
+                ("CONSUL_HTTP_ADDR", &addr),
+                ("CONSUL_HTTP_TOKEN", "acl"),
+                ("CONSUL_DISCOVERY_TAGS", "prod"),
+                ("CONSUL_DISCOVERY_CLUSTER_META", "cluster"),
+                ("CONSUL_DISCOVERY_CLUSTER", "main"),
+            ]),
+            Duration::from_secs(1),
+        )
+        .unwrap()
+        .unwrap();
+
+        let target = |url: &str, cluster: &str| Discovered {
+            name: None,
+            url: url.into(),
+            cluster: Some(cluster.into()),
+        };
+        let first = [
+            target("http://10.0.0.1:8083", "billing"),
+            target("http://10.0.0.2:8083", "main"),
+            target("http://[fd00::3]:8083", "main"),
+        ];
+        assert_eq!(found.targets().await.unwrap(), first);
+        assert!(found.targets().await.unwrap().is_empty());
+        // The index went back from 43 to 7: the next call starts over
+        found.targets().await.unwrap();
+        assert!(found.targets().await.unwrap_err().ends_with("answered HTTP 500 Internal Server Error"));
+        assert_eq!(found.targets().await.unwrap(), first);
+
+        let calls = calls.lock().unwrap();
+        let queries: Vec<&str> = calls.iter().map(|(query, _)| query.as_str()).collect();
+        assert_eq!(
+            queries,
+            [
+                "passing=true&tag=prod",
+                "passing=true&tag=prod&index=42&wait=60s",
+                "passing=true&tag=prod&index=43&wait=60s",
+                "passing=true&tag=prod",
+                "passing=true&tag=prod",
+            ]
+        );
+        assert!(calls.iter().all(|(_, token)| token.as_deref() == Some("acl")));
+    }
+}
@@ -0,0 +1,410 @@
+//! DNS discovery of Connect workers (KAFKA_CONNECT_DNS_SRV, KAFKA_CONNECT_DNS_A).
+//!
+//! A SRV record lists the workers by host and port, as Consul DNS does for
+//! a service; an A/AAAA name lists them by address, on one fixed port. The
+//! name is resolved again at a fixed interval. SRV queries go straight to
+//! the nameserver, over UDP with EDNS and over TCP when the answer is
+//! truncated; A/AAAA names use the system resolver.
+
+use crate::Discovered;
+use rand::Rng;
+use std::{net::SocketAddr, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+};
+use tracing::debug;
+
+/// Longest wait for one answer.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+/// UDP payload size offered through EDNS.
+const EDNS_PAYLOAD: u16 = 4096;
+const TYPE_SRV: u16 = 33;
+const TYPE_OPT: u16 = 41;
+const CLASS_IN: u16 = 1;
+
+/// What is looked up.
+#[derive(Debug)]
+enum Lookup {
+    /// `_service._proto.name`: hosts and ports from SRV records.
+    Srv { name: String, server: SocketAddr },
+    /// `host:port`: every address of `host`, on `port`.
+    Addr { host: String, port: u16 },
+}
+
+/// Settings from KAFKA_CONNECT_DNS_*.
+#[derive(Debug)]
+pub struct Discovery {
+    lookup: Lookup,
+    /// `http` or `https`, for the instance URLs.
+    scheme: String,
+    /// `cluster` of every discovered instance.
+    cluster: Option<String>,
+    /// How often the name is resolved again.
+    pub interval: Duration,
+}
+
+/// One SRV record.
+#[derive(Debug, PartialEq)]
+struct Srv {
+    port: u16,
+    target: String,
+}
+
+/// First `nameserver` of /etc/resolv.conf.
+fn system_nameserver() -> Result<SocketAddr, String> {
+    let conf = std::fs::read_to_string("/etc/resolv.conf").map_err(|e| format!("/etc/resolv.conf: {}", e))?;
+    conf.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .find_map(|addr| addr.trim().parse::<std::net::IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, 53))
+        .ok_or_else(|| "/etc/resolv.conf lists no nameserver: set KAFKA_CONNECT_DNS_SERVER".into())
+}
+
+impl Discovery {
+    /// Settings from KAFKA_CONNECT_DNS_*, when KAFKA_CONNECT_DNS_SRV or
+    /// KAFKA_CONNECT_DNS_A is set. `var` reads one env variable.
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, String> {
+        let nonempty = |key| var(key).map(|v| v.trim().to_owned()).filter(|v| !v.is_empty());
+        let lookup = match (nonempty("KAFKA_CONNECT_DNS_SRV"), nonempty("KAFKA_CONNECT_DNS_A")) {
+            (None, None) => return Ok(None),
+            (Some(_), Some(_)) => return Err("set KAFKA_CONNECT_DNS_SRV or KAFKA_CONNECT_DNS_A, not both".into()),
+            (Some(name), None) => {
+                let server = match nonempty("KAFKA_CONNECT_DNS_SERVER") {
+                    Some(addr) => addr
+                        .parse::<SocketAddr>()
+                        .or_else(|_| addr.parse::<std::net::IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                        .map_err(|_| format!("KAFKA_CONNECT_DNS_SERVER {}: expected ip or ip:port", addr))?,
+                    None => system_nameserver()?,
+                };
+                Lookup::Srv { name: name.trim_end_matches('.').to_owned(), server }
+            }
+            (None, Some(addr)) => {
+                let (host, port) = addr
+                    .rsplit_once(':')
+                    .and_then(|(host, port)| Some((host.trim_matches(['[', ']']), port.parse().ok()?)))
+                    .ok_or_else(|| format!("KAFKA_CONNECT_DNS_A {}: expected host:port", addr))?;
+                Lookup::Addr { host: host.to_owned(), port }
+            }
+        };
+        let scheme = nonempty("KAFKA_CONNECT_DNS_SCHEME").unwrap_or_else(|| "http".into());
+        if scheme != "http" && scheme != "https" {
+            return Err(format!("KAFKA_CONNECT_DNS_SCHEME {}: expected http or https", scheme));
+        }
+        let interval = match nonempty("KAFKA_CONNECT_DNS_INTERVAL_SECS").map(|s| s.parse::<u64>()) {
+            Some(Ok(secs)) if secs > 0 => Duration::from_secs(secs),
+            Some(_) => return Err("KAFKA_CONNECT_DNS_INTERVAL_SECS: expected whole seconds > 0".into()),
+            None => Duration::from_secs(30),
+        };
+        Ok(Some(Self { lookup, scheme, cluster: nonempty("KAFKA_CONNECT_DNS_CLUSTER"), interval }))
+    }
+
+    /// What is discovered, for the startup log.
+    pub fn describe(&self) -> String {
+        match &self.lookup {
+            Lookup::Srv { name, server } => format!("SRV records of {} via {}", name, server),
+            Lookup::Addr { host, port } => format!("addresses of {} on port {}", host, port),
+        }
+    }
+
+    /// One instance per SRV record, or per address of the A/AAAA name, in
+    /// a stable order.
+    pub async fn targets(&self) -> Result<Vec<Discovered>, String> {
+        let mut urls: Vec<String> = match &self.lookup {
+            Lookup::Srv { name, server } => resolve_srv(name, *server)
+                .await?
+                .into_iter()
+                .map(|srv| format!("{}://{}:{}", self.scheme, srv.target, srv.port))
+                .collect(),
+            Lookup::Addr { host, port } => tokio::net::lookup_host((host.as_str(), *port))
+                .await
+                .map_err(|e| format!("{}: {}", host, e))?
+                .map(|addr| format!("{}://{}", self.scheme, addr))
+                .collect(),
+        };
+        urls.sort();
+        urls.dedup();
+        debug!("dns: {} resolves to {} workers", self.describe(), urls.len());
+        Ok(urls.into_iter().map(|url| Discovered { name: None, url, cluster: self.cluster.clone() }).collect())
+    }
+}
+
+// ── Wire format ───────────────────────────────────────────────────────────────
+
+/// A recursive SRV query for `name` with an EDNS OPT record.
+fn query(id: u16, name: &str) -> Result<Vec<u8>, String> {
+    let mut msg = Vec::with_capacity(name.len() + 32);
+    msg.extend(id.to_be_bytes());
+    // RD; one question, one additional (OPT)
+    msg.extend([0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 1]);
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("{}: not a valid DNS name", name));
+        }
+        msg.push(label.len() as u8);
+        msg.extend(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend(TYPE_SRV.to_be_bytes());
+    msg.extend(CLASS_IN.to_be_bytes());
+    // OPT: root name, payload size in the class field, no options
+    msg.push(0);
+    msg.extend(TYPE_OPT.to_be_bytes());
+    msg.extend(EDNS_PAYLOAD.to_be_bytes());
+    msg.extend([0, 0, 0, 0, 0, 0]);
+    Ok(msg)
+}
+
+fn u16_at(msg: &[u8], pos: usize) -> Result<u16, String> {
+    msg.get(pos..pos + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| "truncated DNS message".to_owned())
+}
+
+/// The name at `pos`, following compression pointers, and the position
+/// after it.
+fn read_name(msg: &[u8], mut pos: usize) -> Result<(String, usize), String> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end = None;
+    for _ in 0..128 {
+        let len = *msg.get(pos).ok_or("truncated DNS name")? as usize;
+        match len {
+            0 => return Ok((labels.join("."), end.unwrap_or(pos + 1))),
+            l if l & 0xc0 == 0xc0 => {
+                end.get_or_insert(pos + 2);
+                pos = (u16_at(msg, pos)? & 0x3fff) as usize;
+            }
+            l => {
+                let label = msg.get(pos + 1..pos + 1 + l).ok_or("truncated DNS name")?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + l;
+            }
+        }
+    }
+    Err("DNS name compression loop".into())
+}
+
+/// SRV records of the answer to query `id`. `Ok(None)` when the answer
+/// was truncated.
+fn parse_srv(msg: &[u8], id: u16) -> Result<Option<Vec<Srv>>, String> {
+    if u16_at(msg, 0)? != id {
+        return Err("DNS answer to another query".into());
+    }
+    let flags = u16_at(msg, 2)?;
+    if flags & 0x0200 != 0 {
+        return Ok(None);
+    }
+    match flags & 0x000f {
+        0 => {}
+        // The name does not exist: no workers
+        3 => return Ok(Some(Vec::new())),
+        rcode => return Err(format!("DNS server answered rcode {}", rcode)),
+    }
+    let (questions, answers) = (u16_at(msg, 4)?, u16_at(msg, 6)?);
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = read_name(msg, pos)?.1 + 4;
+    }
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        pos = read_name(msg, pos)?.1;
+        let kind = u16_at(msg, pos)?;
+        let len = u16_at(msg, pos + 8)? as usize;
+        let data = pos + 10;
+        if kind == TYPE_SRV {
+            let port = u16_at(msg, data + 4)?;
+            let (target, _) = read_name(msg, data + 6)?;
+            if !target.is_empty() {
+                records.push(Srv { port, target });
+            }
+        }
+        pos = data + len;
+    }
+    Ok(Some(records))
+}
+
+/// Every SRV record of `name`, whatever its priority and weight: each one
+/// is a worker to scrape.
+async fn resolve_srv(name: &str, server: SocketAddr) -> Result<Vec<Srv>, String> {
+    let id: u16 = rand::rng().random();
+    let msg = query(id, name)?;
+    let bind: SocketAddr = if server.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { ([0u16; 8], 0).into() };
+    let socket = UdpSocket::bind(bind).await.map_err(|e| e.to_string())?;
+    socket.connect(server).await.map_err(|e| format!("{}: {}", server, e))?;
+    socket.send(&msg).await.map_err(|e| format!("{}: {}", server, e))?;
+    let mut buf = vec![0u8; EDNS_PAYLOAD as usize];
+    let len = tokio::time::timeout(QUERY_TIMEOUT, socket.recv(&mut buf))
+        .await
+        .map_err(|_| format!("{}: no DNS answer within {:?}", server, QUERY_TIMEOUT))?
+        .map_err(|e| format!("{}: {}", server, e))?;
+    if let Some(records) = parse_srv(&buf[..len], id)? {
+        return Ok(records);
+    }
+
+    debug!("dns: answer for {} truncated, retrying over TCP", name);
+    let answer = tokio::time::timeout(QUERY_TIMEOUT, async {
+        let mut stream = TcpStream::connect(server).await?;
+        let mut framed = (msg.len() as u16).to_be_bytes().to_vec();
+        framed.extend(&msg);
+        stream.write_all(&framed).await?;
+        let len = stream.read_u16().await? as usize;
+        let mut answer = vec![0u8; len];
+        stream.read_exact(&mut answer).await?;
+        Ok::<_, std::io::Error>(answer)
+    })
+    .await
+    .map_err(|_| format!("{}: no DNS answer over TCP within {:?}", server, QUERY_TIMEOUT))?
+    .map_err(|e| format!("{}: {}", server, e))?;
+    parse_srv(&answer, id)?.ok_or_else(|| "DNS answer truncated over TCP".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        for label in name.split('.').filter(|l| !l.is_empty()) {
+            out.push(label.len() as u8);
+            out.extend(label.as_bytes());
+        }
+        out.push(0);
+        out
+    }
+
+    /// An answer to query `id` for `_connect._tcp.example` with `flags` and
+    /// one record per (type, rdata); every owner name points at the question.
+    fn answer(id: u16, flags: u16, records: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut msg = id.to_be_bytes().to_vec();
+        msg.extend(flags.to_be_bytes());
+        msg.extend([0, 1]);
+        msg.extend((records.len() as u16).to_be_bytes());
+        msg.extend([0, 0, 0, 0]);
+        msg.extend(name("_connect._tcp.example"));
+        msg.extend(TYPE_SRV.to_be_bytes());
+        msg.extend(CLASS_IN.to_be_bytes());
+        for (kind, rdata) in records {
+            msg.extend([0xc0, 12]);
+            msg.extend(kind.to_be_bytes());
+            msg.extend(CLASS_IN.to_be_bytes());
+            msg.extend(60u32.to_be_bytes());
+            msg.extend((rdata.len() as u16).to_be_bytes());
+            msg.extend(rdata);
+        }
+        msg
+    }
+
+    fn srv(port: u16, target: &[u8]) -> (u16, Vec<u8>) {
+        let mut rdata = vec![0, 10, 0, 5];
+        rdata.extend(port.to_be_bytes());
+        rdata.extend(target);
+        (TYPE_SRV, rdata)
+    }
+
+    #[test]
+    fn query_asks_for_srv_with_edns() {
+        let msg = query(0xabcd, "_connect._tcp.example").unwrap();
+        let mut expected = vec![0xab, 0xcd, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 1];
+        expected.extend(name("_connect._tcp.example"));
+        expected.extend([0, 33, 0, 1]);
+        expected.extend([0, 0, 41, 0x10, 0x00, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(msg, expected);
+        assert!(query(1, "a..b").unwrap_err().contains("not a valid DNS name"));
+        assert!(query(1, &"x".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn parses_srv_records_with_compressed_names() {
+        // The second target ends in a pointer to "example" in the question
+        let mut compressed = vec![7];
+        compressed.extend(b"connect");
+        compressed.extend([0xc0, 12 + 1 + 8 + 1 + 4]);
+        let msg = answer(
+            7,
+            0x8180,
+            &[srv(8083, &name("worker-1.example")), (1, vec![10, 0, 0, 1]), srv(8084, &compressed), srv(1, &[0])],
+        );
+        assert_eq!(
+            parse_srv(&msg, 7).unwrap().unwrap(),
+            [
+                Srv { port: 8083, target: "worker-1.example".into() },
+                Srv { port: 8084, target: "connect.example".into() },
+            ]
+        );
+    }
+
+    #[test]
+    fn answer_flags_decide_the_outcome() {
+        assert_eq!(parse_srv(&answer(7, 0x8183, &[]), 7), Ok(Some(Vec::new())), "NXDOMAIN has no workers");
+        assert_eq!(parse_srv(&answer(7, 0x8380, &[]), 7), Ok(None), "truncated");
+        assert_eq!(parse_srv(&answer(7, 0x8182, &[]), 7), Err("DNS server answered rcode 2".into()));
+        assert_eq!(parse_srv(&answer(8, 0x8180, &[]), 7), Err("DNS answer to another query".into()));
+    }
+
+    #[test]
+    fn malformed_answers_are_errors() {
+        let full = answer(7, 0x8180, &[srv(8083, &name("worker-1.example"))]);
+        for cut in [1, 5, 20, full.len() - 3] {
+            assert!(parse_srv(&full[..cut], 7).is_err(), "cut at {}", cut);
+        }
+        // A pointer to itself
+        let mut looped = answer(7, 0x8180, &[]);
+        looped[6..8].copy_from_slice(&[0, 1]);
+        let at = looped.len() as u8;
+        looped.extend([0xc0, at]);
+        assert_eq!(parse_srv(&looped, 7), Err("DNS name compression loop".into()));
+    }
+
+    #[test]
+    fn settings_come_from_the_env() {
+        let env = |pairs: &'static [(&str, &str)]| {
+            move |key: &str| pairs.iter().find(|(k, _)| *k == key).map(|(_, v)| v.to_string())
+        };
+        assert!(Discovery::from_env(env(&[])).unwrap().is_none());
+        let srv = Discovery::from_env(env(&[
+            ("KAFKA_CONNECT_DNS_SRV", "_connect._tcp.service.consul."),
+            ("KAFKA_CONNECT_DNS_SERVER", "127.0.0.1:8600"),
+        ]))
+        .unwrap()
+        .unwrap();
+        assert_eq!(srv.describe(), "SRV records of _connect._tcp.service.consul via 127.0.0.1:8600");
+        let a = Discovery::from_env(env(&[("KAFKA_CONNECT_DNS_A", "[fd00::1]:8083")])).unwrap().unwrap();
+        assert_eq!(a.describe(), "addresses of fd00::1 on port 8083");
+
+        let err = |pairs| Discovery::from_env(env(pairs)).unwrap_err();
+        assert!(err(&[("KAFKA_CONNECT_DNS_SRV", "a"), ("KAFKA_CONNECT_DNS_A", "b:1")]).contains("not both"));
+        assert!(err(&[("KAFKA_CONNECT_DNS_A", "connect")]).contains("expected host:port"));
+        assert!(err(&[("KAFKA_CONNECT_DNS_SRV", "a"), ("KAFKA_CONNECT_DNS_SERVER", "ns1")]).contains("expected ip"));
+        assert!(err(&[("KAFKA_CONNECT_DNS_A", "a:1"), ("KAFKA_CONNECT_DNS_SCHEME", "tcp")]).contains("http or https"));
+        assert!(err(&[("KAFKA_CONNECT_DNS_A", "a:1"), ("KAFKA_CONNECT_DNS_INTERVAL_SECS", "x")]).contains("seconds"));
+    }
+
+    #[tokio::test]
+    async fn truncated_answers_are_asked_again_over_tcp() {
+        // UDP and TCP on the same port, as a nameserver listens
+        let tcp = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = tcp.local_addr().unwrap();
+        let udp = UdpSocket::bind(server).await.unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (_, peer) = udp.recv_from(&mut buf).await.unwrap();
+            let id = u16::from_be_bytes([buf[0], buf[1]]);
+            udp.send_to(&answer(id, 0x8380, &[]), peer).await.unwrap();
+        });
+        tokio::spawn(async move {
+            let (mut stream, _) = tcp.accept().await.unwrap();
+            let len = stream.read_u16().await.unwrap() as usize;
+            let mut msg = vec![0u8; len];
+            stream.read_exact(&mut msg).await.unwrap();
+            let id = u16::from_be_bytes([msg[0], msg[1]]);
+            let records: Vec<_> = (0..3).map(|i| srv(8083, &name(&format!("worker-{}.example", i)))).collect();
+            let reply = answer(id, 0x8180, &records);
+            stream.write_all(&(reply.len() as u16).to_be_bytes()).await.unwrap();
+            stream.write_all(&reply).await.unwrap();
+        });
+
+        let records = resolve_srv("_connect._tcp.example", server).await.unwrap();
+        let targets: Vec<&str> = records.iter().map(|r| r.target.as_str()).collect();
+        assert_eq!(targets, ["worker-0.example", "worker-1.example", "worker-2.example"]);
+    }
+}
@@ -0,0 +1,1013 @@
+//! Unary gRPC calls, for the OTLP/gRPC metrics export.
+//!
+//! HTTP/2 framing and HPACK are implemented here, just enough for one call
+//! per connection: prior knowledge over plain TCP for `http://` endpoints,
+//! ALPN `h2` over TLS for `https://`. Request headers go out as literals, so
+//! only the response side needs the Huffman code and the dynamic table.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_rustls::{rustls::pki_types::ServerName, TlsConnector};
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// Frame types
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+// Frame flags
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+// Settings
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+/// Flow-control window every connection and stream starts with.
+const DEFAULT_WINDOW: i64 = 65_535;
+/// Largest frame either side may send until told otherwise; the exporter
+/// never raises it for the frames it receives.
+const DEFAULT_MAX_FRAME: usize = 16_384;
+/// Dynamic table size the decoder allows, the HTTP/2 default.
+const HEADER_TABLE_SIZE: usize = 4096;
+/// The one stream of a connection.
+const STREAM: u32 = 1;
+
+/// Names of the gRPC status codes, by code.
+const STATUS_NAMES: [&str; 17] = [
+    "OK",
+    "CANCELLED",
+    "UNKNOWN",
+    "INVALID_ARGUMENT",
+    "DEADLINE_EXCEEDED",
+    "NOT_FOUND",
+    "ALREADY_EXISTS",
+    "PERMISSION_DENIED",
+    "RESOURCE_EXHAUSTED",
+    "FAILED_PRECONDITION",
+    "ABORTED",
+    "OUT_OF_RANGE",
+    "UNIMPLEMENTED",
+    "INTERNAL",
+    "UNAVAILABLE",
+    "DATA_LOSS",
+    "UNAUTHENTICATED",
+];
+
+// ── Channel ───────────────────────────────────────────────────────────────────
+
+/// A gRPC server at `http://host:port` or `https://host:port`.
+pub struct Channel {
+    host: String,
+    port: u16,
+    /// `:scheme` of the requests.
+    scheme: &'static str,
+    /// `:authority` of the requests, with the port.
+    authority: String,
+    tls: Option<TlsConnector>,
+    timeout: Duration,
+}
+
+impl Channel {
+    /// Channel to `endpoint`, whose path is ignored. `https` endpoints trust
+    /// the built-in roots and those in `ca_file`. Each call, from connecting
+    /// to the last byte of the response, must finish within `timeout`.
+    pub fn new(endpoint: &str, ca_file: Option<&str>, timeout: Duration) -> Result<Self, String> {
+        let url = reqwest::Url::parse(endpoint).map_err(|e| format!("{}: {}", endpoint, e))?;
+        let (scheme, tls) = match url.scheme() {
+            "http" => ("http", None),
+            "https" => {
+                let config = crate::tls::client_config(ca_file, &[b"h2"])?;
+                ("https", Some(TlsConnector::from(Arc::new(config))))
+            }
+            other => return Err(format!("{}: scheme {} is neither http nor https", endpoint, other)),
+        };
+        let host = url.host_str().ok_or_else(|| format!("{}: no host", endpoint))?;
+        let port = url.port_or_known_default().unwrap_or(80);
+        Ok(Self {
+            host: host.trim_start_matches('[').trim_end_matches(']').to_owned(),
+            port,
+            scheme,
+            authority: format!("{}:{}", host, port),
+            tls,
+            timeout,
+        })
+    }
+
+    /// Call `method` (`/package.Service/Method`) with one request message
+    /// and return the response message.
+    pub async fn call(&self, method: &str, request: &[u8]) -> Result<Vec<u8>, String> {
+        let deadline = format!("{}m", self.timeout.as_millis());
+        let headers = [
+            (":method", "POST"),
+            (":scheme", self.scheme),
+            (":path", method),
+            (":authority", &self.authority),
+            ("content-type", "application/grpc"),
+            ("te", "trailers"),
+            ("grpc-timeout", &deadline),
+            ("user-agent", concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"))),
+        ];
+        let call = async {
+            let tcp = tokio::net::TcpStream::connect((self.host.as_str(), self.port)).await.map_err(|e| e.to_string())?;
+            let _ = tcp.set_nodelay(true);
+            let Some(tls) = &self.tls else {
+                return exchange(tcp, &headers, request).await;
+            };
+            let name = ServerName::try_from(self.host.clone()).map_err(|e| format!("{}: {}", self.host, e))?;
+            let stream = tls.connect(name, tcp).await.map_err(|e| e.to_string())?;
+            if stream.get_ref().1.alpn_protocol() != Some(b"h2") {
+                return Err("the server did not agree to HTTP/2".into());
+            }
+            exchange(stream, &headers, request).await
+        };
+        match tokio::time::timeout(self.timeout, call).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("no response within {}s", self.timeout.as_secs_f64())),
+        }
+    }
+}
+
+// ── HTTP/2 ────────────────────────────────────────────────────────────────────
+
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream: u32,
+    payload: Vec<u8>,
+}
+
+fn put_frame(out: &mut Vec<u8>, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    out.push(kind);
+    out.push(flags);
+    out.extend_from_slice(&stream.to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// `payload` without its padding, when `flags` say it has some.
+fn unpadded(flags: u8, payload: &[u8]) -> Result<&[u8], String> {
+    if flags & PADDED == 0 {
+        return Ok(payload);
+    }
+    let (&pad, rest) = payload.split_first().ok_or("padded frame without a pad length")?;
+    rest.len().checked_sub(pad as usize).map(|len| &rest[..len]).ok_or_else(|| "padding exceeds the frame".into())
+}
+
+/// What the server sent on the stream.
+#[derive(Default)]
+struct Response {
+    /// The header block, followed by the trailers.
+    headers: Vec<Field>,
+    body: Vec<u8>,
+    ended: bool,
+}
+
+/// One connection and its one stream, from the client side.
+struct Connection<S> {
+    io: S,
+    decoder: Decoder,
+    /// Send windows of the connection and of the stream.
+    window: i64,
+    stream_window: i64,
+    /// SETTINGS_INITIAL_WINDOW_SIZE of the server, applied to the stream
+    /// window when it changes.
+    initial_window: i64,
+    /// SETTINGS_MAX_FRAME_SIZE of the server.
+    max_frame: usize,
+    /// Header block being put together from HEADERS and CONTINUATION, and
+    /// whether the stream ends with it.
+    block: Option<(Vec<u8>, bool)>,
+    response: Response,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    async fn write(&mut self, out: &[u8]) -> Result<(), String> {
+        self.io.write_all(out).await.map_err(|e| e.to_string())?;
+        self.io.flush().await.map_err(|e| e.to_string())
+    }
+
+    async fn send(&mut self, kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Result<(), String> {
+        let mut out = Vec::with_capacity(9 + payload.len());
+        put_frame(&mut out, kind, flags, stream, payload);
+        self.write(&out).await
+    }
+
+    async fn receive(&mut self) -> Result<Frame, String> {
+        let closed = |e: std::io::Error| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => "connection closed before the response ended".to_owned(),
+            _ => e.to_string(),
+        };
+        let mut head = [0u8; 9];
+        self.io.read_exact(&mut head).await.map_err(closed)?;
+        let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+        if len > DEFAULT_MAX_FRAME {
+            return Err(format!("frame of {} bytes is above the frame size", len));
+        }
+        let mut payload = vec![0; len];
+        self.io.read_exact(&mut payload).await.map_err(closed)?;
+        let stream = u32::from_be_bytes([head[5], head[6], head[7], head[8]]) & 0x7fff_ffff;
+        Ok(Frame { kind: head[3], flags: head[4], stream, payload })
+    }
+
+    /// Apply one frame from the server.
+    async fn handle(&mut self, frame: Frame) -> Result<(), String> {
+        let Frame { kind, flags, stream, payload } = frame;
+        if self.block.is_some() && (kind != CONTINUATION || stream != STREAM) {
+            return Err("header block interrupted by another frame".into());
+        }
+        match kind {
+            SETTINGS if flags & ACK != 0 => {}
+            SETTINGS => {
+                if stream != 0 || payload.len() % 6 != 0 {
+                    return Err("malformed SETTINGS".into());
+                }
+                for setting in payload.chunks(6) {
+                    let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+                    match u16::from_be_bytes([setting[0], setting[1]]) {
+                        SETTINGS_INITIAL_WINDOW_SIZE if value > 0x7fff_ffff => {
+                            return Err("initial window size above 2^31-1".into());
+                        }
+                        SETTINGS_INITIAL_WINDOW_SIZE => {
+                            self.stream_window += value as i64 - self.initial_window;
+                            self.initial_window = value as i64;
+                        }
+                        SETTINGS_MAX_FRAME_SIZE if !(16_384..=16_777_215).contains(&value) => {
+                            return Err(format!("max frame size {} out of range", value));
+                        }
+                        SETTINGS_MAX_FRAME_SIZE => self.max_frame = value as usize,
+                        _ => {}
+                    }
+                }
+                self.send(SETTINGS, ACK, 0, &[]).await?;
+            }
+            PING if payload.len() != 8 => return Err("malformed PING".into()),
+            PING if flags & ACK == 0 => self.send(PING, ACK, 0, &payload).await?,
+            WINDOW_UPDATE => {
+                let bytes: [u8; 4] = payload.as_slice().try_into().map_err(|_| "malformed WINDOW_UPDATE")?;
+                let increment = (u32::from_be_bytes(bytes) & 0x7fff_ffff) as i64;
+                match stream {
+                    0 => self.window += increment,
+                    STREAM => self.stream_window += increment,
+                    _ => {}
+                }
+            }
+            HEADERS if stream == STREAM => {
+                let mut fragment = unpadded(flags, &payload)?;
+                if flags & PRIORITY != 0 {
+                    fragment = fragment.get(5..).ok_or("HEADERS too short for its priority")?;
+                }
+                self.block = Some((fragment.to_vec(), flags & END_STREAM != 0));
+                if flags & END_HEADERS != 0 {
+                    self.end_block()?;
+                }
+            }
+            CONTINUATION if self.block.is_some() => {
+                if let Some((block, _)) = &mut self.block {
+                    block.extend_from_slice(&payload);
+                }
+                if flags & END_HEADERS != 0 {
+                    self.end_block()?;
+                }
+            }
+            DATA if stream == STREAM => {
+                self.response.body.extend_from_slice(unpadded(flags, &payload)?);
+                self.response.ended |= flags & END_STREAM != 0;
+                // Hand the flow-control credit back, padding included
+                if !payload.is_empty() {
+                    let increment = (payload.len() as u32).to_be_bytes();
+                    let mut out = Vec::with_capacity(26);
+                    put_frame(&mut out, WINDOW_UPDATE, 0, 0, &increment);
+                    if !self.response.ended {
+                        put_frame(&mut out, WINDOW_UPDATE, 0, STREAM, &increment);
+                    }
+                    self.write(&out).await?;
+                }
+            }
+            RST_STREAM if stream == STREAM => {
+                let code = payload.get(..4).map_or(0, |c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]));
+                return Err(format!("stream reset by the server (error code {:#x})", code));
+            }
+            GOAWAY => {
+                // The stream is still answered if the server got to it
+                let last = payload.get(..4).map_or(0, |c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]) & 0x7fff_ffff);
+                if last < STREAM {
+                    let code = payload.get(4..8).map_or(0, |c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]));
+                    return Err(format!("connection refused by the server (error code {:#x})", code));
+                }
+            }
+            PUSH_PROMISE => return Err("PUSH_PROMISE although push is disabled".into()),
+            HEADERS | DATA | CONTINUATION => return Err(format!("frame type {} on stream {}", kind, stream)),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn end_block(&mut self) -> Result<(), String> {
+        let Some((block, end_stream)) = self.block.take() else { return Ok(()) };
+        let fields = self.decoder.decode(&block)?;
+        self.response.headers.extend(fields);
+        self.response.ended |= end_stream;
+        Ok(())
+    }
+}
+
+/// Send one request on a fresh connection `io` and read the response.
+async fn exchange<S>(io: S, headers: &[(&str, &str)], message: &[u8]) -> Result<Vec<u8>, String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let block = encode_headers(headers);
+    if block.len() > DEFAULT_MAX_FRAME {
+        return Err("request headers above the frame size".into());
+    }
+    let mut conn = Connection {
+        io,
+        decoder: Decoder { table: VecDeque::new(), size: 0, max_size: HEADER_TABLE_SIZE },
+        window: DEFAULT_WINDOW,
+        stream_window: DEFAULT_WINDOW,
+        initial_window: DEFAULT_WINDOW,
+        max_frame: DEFAULT_MAX_FRAME,
+        block: None,
+        response: Response::default(),
+    };
+    let mut out = PREFACE.to_vec();
+    let mut settings = SETTINGS_ENABLE_PUSH.to_be_bytes().to_vec();
+    settings.extend_from_slice(&0u32.to_be_bytes());
+    put_frame(&mut out, SETTINGS, 0, 0, &settings);
+    put_frame(&mut out, HEADERS, END_HEADERS, STREAM, &block);
+    conn.write(&out).await?;
+
+    // The message with its length prefix, uncompressed
+    let mut body = Vec::with_capacity(5 + message.len());
+    body.push(0);
+    body.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    body.extend_from_slice(message);
+    let mut sent = 0;
+    // A server may answer before it has read the whole request
+    while sent < body.len() && !conn.response.ended {
+        let room = conn.window.min(conn.stream_window).min(conn.max_frame as i64);
+        if room <= 0 {
+            let frame = conn.receive().await?;
+            conn.handle(frame).await?;
+            continue;
+        }
+        let n = (room as usize).min(body.len() - sent);
+        let flags = if sent + n == body.len() { END_STREAM } else { 0 };
+        conn.send(DATA, flags, STREAM, &body[sent..sent + n]).await?;
+        conn.window -= n as i64;
+        conn.stream_window -= n as i64;
+        sent += n;
+    }
+    while !conn.response.ended {
+        let frame = conn.receive().await?;
+        conn.handle(frame).await?;
+    }
+    // Last stream 0, no error
+    let _ = conn.send(GOAWAY, 0, 0, &[0; 8]).await;
+    response_message(&conn.response)
+}
+
+/// The message of a finished response, or why the call failed.
+fn response_message(response: &Response) -> Result<Vec<u8>, String> {
+    let header = |name: &[u8]| {
+        let field = response.headers.iter().rev().find(|(n, _)| n == name);
+        field.map(|(_, v)| String::from_utf8_lossy(v).into_owned())
+    };
+    match header(b":status") {
+        Some(status) if status == "200" => {}
+        Some(status) => return Err(format!("HTTP {}", status)),
+        None => return Err("response without :status".into()),
+    }
+    match header(b"grpc-status") {
+        Some(code) if code == "0" => {}
+        Some(code) => {
+            let name = code.parse::<usize>().ok().and_then(|c| STATUS_NAMES.get(c)).unwrap_or(&"");
+            let message = header(b"grpc-message").unwrap_or_default();
+            let message = percent_encoding::percent_decode_str(&message).decode_utf8_lossy().into_owned();
+            return Err(format!("grpc-status {} {}: {}", code, name, message));
+        }
+        None => return Err("response without grpc-status".into()),
+    }
+    let body = &response.body;
+    if body.is_empty() {
+        return Ok(Vec::new());
+    }
+    if body.len() < 5 || body[0] != 0 {
+        return Err("response message truncated or compressed".into());
+    }
+    let len = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+    body.get(5..5 + len).map(<[u8]>::to_vec).ok_or_else(|| "response message truncated".into())
+}
+
+// ── HPACK ─────────────────────────────────────────────────────────────────────
+
+/// A header name and value, as sent.
+type Field = (Vec<u8>, Vec<u8>);
+
+/// RFC 7541 Appendix A.
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Code and bit length of each symbol, EOS last (RFC 7541 Appendix B).
+const HUFFMAN: [(u32, u8); 257] = [
+    (0x1ff8, 13), (0x7fffd8, 23), (0xfffffe2, 28), (0xfffffe3, 28), (0xfffffe4, 28),
+    (0xfffffe5, 28), (0xfffffe6, 28), (0xfffffe7, 28), (0xfffffe8, 28), (0xffffea, 24),
+    (0x3ffffffc, 30), (0xfffffe9, 28), (0xfffffea, 28), (0x3ffffffd, 30), (0xfffffeb, 28),
+    (0xfffffec, 28), (0xfffffed, 28), (0xfffffee, 28), (0xfffffef, 28), (0xffffff0, 28),
+    (0xffffff1, 28), (0xffffff2, 28), (0x3ffffffe, 30), (0xffffff3, 28), (0xffffff4, 28),
+    (0xffffff5, 28), (0xffffff6, 28), (0xffffff7, 28), (0xffffff8, 28), (0xffffff9, 28),
+    (0xffffffa, 28), (0xffffffb, 28), (0x14, 6), (0x3f8, 10), (0x3f9, 10), (0xffa, 12),
+    (0x1ff9, 13), (0x15, 6), (0xf8, 8), (0x7fa, 11), (0x3fa, 10), (0x3fb, 10), (0xf9, 8),
+    (0x7fb, 11), (0xfa, 8), (0x16, 6), (0x17, 6), (0x18, 6), (0x0, 5), (0x1, 5), (0x2, 5),
+    (0x19, 6), (0x1a, 6), (0x1b, 6), (0x1c, 6), (0x1d, 6), (0x1e, 6), (0x1f, 6), (0x5c, 7),
+    (0xfb, 8), (0x7ffc, 15), (0x20, 6), (0xffb, 12), (0x3fc, 10), (0x1ffa, 13), (0x21, 6),
+    (0x5d, 7), (0x5e, 7), (0x5f, 7), (0x60, 7), (0x61, 7), (0x62, 7), (0x63, 7), (0x64, 7),
+    (0x65, 7), (0x66, 7), (0x67, 7), (0x68, 7), (0x69, 7), (0x6a, 7), (0x6b, 7), (0x6c, 7),
+    (0x6d, 7), (0x6e, 7), (0x6f, 7), (0x70, 7), (0x71, 7), (0x72, 7), (0xfc, 8), (0x73, 7),
+    (0xfd, 8), (0x1ffb, 13), (0x7fff0, 19), (0x1ffc, 13), (0x3ffc, 14), (0x22, 6), (0x7ffd, 15),
+    (0x3, 5), (0x23, 6), (0x4, 5), (0x24, 6), (0x5, 5), (0x25, 6), (0x26, 6), (0x27, 6), (0x6, 5),
+    (0x74, 7), (0x75, 7), (0x28, 6), (0x29, 6), (0x2a, 6), (0x7, 5), (0x2b, 6), (0x76, 7),
+    (0x2c, 6), (0x8, 5), (0x9, 5), (0x2d, 6), (0x77, 7), (0x78, 7), (0x79, 7), (0x7a, 7), (0x7b, 7),
+    (0x7ffe, 15), (0x7fc, 11), (0x3ffd, 14), (0x1ffd, 13), (0xffffffc, 28), (0xfffe6, 20),
+    (0x3fffd2, 22), (0xfffe7, 20), (0xfffe8, 20), (0x3fffd3, 22), (0x3fffd4, 22), (0x3fffd5, 22),
+    (0x7fffd9, 23), (0x3fffd6, 22), (0x7fffda, 23), (0x7fffdb, 23), (0x7fffdc, 23), (0x7fffdd, 23),
+    (0x7fffde, 23), (0xffffeb, 24), (0x7fffdf, 23), (0xffffec, 24), (0xffffed, 24), (0x3fffd7, 22),
+    (0x7fffe0, 23), (0xffffee, 24), (0x7fffe1, 23), (0x7fffe2, 23), (0x7fffe3, 23), (0x7fffe4, 23),
+    (0x1fffdc, 21), (0x3fffd8, 22), (0x7fffe5, 23), (0x3fffd9, 22), (0x7fffe6, 23), (0x7fffe7, 23),
+    (0xffffef, 24), (0x3fffda, 22), (0x1fffdd, 21), (0xfffe9, 20), (0x3fffdb, 22), (0x3fffdc, 22),
+    (0x7fffe8, 23), (0x7fffe9, 23), (0x1fffde, 21), (0x7fffea, 23), (0x3fffdd, 22), (0x3fffde, 22),
+    (0xfffff0, 24), (0x1fffdf, 21), (0x3fffdf, 22), (0x7fffeb, 23), (0x7fffec, 23), (0x1fffe0, 21),
+    (0x1fffe1, 21), (0x3fffe0, 22), (0x1fffe2, 21), (0x7fffed, 23), (0x3fffe1, 22), (0x7fffee, 23),
+    (0x7fffef, 23), (0xfffea, 20), (0x3fffe2, 22), (0x3fffe3, 22), (0x3fffe4, 22), (0x7ffff0, 23),
+    (0x3fffe5, 22), (0x3fffe6, 22), (0x7ffff1, 23), (0x3ffffe0, 26), (0x3ffffe1, 26), (0xfffeb, 20),
+    (0x7fff1, 19), (0x3fffe7, 22), (0x7ffff2, 23), (0x3fffe8, 22), (0x1ffffec, 25), (0x3ffffe2, 26),
+    (0x3ffffe3, 26), (0x3ffffe4, 26), (0x7ffffde, 27), (0x7ffffdf, 27), (0x3ffffe5, 26),
+    (0xfffff1, 24), (0x1ffffed, 25), (0x7fff2, 19), (0x1fffe3, 21), (0x3ffffe6, 26),
+    (0x7ffffe0, 27), (0x7ffffe1, 27), (0x3ffffe7, 26), (0x7ffffe2, 27), (0xfffff2, 24),
+    (0x1fffe4, 21), (0x1fffe5, 21), (0x3ffffe8, 26), (0x3ffffe9, 26), (0xffffffd, 28),
+    (0x7ffffe3, 27), (0x7ffffe4, 27), (0x7ffffe5, 27), (0xfffec, 20), (0xfffff3, 24), (0xfffed, 20),
+    (0x1fffe6, 21), (0x3fffe9, 22), (0x1fffe7, 21), (0x1fffe8, 21), (0x7ffff3, 23), (0x3fffea, 22),
+    (0x3fffeb, 22), (0x1ffffee, 25), (0x1ffffef, 25), (0xfffff4, 24), (0xfffff5, 24),
+    (0x3ffffea, 26), (0x7ffff4, 23), (0x3ffffeb, 26), (0x7ffffe6, 27), (0x3ffffec, 26),
+    (0x3ffffed, 26), (0x7ffffe7, 27), (0x7ffffe8, 27), (0x7ffffe9, 27), (0x7ffffea, 27),
+    (0x7ffffeb, 27), (0xffffffe, 28), (0x7ffffec, 27), (0x7ffffed, 27), (0x7ffffee, 27),
+    (0x7ffffef, 27), (0x7fffff0, 27), (0x3ffffee, 26), (0x3fffffff, 30),
+];
+
+/// Tree nodes of HUFFMAN hold this bit on a child that is a symbol.
+const LEAF: u16 = 0x8000;
+
+fn put_int(out: &mut Vec<u8>, first: u8, prefix: u8, value: u64) {
+    let max = (1u64 << prefix) - 1;
+    if value < max {
+        out.push(first | value as u8);
+        return;
+    }
+    out.push(first | max as u8);
+    let mut rest = value - max;
+    while rest >= 0x80 {
+        out.push((rest as u8 & 0x7f) | 0x80);
+        rest >>= 7;
+    }
+    out.push(rest as u8);
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    put_int(out, 0, 7, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Header block of `headers`: static entries are indexed, everything else
+/// is a literal without indexing, so the server keeps no table for us.
+fn encode_headers(headers: &[(&str, &str)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for &(name, value) in headers {
+        if let Some(i) = STATIC_TABLE.iter().position(|entry| *entry == (name, value)) {
+            put_int(&mut out, 0x80, 7, i as u64 + 1);
+            continue;
+        }
+        match STATIC_TABLE.iter().position(|(n, _)| *n == name) {
+            Some(i) => put_int(&mut out, 0, 4, i as u64 + 1),
+            None => {
+                out.push(0);
+                put_str(&mut out, name);
+            }
+        }
+        put_str(&mut out, value);
+    }
+    out
+}
+
+fn get_int(block: &mut &[u8], prefix: u8) -> Result<u64, String> {
+    let (&first, mut rest) = block.split_first().ok_or("header block truncated")?;
+    let max = (1u64 << prefix) - 1;
+    let mut value = first as u64 & max;
+    if value == max {
+        let mut shift = 0;
+        loop {
+            let (&b, tail) = rest.split_first().ok_or("header block truncated")?;
+            rest = tail;
+            if shift > 49 {
+                return Err("header integer too large".into());
+            }
+            value += ((b & 0x7f) as u64) << shift;
+            shift += 7;
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+    }
+    *block = rest;
+    Ok(value)
+}
+
+fn get_str(block: &mut &[u8]) -> Result<Vec<u8>, String> {
+    let huffman = block.first().is_some_and(|b| b & 0x80 != 0);
+    let len = get_int(block, 7)? as usize;
+    if block.len() < len {
+        return Err("header block truncated".into());
+    }
+    let (s, rest) = block.split_at(len);
+    *block = rest;
+    if huffman {
+        huffman_decode(s)
+    } else {
+        Ok(s.to_vec())
+    }
+}
+
+/// The decoding tree of HUFFMAN: each node holds its two children, either
+/// another node's index or a symbol marked with LEAF.
+fn huffman_tree() -> &'static [[u16; 2]] {
+    static TREE: OnceLock<Vec<[u16; 2]>> = OnceLock::new();
+    TREE.get_or_init(|| {
+        let mut tree = vec![[0u16; 2]];
+        for (symbol, &(code, len)) in HUFFMAN.iter().enumerate() {
+            let mut node = 0;
+            for i in (0..len).rev() {
+                let bit = (code >> i) as usize & 1;
+                if i == 0 {
+                    tree[node][bit] = LEAF | symbol as u16;
+                } else {
+                    if tree[node][bit] == 0 {
+                        tree.push([0; 2]);
+                        tree[node][bit] = (tree.len() - 1) as u16;
+                    }
+                    node = tree[node][bit] as usize;
+                }
+            }
+        }
+        tree
+    })
+}
+
+fn huffman_decode(input: &[u8]) -> Result<Vec<u8>, String> {
+    let tree = huffman_tree();
+    let mut out = Vec::with_capacity(input.len() * 8 / 5);
+    // Bits since the last symbol, and whether they were all ones
+    let (mut node, mut pending, mut ones) = (0, 0, true);
+    for byte in input {
+        for i in (0..8).rev() {
+            let bit = (byte >> i) as usize & 1;
+            let child = tree[node][bit];
+            pending += 1;
+            ones &= bit == 1;
+            if child & LEAF == 0 {
+                node = child as usize;
+                continue;
+            }
+            match child & !LEAF {
+                256 => return Err("EOS inside a Huffman string".into()),
+                symbol => out.push(symbol as u8),
+            }
+            (node, pending, ones) = (0, 0, true);
+        }
+    }
+    // The padding is the start of EOS: up to seven one bits
+    if pending > 7 || !ones {
+        return Err("invalid Huffman padding".into());
+    }
+    Ok(out)
+}
+
+/// HPACK decoding state of a connection: the dynamic table, newest entry
+/// first, and its size as RFC 7541 counts it.
+struct Decoder {
+    table: VecDeque<Field>,
+    size: usize,
+    max_size: usize,
+}
+
+fn entry_size((name, value): &Field) -> usize {
+    name.len() + value.len() + 32
+}
+
+impl Decoder {
+    fn decode(&mut self, mut block: &[u8]) -> Result<Vec<Field>, String> {
+        let mut fields = Vec::new();
+        while let Some(&b) = block.first() {
+            if b & 0x80 != 0 {
+                let index = get_int(&mut block, 7)?;
+                fields.push(self.entry(index)?);
+            } else if b & 0x40 != 0 {
+                // Literal with incremental indexing
+                let field = self.literal(&mut block, 6)?;
+                self.insert(field.clone());
+                fields.push(field);
+            } else if b & 0x20 != 0 {
+                let size = get_int(&mut block, 5)? as usize;
+                if size > HEADER_TABLE_SIZE {
+                    return Err(format!("dynamic table size {} above {}", size, HEADER_TABLE_SIZE));
+                }
+                self.max_size = size;
+                self.evict(0);
+            } else {
+                // Literal without indexing, or never indexed
+                fields.push(self.literal(&mut block, 4)?);
+            }
+        }
+        Ok(fields)
+    }
+
+    fn entry(&self, index: u64) -> Result<Field, String> {
+        match index as usize {
+            0 => Err("header index 0".into()),
+            i @ 1..=61 => {
+                let (name, value) = STATIC_TABLE[i - 1];
+                Ok((name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            }
+            i => self.table.get(i - 62).cloned().ok_or_else(|| format!("header index {} beyond the table", i)),
+        }
+    }
+
+    fn literal(&self, block: &mut &[u8], prefix: u8) -> Result<Field, String> {
+        let name = match get_int(block, prefix)? {
+            0 => get_str(block)?,
+            index => self.entry(index)?.0,
+        };
+        Ok((name, get_str(block)?))
+    }
+
+    /// Add `field`, evicting the oldest entries to make room; one larger
+    /// than the whole table just empties it.
+    fn insert(&mut self, field: Field) {
+        let size = entry_size(&field);
+        self.evict(size);
+        if self.size + size <= self.max_size {
+            self.size += size;
+            self.table.push_front(field);
+        }
+    }
+
+    fn evict(&mut self, room: usize) {
+        while self.size + room > self.max_size {
+            let Some(oldest) = self.table.pop_back() else { break };
+            self.size -= entry_size(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, DuplexStream};
+
+    const REQUEST_HEADERS: [(&str, &str); 6] = [
+        (":method", "POST"),
+        (":scheme", "http"),
+        (":path", "/pkg.Service/Method"),
+        (":authority", "collector:4317"),
+        ("content-type", "application/grpc"),
+        ("te", "trailers"),
+    ];
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<Field> {
+        pairs.iter().map(|(n, v)| (n.as_bytes().to_vec(), v.as_bytes().to_vec())).collect()
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        let digits: Vec<u8> = s.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        digits.chunks(2).map(|d| u8::from_str_radix(std::str::from_utf8(d).unwrap(), 16).unwrap()).collect()
+    }
+
+    fn decoder() -> Decoder {
+        Decoder { table: VecDeque::new(), size: 0, max_size: HEADER_TABLE_SIZE }
+    }
+
+    #[test]
+    fn huffman_code_is_complete() {
+        // Every bit string is a prefix of exactly one code: the lengths fill
+        // the code space
+        let space: u64 = HUFFMAN.iter().map(|&(_, len)| 1u64 << (30 - len)).sum();
+        assert_eq!(space, 1 << 30);
+        assert!(HUFFMAN.iter().all(|&(code, len)| code < 1 << len));
+    }
+
+    #[test]
+    fn decodes_the_rfc_examples() {
+        // RFC 7541 C.3 and C.4: the same three requests, plain and Huffman
+        // coded, each block relying on the table the previous one left
+        for blocks in [
+            [
+                "8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+                "8286 84be 5808 6e6f 2d63 6163 6865",
+                "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65",
+            ],
+            [
+                "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+                "8286 84be 5886 a8eb 1064 9cbf",
+                "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+            ],
+        ] {
+            let mut decoder = decoder();
+            let first = [(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com")];
+            assert_eq!(decoder.decode(&hex(blocks[0])), Ok(fields(&first)));
+            assert_eq!(decoder.size, 57);
+            let mut second = first.to_vec();
+            second.push(("cache-control", "no-cache"));
+            assert_eq!(decoder.decode(&hex(blocks[1])), Ok(fields(&second)));
+            let third = [
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":path", "/index.html"),
+                (":authority", "www.example.com"),
+                ("custom-key", "custom-value"),
+            ];
+            assert_eq!(decoder.decode(&hex(blocks[2])), Ok(fields(&third)));
+            assert_eq!(decoder.size, 164);
+            assert_eq!(decoder.table[0], fields(&[("custom-key", "custom-value")])[0]);
+        }
+    }
+
+    #[test]
+    fn the_table_evicts_its_oldest_entries() {
+        let mut decoder = decoder();
+        decoder.max_size = 100;
+        let mut block = Vec::new();
+        for value in ["1", "2", "3"] {
+            // Literal with incremental indexing, new name: 34 bytes each
+            block.push(0x40);
+            put_str(&mut block, "a");
+            put_str(&mut block, value);
+        }
+        decoder.decode(&block).unwrap();
+        assert_eq!(decoder.size, 68);
+        assert_eq!(decoder.decode(&[0xbe, 0xbf]), Ok(fields(&[("a", "3"), ("a", "2")])));
+        assert_eq!(decoder.decode(&[0xc0]), Err("header index 64 beyond the table".into()));
+        // A size update to 0 empties the table; one above the limit is refused
+        assert_eq!(decoder.decode(&[0x20]), Ok(Vec::new()));
+        assert!(decoder.table.is_empty());
+        assert!(decoder.decode(&hex("3fe21f")).unwrap_err().contains("above 4096"));
+    }
+
+    #[test]
+    fn malformed_blocks_are_errors() {
+        let mut decoder = decoder();
+        assert_eq!(decoder.decode(&[0x80]), Err("header index 0".into()));
+        assert_eq!(decoder.decode(&[0x04, 0x05, b'a']), Err("header block truncated".into()));
+        // Padding of more than seven bits, or not all ones, or EOS itself
+        assert_eq!(huffman_decode(&[0x1f, 0xff]), Err("invalid Huffman padding".into()));
+        assert_eq!(huffman_decode(&[0x00]), Err("invalid Huffman padding".into()));
+        assert_eq!(huffman_decode(&[0xff, 0xff, 0xff, 0xff]), Err("EOS inside a Huffman string".into()));
+        assert_eq!(huffman_decode(&hex("f1e3 c2e5 f23a 6ba0 ab90 f4ff")), Ok(b"www.example.com".to_vec()));
+    }
+
+    #[test]
+    fn request_headers_index_what_the_static_table_has() {
+        let block = encode_headers(&REQUEST_HEADERS);
+        // :method POST and :scheme http are full static entries
+        assert_eq!(block[..2], [0x83, 0x86]);
+        // :path has its name indexed, te is a new name
+        assert_eq!(block[2], 0x04);
+        assert_eq!(decoder().decode(&block), Ok(fields(&REQUEST_HEADERS)));
+    }
+
+    async fn read_frame(io: &mut DuplexStream) -> Option<Frame> {
+        let mut head = [0u8; 9];
+        io.read_exact(&mut head).await.ok()?;
+        let mut payload = vec![0; u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize];
+        io.read_exact(&mut payload).await.ok()?;
+        let stream = u32::from_be_bytes([head[5], head[6], head[7], head[8]]);
+        Some(Frame { kind: head[3], flags: head[4], stream, payload })
+    }
+
+    /// What the server side saw of a call.
+    #[derive(Default)]
+    struct Seen {
+        headers: Vec<Field>,
+        body: Vec<u8>,
+        /// Type and flags of every other frame.
+        frames: Vec<(u8, u8)>,
+    }
+
+    /// Read a request as a server with a stream window of 1000 bytes,
+    /// handing credit back frame by frame, and ping the client on the way.
+    async fn read_request(io: &mut DuplexStream, seen: &mut Seen) {
+        let mut preface = [0u8; 24];
+        io.read_exact(&mut preface).await.unwrap();
+        assert_eq!(preface, PREFACE);
+        let mut out = Vec::new();
+        let settings = [0, 4, 0, 0, 0x03, 0xe8];
+        put_frame(&mut out, SETTINGS, 0, 0, &settings);
+        put_frame(&mut out, PING, 0, 0, b"pingpong");
+        io.write_all(&out).await.unwrap();
+        let mut decoder = decoder();
+        loop {
+            let frame = read_frame(io).await.expect("request ended early");
+            match frame.kind {
+                HEADERS => seen.headers = decoder.decode(&frame.payload).unwrap(),
+                DATA => {
+                    seen.body.extend_from_slice(&frame.payload);
+                    let credit = (frame.payload.len() as u32).to_be_bytes();
+                    let mut out = Vec::new();
+                    put_frame(&mut out, WINDOW_UPDATE, 0, 0, &credit);
+                    put_frame(&mut out, WINDOW_UPDATE, 0, STREAM, &credit);
+                    io.write_all(&out).await.unwrap();
+                    if frame.flags & END_STREAM != 0 {
+                        return;
+                    }
+                }
+                kind => seen.frames.push((kind, frame.flags)),
+            }
+        }
+    }
+
+    /// Run a call against `respond`, which answers after the request is in.
+    async fn call_with<F>(message: &[u8], respond: F) -> (Result<Vec<u8>, String>, Seen)
+    where
+        F: FnOnce(&mut Vec<u8>) + Send + 'static,
+    {
+        let (client, mut server) = duplex(1 << 20);
+        let server = tokio::spawn(async move {
+            let mut seen = Seen::default();
+            read_request(&mut server, &mut seen).await;
+            let mut out = Vec::new();
+            respond(&mut out);
+            server.write_all(&out).await.unwrap();
+            while let Some(frame) = read_frame(&mut server).await {
+                seen.frames.push((frame.kind, frame.flags));
+            }
+            seen
+        });
+        let result = exchange(client, &REQUEST_HEADERS, message).await;
+        (result, server.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn a_call_waits_for_window_and_reads_the_trailers() {
+        // Above the 64 KiB that go out before the first window update
+        let message = vec![7u8; 70_000];
+        let (result, seen) = call_with(&message, |out| {
+            let mut block = vec![0x88];
+            // content-type goes into the dynamic table, for the trailers
+            put_int(&mut block, 0x40, 6, 31);
+            put_str(&mut block, "application/grpc");
+            put_frame(out, HEADERS, END_HEADERS, STREAM, &block);
+            // Padded message: pad length 3, then "pong" with its prefix
+            let mut data = vec![3, 0, 0, 0, 0, 4];
+            data.extend_from_slice(b"pong\0\0\0");
+            put_frame(out, DATA, PADDED, STREAM, &data);
+            let mut trailers = vec![0xbe, 0x00];
+            put_str(&mut trailers, "grpc-status");
+            put_str(&mut trailers, "0");
+            let (head, tail) = trailers.split_at(3);
+            put_frame(out, HEADERS, END_STREAM, STREAM, head);
+            put_frame(out, CONTINUATION, END_HEADERS, STREAM, tail);
+        })
+        .await;
+        assert_eq!(result, Ok(b"pong".to_vec()));
+        assert_eq!(seen.headers, fields(&REQUEST_HEADERS));
+        assert_eq!(seen.body[..5], [0, 0, 1, 0x11, 0x70]);
+        assert_eq!(seen.body[5..], message);
+        // Client settings, our settings and ping acknowledged, a window
+        // update for the response, and a clean goodbye
+        assert_eq!(seen.frames[0], (SETTINGS, 0));
+        for frame in [(SETTINGS, ACK), (PING, ACK), (WINDOW_UPDATE, 0), (GOAWAY, 0)] {
+            assert!(seen.frames.contains(&frame), "{:?} missing from {:?}", frame, seen.frames);
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_calls_say_why() {
+        // Trailers-only answer with a percent-encoded message
+        let (result, _) = call_with(b"", |out| {
+            let mut block = vec![0x88, 0x00];
+            put_str(&mut block, "grpc-status");
+            put_str(&mut block, "14");
+            block.push(0x00);
+            put_str(&mut block, "grpc-message");
+            put_str(&mut block, "collector%20is%20down");
+            put_frame(out, HEADERS, END_STREAM | END_HEADERS, STREAM, &block);
+        })
+        .await;
+        assert_eq!(result, Err("grpc-status 14 UNAVAILABLE: collector is down".into()));
+
+        let (result, _) = call_with(b"", |out| put_frame(out, HEADERS, END_STREAM | END_HEADERS, STREAM, &[0x8d])).await;
+        assert_eq!(result, Err("HTTP 404".into()));
+
+        let (result, _) = call_with(b"", |out| put_frame(out, HEADERS, END_STREAM | END_HEADERS, STREAM, &[0x88])).await;
+        assert_eq!(result, Err("response without grpc-status".into()));
+
+        let (result, _) = call_with(b"", |out| put_frame(out, RST_STREAM, 0, STREAM, &[0, 0, 0, 2])).await;
+        assert_eq!(result, Err("stream reset by the server (error code 0x2)".into()));
+
+        let (result, _) = call_with(b"", |out| put_frame(out, GOAWAY, 0, 0, &[0, 0, 0, 0, 0, 0, 0, 0xb])).await;
+        assert_eq!(result, Err("connection refused by the server (error code 0xb)".into()));
+
+        // The server hangs up without answering
+        let (client, mut server) = duplex(1 << 20);
+        tokio::spawn(async move { read_request(&mut server, &mut Seen::default()).await });
+        let result = exchange(client, &REQUEST_HEADERS, b"").await;
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn channels_take_http_and_https_endpoints() {
+        let channel = Channel::new("http://collector:4317/ignored", None, Duration::from_secs(1)).unwrap();
+        assert_eq!((channel.host.as_str(), channel.port, channel.scheme), ("collector", 4317, "http"));
+        assert_eq!(channel.authority, "collector:4317");
+        let channel = Channel::new("https://[::1]", None, Duration::from_secs(1)).unwrap();
+        assert_eq!((channel.host.as_str(), channel.port, channel.scheme), ("::1", 443, "https"));
+        assert_eq!(channel.authority, "[::1]:443");
+        assert!(channel.tls.is_some());
+        assert!(Channel::new("grpc://collector:4317", None, Duration::from_secs(1)).is_err());
+        assert!(Channel::new("https://collector", Some("/nonexistent/ca.pem"), Duration::from_secs(1)).is_err());
+    }
+}
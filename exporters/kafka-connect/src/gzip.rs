@@ -0,0 +1,437 @@
+//! Minimal gzip (RFC 1952) encoder/decoder for the metrics cache, plus the
+//! zlib (RFC 1950) wrapping that HTTP calls `deflate`, for responses.
+//!
+//! The encoder does greedy LZ77 over a 32 KiB window with hash chains and
+//! emits a single fixed-Huffman DEFLATE block. That is far from zlib's best
+//! ratio but exposition text is so repetitive that it still shrinks ~10x,
+//! and it keeps the exporter free of native compression dependencies.
+//!
+//! The decoder handles stored and fixed-Huffman blocks, i.e. everything
+//! `compress` produces; dynamic-Huffman input is rejected.
+
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 48;
+const HASH_BITS: u32 = 15;
+
+const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// ── CRC-32 ────────────────────────────────────────────────────────────────────
+
+fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for (i, slot) in table.iter_mut().enumerate() {
+        let mut c = i as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 };
+        }
+        *slot = c;
+    }
+    table
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let table = crc32_table();
+    let mut crc = !0u32;
+    for &b in data {
+        crc = table[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+// ── Bit I/O ───────────────────────────────────────────────────────────────────
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    nbits: u32,
+}
+
+impl BitWriter {
+    fn new(out: Vec<u8>) -> Self {
+        Self { out, acc: 0, nbits: 0 }
+    }
+
+    /// Append `n` bits of `value`, least significant bit first.
+    fn bits(&mut self, value: u32, n: u32) {
+        self.acc |= (value as u64) << self.nbits;
+        self.nbits += n;
+        while self.nbits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.nbits -= 8;
+        }
+    }
+
+    /// Append a Huffman code, which DEFLATE packs most significant bit first.
+    fn code(&mut self, code: u32, len: u32) {
+        self.bits(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.nbits > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bit(&mut self) -> Result<u32, &'static str> {
+        let byte = *self.data.get(self.pos).ok_or("unexpected end of deflate stream")?;
+        let b = (byte >> self.bit) & 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+        Ok(b as u32)
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, &'static str> {
+        let mut v = 0;
+        for i in 0..n {
+            v |= self.bit()? << i;
+        }
+        Ok(v)
+    }
+
+    /// Read a Huffman code of `n` bits, most significant bit first.
+    fn code(&mut self, n: u32) -> Result<u32, &'static str> {
+        let mut v = 0;
+        for _ in 0..n {
+            v = (v << 1) | self.bit()?;
+        }
+        Ok(v)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+// ── Encoder ───────────────────────────────────────────────────────────────────
+
+fn write_literal(w: &mut BitWriter, sym: u32) {
+    match sym {
+        0..=143 => w.code(0x30 + sym, 8),
+        144..=255 => w.code(0x190 + sym - 144, 9),
+        256..=279 => w.code(sym - 256, 7),
+        _ => w.code(0xC0 + sym - 280, 8),
+    }
+}
+
+fn write_match(w: &mut BitWriter, len: usize, dist: usize) {
+    let li = LEN_BASE.iter().rposition(|&b| b as usize <= len).unwrap();
+    write_literal(w, 257 + li as u32);
+    w.bits((len - LEN_BASE[li] as usize) as u32, LEN_EXTRA[li] as u32);
+
+    let di = DIST_BASE.iter().rposition(|&b| b as usize <= dist).unwrap();
+    w.code(di as u32, 5);
+    w.bits((dist - DIST_BASE[di] as usize) as u32, DIST_EXTRA[di] as u32);
+}
+
+fn hash3(data: &[u8], i: usize) -> usize {
+    let v = (data[i] as u32) << 16 | (data[i + 1] as u32) << 8 | data[i + 2] as u32;
+    (v.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Raw DEFLATE stream (one final fixed-Huffman block) for `data`.
+fn deflate_into(out: Vec<u8>, data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter::new(out);
+    w.bits(1, 1); // BFINAL
+    w.bits(1, 2); // BTYPE = fixed Huffman
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW];
+    let mut i = 0;
+
+    let insert = |head: &mut [usize], prev: &mut [usize], pos: usize| {
+        if pos + MIN_MATCH <= data.len() {
+            let h = hash3(data, pos);
+            prev[pos % WINDOW] = head[h];
+            head[h] = pos;
+        }
+    };
+
+    while i < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        if i + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - i);
+            let mut cand = head[hash3(data, i)];
+            let mut chain = 0;
+            while cand != usize::MAX && i - cand <= WINDOW && chain < MAX_CHAIN {
+                let len = data[cand..]
+                    .iter()
+                    .zip(&data[i..i + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = i - cand;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                let next = prev[cand % WINDOW];
+                if next == usize::MAX || next >= cand {
+                    break;
+                }
+                cand = next;
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            write_match(&mut w, best_len, best_dist);
+            for p in i..i + best_len {
+                insert(&mut head, &mut prev, p);
+            }
+            i += best_len;
+        } else {
+            write_literal(&mut w, data[i] as u32);
+            insert(&mut head, &mut prev, i);
+            i += 1;
+        }
+    }
+
+    write_literal(&mut w, 256); // end of block
+    w.finish()
+}
+
+/// gzip-compress `data`.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let header = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    let mut out = deflate_into(header, data);
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that cannot overflow before the modulo
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+/// zlib-compress `data`: the `deflate` content coding of HTTP.
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    // CMF: deflate with a 32 KiB window; FLG: fastest level, check bits
+    let mut out = deflate_into(vec![0x78, 0x01], data);
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+// ── Decoder ───────────────────────────────────────────────────────────────────
+
+/// Decode one fixed-Huffman literal/length symbol.
+fn read_fixed_symbol(r: &mut BitReader) -> Result<u32, &'static str> {
+    let v7 = r.code(7)?;
+    if v7 <= 0x17 {
+        return Ok(256 + v7);
+    }
+    let v8 = (v7 << 1) | r.bit()?;
+    match v8 {
+        0x30..=0xBF => Ok(v8 - 0x30),
+        0xC0..=0xC7 => Ok(280 + v8 - 0xC0),
+        _ => Ok(144 + ((v8 << 1) | r.bit()?) - 0x190),
+    }
+}
+
+fn inflate(data: &[u8], size_hint: usize) -> Result<Vec<u8>, &'static str> {
+    let mut r = BitReader { data, pos: 0, bit: 0 };
+    let mut out = Vec::with_capacity(size_hint);
+    loop {
+        let last = r.bit()? == 1;
+        match r.bits(2)? {
+            0 => {
+                r.align();
+                let raw = data.get(r.pos..r.pos + 4).ok_or("truncated stored block")?;
+                let len = u16::from_le_bytes([raw[0], raw[1]]) as usize;
+                let nlen = u16::from_le_bytes([raw[2], raw[3]]) as usize;
+                if len != !nlen & 0xFFFF {
+                    return Err("corrupt stored block length");
+                }
+                let start = r.pos + 4;
+                out.extend_from_slice(data.get(start..start + len).ok_or("truncated stored block")?);
+                r.pos = start + len;
+            }
+            1 => loop {
+                let sym = read_fixed_symbol(&mut r)?;
+                match sym {
+                    0..=255 => out.push(sym as u8),
+                    256 => break,
+                    _ => {
+                        let li = (sym - 257) as usize;
+                        if li >= LEN_BASE.len() {
+                            return Err("invalid length symbol");
+                        }
+                        let len = LEN_BASE[li] as usize + r.bits(LEN_EXTRA[li] as u32)? as usize;
+                        let di = r.code(5)? as usize;
+                        if di >= DIST_BASE.len() {
+                            return Err("invalid distance symbol");
+                        }
+                        let dist = DIST_BASE[di] as usize + r.bits(DIST_EXTRA[di] as u32)? as usize;
+                        if dist > out.len() {
+                            return Err("distance beyond start of output");
+                        }
+                        let start = out.len() - dist;
+                        for k in 0..len {
+                            out.push(out[start + k]);
+                        }
+                    }
+                }
+            },
+            _ => return Err("unsupported deflate block type"),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+/// Decompress gzip data produced by [`compress`].
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    if data.len() < 18 || data[0] != 0x1f || data[1] != 0x8b || data[2] != 8 {
+        return Err("not a gzip stream");
+    }
+    if data[3] != 0 {
+        return Err("gzip header flags are not supported");
+    }
+    let trailer = &data[data.len() - 8..];
+    let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+    let size = u32::from_le_bytes(trailer[4..].try_into().unwrap()) as usize;
+
+    let out = inflate(&data[10..data.len() - 8], size)?;
+    if out.len() != size || crc32(&out) != crc {
+        return Err("gzip checksum mismatch");
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `kafka_connect_up` of two instances, gzipped by zlib with Z_FIXED.
+    const ZLIB_FIXED: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x4e, 0x4c, 0xcb, 0x4e, 0x8c, 0x4f, 0xce,
+        0xcf, 0xcb, 0x4b, 0x4d, 0x2e, 0x89, 0x2f, 0x2d, 0xa8, 0xce, 0xcc, 0x2b, 0x2e, 0x49, 0xcc, 0x4b, 0x4e, 0xb5,
+        0x55, 0x82, 0x0a, 0xea, 0x1a, 0x5a, 0x59, 0x18, 0x58, 0x18, 0x2b, 0xd5, 0x2a, 0x18, 0x72, 0x65, 0x13, 0x56,
+        0x6d, 0x84, 0x50, 0x0d, 0x00, 0x8d, 0x35, 0xd1, 0x12, 0x5c, 0x00, 0x00, 0x00,
+    ];
+
+    fn gzip_stored(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+        // BFINAL, BTYPE = stored, then LEN and NLEN on the next byte
+        out.push(1);
+        out.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(!(data.len() as u16)).to_le_bytes());
+        out.extend_from_slice(data);
+        out.extend_from_slice(&crc32(data).to_le_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn checksums_match_reference_values() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn round_trips_every_match_length_and_distance() {
+        let mut data = Vec::new();
+        for i in 0..40_000u32 {
+            data.extend_from_slice(format!("{}:{};", i % 977, i % 13).as_bytes());
+        }
+        data.extend(std::iter::repeat_n(b'x', 1000));
+        data.extend((0..=255u8).cycle().take(70_000));
+        let gz = compress(&data);
+        assert_eq!(decompress(&gz).unwrap(), data);
+        assert!(gz.len() < data.len() / 2, "{} bytes from {}", gz.len(), data.len());
+    }
+
+    #[test]
+    fn round_trips_empty_and_tiny_input() {
+        for data in [&b""[..], b"a", b"ab", b"abc", b"abcabcabc"] {
+            assert_eq!(decompress(&compress(data)).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn trailer_is_crc32_and_size() {
+        let data = b"kafka_connect_up{instance=\"a\"} 1\n".repeat(100);
+        let gz = compress(&data);
+        assert_eq!(&gz[..4], &[0x1f, 0x8b, 8, 0]);
+        let trailer = &gz[gz.len() - 8..];
+        assert_eq!(trailer[..4], crc32(&data).to_le_bytes());
+        assert_eq!(trailer[4..], (data.len() as u32).to_le_bytes());
+    }
+
+    #[test]
+    fn decodes_zlib_fixed_huffman_stream() {
+        let text = "kafka_connect_up{instance=\"connect-1:8083\"} 1\nkafka_connect_up{instance=\"connect-2:8083\"} 1\n";
+        assert_eq!(decompress(ZLIB_FIXED).unwrap(), text.as_bytes());
+    }
+
+    #[test]
+    fn decodes_stored_blocks() {
+        let data = b"stored, not compressed";
+        assert_eq!(decompress(&gzip_stored(data)).unwrap(), data);
+    }
+
+    #[test]
+    fn rejects_corrupt_streams() {
+        let mut gz = compress(b"kafka_connect_up 1\n");
+        let n = gz.len();
+        gz[n - 8] ^= 1;
+        assert_eq!(decompress(&gz), Err("gzip checksum mismatch"));
+        let mut stored = gzip_stored(b"abc");
+        stored[13] ^= 1;
+        assert_eq!(decompress(&stored), Err("corrupt stored block length"));
+        assert_eq!(decompress(b"plain text, not gzip"), Err("not a gzip stream"));
+    }
+
+    #[test]
+    fn zlib_stream_has_header_and_adler32() {
+        let data = b"kafka_connect_up 1\n".repeat(10);
+        let z = zlib_compress(&data);
+        assert_eq!(u16::from_be_bytes([z[0], z[1]]) % 31, 0);
+        assert_eq!(inflate(&z[2..z.len() - 4], 0).unwrap(), data);
+        assert_eq!(z[z.len() - 4..], adler32(&data).to_be_bytes());
+    }
+}
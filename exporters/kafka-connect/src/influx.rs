@@ -0,0 +1,118 @@
+//! InfluxDB line protocol rendering of the cached samples, for
+//! GET /metrics/influx.
+//!
+//! Every sample goes into the measurement `kafka_connect`: its labels
+//! become tags, its name without the `kafka_connect_` prefix the field.
+//! Samples with the same labels share one line, all stamped with the time
+//! of the scrape cycle that produced them.
+
+use crate::registry::Sample;
+use std::collections::HashMap;
+
+/// Measurement of every line.
+pub const MEASUREMENT: &str = "kafka_connect";
+/// Content type of the rendered text.
+pub const CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+/// `s` with the characters that end a tag key, tag value or field key
+/// escaped.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            ',' | '=' | ' ' => {
+                out.push('\\');
+                out.push(c);
+            }
+            // A line break cannot be escaped
+            '\n' | '\r' => out.push(' '),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// `samples` as line protocol with `timestamp_ns`. Tags
+/// are sorted by key and empty tag values left out, as Influx requires;
+/// samples that are NaN or infinite have no line protocol form and are
+/// dropped.
+pub fn render(samples: &[Sample], timestamp_ns: u64) -> String {
+    let mut order: Vec<String> = Vec::new();
+    let mut fields: HashMap<String, Vec<String>> = HashMap::new();
+    for sample in samples {
+        if !sample.value.is_finite() {
+            continue;
+        }
+        let mut tags: Vec<&(String, String)> = sample.labels.pairs().iter().filter(|(_, v)| !v.is_empty()).collect();
+        tags.sort();
+        let mut series = MEASUREMENT.to_owned();
+        for (k, v) in tags {
+            series.push_str(&format!(",{}={}", escape(k), escape(v)));
+        }
+        let name = sample.name();
+        let field = name.strip_prefix("kafka_connect_").unwrap_or(&name);
+        let field = format!("{}={}", escape(field), sample.value);
+        fields
+            .entry(series)
+            .or_insert_with_key(|series| {
+                order.push(series.clone());
+                Vec::new()
+            })
+            .push(field);
+    }
+    let mut out = String::new();
+    for series in order {
+        out.push_str(&format!("{} {} {}\n", series, fields[&series].join(","), timestamp_ns));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry;
+
+    #[test]
+    fn samples_with_the_same_tags_share_a_line() {
+        let base = registry::Labels::default().with("instance", "a:8083").with("cluster", "");
+        let samples = [
+            registry::gauge("kafka_connect_up").with(&base).set(true),
+            registry::gauge("kafka_connect_workers_total").with(&base).set(3_usize),
+            registry::gauge("kafka_connect_connector_state").with(&base).label("connector", "s").set(false),
+            registry::gauge("kafka_connect_scrape_error_ratio").with(&base).set(f64::NAN),
+            registry::gauge("kafka_connect_maintenance_mode").set(false),
+        ];
+        assert_eq!(
+            render(&samples, 1_700_000_000_000_000_000),
+            "kafka_connect,instance=a:8083 up=1,workers_total=3 1700000000000000000\n\
+             kafka_connect,connector=s,instance=a:8083 connector_state=0 1700000000000000000\n\
+             kafka_connect maintenance_mode=0 1700000000000000000\n"
+        );
+        assert_eq!(render(&[], 1), "");
+    }
+
+    #[test]
+    fn tags_and_fields_are_escaped() {
+        assert_eq!(escape("a b,c=d"), r"a\ b\,c\=d");
+        assert_eq!(escape("two\nlines\r"), "two lines ");
+        let sample = registry::gauge("kafka_connect_connector_state")
+            .labels(&[("connector", "my sink,v2"), ("state", "a=b")])
+            .set(true);
+        assert_eq!(
+            render(&[sample], 5),
+            r"kafka_connect,connector=my\ sink\,v2,state=a\=b connector_state=1 5".to_owned() + "\n"
+        );
+    }
+
+    #[test]
+    fn summaries_keep_their_suffixes_as_fields() {
+        let latency = registry::summary("kafka_connect_instance_request_latency_seconds").label("instance", "a");
+        let out = render(&[latency.quantile(0.99, 0.5), latency.sum(2.0), latency.count(4)], 7);
+        assert_eq!(
+            out,
+            "kafka_connect,instance=a,quantile=0.99 instance_request_latency_seconds=0.5 7\n\
+             kafka_connect,instance=a instance_request_latency_seconds_sum=2,\
+             instance_request_latency_seconds_count=4 7\n"
+        );
+    }
+}
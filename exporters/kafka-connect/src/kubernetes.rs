@@ -0,0 +1,297 @@
+//! Kubernetes discovery of Connect workers (K8S_DISCOVERY_SELECTOR).
+//!
+//! The pods matching a label selector are listed from the API server at a
+//! fixed interval, and every ready one becomes an instance, scraped at its
+//! pod IP. In a cluster the service account's token and CA are used; with
+//! K8S_API_URL the API can also be reached through `kubectl proxy`.
+
+use crate::Discovered;
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::debug;
+
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+/// Where and what to discover.
+#[derive(Debug)]
+pub struct Discovery {
+    /// API server base URL, without a trailing slash.
+    api_url: String,
+    namespace: String,
+    selector: String,
+    /// Connect REST port on every pod.
+    port: u16,
+    /// `http` or `https`, for the instance URLs.
+    scheme: String,
+    /// `cluster` of every discovered instance.
+    cluster: Option<String>,
+    /// How often the pod list is refreshed.
+    pub interval: Duration,
+    /// Read again for every request, since projected tokens rotate.
+    token_file: Option<String>,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct PodList {
+    items: Vec<Pod>,
+}
+
+#[derive(Deserialize)]
+struct Pod {
+    metadata: Metadata,
+    #[serde(default)]
+    status: PodStatus,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Metadata {
+    name: String,
+    #[serde(default)]
+    deletion_timestamp: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PodStatus {
+    #[serde(default)]
+    phase: String,
+    #[serde(default, rename = "podIP")]
+    pod_ip: Option<String>,
+    #[serde(default)]
+    conditions: Vec<Condition>,
+}
+
+#[derive(Deserialize)]
+struct Condition {
+    #[serde(rename = "type")]
+    kind: String,
+    status: String,
+}
+
+impl Pod {
+    /// Running, ready and not being deleted: a worker that can answer.
+    fn is_ready(&self) -> bool {
+        self.metadata.deletion_timestamp.is_none()
+            && self.status.phase == "Running"
+            && self.status.conditions.iter().any(|c| c.kind == "Ready" && c.status == "True")
+    }
+}
+
+impl Discovery {
+    /// Settings from K8S_DISCOVERY_*, when K8S_DISCOVERY_SELECTOR is set.
+    /// `var` reads one env variable.
+    pub fn from_env(var: impl Fn(&str) -> Option<String>, timeout: Duration) -> Result<Option<Self>, String> {
+        let Some(selector) = var("K8S_DISCOVERY_SELECTOR").filter(|s| !s.trim().is_empty()) else {
+            return Ok(None);
+        };
+        let in_cluster = var("K8S_API_URL").is_none();
+        let api_url = match var("K8S_API_URL") {
+            Some(url) => url.trim_end_matches('/').to_owned(),
+            None => match (var("KUBERNETES_SERVICE_HOST"), var("KUBERNETES_SERVICE_PORT")) {
+                (Some(host), Some(port)) if host.contains(':') => format!("https://[{}]:{}", host, port),
+                (Some(host), Some(port)) => format!("https://{}:{}", host, port),
+                _ => return Err("not running in Kubernetes: set K8S_API_URL".into()),
+            },
+        };
+        let namespace = match var("K8S_DISCOVERY_NAMESPACE") {
+            Some(namespace) => namespace,
+            None => std::fs::read_to_string(format!("{}/namespace", SERVICE_ACCOUNT_DIR))
+                .map(|ns| ns.trim().to_owned())
+                .map_err(|e| format!("K8S_DISCOVERY_NAMESPACE is not set and the pod namespace is unknown: {}", e))?,
+        };
+        let port = match var("K8S_DISCOVERY_PORT") {
+            Some(port) => port.parse().map_err(|_| format!("K8S_DISCOVERY_PORT {}: not a port", port))?,
+            None => 8083,
+        };
+        let scheme = var("K8S_DISCOVERY_SCHEME").unwrap_or_else(|| "http".into());
+        if scheme != "http" && scheme != "https" {
+            return Err(format!("K8S_DISCOVERY_SCHEME {}: expected http or https", scheme));
+        }
+        let interval = match var("K8S_DISCOVERY_INTERVAL_SECS").map(|s| s.parse::<u64>()) {
+            Some(Ok(secs)) if secs > 0 => Duration::from_secs(secs),
+            Some(_) => return Err("K8S_DISCOVERY_INTERVAL_SECS: expected whole seconds > 0".into()),
+            None => Duration::from_secs(30),
+        };
+
+        let mut builder = reqwest::Client::builder().timeout(timeout);
+        let mut token_file = var("K8S_TOKEN_FILE");
+        if in_cluster {
+            for cert in crate::tls::ca_certificates(&format!("{}/ca.crt", SERVICE_ACCOUNT_DIR))? {
+                builder = builder.add_root_certificate(cert);
+            }
+            token_file.get_or_insert_with(|| format!("{}/token", SERVICE_ACCOUNT_DIR));
+        }
+        let client = builder.build().map_err(|e| e.to_string())?;
+        Ok(Some(Self {
+            api_url,
+            namespace,
+            selector,
+            port,
+            scheme,
+            cluster: var("K8S_DISCOVERY_CLUSTER").filter(|c| !c.is_empty()),
+            interval,
+            token_file,
+            client,
+        }))
+    }
+
+    /// What is discovered, for the startup log.
+    pub fn describe(&self) -> String {
+        format!("pods `{}` in namespace {} via {}", self.selector, self.namespace, self.api_url)
+    }
+
+    /// The ready pods matching the selector, named after the pod.
+    pub async fn targets(&self) -> Result<Vec<Discovered>, String> {
+        let url = format!("{}/api/v1/namespaces/{}/pods", self.api_url, self.namespace);
+        let mut req = self.client.get(&url).query(&[("labelSelector", &self.selector)]);
+        if let Some(path) = &self.token_file {
+            let token = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            req = req.bearer_auth(token.trim());
+        }
+        let resp = req.send().await.map_err(|e| e.to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("{} answered HTTP {}", url, resp.status()));
+        }
+        let pods: PodList = resp.json().await.map_err(|e| e.to_string())?;
+        let listed = pods.items.len();
+        let mut targets: Vec<Discovered> = pods
+            .items
+            .into_iter()
+            .filter(Pod::is_ready)
+            .filter_map(|pod| {
+                let ip = pod.status.pod_ip?;
+                let host = if ip.contains(':') { format!("[{}]", ip) } else { ip };
+                Some(Discovered {
+                    name: Some(pod.metadata.name),
+                    url: format!("{}://{}:{}", self.scheme, host, self.port),
+                    cluster: self.cluster.clone(),
+                })
+            })
+            .collect();
+        targets.sort_by(|a, b| a.name.cmp(&b.name));
+        debug!("kubernetes: {} of {} matching pods are ready", targets.len(), listed);
+        Ok(targets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn env(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let pairs: Vec<(String, String)> = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |key| pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone())
+    }
+
+    fn discovery(url: &str, extra: &[(&str, &str)]) -> Result<Option<Discovery>, String> {
+        let mut pairs = vec![
+            ("K8S_DISCOVERY_SELECTOR", "app=connect"),
+            ("K8S_API_URL", url),
+            ("K8S_DISCOVERY_NAMESPACE", "data"),
+        ];
+        pairs.extend_from_slice(extra);
+        Discovery::from_env(env(&pairs), Duration::from_secs(5))
+    }
+
+    #[test]
+    fn settings_come_from_the_env() {
+        for unset in [&[][..], &[("K8S_DISCOVERY_SELECTOR", " ")]] {
+            assert!(Discovery::from_env(env(unset), Duration::from_secs(1)).unwrap().is_none());
+        }
+        let found = discovery("http://127.0.0.1:8001/", &[]).unwrap().unwrap();
+        assert_eq!(found.api_url, "http://127.0.0.1:8001");
+        assert_eq!((found.port, found.scheme.as_str(), found.interval), (8083, "http", Duration::from_secs(30)));
+        assert_eq!(found.describe(), "pods `app=connect` in namespace data via http://127.0.0.1:8001");
+
+        let err = |extra: &[(&str, &str)]| discovery("http://api", extra).unwrap_err();
+        assert_eq!(err(&[("K8S_DISCOVERY_PORT", "http")]), "K8S_DISCOVERY_PORT http: not a port");
+        assert_eq!(err(&[("K8S_DISCOVERY_SCHEME", "ftp")]), "K8S_DISCOVERY_SCHEME ftp: expected http or https");
+        assert!(err(&[("K8S_DISCOVERY_INTERVAL_SECS", "0")]).starts_with("K8S_DISCOVERY_INTERVAL_SECS:"));
+        let outside = Discovery::from_env(env(&[("K8S_DISCOVERY_SELECTOR", "app=connect")]), Duration::from_secs(1));
+        assert_eq!(outside.unwrap_err(), "not running in Kubernetes: set K8S_API_URL");
+    }
+
+    /// Path and query, and the Authorization header, of a list call.
+    type Calls = Arc<Mutex<Vec<(String, Option<String>)>>>;
+
+    /// An API server answering every pod list with `pods`.
+    async fn api_server(pods: serde_json::Value) -> (String, Calls) {
+        let calls: Calls = Arc::default();
+        let seen = calls.clone();
+        let app = axum::Router::new().fallback(move |req: axum::extract::Request| {
+            let auth = req.headers().get("authorization").map(|v| v.to_str().unwrap().to_owned());
+            seen.lock().unwrap().push((req.uri().to_string(), auth));
+            let pods = pods.clone();
+            async move { axum::Json(pods) }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, calls)
+    }
+
+    fn pod(name: &str, phase: &str, ip: Option<&str>, ready: &str) -> serde_json::Value {
+        serde_json::json!({
+            "metadata": { "name": name },
+            "status": {
+                "phase": phase,
+                "podIP": ip,
+                "conditions": [{ "type": "Initialized", "status": "True" }, { "type": "Ready", "status": ready }],
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn targets_are_the_ready_pods() {
+        let mut deleting = pod("connect-3", "Running", Some("10.0.0.3"), "True");
+        deleting["metadata"]["deletionTimestamp"] = "2024-01-01T00:00:00Z".into();
+        let pods = serde_json::json!({ "items": [
+            pod("connect-1", "Running", Some("10.0.0.1"), "True"),
+            pod("connect-0", "Running", Some("fd00::5"), "True"),
+            pod("connect-2", "Running", Some("10.0.0.2"), "False"),
+            deleting,
+            pod("connect-4", "Pending", None, "False"),
+            pod("connect-5", "Running", None, "True"),
+            { "metadata": { "name": "connect-6" } },
+        ]});
+        let (url, calls) = api_server(pods).await;
+        let token = std::env::temp_dir().join(format!("k8s-token-{}", std::process::id()));
+        std::fs::write(&token, "sa-token\n").unwrap();
+        let found = discovery(
+            &url,
+            &[
+                ("K8S_DISCOVERY_PORT", "8443"),
+                ("K8S_DISCOVERY_SCHEME", "https"),
+                ("K8S_DISCOVERY_CLUSTER", "prod"),
+                ("K8S_TOKEN_FILE", token.to_str().unwrap()),
+            ],
+        )
+        .unwrap()
+        .unwrap();
+
+        let targets = found.targets().await.unwrap();
+        let target = |name: &str, url: &str| Discovered {
+            name: Some(name.into()),
+            url: url.into(),
+            cluster: Some("prod".into()),
+        };
+        assert_eq!(
+            targets,
+            [target("connect-0", "https://[fd00::5]:8443"), target("connect-1", "https://10.0.0.1:8443")]
+        );
+        assert_eq!(
+            calls.lock().unwrap()[0],
+            ("/api/v1/namespaces/data/pods?labelSelector=app%3Dconnect".to_owned(), Some("Bearer sa-token".to_owned()))
+        );
+
+        // The token is read again for every list, so a rotated one is picked up
+        std::fs::write(&token, "rotated").unwrap();
+        found.targets().await.unwrap();
+        assert_eq!(calls.lock().unwrap()[1].1.as_deref(), Some("Bearer rotated"));
+        std::fs::remove_file(&token).unwrap();
+        assert!(found.targets().await.unwrap_err().contains("k8s-token-"));
+    }
+}
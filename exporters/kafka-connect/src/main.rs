@@ -7,241 +7,5060 @@
  * Metrics exposed:
  *   kafka_connect_connector_state{connector,state,instance}       1 if in that state
  *   kafka_connect_connector_task_state{connector,task,state,instance} 1 if in that state
+ *   kafka_connect_connector_tasks{connector,state,instance}        tasks of the connector in that state
+ *   kafka_connect_connector_info{connector,type,class,version,instance}  1 per connector; only
+ *                                                                  connector and instance in LITE_MODE
+ *   kafka_connect_connector_single_worker{connector,instance}      1 if all tasks share one worker
+ *   kafka_connect_connector_degraded{connector,instance}           1 if RUNNING with a non-RUNNING task
+ *   kafka_connect_connector_config_version{connector,instance}     bumps on each observed config change
+ *   kafka_connect_connector_became_failed{connector,instance}      1 for the cycle a connector turned FAILED
+ *   kafka_connect_connector_task_count_dropped{connector,instance} 1 for the cycle a connector lost tasks
+ *   kafka_connect_connector_worker_info{connector,worker_id,instance}  1, with the worker running the connector
+ *   kafka_connect_task_worker_info{connector,task,worker_id,instance}  1, with the worker running the task
+ *   kafka_connect_task_error_info{connector,task,exception_class,instance}  1 per task with a failure trace
+ *   kafka_connect_connector_state_transitions_total{connector,from,to,instance}  observed state changes
+ *   kafka_connect_connector_flapping{connector,instance}           FAILED entries/exits per hour (FLAP_WINDOW_SECS)
+ *   kafka_connect_task_state_transitions_total{connector,task,from,to,instance}  observed task state changes
+ *   kafka_connect_connector_topic{connector,topic,instance}        1 per active topic (SCRAPE_CONNECTOR_TOPICS)
  *   kafka_connect_up{instance}                                     1 if reachable
+ *   kafka_connect_instance_info{instance,url}                      1, with the instance's Connect URL
+ *   kafka_connect_version_info{version,instance}                   1, with the version reported by GET /
+ *   kafka_connect_cluster_info{version,commit,kafka_cluster_id,instance}  1, with the whole GET / answer
  *   kafka_connect_connectors_total{instance}                       total connectors
- *   kafka_connect_connectors_running{instance}                     running connectors
- *   kafka_connect_connectors_failed{instance}                      failed connectors
+ *   kafka_connect_connectors_scraped{instance}                     connectors whose status was fetched
+ *   kafka_connect_scrape_complete{instance}                        1 if every listed connector was scraped
+ *   kafka_connect_connectors_running{instance[,type]}              running connectors
+ *   kafka_connect_connectors_failed{instance[,type]}               failed connectors
+ *   kafka_connect_connectors_degraded{instance}                    RUNNING connectors with a non-RUNNING task
+ *   kafka_connect_task_reassignments_total{instance}               tasks that moved to another worker
+ *   kafka_connect_workers_total{instance}                          distinct worker_ids seen on connectors/tasks
+ *   kafka_connect_orphaned_tasks{instance}                         connector/task inconsistencies
+ *   kafka_connect_oldest_failed_connector_age_seconds{instance}    longest time a connector has been FAILED
+ *   kafka_connect_data_stale{instance}                             1 if series are repeated from the last successful
+ *                                                                  scrape (DOWN_INSTANCE_POLICY=last,
+ *                                                                  DOWN_INSTANCE_KEEP_CYCLES)
+ *   kafka_connect_requests_per_scrape{instance}                    HTTP requests made in the last cycle
+ *   kafka_connect_status_not_modified_total{instance}              status calls answered 304 (ETag reuse)
+ *   kafka_connect_instance_request_latency_seconds{instance,quantile}  list/status latency this cycle
+ *   kafka_connect_expand_status_supported{instance}                1 if the list call returned expanded statuses
+ *   kafka_connect_request_concurrency{instance}                    per-connector calls in flight this cycle
+ *   kafka_connect_scrape_error_ratio{instance}                     failed / total requests in the last cycle
+ *   kafka_connect_required_connector_missing{connector,instance}   1 if a required connector is absent
+ *   kafka_connect_resolved_addr_info{instance,addr}                addresses the instance host resolves to
+ *   kafka_connect_last_successful_scrape_timestamp_seconds{instance}  when the instance was last reached
+ *   kafka_connect_plugin_info{class,type,version,instance}         1 per plugin installed on the worker
+ *   kafka_connect_plugin_version_skew{cluster,class}               1 if workers of a cluster disagree
+ *   kafka_connect_connector_name_collision{connector}              clusters listing the same connector name
+ *   kafka_connect_maintenance_mode                                 1 while in maintenance mode
+ *   kafka_connect_remote_write_failures_total                      snapshots remote-write failed to deliver
+ *   kafka_connect_pushgateway_failures_total                       snapshots the Pushgateway push failed to deliver
+ *   kafka_connect_otlp_metrics_failures_total                      snapshots the OTLP metrics export failed to deliver
+ *   kafka_connect_dogstatsd_failures_total                         snapshots the DogStatsD sink failed to send
+ *   kafka_connect_webhook_deliveries_total{result}                 event webhook deliveries by outcome
+ *   kafka_connect_channel_notifications_total{channel,result}      Slack/Teams channel deliveries by outcome
+ *   kafka_connect_alerts_firing{alert}                             firing series of the alert rule
+ *   kafka_connect_alert_notifications_total{result}                alert notification deliveries by outcome
+ *   kafka_connect_heal_attempts_total{connector,result}            finished heal requests by outcome
+ *   kafka_connect_exporter_cache_bytes{compression}                size of the metrics cache
+ *   kafka_connect_exporter_scrape_duration_seconds{instance}       wall time of the last scrape
+ *   kafka_connect_exporter_http_requests_made_total{instance}      HTTP requests made since startup
+ *   kafka_connect_exporter_scrape_errors_total{instance,reason}    failed requests by reason
+ *   kafka_connect_exporter_task_restarts_total{connector,task,result,instance}
+ *                                                                  automatic task restarts by outcome
+ *
+ * Every series of an instance also carries that instance's `labels` from the
+ * config file (--config or CONFIG_FILE), e.g. `color="blue"` for blue-green clusters.
+ * EXTRA_LABELS adds labels to every series the exporter serves.
+ *
+ * Endpoints:
+ *   GET /metrics    cached exposition from the background scrape loop (text format, or
+ *                   OpenMetrics when the Accept header prefers it); with SCRAPE_ON_DEMAND=true
+ *                   a stale cache is refreshed first
+ *   GET /metrics/delta  series of connectors whose state changed in the last cycle
+ *   GET /metrics/influx  the cached exposition in InfluxDB line protocol
+ *   GET /api/v1/connectors  connector and task states of every instance from the last scrape, as JSON
+ *   GET /api/v1/connectors/:name/errors  connector and task failure traces from the last scrape
+ *   GET /probe?target=URL[&module=NAME]  one-off scrape of an allowlisted target
+ *                   (PROBE_ALLOWED_HOSTS) with the settings of a config file probe module
+ *   GET /health     ok, or 503 when every instance is down or the scrapes stalled
+ *   GET /livez      ok, or 503 when the scrapes stalled
+ *   GET /readyz     ok once a scrape filled the cache
+ *   GET /selftest   active connectivity probe of every instance (rate-limited);
+ *                   200 if all reachable, 503 with a per-instance JSON breakdown
+ *   GET|PUT /admin/maintenance?enabled=true|false   (ADMIN_ENABLED=true only)
+ *   POST /connectors/:name/heal?instance=...        restart until RUNNING (ADMIN_ENABLED
+ *                                                   and HEAL_ALLOWLIST only)
+ *   POST /api/v1/clusters/:cluster/connectors/:name/pause|resume|restart
+ *                   proxied to a worker of the cluster (ADMIN_ENABLED,
+ *                   CONNECTOR_ACTIONS_ENABLED and web auth only)
+ *
+ * With OTEL_EXPORTER_OTLP_ENDPOINT set, scrape cycles, instance scrapes and
+ * connector fetches are exported as OTLP/HTTP JSON trace spans. With
+ * OTEL_EXPORTER_OTLP_METRICS_ENDPOINT set, every snapshot is exported as OTLP metrics,
+ * over HTTP (JSON or protobuf) or gRPC as OTEL_EXPORTER_OTLP_METRICS_PROTOCOL selects.
+ *
+ * With DOGSTATSD_URL set, every snapshot's gauges are also sent to a DogStatsD agent.
+ *
+ * With PUSHGATEWAY_URL set, every snapshot is also PUT to a Prometheus Pushgateway;
+ * PUSH_ONLY=true then skips the metrics listener.
+ *
+ * With K8S_DISCOVERY_SELECTOR set, the ready pods matching it are scraped instead of
+ * the configured instances, and the list follows the pods as they come and go.
+ * KAFKA_CONNECT_DNS_SRV (or KAFKA_CONNECT_DNS_A) does the same with the workers a
+ * SRV record (or the addresses of a name) lists, resolved again every interval, and
+ * CONSUL_DISCOVERY_SERVICE with the passing instances of a Consul service, watched
+ * with blocking queries.
+ *
+ * With `alerts` in the config file, its rules are evaluated on every snapshot and
+ * firing and resolved alerts are POSTed to its webhook.
+ *
+ * With `channels` in the config file, connector FAILED transitions are also sent
+ * to the Slack and Teams channels that match them.
+ *
+ * With TASK_AUTO_RESTART=true, FAILED tasks are restarted with a growing backoff,
+ * up to TASK_RESTART_MAX_ATTEMPTS times per task.
+ *
+ * With ADMIN_BIND_ADDR set, the admin routes (plus the health endpoints) are served
+ * on that address instead of the metrics listener.
+ *
+ * With TLS_CERT_FILE and TLS_KEY_FILE set, every listener serves HTTPS and picks up
+ * rotated certificate files without a restart.
+ *
+ * With WEB_AUTH_* (or `web_auth` in the config file) set, every endpoint but the
+ * health endpoints requires basic auth or a bearer token.
+ *
+ * SIGHUP re-reads the config file's instance list without restarting the listeners.
+ * SIGTERM finishes open requests and the running scrape cycle (up to
+ * SHUTDOWN_GRACE_SECS), then exits.
+ *
+ * --connect-url, --bind, --interval, --config and --log-level override their env
+ * variables; see --help.
  */
 
-use axum::{routing::get, Router};
-use serde::Deserialize;
+mod alerts;
+mod channels;
+mod cli;
+mod consul;
+mod dns;
+mod grpc;
+mod gzip;
+mod influx;
+mod kubernetes;
+mod oauth;
+mod otlp;
+mod registry;
+mod pushgateway;
+mod remote_write;
+mod statsd;
+mod tls;
+mod toml;
+mod version;
+mod web_auth;
+mod webhook;
+mod yaml;
+
+use axum::{
+    extract::{Path, Query, RawQuery, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use bytes::Bytes;
+use futures_util::{future::join_all, FutureExt, StreamExt};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock},
-    time::Duration,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant, SystemTime},
 };
-use tracing::{info, warn};
+use tokio::sync::{watch, Notify, Semaphore};
+use tracing::{debug, error, info, trace_span, warn, Instrument};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
 
 // ── Config ────────────────────────────────────────────────────────────────────
 
 struct Config {
+    /// Config file (--config or CONFIG_FILE), re-read on SIGHUP.
+    config_path: Option<String>,
+    /// --connect-url; replaces the env and config file instances.
     connect_urls: Vec<String>,
+    /// Env settings for instances that leave them out.
+    instance_defaults: InstanceDefaults,
+    /// Extra CA bundle, also trusted by the shared client (KAFKA_CONNECT_CA_FILE).
+    ca_file: Option<String>,
+    /// Listener for /metrics, /health and /selftest (METRICS_BIND_ADDR, or BIND_ADDR).
     bind_addr: String,
+    /// Separate listener for /admin/* (ADMIN_BIND_ADDR); on bind_addr when unset.
+    admin_bind_addr: Option<String>,
+    /// Serve HTTPS with this certificate and key (TLS_CERT_FILE, TLS_KEY_FILE).
+    server_tls: Option<(String, String)>,
+    /// Credentials required on every endpoint but the health endpoints (`web_auth`, WEB_AUTH_*).
+    web_auth: Option<web_auth::WebAuth>,
     scrape_interval: Duration,
+    selftest_timeout: Duration,
+    selftest_min_interval: Duration,
+    /// Scrape intervals without a refresh after which /health fails
+    /// (HEALTH_STALE_INTERVALS); 0 never fails on age.
+    health_stale_intervals: u32,
+    /// How long SIGTERM waits for open requests and a running scrape cycle
+    /// (SHUTDOWN_GRACE_SECS).
+    shutdown_grace: Duration,
+    /// Retries of a Connect request answered `409` or `5xx` (REQUEST_RETRIES).
+    request_retries: u32,
+    /// First retry delay (RETRY_BACKOFF_MS).
+    retry_backoff: Duration,
+    /// Window of kafka_connect_connector_flapping (FLAP_WINDOW_SECS).
+    flap_window: Duration,
+    /// Keep the cached exposition gzip-compressed (CACHE_COMPRESSION=gzip).
+    cache_gzip: bool,
+    /// Maintenance mode at startup (MAINTENANCE_MODE).
+    maintenance_mode: bool,
+    /// While in maintenance, drop `up 0` and FAILED series (MAINTENANCE_SUPPRESS).
+    maintenance_suppress: bool,
+    /// Serve the /admin/* routes (ADMIN_ENABLED).
+    admin_enabled: bool,
+    /// Scrape discovered workers instead of configured instances
+    /// (K8S_DISCOVERY_SELECTOR, KAFKA_CONNECT_DNS_SRV, KAFKA_CONNECT_DNS_A,
+    /// CONSUL_DISCOVERY_SERVICE).
+    discovery: Option<Discovery>,
+    /// Spread instance scrapes across the interval (SCRAPE_JITTER).
+    scrape_jitter: bool,
+    /// Largest random change of each cycle's length, as a fraction of the
+    /// interval (SCRAPE_INTERVAL_JITTER_PERCENT).
+    interval_jitter: f64,
+    /// Longest random delay of each instance's first scrape (SCRAPE_INITIAL_SPLAY_SECS).
+    initial_splay: Option<Duration>,
+    /// Scrape only when /metrics asks, at most once per this long
+    /// (SCRAPE_ON_DEMAND, SCRAPE_MIN_INTERVAL_SECS).
+    scrape_on_demand: Option<Duration>,
+    /// Exit instead of serving when the initial scrape reached no instance.
+    fail_if_all_down: bool,
+    /// Push each snapshot via Prometheus remote-write (REMOTE_WRITE_URL).
+    remote_write_url: Option<String>,
+    remote_write_max_retries: u32,
+    /// Credentials for remote-write (REMOTE_WRITE_USERNAME/PASSWORD or REMOTE_WRITE_BEARER_TOKEN).
+    remote_write_auth: Option<remote_write::Auth>,
+    /// Export each snapshot as OTLP metrics here (OTEL_EXPORTER_OTLP_METRICS_ENDPOINT).
+    otlp_metrics_url: Option<String>,
+    /// OTEL_EXPORTER_OTLP_METRICS_PROTOCOL.
+    otlp_metrics_protocol: otlp::Protocol,
+    /// Resource attributes of the OTLP metrics (OTEL_SERVICE_NAME, OTEL_RESOURCE_ATTRIBUTES).
+    otel_resource: Vec<(String, String)>,
+    /// Send each snapshot's gauges to this DogStatsD agent (DOGSTATSD_URL).
+    dogstatsd: Option<statsd::Target>,
+    /// Tags added to every DogStatsD gauge (DOGSTATSD_TAGS).
+    dogstatsd_tags: Vec<String>,
+    /// PUT each snapshot to this Pushgateway (PUSHGATEWAY_URL).
+    pushgateway_url: Option<String>,
+    /// `job` of the pushed group (PUSHGATEWAY_JOB).
+    pushgateway_job: String,
+    /// Labels added to every series (EXTRA_LABELS).
+    extra_labels: Vec<(String, String)>,
+    /// Further grouping labels of the pushed group (PUSHGATEWAY_GROUPING).
+    pushgateway_grouping: Vec<(String, String)>,
+    pushgateway_max_retries: u32,
+    /// Only push; no metrics listener (PUSH_ONLY).
+    push_only: bool,
+    /// Emit the edge-triggered kafka_connect_connector_became_failed.
+    emit_became_failed: bool,
+    /// Wall-clock bound on one whole scrape cycle (CYCLE_BUDGET_SECS).
+    cycle_budget: Option<Duration>,
+    /// POST connector FAILED transitions here (EVENT_WEBHOOK_URL).
+    event_webhook_url: Option<String>,
+    event_webhook_max_retries: u32,
+    /// Fetch each connector's config to track config changes (SCRAPE_CONNECTOR_CONFIG).
+    scrape_connector_config: bool,
+    /// Fetch each connector's active topics (SCRAPE_CONNECTOR_TOPICS).
+    scrape_connector_topics: bool,
+    /// Hosts (`host` or `host:port`) GET /probe may scrape (PROBE_ALLOWED_HOSTS).
+    probe_allowed_hosts: Vec<String>,
+    /// `probe_modules` of the config file, by name, as instance templates.
+    probe_modules: HashMap<String, Instance>,
+    /// `alerts` of the config file: rules evaluated on every snapshot.
+    alerts: Option<alerts::Alerts>,
+    /// `channels` of the config file: Slack and Teams destinations of the events.
+    channels: Arc<Vec<channels::Channel>>,
+    /// Connectors POST /connectors/:name/heal may act on (HEAL_ALLOWLIST).
+    heal_allowlist: Vec<String>,
+    heal_timeout: Duration,
+    heal_poll_interval: Duration,
+    heal_max_restarts: u32,
+    /// Serve the pause, resume and restart routes under /api/v1/clusters
+    /// (CONNECTOR_ACTIONS_ENABLED); needs web auth too.
+    connector_actions: bool,
+    /// Restart FAILED tasks on their own (TASK_AUTO_RESTART).
+    task_auto_restart: bool,
+    /// Wait after the first automatic restart of a task, doubled after each
+    /// further one up to `task_restart_max_backoff`.
+    task_restart_backoff: Duration,
+    task_restart_max_backoff: Duration,
+    /// Automatic restarts of one task before it is left FAILED.
+    task_restart_max_attempts: u32,
+    /// Split the running/failed summaries by connector type (SPLIT_SUMMARY_BY_TYPE).
+    split_summary_by_type: bool,
+    /// What a down instance emits besides `up 0` (DOWN_INSTANCE_POLICY).
+    down_policy: DownPolicy,
+    /// Failed cycles a down instance keeps its last per-connector series
+    /// for (DOWN_INSTANCE_KEEP_CYCLES); 0 drops them at once.
+    down_keep_cycles: u32,
+    /// Per-connector calls in flight per instance (CONNECTOR_FETCH_CONCURRENCY).
+    fetch_concurrency: usize,
+    /// Cycles over which concurrency ramps up to the max (CONCURRENCY_RAMP_CYCLES).
+    concurrency_ramp_cycles: u32,
+    /// Fetch previously FAILED connectors first (PRIORITIZE_FAILED).
+    prioritize_failed: bool,
+    /// Poll previously FAILED connectors between cycles (FAILED_RECHECK_SECS).
+    failed_recheck: Option<Duration>,
+    /// Instances scraped at the same time, 0 for all (INSTANCE_CONCURRENCY).
+    instance_concurrency: usize,
+    /// Connector config property copied into a `tenant` label (TENANT_CONFIG_KEY).
+    tenant_config_key: Option<String>,
+    /// Unmodelled status fields promoted to labels (EXTRA_STATUS_FIELDS).
+    extra_status_fields: Vec<String>,
+}
+
+/// Summary gauges of an unreachable instance.
+#[derive(Clone, Copy, PartialEq)]
+enum DownPolicy {
+    /// Leave them out (honest absence).
+    Absent,
+    /// Emit them as 0.
+    Zero,
+    /// Repeat the last successful values, flagged with kafka_connect_data_stale.
+    Last,
+}
+
+/// Where the instances come from when they are discovered.
+#[derive(Debug)]
+enum Discovery {
+    Kubernetes(kubernetes::Discovery),
+    Dns(dns::Discovery),
+    Consul(consul::Discovery),
+}
+
+/// A discovered worker, as an instance to scrape.
+#[derive(Debug, PartialEq)]
+struct Discovered {
+    /// `name` of the instance; the URL's host:port labels it otherwise.
+    name: Option<String>,
+    url: String,
+    cluster: Option<String>,
+}
+
+impl Discovery {
+    /// The one discovery the env configures, if any.
+    fn from_env(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, String> {
+        let configured: Vec<Self> = [
+            kubernetes::Discovery::from_env(&var, REQUEST_TIMEOUT)?.map(Self::Kubernetes),
+            dns::Discovery::from_env(&var)?.map(Self::Dns),
+            consul::Discovery::from_env(&var, REQUEST_TIMEOUT)?.map(Self::Consul),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if configured.len() > 1 {
+            return Err("set only one of K8S_DISCOVERY_SELECTOR, KAFKA_CONNECT_DNS_* and CONSUL_DISCOVERY_SERVICE".into());
+        }
+        Ok(configured.into_iter().next())
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Kubernetes(k) => format!("Kubernetes discovery of {}", k.describe()),
+            Self::Dns(d) => format!("DNS discovery of {}", d.describe()),
+            Self::Consul(c) => format!("Consul discovery of {}", c.describe()),
+        }
+    }
+
+    async fn targets(&self) -> Result<Vec<Discovered>, String> {
+        match self {
+            Self::Kubernetes(k) => k.targets().await,
+            Self::Dns(d) => d.targets().await,
+            Self::Consul(c) => c.targets().await,
+        }
+    }
+
+    /// Time between lookups, or `None` for a watch, whose lookups wait
+    /// for the next change themselves.
+    fn interval(&self) -> Option<Duration> {
+        match self {
+            Self::Kubernetes(k) => Some(k.interval),
+            Self::Dns(d) => Some(d.interval),
+            Self::Consul(_) => None,
+        }
+    }
+
+    /// Pause after a failed lookup, before the next one.
+    fn retry_delay(&self) -> Duration {
+        match self {
+            Self::Consul(c) => c.retry_delay(),
+            _ => self.interval().unwrap_or_default(),
+        }
+    }
 }
 
 impl Config {
-    fn from_env() -> Self {
-        let urls = std::env::var("KAFKA_CONNECT_URLS")
-            .unwrap_or_else(|_| "http://localhost:8083".into());
-        let connect_urls = urls
-            .split(',')
-            .map(|u| u.trim().trim_end_matches('/').to_owned())
-            .filter(|u| !u.is_empty())
+    /// The command-line flags, then the env vars, with the config file as
+    /// the fallback for the instances and `web_auth`. Panics on invalid
+    /// settings. The instances come from [`Config::load_instances`].
+    fn load(args: &cli::Args) -> Self {
+        let config_path = args.config.clone().or_else(|| std::env::var("CONFIG_FILE").ok());
+        let file = config_path.as_deref().map(|path| {
+            FileConfig::read(path).unwrap_or_else(|e| panic!("Invalid config file {}: {}", path, e))
+        });
+
+        let (file_web_auth, file_probe_modules, file_alerts, file_channels) =
+            file.map(|f| (f.web_auth, f.probe_modules, f.alerts, f.channels)).unwrap_or_default();
+        let web_auth = web_auth_settings(|key| std::env::var(key).ok(), file_web_auth);
+        web_auth.validate().unwrap_or_else(|e| panic!("Invalid web auth settings: {}", e));
+
+        let instance_defaults = InstanceDefaults {
+            required_connectors: split_list(&std::env::var("REQUIRED_CONNECTORS").unwrap_or_default()),
+            lite: env_or("LITE_MODE", false),
+            auth: global_auth(|key| std::env::var(key).ok()),
+            tls: global_tls(|key| std::env::var(key).ok()),
+            timeout_secs: env_secs("KAFKA_CONNECT_TIMEOUT_SECS"),
+            connect_timeout_secs: env_secs("KAFKA_CONNECT_CONNECT_TIMEOUT_SECS"),
+            connector_include_regex: std::env::var("CONNECTOR_INCLUDE_REGEX").ok().filter(|s| !s.is_empty()),
+            connector_exclude_regex: std::env::var("CONNECTOR_EXCLUDE_REGEX").ok().filter(|s| !s.is_empty()),
+        };
+
+        let extra_status_fields = split_list(&std::env::var("EXTRA_STATUS_FIELDS").unwrap_or_default());
+        for field in &extra_status_fields {
+            if RESERVED_LABELS.contains(&field.as_str()) || field == "tenant" || !is_valid_label_name(field) {
+                panic!("Invalid EXTRA_STATUS_FIELDS entry `{}`: not usable as a label name", field);
+            }
+        }
+
+        let probe_allowed_hosts = split_list(&std::env::var("PROBE_ALLOWED_HOSTS").unwrap_or_default());
+        let probe_modules = file_probe_modules
+            .into_iter()
+            .map(|(name, module)| Ok((name.clone(), module.instance(&name, &probe_allowed_hosts)?)))
+            .collect::<Result<_, String>>()
+            .unwrap_or_else(|e| panic!("Invalid config file {}: {}", config_path.as_deref().unwrap_or_default(), e));
+        let alerts = file_alerts.map(|alerts| {
+            alerts::Alerts::compile(alerts).unwrap_or_else(|e| {
+                panic!("Invalid config file {}: alerts.{}", config_path.as_deref().unwrap_or_default(), e)
+            })
+        });
+        let channels = channels::compile(file_channels).unwrap_or_else(|e| {
+            panic!("Invalid config file {}: channels{}", config_path.as_deref().unwrap_or_default(), e)
+        });
+
+        let otlp_metrics_protocol = match std::env::var("OTEL_EXPORTER_OTLP_METRICS_PROTOCOL") {
+            Ok(v) => otlp::Protocol::parse(&v).unwrap_or_else(|| {
+                panic!("Unsupported OTEL_EXPORTER_OTLP_METRICS_PROTOCOL {}: use http/json, http/protobuf or grpc", v)
+            }),
+            Err(_) => otlp::Protocol::default(),
+        };
+        let mut otel_resource = vec![(
+            "service.name".to_owned(),
+            std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "kafka-connect-exporter".into()),
+        )];
+        let resource_attributes = std::env::var("OTEL_RESOURCE_ATTRIBUTES").unwrap_or_default();
+        otel_resource.extend(otlp::resource_attributes(&resource_attributes).into_iter().filter(|(k, _)| k != "service.name"));
+
+        let pushgateway_url = std::env::var("PUSHGATEWAY_URL").ok().filter(|u| !u.is_empty());
+        let pushgateway_grouping: Vec<(String, String)> =
+            split_list(&std::env::var("PUSHGATEWAY_GROUPING").unwrap_or_default())
+                .into_iter()
+                .map(|entry| match entry.split_once('=') {
+                    Some((name, value))
+                        if is_valid_label_name(name.trim())
+                            && name.trim() != "job"
+                            && !RESERVED_LABELS.contains(&name.trim()) =>
+                    {
+                        (name.trim().to_owned(), value.trim().to_owned())
+                    }
+                    _ => panic!(
+                        "Invalid PUSHGATEWAY_GROUPING entry `{}`: expected label=value, not job or an exporter label",
+                        entry
+                    ),
+                })
+                .collect();
+        let extra_labels: Vec<(String, String)> = split_list(&std::env::var("EXTRA_LABELS").unwrap_or_default())
+            .into_iter()
+            .map(|entry| match entry.split_once('=') {
+                Some((name, value)) if is_valid_label_name(name.trim()) && !RESERVED_LABELS.contains(&name.trim()) => {
+                    (name.trim().to_owned(), value.trim().to_owned())
+                }
+                _ => panic!("Invalid EXTRA_LABELS entry `{}`: expected label=value, not an exporter label", entry),
+            })
             .collect();
+        let push_only = env_or("PUSH_ONLY", false);
+        if push_only && pushgateway_url.is_none() {
+            panic!("PUSH_ONLY is set but PUSHGATEWAY_URL is not");
+        }
+
+        let tenant_config_key = std::env::var("TENANT_CONFIG_KEY").ok().filter(|k| !k.is_empty());
 
         Self {
-            connect_urls,
-            bind_addr: std::env::var("BIND_ADDR")
-                .unwrap_or_else(|_| "0.0.0.0:9407".into()),
-            scrape_interval: Duration::from_secs(
-                std::env::var("SCRAPE_INTERVAL_SECS")
-                    .ok()
-                    .and_then(|s| s.parse().ok())
-                    .unwrap_or(30),
-            ),
+            config_path,
+            connect_urls: args.connect_urls.clone(),
+            ca_file: instance_defaults.tls.ca_file.clone(),
+            instance_defaults,
+            bind_addr: args.bind.clone().unwrap_or_else(|| {
+                std::env::var("METRICS_BIND_ADDR")
+                    .or_else(|_| std::env::var("BIND_ADDR"))
+                    .unwrap_or_else(|_| "0.0.0.0:9407".into())
+            }),
+            admin_bind_addr: std::env::var("ADMIN_BIND_ADDR").ok().filter(|a| !a.is_empty()),
+            web_auth: Some(web_auth).filter(|a| !a.is_empty()),
+            server_tls: match (std::env::var("TLS_CERT_FILE").ok(), std::env::var("TLS_KEY_FILE").ok()) {
+                (Some(cert), Some(key)) => Some((cert, key)),
+                (None, None) => None,
+                _ => panic!("Set both TLS_CERT_FILE and TLS_KEY_FILE, or neither"),
+            },
+            scrape_interval: args.interval.unwrap_or_else(|| Duration::from_secs(env_or("SCRAPE_INTERVAL_SECS", 30))),
+            selftest_timeout: Duration::from_secs(env_or("SELFTEST_TIMEOUT_SECS", 3)),
+            selftest_min_interval: Duration::from_secs(env_or("SELFTEST_MIN_INTERVAL_SECS", 10)),
+            health_stale_intervals: env_or("HEALTH_STALE_INTERVALS", 3),
+            shutdown_grace: Duration::from_secs(env_or("SHUTDOWN_GRACE_SECS", 10)),
+            request_retries: env_or("REQUEST_RETRIES", 2),
+            retry_backoff: Duration::from_millis(env_or("RETRY_BACKOFF_MS", 200)),
+            flap_window: Duration::from_secs(env_or("FLAP_WINDOW_SECS", 3600).max(1)),
+            cache_gzip: std::env::var("CACHE_COMPRESSION").is_ok_and(|v| v == "gzip"),
+            maintenance_mode: env_or("MAINTENANCE_MODE", false),
+            maintenance_suppress: env_or("MAINTENANCE_SUPPRESS", false),
+            admin_enabled: env_or("ADMIN_ENABLED", false),
+            discovery: Discovery::from_env(|key| std::env::var(key).ok())
+                .unwrap_or_else(|e| panic!("Invalid discovery settings: {}", e)),
+            scrape_jitter: env_or("SCRAPE_JITTER", false),
+            interval_jitter: match env_or("SCRAPE_INTERVAL_JITTER_PERCENT", 0u32) {
+                percent @ 0..=99 => percent as f64 / 100.0,
+                percent => panic!("Invalid SCRAPE_INTERVAL_JITTER_PERCENT {}: expected 0 to 99", percent),
+            },
+            initial_splay: env_secs("SCRAPE_INITIAL_SPLAY_SECS").map(Duration::from_secs),
+            scrape_on_demand: env_or("SCRAPE_ON_DEMAND", false)
+                .then(|| Duration::from_secs(env_or("SCRAPE_MIN_INTERVAL_SECS", 5))),
+            fail_if_all_down: env_or("FAIL_IF_ALL_DOWN", false),
+            remote_write_url: std::env::var("REMOTE_WRITE_URL").ok().filter(|u| !u.is_empty()),
+            remote_write_max_retries: env_or("REMOTE_WRITE_MAX_RETRIES", 3),
+            remote_write_auth: remote_write::Auth::from_env(|key| std::env::var(key).ok().filter(|v| !v.is_empty()))
+                .unwrap_or_else(|e| panic!("Invalid remote-write settings: {}", e)),
+            otlp_metrics_url: std::env::var("OTEL_EXPORTER_OTLP_METRICS_ENDPOINT").ok().filter(|u| !u.is_empty()),
+            otlp_metrics_protocol,
+            otel_resource,
+            dogstatsd: std::env::var("DOGSTATSD_URL").ok().filter(|u| !u.is_empty()).map(|url| {
+                statsd::Target::parse(&url).unwrap_or_else(|e| panic!("Invalid DOGSTATSD_URL {}", e))
+            }),
+            dogstatsd_tags: split_list(&std::env::var("DOGSTATSD_TAGS").unwrap_or_default()),
+            pushgateway_url,
+            pushgateway_job: std::env::var("PUSHGATEWAY_JOB")
+                .ok()
+                .filter(|j| !j.is_empty())
+                .unwrap_or_else(|| "kafka-connect-exporter".into()),
+            extra_labels,
+            pushgateway_grouping,
+            pushgateway_max_retries: env_or("PUSHGATEWAY_MAX_RETRIES", 3),
+            push_only,
+            emit_became_failed: env_or("EMIT_BECAME_FAILED", false),
+            cycle_budget: Some(Duration::from_secs(env_or("CYCLE_BUDGET_SECS", 0)))
+                .filter(|b| !b.is_zero()),
+            event_webhook_url: std::env::var("EVENT_WEBHOOK_URL").ok().filter(|u| !u.is_empty()),
+            event_webhook_max_retries: env_or("EVENT_WEBHOOK_MAX_RETRIES", 3),
+            scrape_connector_config: env_or("SCRAPE_CONNECTOR_CONFIG", false),
+            scrape_connector_topics: env_or("SCRAPE_CONNECTOR_TOPICS", false),
+            probe_allowed_hosts,
+            probe_modules,
+            alerts,
+            channels: Arc::new(channels),
+            heal_allowlist: split_list(&std::env::var("HEAL_ALLOWLIST").unwrap_or_default()),
+            heal_timeout: Duration::from_secs(env_or("HEAL_TIMEOUT_SECS", 120)),
+            heal_poll_interval: Duration::from_secs(env_or("HEAL_POLL_SECS", 5)),
+            heal_max_restarts: env_or("HEAL_MAX_RESTARTS", 3),
+            connector_actions: env_or("CONNECTOR_ACTIONS_ENABLED", false),
+            task_auto_restart: env_or("TASK_AUTO_RESTART", false),
+            task_restart_backoff: Duration::from_secs(env_or("TASK_RESTART_BACKOFF_SECS", 30).max(1)),
+            task_restart_max_backoff: Duration::from_secs(env_or("TASK_RESTART_MAX_BACKOFF_SECS", 600).max(1)),
+            task_restart_max_attempts: env_or("TASK_RESTART_MAX_ATTEMPTS", 5),
+            split_summary_by_type: env_or("SPLIT_SUMMARY_BY_TYPE", false),
+            down_policy: match std::env::var("DOWN_INSTANCE_POLICY").as_deref() {
+                Err(_) | Ok("absent") => DownPolicy::Absent,
+                Ok("zero") => DownPolicy::Zero,
+                Ok("last") => DownPolicy::Last,
+                Ok(other) => panic!("Invalid DOWN_INSTANCE_POLICY {}: expected absent, zero or last", other),
+            },
+            down_keep_cycles: env_or("DOWN_INSTANCE_KEEP_CYCLES", 0),
+            fetch_concurrency: env_or("CONNECTOR_FETCH_CONCURRENCY", 1usize).max(1),
+            concurrency_ramp_cycles: env_or("CONCURRENCY_RAMP_CYCLES", 0),
+            prioritize_failed: env_or("PRIORITIZE_FAILED", false),
+            failed_recheck: Some(Duration::from_secs(env_or("FAILED_RECHECK_SECS", 0)))
+                .filter(|d| !d.is_zero()),
+            instance_concurrency: env_or("INSTANCE_CONCURRENCY", 0),
+            tenant_config_key,
+            extra_status_fields,
+        }
+    }
+
+    /// Instances to scrape (see [`pick_instances`]), with the env defaults
+    /// filled in. Instances of `current` whose settings did not change are
+    /// reused as they are, with their HTTP client and OAuth token.
+    fn load_instances(&self, current: &[Instance]) -> Result<Vec<Instance>, String> {
+        let file_instances = match &self.config_path {
+            Some(path) => {
+                FileConfig::read(path).map_err(|e| format!("Invalid config file {}: {}", path, e))?.instances
+            }
+            None => Vec::new(),
+        };
+        let instances = pick_instances(&self.connect_urls, |key| std::env::var(key).ok(), file_instances);
+        self.prepare_instances(instances, current)
+    }
+
+    /// The workers `discovery` finds, as instances.
+    async fn discover_instances(&self, discovery: &Discovery, current: &[Instance]) -> Result<Vec<Instance>, String> {
+        let instances = discovery
+            .targets()
+            .await?
+            .into_iter()
+            .map(|target| Instance {
+                url: target.url,
+                name: target.name,
+                cluster: target.cluster,
+                ..Default::default()
+            })
+            .collect();
+        self.prepare_instances(instances, current)
+    }
+
+    /// `instances` with the env defaults applied and ready to scrape; those
+    /// unchanged from `current` are reused as they are.
+    fn prepare_instances(&self, mut instances: Vec<Instance>, current: &[Instance]) -> Result<Vec<Instance>, String> {
+        if self.tenant_config_key.is_some() {
+            if let Some(inst) = instances.iter().find(|i| i.labels.contains_key("tenant")) {
+                return Err(format!("instance {}: label `tenant` is reserved while TENANT_CONFIG_KEY is set", inst.url));
+            }
+        }
+        for inst in &mut instances {
+            self.instance_defaults.apply(inst);
+            if let Some(prev) = current.iter().find(|prev| prev.same_settings(inst)) {
+                *inst = prev.clone();
+                continue;
+            }
+            let name = format!("instance {}", inst.url);
+            inst.prepare(&name, reqwest::redirect::Policy::default())?;
         }
+        Ok(instances)
     }
 }
 
-// ── Kafka Connect API types ───────────────────────────────────────────────────
+/// REQUIRED_CONNECTORS, LITE_MODE, the global credentials, the global TLS
+/// files, the KAFKA_CONNECT_*TIMEOUT_SECS timeouts and the CONNECTOR_*_REGEX
+/// filters, for every instance without its own setting.
+struct InstanceDefaults {
+    required_connectors: Vec<String>,
+    lite: bool,
+    auth: Option<Credentials>,
+    tls: tls::ClientTls,
+    timeout_secs: Option<u64>,
+    connect_timeout_secs: Option<u64>,
+    connector_include_regex: Option<String>,
+    connector_exclude_regex: Option<String>,
+}
 
-#[derive(Deserialize, Debug)]
-struct ConnectorStatus {
-    connector: ConnectorInfo,
-    tasks: Vec<TaskInfo>,
+impl InstanceDefaults {
+    fn apply(&self, inst: &mut Instance) {
+        if inst.required_connectors.is_empty() {
+            inst.required_connectors = self.required_connectors.clone();
+        }
+        inst.lite.get_or_insert(self.lite);
+        if inst.auth.is_none() {
+            inst.auth = self.auth.clone();
+        }
+        inst.tls = Some(inst.tls.take().unwrap_or_default().or(&self.tls)).filter(|tls| !tls.is_empty());
+        inst.timeout_secs = inst.timeout_secs.or(self.timeout_secs);
+        inst.connect_timeout_secs = inst.connect_timeout_secs.or(self.connect_timeout_secs);
+        if inst.connector_include_regex.is_none() {
+            inst.connector_include_regex = self.connector_include_regex.clone();
+        }
+        if inst.connector_exclude_regex.is_none() {
+            inst.connector_exclude_regex = self.connector_exclude_regex.clone();
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
-struct ConnectorInfo {
-    state: String,
+/// Name filter of an instance: CONNECTOR_INCLUDE_REGEX and
+/// CONNECTOR_EXCLUDE_REGEX, or the instance's own pair. Both are anchored,
+/// so a pattern must match the whole connector name.
+#[derive(Clone, Debug, Default)]
+struct ConnectorFilter {
+    include: Option<regex_automata::meta::Regex>,
+    exclude: Option<regex_automata::meta::Regex>,
 }
 
-#[derive(Deserialize, Debug)]
-struct TaskInfo {
-    id: u32,
-    state: String,
+impl ConnectorFilter {
+    fn new(include: Option<&str>, exclude: Option<&str>) -> Result<Self, String> {
+        let compile = |key: &str, pattern: Option<&str>| {
+            let Some(p) = pattern else { return Ok(None) };
+            // Parsed on its own first, so the error points into the pattern as written;
+            // its last line is the reason
+            if let Err(e) = regex_automata::util::syntax::parse(p) {
+                let e = e.to_string();
+                return Err(format!("{key}: {}", e.lines().last().unwrap_or(&e).trim_start_matches("error: ")));
+            }
+            regex_automata::meta::Regex::new(&format!("^(?:{p})$")).map(Some).map_err(|e| format!("{key}: {e}"))
+        };
+        Ok(Self {
+            include: compile("connector_include_regex", include)?,
+            exclude: compile("connector_exclude_regex", exclude)?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.include.is_none() && self.exclude.is_none()
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.include.as_ref().is_none_or(|re| re.is_match(name))
+            && !self.exclude.as_ref().is_some_and(|re| re.is_match(name))
+    }
 }
 
-// ── Metrics cache ─────────────────────────────────────────────────────────────
+/// Instances from the strongest source that names any: `connect_urls`
+/// (--connect-url), then KAFKA_CONNECT_URLS and the indexed variables, then
+/// the config file's `file_instances`; `http://localhost:8083` when none does.
+fn pick_instances(
+    connect_urls: &[String],
+    var: impl Fn(&str) -> Option<String>,
+    file_instances: Vec<Instance>,
+) -> Vec<Instance> {
+    if !connect_urls.is_empty() {
+        return connect_urls.iter().map(|url| Instance { url: url.clone(), ..Default::default() }).collect();
+    }
+    let instances = env_instances(var);
+    if !instances.is_empty() {
+        return instances;
+    }
+    if !file_instances.is_empty() {
+        return file_instances;
+    }
+    vec![Instance { url: "http://localhost:8083".into(), ..Default::default() }]
+}
 
-type MetricsCache = Arc<RwLock<String>>;
+/// Instances from KAFKA_CONNECT_URLS, then the indexed variables.
+fn env_instances(var: impl Fn(&str) -> Option<String>) -> Vec<Instance> {
+    let urls = var("KAFKA_CONNECT_URLS").unwrap_or_default();
+    let mut instances: Vec<Instance> = urls
+        .split(',')
+        .map(|u| u.trim().trim_end_matches('/').to_owned())
+        .filter(|u| !u.is_empty())
+        .map(|url| Instance { url, ..Default::default() })
+        .collect();
+    instances.extend(indexed_instances(var));
+    instances
+}
 
-// ── Scraper ───────────────────────────────────────────────────────────────────
+/// The WEB_AUTH_* credentials when any is set, else the config file's
+/// `web_auth`.
+fn web_auth_settings(var: impl Fn(&str) -> Option<String>, file: Option<web_auth::WebAuth>) -> web_auth::WebAuth {
+    let env = web_auth::WebAuth {
+        username: var("WEB_AUTH_USERNAME"),
+        password: var("WEB_AUTH_PASSWORD"),
+        bearer_token: var("WEB_AUTH_BEARER_TOKEN"),
+    };
+    match file {
+        Some(file) if env.is_empty() => file,
+        _ => env,
+    }
+}
 
-async fn scrape_connect(client: &reqwest::Client, base_url: &str) -> String {
-    let instance = base_url
-        .trim_start_matches("http://")
-        .trim_start_matches("https://");
+/// Client certificate from KAFKA_CONNECT_CLIENT_CERT_FILE and
+/// KAFKA_CONNECT_CLIENT_KEY_FILE (both or neither), CA bundle from
+/// KAFKA_CONNECT_CA_FILE.
+fn global_tls(var: impl Fn(&str) -> Option<String>) -> tls::ClientTls {
+    let client_cert = var("KAFKA_CONNECT_CLIENT_CERT_FILE");
+    let client_key = var("KAFKA_CONNECT_CLIENT_KEY_FILE");
+    if client_cert.is_some() != client_key.is_some() {
+        panic!("Set both KAFKA_CONNECT_CLIENT_CERT_FILE and KAFKA_CONNECT_CLIENT_KEY_FILE, or neither");
+    }
+    tls::ClientTls { client_cert, client_key, ca_file: var("KAFKA_CONNECT_CA_FILE"), insecure_skip_verify: false }
+}
 
-    // Fetch connector list
-    let connector_names: Vec<String> = match client
-        .get(format!("{}/connectors?expand=status", base_url))
-        .send()
-        .await
-    {
-        Ok(r) => {
-            match r.json::<HashMap<String, serde_json::Value>>().await {
-                Ok(map) => map.into_keys().collect(),
-                Err(e) => {
-                    warn!("Failed to parse connectors from {}: {}", base_url, e);
-                    return format!(
-                        "kafka_connect_up{{instance=\"{instance}\"}} 0\n"
-                    );
-                }
-            }
+/// Instances from KAFKA_CONNECT_URL_0, KAFKA_CONNECT_URL_1, ..., each with
+/// optional KAFKA_CONNECT_USERNAME_<i> / KAFKA_CONNECT_PASSWORD_<i>. Stops at
+/// the first missing index.
+fn indexed_instances(var: impl Fn(&str) -> Option<String>) -> Vec<Instance> {
+    let mut instances = Vec::new();
+    for i in 0.. {
+        let Some(url) = var(&format!("KAFKA_CONNECT_URL_{i}")) else { break };
+        let username = var(&format!("KAFKA_CONNECT_USERNAME_{i}"));
+        let password = var(&format!("KAFKA_CONNECT_PASSWORD_{i}"));
+        let auth = match (username, password) {
+            (Some(username), password) => Some(Credentials::Basic(BasicAuth { username, password })),
+            (None, Some(_)) => panic!("KAFKA_CONNECT_PASSWORD_{i} is set without KAFKA_CONNECT_USERNAME_{i}"),
+            (None, None) => None,
+        };
+        let url = url.trim().trim_end_matches('/').to_owned();
+        instances.push(Instance { url, auth, ..Default::default() });
+    }
+    instances
+}
+
+/// Credentials for every instance without its own: basic auth
+/// (KAFKA_CONNECT_USERNAME), a static bearer token (KAFKA_CONNECT_BEARER_TOKEN)
+/// or OAuth client credentials (KAFKA_CONNECT_OAUTH_*). At most one kind.
+fn global_auth(var: impl Fn(&str) -> Option<String>) -> Option<Credentials> {
+    let basic = match (var("KAFKA_CONNECT_USERNAME"), var("KAFKA_CONNECT_PASSWORD")) {
+        (Some(username), password) => Some(Credentials::Basic(BasicAuth { username, password })),
+        (None, Some(_)) => panic!("KAFKA_CONNECT_PASSWORD is set without KAFKA_CONNECT_USERNAME"),
+        (None, None) => None,
+    };
+    let bearer = var("KAFKA_CONNECT_BEARER_TOKEN").map(|bearer_token| Credentials::Bearer(BearerAuth { bearer_token }));
+    let oauth = var("KAFKA_CONNECT_OAUTH_TOKEN_URL").map(|token_url| {
+        let required = |key: &str| var(key).unwrap_or_else(|| panic!("KAFKA_CONNECT_OAUTH_TOKEN_URL is set without {}", key));
+        Credentials::OAuth(oauth::OAuthClient::new(
+            token_url,
+            required("KAFKA_CONNECT_OAUTH_CLIENT_ID"),
+            required("KAFKA_CONNECT_OAUTH_CLIENT_SECRET"),
+            var("KAFKA_CONNECT_OAUTH_SCOPE"),
+        ))
+    });
+    let mut all = [basic, bearer, oauth].into_iter().flatten();
+    let auth = all.next();
+    if all.next().is_some() {
+        panic!("Set only one of KAFKA_CONNECT_USERNAME, KAFKA_CONNECT_BEARER_TOKEN and KAFKA_CONNECT_OAUTH_TOKEN_URL");
+    }
+    auth
+}
+
+/// Credentials of one instance; the config file `auth` key holds one of
+/// the three shapes.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum Credentials {
+    Basic(BasicAuth),
+    Bearer(BearerAuth),
+    OAuth(oauth::OAuthClient),
+}
+
+impl Credentials {
+    fn apply(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self {
+            Credentials::Basic(basic) => req.basic_auth(&basic.username, basic.password.as_ref()),
+            Credentials::Bearer(bearer) => req.bearer_auth(&bearer.bearer_token),
+            Credentials::OAuth(client) => client.apply(req),
         }
-        Err(e) => {
-            warn!("Cannot reach Kafka Connect at {}: {}", base_url, e);
-            return format!("kafka_connect_up{{instance=\"{instance}\"}} 0\n");
+    }
+}
+
+/// HTTP basic auth of one instance.
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BasicAuth {
+    username: String,
+    #[serde(default)]
+    password: Option<String>,
+}
+
+// Keep the password out of logs
+impl std::fmt::Debug for BasicAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BasicAuth").field("username", &self.username).finish_non_exhaustive()
+    }
+}
+
+/// Static `Authorization: Bearer` token of one instance.
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BearerAuth {
+    bearer_token: String,
+}
+
+impl std::fmt::Debug for BearerAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BearerAuth").finish_non_exhaustive()
+    }
+}
+
+/// One Kafka Connect REST endpoint to scrape.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Instance {
+    url: String,
+    /// Alias used as the `instance` label instead of the URL's host:port.
+    #[serde(default)]
+    name: Option<String>,
+    /// Extra labels attached to every series of this instance (e.g. a
+    /// blue-green `color`). Exporter-owned label names are rejected.
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    /// Connectors that must exist on this instance; reported as missing
+    /// from the first scrape on.
+    #[serde(default)]
+    required_connectors: Vec<String>,
+    /// Logical Connect cluster this worker URL belongs to. Instances sharing
+    /// a `cluster` are replicas of one cluster and are compared with each other.
+    #[serde(default)]
+    cluster: Option<String>,
+    /// Only list connectors, without per-connector status (LITE_MODE default).
+    #[serde(default)]
+    lite: Option<bool>,
+    /// Credentials sent with every request to this instance.
+    #[serde(default)]
+    auth: Option<Credentials>,
+    /// Per-request timeout, 10s by default.
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    /// Timeout for opening a connection (TCP and TLS handshake); only the
+    /// request timeout bounds it by default.
+    #[serde(default)]
+    connect_timeout_secs: Option<u64>,
+    /// Client certificate, CA bundle and verification settings.
+    #[serde(default)]
+    tls: Option<tls::ClientTls>,
+    /// Only connectors whose whole name matches are emitted and counted.
+    #[serde(default)]
+    connector_include_regex: Option<String>,
+    /// Connectors whose whole name matches are left out.
+    #[serde(default)]
+    connector_exclude_regex: Option<String>,
+    /// Compiled from the two regexes at startup.
+    #[serde(skip)]
+    connector_filter: ConnectorFilter,
+    /// Built from `tls` and `connect_timeout_secs` at startup; the shared
+    /// client is used without either.
+    #[serde(skip)]
+    client: Option<reqwest::Client>,
+}
+
+impl Instance {
+    fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or_else(|| instance_label(&self.url))
+    }
+
+    /// Same URL and settings; the HTTP client is not compared.
+    fn same_settings(&self, other: &Instance) -> bool {
+        self.url == other.url
+            && self.name == other.name
+            && self.labels == other.labels
+            && self.required_connectors == other.required_connectors
+            && self.cluster == other.cluster
+            && self.lite == other.lite
+            && self.auth == other.auth
+            && self.timeout_secs == other.timeout_secs
+            && self.connect_timeout_secs == other.connect_timeout_secs
+            && self.tls == other.tls
+            && self.connector_include_regex == other.connector_include_regex
+            && self.connector_exclude_regex == other.connector_exclude_regex
+    }
+
+    fn oauth(&self) -> Option<&oauth::OAuthClient> {
+        match &self.auth {
+            Some(Credentials::OAuth(client)) => Some(client),
+            _ => None,
         }
-    };
+    }
 
-    let total = connector_names.len();
-    let mut running = 0usize;
-    let mut failed = 0usize;
-    let mut lines = Vec::new();
+    fn timeout(&self) -> Duration {
+        self.timeout_secs.map_or(REQUEST_TIMEOUT, Duration::from_secs)
+    }
 
-    // Fetch status for each connector
-    for name in &connector_names {
-        let url = format!("{}/connectors/{}/status", base_url, name);
-        let status: ConnectorStatus = match client.get(&url).send().await {
-            Ok(r) => match r.json().await {
-                Ok(s) => s,
-                Err(e) => {
-                    warn!("Failed to parse status for {}: {}", name, e);
-                    continue;
-                }
-            },
-            Err(e) => {
-                warn!("Failed to fetch status for {}: {}", name, e);
-                continue;
+    /// Compile the connector filter and build the HTTP client the settings
+    /// call for, following the redirects `redirect` allows. `name` leads the
+    /// error messages.
+    fn prepare(&mut self, name: &str, redirect: reqwest::redirect::Policy) -> Result<(), String> {
+        self.connector_filter =
+            ConnectorFilter::new(self.connector_include_regex.as_deref(), self.connector_exclude_regex.as_deref())
+                .map_err(|e| format!("{}: {}", name, e))?;
+        if self.tls.is_some() || self.connect_timeout_secs.is_some() {
+            let tls = self.tls.clone().unwrap_or_default();
+            if tls.insecure_skip_verify {
+                warn!("{}: TLS certificate verification disabled (insecure_skip_verify)", name);
             }
-        };
+            let client = tls
+                .client(self.timeout(), self.connect_timeout_secs.map(Duration::from_secs), redirect)
+                .map_err(|e| format!("{}: invalid TLS settings: {}", name, e))?;
+            self.client = Some(client);
+        }
+        Ok(())
+    }
 
-        let c_state = status.connector.state.to_lowercase();
-        if c_state == "running" { running += 1; }
-        if c_state == "failed"  { failed  += 1; }
+    /// This instance's own HTTP client, or `shared`.
+    fn client<'a>(&'a self, shared: &'a reqwest::Client) -> &'a reqwest::Client {
+        self.client.as_ref().unwrap_or(shared)
+    }
 
-        // Emit state metrics as separate time series (one per state)
-        for state in &["running", "failed", "paused", "unassigned"] {
-            lines.push(format!(
-                "kafka_connect_connector_state{{connector=\"{name}\",state=\"{state}\",instance=\"{instance}\"}} {}",
-                if c_state == *state { 1 } else { 0 }
-            ));
+    /// `req` with this instance's credentials, if it has any.
+    fn authorize(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.auth {
+            Some(auth) => auth.apply(req),
+            None => req,
         }
+    }
 
-        // Task-level metrics
-        for task in &status.tasks {
-            let t_state = task.state.to_lowercase();
-            for state in &["running", "failed", "paused", "unassigned"] {
-                lines.push(format!(
-                    "kafka_connect_connector_task_state{{connector=\"{name}\",task=\"{}\",state=\"{state}\",instance=\"{instance}\"}} {}",
-                    task.id,
-                    if t_state == *state { 1 } else { 0 }
-                ));
-            }
+    /// `instance` followed by the per-instance labels, for every series of
+    /// the instance.
+    fn base_labels(&self) -> registry::Labels {
+        let mut out = registry::Labels::default().with("instance", self.label());
+        for (k, v) in &self.labels {
+            out.push(k, v);
         }
+        out
     }
+}
 
-    // Summary metrics
-    lines.push(format!("kafka_connect_up{{instance=\"{instance}\"}} 1"));
-    lines.push(format!("kafka_connect_connectors_total{{instance=\"{instance}\"}} {total}"));
-    lines.push(format!("kafka_connect_connectors_running{{instance=\"{instance}\"}} {running}"));
-    lines.push(format!("kafka_connect_connectors_failed{{instance=\"{instance}\"}} {failed}"));
+/// Label names the exporter sets itself; config labels may not shadow them.
+const RESERVED_LABELS: &[&str] = &["instance", "url", "connector", "task", "state"];
+
+/// JSON or TOML config file (`--config` or `CONFIG_FILE`).
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    #[serde(default)]
+    instances: Vec<Instance>,
+    #[serde(default)]
+    web_auth: Option<web_auth::WebAuth>,
+    #[serde(default)]
+    probe_modules: BTreeMap<String, ProbeModule>,
+    #[serde(default)]
+    alerts: Option<alerts::AlertsConfig>,
+    #[serde(default)]
+    channels: Vec<channels::ChannelConfig>,
+}
 
-    lines.join("\n")
+/// Settings GET /probe applies when asked for `?module=` by name; the
+/// connection settings of an instance, without the env defaults.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProbeModule {
+    #[serde(default)]
+    lite: Option<bool>,
+    #[serde(default)]
+    auth: Option<Credentials>,
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[serde(default)]
+    connect_timeout_secs: Option<u64>,
+    #[serde(default)]
+    tls: Option<tls::ClientTls>,
+    #[serde(default)]
+    connector_include_regex: Option<String>,
+    #[serde(default)]
+    connector_exclude_regex: Option<String>,
 }
 
-async fn scrape_all(client: reqwest::Client, urls: Vec<String>) -> String {
-    let mut all = Vec::new();
-    for url in &urls {
-        all.push(scrape_connect(&client, url).await);
+impl ProbeModule {
+    /// An instance template with these settings, its URL left to the probe.
+    /// Its own client, when it needs one, only follows redirects to `allowed`
+    /// hosts.
+    fn instance(self, name: &str, allowed: &[String]) -> Result<Instance, String> {
+        let mut inst = Instance {
+            lite: self.lite,
+            auth: self.auth,
+            timeout_secs: self.timeout_secs,
+            connect_timeout_secs: self.connect_timeout_secs,
+            tls: self.tls,
+            connector_include_regex: self.connector_include_regex,
+            connector_exclude_regex: self.connector_exclude_regex,
+            ..Default::default()
+        };
+        inst.prepare(&format!("probe module {}", name), probe_redirects(allowed.to_vec()))?;
+        Ok(inst)
     }
-    all.join("\n")
 }
 
-// ── Background scrape loop ────────────────────────────────────────────────────
+/// `path: msg` for a deserialization error, or just `msg` at the top level.
+fn key_error<E: std::fmt::Display>(e: &serde_path_to_error::Error<E>) -> String {
+    match e.path().to_string().as_str() {
+        "." => e.inner().to_string(),
+        key => format!("{}: {}", key, e.inner()),
+    }
+}
 
-async fn scrape_loop(
-    client: reqwest::Client,
-    urls: Vec<String>,
-    interval: Duration,
-    cache: MetricsCache,
-) {
-    loop {
-        let metrics = scrape_all(client.clone(), urls.clone()).await;
-        *cache.write().unwrap() = metrics;
-        tokio::time::sleep(interval).await;
+impl FileConfig {
+    // Errors name the offending key, e.g. `instances[1].tls: unknown field ...`
+    fn from_json(raw: &str) -> Result<Self, String> {
+        serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_str(raw)).map_err(|e| key_error(&e))
+    }
+
+    /// Read as the JSON the TOML stands for; errors also give the line of the key.
+    fn from_toml(raw: &str) -> Result<Self, String> {
+        Self::from_document(toml::parse(raw)?)
+    }
+
+    /// Read as the JSON the YAML stands for; errors also give the line of the key.
+    fn from_yaml(raw: &str) -> Result<Self, String> {
+        Self::from_document(yaml::parse(raw)?)
+    }
+
+    fn from_document(doc: toml::Document) -> Result<Self, String> {
+        serde_path_to_error::deserialize(doc.value.clone()).map_err(|e| {
+            match doc.line_of(&e.path().to_string()) {
+                Some(line) => format!("{} (line {})", key_error(&e), line),
+                None => key_error(&e),
+            }
+        })
+    }
+
+    fn read(path: &str) -> Result<Self, String> {
+        let raw = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut file = if path.ends_with(".toml") {
+            Self::from_toml(&raw)?
+        } else if [".yaml", ".yml"].iter().any(|ext| path.ends_with(ext)) {
+            Self::from_yaml(&raw)?
+        } else {
+            Self::from_json(&raw)?
+        };
+        for (i, inst) in file.instances.iter_mut().enumerate() {
+            inst.url = inst.url.trim().trim_end_matches('/').to_owned();
+            if inst.url.is_empty() {
+                return Err(format!("instances[{i}].url: must not be empty"));
+            }
+            if let Some(name) = &mut inst.name {
+                *name = name.trim().to_owned();
+                if name.is_empty() {
+                    return Err(format!("instances[{i}].name: must not be empty"));
+                }
+            }
+            for name in inst.labels.keys() {
+                if RESERVED_LABELS.contains(&name.as_str()) {
+                    return Err(format!("instances[{i}].labels.{name}: label `{name}` is reserved"));
+                }
+                if !is_valid_label_name(name) {
+                    return Err(format!("instances[{i}].labels.{name}: invalid label name"));
+                }
+            }
+            if inst.timeout_secs == Some(0) {
+                return Err(format!("instances[{i}].timeout_secs: must be at least 1"));
+            }
+            if inst.connect_timeout_secs == Some(0) {
+                return Err(format!("instances[{i}].connect_timeout_secs: must be at least 1"));
+            }
+        }
+        // Two instances under one `instance` label would merge their series
+        for (i, inst) in file.instances.iter().enumerate() {
+            let Some(name) = &inst.name else { continue };
+            if let Some(j) = (0..file.instances.len()).find(|&j| j != i && file.instances[j].label() == name) {
+                return Err(format!("instances[{i}].name: `{name}` is also the instance label of instances[{j}]"));
+            }
+        }
+        for (name, module) in &file.probe_modules {
+            if module.timeout_secs == Some(0) {
+                return Err(format!("probe_modules.{name}.timeout_secs: must be at least 1"));
+            }
+            if module.connect_timeout_secs == Some(0) {
+                return Err(format!("probe_modules.{name}.connect_timeout_secs: must be at least 1"));
+            }
+        }
+        if let Some(auth) = &file.web_auth {
+            auth.validate().map_err(|e| format!("web_auth: {}", e))?;
+        }
+        Ok(file)
     }
 }
 
-// ── HTTP handlers ─────────────────────────────────────────────────────────────
+fn is_valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with("__")
+}
+
+/// Add `labels` (EXTRA_LABELS) to every sample. A label the sample already
+/// has keeps its own value.
+fn add_extra_labels(samples: &mut [registry::Sample], labels: &[(String, String)]) {
+    for sample in samples {
+        for (name, value) in labels {
+            if sample.labels.get(name).is_none() {
+                sample.labels.push(name, value);
+            }
+        }
+    }
+}
 
-async fn metrics_handler(
-    axum::extract::State(cache): axum::extract::State<MetricsCache>,
-) -> String {
-    cache.read().unwrap().clone()
+/// Split a comma-separated env value, dropping empty entries.
+fn split_list(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .collect()
 }
 
-async fn health_handler() -> &'static str { "ok" }
+/// Parse an env var, falling back to `default` when unset or malformed.
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(default)
+}
 
-// ── Main ──────────────────────────────────────────────────────────────────────
+/// Whole seconds from `key`; unset when missing, invalid or 0.
+fn env_secs(key: &str) -> Option<u64> {
+    Some(env_or(key, 0)).filter(|&secs| secs > 0)
+}
 
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive("kafka_connect_exporter=info".parse().unwrap()),
-        )
-        .init();
+// ── Kafka Connect API types ───────────────────────────────────────────────────
 
-    let config = Config::from_env();
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(10))
-        .build()
-        .expect("Failed to build HTTP client");
+#[derive(Deserialize, Debug, Clone)]
+struct ConnectorStatus {
+    #[serde(default)]
+    name: Option<String>,
+    /// `source` or `sink`; missing on some older workers.
+    #[serde(default, rename = "type")]
+    connector_type: Option<String>,
+    connector: ConnectorInfo,
+    tasks: Vec<TaskInfo>,
+    /// Fields this struct does not model, for EXTRA_STATUS_FIELDS.
+    #[serde(flatten)]
+    extra: HashMap<String, serde_json::Value>,
+}
 
-    let cache: MetricsCache = Arc::new(RwLock::new(String::new()));
+#[derive(Deserialize, Debug, Clone)]
+struct ConnectorInfo {
+    state: String,
+    #[serde(default)]
+    worker_id: Option<String>,
+    /// Stack trace of the failure; only set while FAILED.
+    #[serde(default)]
+    trace: Option<String>,
+    #[serde(flatten)]
+    extra: HashMap<String, serde_json::Value>,
+}
 
-    // Initial scrape before starting server
-    {
-        let metrics = scrape_all(client.clone(), config.connect_urls.clone()).await;
-        *cache.write().unwrap() = metrics;
-    }
+/// Entry of `GET /connectors/{name}/topics`, keyed by connector name.
+#[derive(Deserialize, Debug)]
+struct ActiveTopics {
+    #[serde(default)]
+    topics: Vec<String>,
+}
 
-    // Background scrape loop
-    tokio::spawn(scrape_loop(
-        client,
-        config.connect_urls.clone(),
-        config.scrape_interval,
-        cache.clone(),
-    ));
+/// Entry of `GET /connector-plugins`, as installed on the answering worker.
+#[derive(Deserialize, Debug, Clone)]
+struct PluginInfo {
+    class: String,
+    /// `source` or `sink`.
+    #[serde(default, rename = "type")]
+    plugin_type: String,
+    #[serde(default)]
+    version: String,
+}
 
-    let app = Router::new()
-        .route("/metrics", get(metrics_handler))
-        .route("/health", get(health_handler))
-        .with_state(cache);
+#[derive(Deserialize, Debug, Clone)]
+struct TaskInfo {
+    id: u32,
+    state: String,
+    #[serde(default)]
+    worker_id: Option<String>,
+    /// Stack trace of the failure; only set while FAILED.
+    #[serde(default)]
+    trace: Option<String>,
+    #[serde(flatten)]
+    extra: HashMap<String, serde_json::Value>,
+}
 
-    info!(
-        "kafka-connect-exporter listening on http://{} scraping: {:?}",
-        config.bind_addr, config.connect_urls
-    );
+/// Exception class a trace starts with (`org.apache.kafka.connect.errors.ConnectException`
+/// of `org.apache...ConnectException: message`), cut to 200 characters and
+/// stripped of anything a class name cannot hold.
+fn exception_class(trace: &str) -> Option<String> {
+    let head = trace.lines().next()?.split(':').next()?.trim();
+    let class: String = head
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '$'))
+        .take(200)
+        .collect();
+    (!class.is_empty()).then_some(class)
+}
 
-    let listener = tokio::net::TcpListener::bind(&config.bind_addr)
-        .await
-        .unwrap_or_else(|e| panic!("Failed to bind {}: {}", config.bind_addr, e));
+/// Connector and task states Connect documents, in series order.
+const CONNECTOR_STATES: &[&str] = &["running", "failed", "paused", "unassigned", "restarting", "stopped", "destroyed"];
+
+/// Which of the `emitted` state series is 1 for the lowercased `state`: its
+/// own, or `unknown` for a state Connect does not document or this worker's
+/// version leaves out. None for FAILED while outages are suppressed.
+fn state_series<'a>(state: &str, emitted: &[&'a str], suppress_outages: bool) -> Option<&'a str> {
+    match emitted.iter().copied().find(|s| *s == state) {
+        Some(s) => Some(s),
+        None if suppress_outages && state == "failed" => None,
+        None => Some("unknown"),
+    }
+}
+
+/// A label for every EXTRA_STATUS_FIELDS entry, taken from the first of
+/// `sources` that has it; empty when none does.
+fn extra_labels(fields: &[String], sources: &[&HashMap<String, serde_json::Value>]) -> registry::Labels {
+    let mut out = registry::Labels::default();
+    for field in fields {
+        let value = match sources.iter().find_map(|s| s.get(field)) {
+            Some(serde_json::Value::String(v)) => v.clone(),
+            Some(v) => v.to_string(),
+            None => String::new(),
+        };
+        out.push(field, &value);
+    }
+    out
+}
 
-    axum::serve(listener, app).await.unwrap();
+// ── Cross-scrape state ────────────────────────────────────────────────────────
+
+/// Observed state changes of one connector, as (from, to) counts.
+#[derive(Clone, Default)]
+struct Transitions {
+    connector: BTreeMap<(String, String), u64>,
+    tasks: BTreeMap<(u32, String, String), u64>,
+    /// Lowercased task states from the previous cycle, by task id.
+    task_states: HashMap<u32, String>,
+    /// When the connector or a task last entered or left FAILED, oldest
+    /// first, within the flap window.
+    flaps: VecDeque<Instant>,
+}
+
+impl Transitions {
+    /// Entries or exits of FAILED per hour over the last `window`.
+    fn flap_score(&mut self, window: Duration) -> f64 {
+        while self.flaps.front().is_some_and(|t| t.elapsed() > window) {
+            self.flaps.pop_front();
+        }
+        self.flaps.len() as f64 * 3600.0 / window.as_secs_f64()
+    }
+}
+
+/// What the scraper remembers about one instance between cycles.
+#[derive(Clone, Default)]
+struct InstanceState {
+    /// Whether the last scrape reached the instance.
+    up: bool,
+    /// Worker each task ran on in the previous cycle, by (connector, task id).
+    task_workers: HashMap<(String, u32), String>,
+    /// Tasks seen on a different worker than in the previous cycle.
+    task_reassignments: u64,
+    /// Plugins installed on this worker, for the skew check of grouped
+    /// instances and the plugin version of each connector.
+    plugins: Vec<PluginInfo>,
+    /// `connector.class` by connector name.
+    connector_classes: HashMap<String, String>,
+    /// Last fetched active topics by connector name (SCRAPE_CONNECTOR_TOPICS).
+    connector_topics: HashMap<String, Vec<String>>,
+    /// Lowercased connector state from the previous cycle, by connector name.
+    connector_states: HashMap<String, String>,
+    /// State changes of each connector and its tasks since startup.
+    transitions: HashMap<String, Transitions>,
+    /// (config hash, local version) by connector name; the version goes up
+    /// by one each time the hash changes.
+    config_versions: HashMap<String, (u64, u64)>,
+    /// Summary gauges of the last successful scrape, for DOWN_INSTANCE_POLICY.
+    last_summary: Vec<registry::Sample>,
+    /// Per-connector samples of the last successful scrape, for DOWN_INSTANCE_KEEP_CYCLES.
+    last_series: Vec<registry::Sample>,
+    /// Consecutive cycles the instance was down.
+    down_cycles: u32,
+    /// Cumulative _sum and _count of kafka_connect_instance_request_latency_seconds.
+    latency_sum: f64,
+    latency_count: u64,
+    /// Consecutive cycles the instance was up, for the concurrency ramp.
+    warm_cycles: u32,
+    /// Connector and task states of the previous cycle, by connector name.
+    fingerprints: HashMap<String, String>,
+    /// Series of the connectors that changed in the last cycle (/metrics/delta).
+    delta: Vec<registry::Sample>,
+    /// (task count, `tasks.max` when the config was fetched) of the previous
+    /// cycle, by connector name.
+    task_counts: HashMap<String, (usize, Option<u64>)>,
+    /// Last status of each connector, with the ETag of the response if it
+    /// had one; reused on `304 Not Modified`, and while a rebalance keeps
+    /// answering `409`.
+    last_statuses: HashMap<String, (Option<String>, ConnectorStatus)>,
+    /// Status calls answered with `304 Not Modified`.
+    status_not_modified: u64,
+    /// TENANT_CONFIG_KEY value from the last config fetch, by connector name.
+    tenants: HashMap<String, String>,
+    /// When each currently FAILED connector was first seen FAILED.
+    failed_since: HashMap<String, Instant>,
+    /// Read from `GET /` once the instance is up, again after each outage.
+    version: Option<version::ConnectVersion>,
+    /// The `GET /` answer `version` was parsed from.
+    root: Option<version::RootInfo>,
+    /// HTTP requests made to the instance since startup.
+    requests_made: u64,
+    /// Failed requests since startup, by reason (see ERROR_REASONS).
+    scrape_errors: HashMap<&'static str, u64>,
+    /// How long the last scrape of the instance took.
+    scrape_duration: Duration,
+    /// When the connector list was last fetched.
+    last_success: Option<SystemTime>,
+    /// Series of the last scrape, as published.
+    output: Vec<registry::Sample>,
+}
+
+/// `reason` values of kafka_connect_exporter_scrape_errors_total.
+const ERROR_REASONS: [&str; 4] = ["connect", "timeout", "http", "decode"];
+
+/// Reason a failed request or body read counts under. An error status
+/// (`http`) is told apart by the caller, before the body is read.
+fn error_reason(e: &reqwest::Error) -> &'static str {
+    if e.is_timeout() {
+        "timeout"
+    } else if e.is_decode() {
+        "decode"
+    } else {
+        "connect"
+    }
+}
+
+impl InstanceState {
+    /// Count one cycle's requests and failures.
+    fn record_requests(&mut self, requests: usize, errors: &[&'static str]) {
+        self.requests_made += requests as u64;
+        for reason in errors {
+            *self.scrape_errors.entry(reason).or_default() += 1;
+        }
+    }
+
+    /// The exporter's own series for this instance.
+    fn self_lines(&self, base: &registry::Labels) -> Vec<registry::Sample> {
+        let mut lines = vec![
+            registry::gauge("kafka_connect_exporter_scrape_duration_seconds")
+                .with(base)
+                .set(self.scrape_duration.as_millis() as f64 / 1000.0),
+            registry::counter("kafka_connect_exporter_http_requests_made_total").with(base).total(self.requests_made),
+            // 0 until the first success, so `time() - …` alerts cover it too
+            registry::gauge("kafka_connect_last_successful_scrape_timestamp_seconds").with(base).set(
+                self.last_success
+                    .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                    .map_or(0, |d| d.as_secs()),
+            ),
+        ];
+        for reason in ERROR_REASONS {
+            lines.push(
+                registry::counter("kafka_connect_exporter_scrape_errors_total")
+                    .with(base)
+                    .label("reason", reason)
+                    .total(self.scrape_errors.get(reason).copied().unwrap_or(0)),
+            );
+        }
+        lines
+    }
+}
+
+/// Per-instance state, keyed by instance URL.
+type ScrapeState = HashMap<String, InstanceState>;
+
+// ── Metrics cache ─────────────────────────────────────────────────────────────
+
+/// Last scrape output. With CACHE_COMPRESSION=gzip the exposition is held
+/// compressed, trading a little CPU per plain-text request for memory on
+/// large fleets; gzip-accepting scrapers get the stored bytes as-is.
+/// Either way the bytes are shared with the responses, never copied.
+#[derive(Clone)]
+enum CachedMetrics {
+    Plain(Bytes),
+    Gzip(Bytes),
+}
+
+impl CachedMetrics {
+    fn len(&self) -> usize {
+        match self {
+            CachedMetrics::Plain(s) => s.len(),
+            CachedMetrics::Gzip(b) => b.len(),
+        }
+    }
+
+    /// The exposition in the text format, decompressed when held as gzip.
+    fn text(&self) -> Result<Bytes, String> {
+        match self {
+            CachedMetrics::Plain(text) => Ok(text.clone()),
+            CachedMetrics::Gzip(gz) => gzip::decompress(gz).map(Bytes::from).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+type MetricsCache = Arc<RwLock<CachedMetrics>>;
+
+/// Instance, connector name and task id.
+type TaskKey = (String, String, u32);
+
+/// Encode the samples of a fresh exposition and replace the cache contents
+/// with them.
+fn store_metrics(cache: &MetricsCache, samples: &[registry::Sample], compress: bool, labels: &[(String, String)]) {
+    // Self-metric for the cache footprint; it lags one cycle since the
+    // compressed size is only known after encoding.
+    let prev_bytes = cache.read().unwrap().len();
+    let mut size = [registry::gauge("kafka_connect_exporter_cache_bytes")
+        .label("compression", if compress { "gzip" } else { "none" })
+        .set(prev_bytes)];
+    add_extra_labels(&mut size, labels);
+    // A family of its own, so it can follow the rest as encoded
+    let mut metrics = registry::encode(samples);
+    metrics.push_str(&registry::encode(&size));
+
+    let entry = if compress {
+        let gz = gzip::compress(metrics.as_bytes());
+        debug!(
+            "metrics cache: {} bytes plain, {} bytes gzip ({:.1}x)",
+            metrics.len(),
+            gz.len(),
+            metrics.len() as f64 / gz.len().max(1) as f64
+        );
+        CachedMetrics::Gzip(Bytes::from(gz))
+    } else {
+        CachedMetrics::Plain(Bytes::from(metrics))
+    };
+    *cache.write().unwrap() = entry;
+}
+
+// ── App state ─────────────────────────────────────────────────────────────────
+
+#[derive(Clone)]
+struct AppState {
+    cache: MetricsCache,
+    /// Unix time in nanoseconds the cache was last filled.
+    published: Arc<AtomicU64>,
+    /// The samples the cache was last filled from, for /metrics/influx.
+    samples: Arc<RwLock<registry::Snapshot>>,
+    config: Arc<Config>,
+    /// Instances being scraped; replaced as a whole on config reload.
+    instances: Arc<RwLock<Arc<Vec<Instance>>>>,
+    client: reqwest::Client,
+    /// The shared client for /probe, whose redirects must stay on
+    /// PROBE_ALLOWED_HOSTS.
+    probe_client: reqwest::Client,
+    /// When the last /selftest probe ran; used to rate-limit active probing.
+    last_selftest: Arc<Mutex<Option<Instant>>>,
+    /// Global maintenance mode, toggled at runtime via /admin/maintenance.
+    maintenance: Arc<AtomicBool>,
+    /// Wakes every instance's scrape ahead of its interval.
+    rescrape: Arc<watch::Sender<()>>,
+    /// Each instance's state and series as of its last scrape, by URL.
+    scraped: Arc<Mutex<ScrapeState>>,
+    /// Wakes the scrape loop to publish a scrape that landed.
+    landed: Arc<Notify>,
+    /// When the last full scrape cycle finished, for SCRAPE_ON_DEMAND.
+    cycle_done: Arc<watch::Sender<Instant>>,
+    /// Latest samples for the remote-write task, when enabled.
+    remote_write: Option<Arc<watch::Sender<registry::Snapshot>>>,
+    remote_write_failures: Arc<AtomicU64>,
+    /// Latest samples for the OTLP metrics task, when enabled.
+    otlp_metrics: Option<Arc<watch::Sender<registry::Snapshot>>>,
+    otlp_metrics_failures: Arc<AtomicU64>,
+    /// Latest samples for the DogStatsD task, when enabled.
+    dogstatsd: Option<Arc<watch::Sender<registry::Snapshot>>>,
+    dogstatsd_failures: Arc<AtomicU64>,
+    /// Latest samples for the Pushgateway task, when enabled.
+    pushgateway: Option<Arc<watch::Sender<registry::Snapshot>>>,
+    pushgateway_failures: Arc<AtomicU64>,
+    /// Connector transition events for the webhook task, when enabled.
+    events: Option<webhook::EventSink>,
+    /// Alert rules of the config file, when set.
+    alerts: Option<Arc<alerts::Engine>>,
+    /// Finished heal requests, by (connector, result).
+    heal_attempts: Arc<Mutex<BTreeMap<(String, &'static str), u64>>>,
+    /// Heals in progress, by instance URL and connector name.
+    healing: Arc<Mutex<HashSet<(String, String)>>>,
+    /// Automatic restarts of FAILED tasks, by instance URL, connector and task.
+    task_restarts: Arc<Mutex<HashMap<TaskKey, TaskRestarts>>>,
+    /// Finished automatic task restarts, by (instance, connector, task, result).
+    task_restarts_total: Arc<Mutex<BTreeMap<(TaskKey, &'static str), u64>>>,
+    /// Series of connectors that changed in the last cycle of their instance.
+    delta: Arc<RwLock<String>>,
+    /// Outcome of the latest scrapes, for /health.
+    health: Arc<RwLock<ScrapeHealth>>,
+    /// Failure traces from the latest statuses, by connector name.
+    errors: Arc<RwLock<BTreeMap<String, Vec<ConnectorErrors>>>>,
+    /// Latest statuses of every instance, for /api/v1/connectors.
+    connectors: Arc<RwLock<Vec<InstanceConnectors>>>,
+    /// Turns true on SIGTERM: the scrape loop stops and the listeners drain.
+    shutdown: watch::Receiver<bool>,
+}
+
+#[derive(Default)]
+struct ScrapeHealth {
+    /// When a scrape last refreshed the cache.
+    updated: Option<Instant>,
+    instances: usize,
+    /// Labels of the instances their last scrape could not reach.
+    down: Vec<String>,
+}
+
+/// Traces of a connector that has failed parts on one instance.
+#[derive(Clone, Serialize)]
+struct ConnectorErrors {
+    instance: String,
+    state: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace: Option<String>,
+    tasks: Vec<TaskError>,
+}
+
+#[derive(Clone, Serialize)]
+struct TaskError {
+    id: u32,
+    state: String,
+    worker_id: Option<String>,
+    exception_class: Option<String>,
+    trace: String,
+}
+
+/// Connectors of one instance as of its last scrape.
+#[derive(Clone, Serialize)]
+struct InstanceConnectors {
+    instance: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cluster: Option<String>,
+    up: bool,
+    /// Unix time of the last scrape that reached the instance.
+    last_scrape_time: Option<u64>,
+    connectors: Vec<ConnectorSummary>,
+}
+
+#[derive(Clone, Serialize)]
+struct ConnectorSummary {
+    name: String,
+    #[serde(rename = "type")]
+    connector_type: Option<String>,
+    state: String,
+    worker_id: Option<String>,
+    tasks: Vec<TaskSummary>,
+}
+
+#[derive(Clone, Serialize)]
+struct TaskSummary {
+    id: u32,
+    state: String,
+    worker_id: Option<String>,
+}
+
+impl AppState {
+    fn instances(&self) -> Arc<Vec<Instance>> {
+        self.instances.read().unwrap().clone()
+    }
+}
+
+// ── Scraper ───────────────────────────────────────────────────────────────────
+
+/// `instance` label value for a Connect base URL (scheme stripped).
+fn instance_label(base_url: &str) -> &str {
+    base_url
+        .trim_start_matches("http://")
+        .trim_start_matches("https://")
+}
+
+/// Timeout of a single Kafka Connect request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest wait before a retry, however many came before.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Samples kept per instance and cycle for the latency quantiles.
+const LATENCY_RESERVOIR: usize = 1024;
+const LATENCY_QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+/// Uniform sample (Algorithm R) of one cycle's list and status latencies;
+/// memory stays bounded however many connectors an instance has.
+#[derive(Default)]
+struct LatencyReservoir {
+    samples: Vec<f64>,
+    seen: usize,
+    sum: f64,
+}
+
+impl LatencyReservoir {
+    fn record(&mut self, secs: f64) {
+        self.seen += 1;
+        self.sum += secs;
+        if self.samples.len() < LATENCY_RESERVOIR {
+            self.samples.push(secs);
+        } else {
+            let slot = rand::rng().random_range(0..self.seen);
+            if slot < LATENCY_RESERVOIR {
+                self.samples[slot] = secs;
+            }
+        }
+    }
+
+    /// `kafka_connect_instance_request_latency_seconds` summary lines;
+    /// quantiles cover this cycle, _sum/_count accumulate in `history`.
+    fn lines(mut self, history: &mut InstanceState, base: &registry::Labels) -> Vec<registry::Sample> {
+        history.latency_sum += self.sum;
+        history.latency_count += self.seen as u64;
+        let summary = registry::summary("kafka_connect_instance_request_latency_seconds").with(base);
+        let mut lines = Vec::new();
+        self.samples.sort_by(f64::total_cmp);
+        if !self.samples.is_empty() {
+            for q in LATENCY_QUANTILES {
+                // Nearest rank
+                let rank = ((q * self.samples.len() as f64).ceil() as usize).max(1) - 1;
+                lines.push(summary.quantile(q, self.samples[rank]));
+            }
+        }
+        lines.push(summary.sum(history.latency_sum));
+        lines.push(summary.count(history.latency_count));
+        lines
+    }
+}
+
+/// Per-cycle knobs of `scrape_connect`.
+#[derive(Clone, Copy)]
+struct ScrapeOptions<'a> {
+    /// Maintenance with suppression: leave out `up 0` and FAILED series.
+    suppress_outages: bool,
+    /// Emit kafka_connect_connector_became_failed.
+    emit_edges: bool,
+    /// Fetch /connectors/{name}/config for kafka_connect_connector_config_version.
+    fetch_config: bool,
+    /// Fetch the class of new connectors and the plugin list every cycle,
+    /// for kafka_connect_connector_info and kafka_connect_plugin_info.
+    describe: bool,
+    /// Fetch /connectors/{name}/topics for kafka_connect_connector_topic.
+    fetch_topics: bool,
+    /// Emit the running/failed summaries per connector type.
+    split_by_type: bool,
+    down_policy: DownPolicy,
+    /// Failed cycles the last per-connector series are repeated for.
+    keep_cycles: u32,
+    /// Per-connector calls in flight, after the ramp.
+    concurrency: usize,
+    /// Fetch connectors that were FAILED last cycle first.
+    prioritize_failed: bool,
+    /// End of the cycle budget; no request is started or kept running past it.
+    deadline: Option<tokio::time::Instant>,
+    /// Where FAILED transitions are reported, when the webhook is enabled.
+    events: Option<&'a webhook::EventSink>,
+    /// Credentials of the instance being scraped.
+    auth: Option<&'a Credentials>,
+    /// Per-request timeout of the instance being scraped.
+    timeout: Duration,
+    /// Config property to label connector series with as `tenant`.
+    tenant_key: Option<&'a str>,
+    /// Extra status fields to add as labels to the state series.
+    extra_fields: &'a [String],
+    /// Retries of a `409` or `5xx` answer (REQUEST_RETRIES).
+    retries: u32,
+    /// First retry delay, doubled on each further one (RETRY_BACKOFF_MS).
+    retry_backoff: Duration,
+    /// Window the flap score counts transitions over (FLAP_WINDOW_SECS).
+    flap_window: Duration,
+}
+
+impl ScrapeOptions<'_> {
+    fn over_budget(&self) -> bool {
+        self.deadline.is_some_and(|d| tokio::time::Instant::now() >= d)
+    }
+
+    /// Whether a down instance can serve repeated values, which
+    /// kafka_connect_data_stale then tells apart from fresh ones.
+    fn marks_stale(&self) -> bool {
+        self.down_policy == DownPolicy::Last || self.keep_cycles > 0
+    }
+
+    /// GET `url`, retrying `409` (a rebalance in progress) and `5xx` answers
+    /// with exponential backoff and full jitter. Returns the last result and
+    /// the number of requests made. No retry waits past the cycle budget.
+    async fn send_with(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        prepare: impl Fn(reqwest::RequestBuilder) -> reqwest::RequestBuilder,
+    ) -> (reqwest::Result<reqwest::Response>, usize) {
+        let mut attempt = 0;
+        loop {
+            let result = prepare(self.get(client, url.to_owned())).send().await;
+            let transient = result
+                .as_ref()
+                .is_ok_and(|r| r.status() == reqwest::StatusCode::CONFLICT || r.status().is_server_error());
+            if !transient || attempt == self.retries {
+                return (result, attempt as usize + 1);
+            }
+            let ceiling = self.retry_backoff.saturating_mul(1 << attempt.min(16)).min(MAX_RETRY_DELAY);
+            let delay = ceiling.mul_f64(rand::rng().random_range(0.0..=1.0));
+            if self.deadline.is_some_and(|d| tokio::time::Instant::now() + delay >= d) {
+                return (result, attempt as usize + 1);
+            }
+            debug!("{} answered {}, retrying in {:?}", url, result.as_ref().map_or(0, |r| r.status().as_u16()), delay);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn send(&self, client: &reqwest::Client, url: &str) -> (reqwest::Result<reqwest::Response>, usize) {
+        self.send_with(client, url, |req| req).await
+    }
+
+    /// GET whose timeout is cut to what is left of the cycle budget.
+    fn get(&self, client: &reqwest::Client, url: String) -> reqwest::RequestBuilder {
+        let timeout = match self.deadline {
+            Some(d) => d.saturating_duration_since(tokio::time::Instant::now()).min(self.timeout),
+            None => self.timeout,
+        };
+        let req = client.get(url).timeout(timeout);
+        match self.auth {
+            Some(auth) => auth.apply(req),
+            None => req,
+        }
+    }
+}
+
+async fn scrape_connect(
+    client: &reqwest::Client,
+    inst: &Instance,
+    history: &mut InstanceState,
+    opts: ScrapeOptions<'_>,
+) -> Vec<registry::Sample> {
+    let base_url = inst.url.as_str();
+    let base = inst.base_labels();
+    let suppress_outages = opts.suppress_outages;
+    history.delta.clear();
+
+    // Budget spent before this instance's turn: report it incomplete and
+    // leave its cross-scrape state untouched
+    if opts.over_budget() {
+        warn!("Cycle budget exhausted, skipping {}", base_url);
+        return vec![registry::gauge("kafka_connect_scrape_complete").with(&base).set(false)];
+    }
+
+    let resolved = resolved_addr_lines(base_url, &base).await;
+    // In maintenance with suppression, down instances and FAILED series are
+    // left out entirely so outage alerts resolve on their own.
+    let down = |history: &mut InstanceState| {
+        history.down_cycles = history.down_cycles.saturating_add(1);
+        if suppress_outages {
+            return resolved.clone();
+        }
+        let mut out = resolved.clone();
+        out.push(registry::gauge("kafka_connect_up").with(&base).set(false));
+        // The list call was the only request and it failed
+        out.push(registry::gauge("kafka_connect_scrape_error_ratio").with(&base).set(1.0));
+        match opts.down_policy {
+            DownPolicy::Absent => {}
+            DownPolicy::Zero if history.last_summary.is_empty() => {
+                for name in [
+                    "kafka_connect_connectors_total",
+                    "kafka_connect_connectors_scraped",
+                    "kafka_connect_connectors_running",
+                    "kafka_connect_connectors_failed",
+                ] {
+                    out.push(registry::gauge(name).with(&base).set(0_usize));
+                }
+            }
+            DownPolicy::Zero => out.extend(history.last_summary.iter().map(|sample| sample.clone().with_value(0.0))),
+            DownPolicy::Last => out.extend(history.last_summary.iter().cloned()),
+        }
+        let keep = history.down_cycles <= opts.keep_cycles && !history.last_series.is_empty();
+        if keep {
+            out.extend(history.last_series.iter().cloned());
+        }
+        if opts.down_policy == DownPolicy::Last || keep {
+            out.push(registry::gauge("kafka_connect_data_stale").with(&base).set(true));
+        }
+        out
+    };
+    // Fetch connector list. Lite mode asks for the bare name array, as do
+    // workers known to predate ?expand=status.
+    let lite = inst.lite == Some(true);
+    let list_url = if lite || !version::expand_status(history.version.as_ref()) {
+        format!("{}/connectors", base_url)
+    } else {
+        format!("{}/connectors?expand=status", base_url)
+    };
+    let mut latency = LatencyReservoir::default();
+    let started = Instant::now();
+    let (resp, list_requests) = opts.send(client, &list_url).await;
+    let ConnectorList { names: mut connector_names, statuses: mut listed } = match resp {
+        Ok(r) => {
+            latency.record(started.elapsed().as_secs_f64());
+            match r.json::<serde_json::Value>().await.map(connector_list) {
+                Ok(Some(list)) => list,
+                Ok(None) => {
+                    warn!("Unexpected connector list from {}", base_url);
+                    history.record_requests(list_requests, &["decode"]);
+                    history.up = false;
+                    history.warm_cycles = 0;
+                    return down(history);
+                }
+                Err(e) => {
+                    warn!("Failed to parse connectors from {}: {}", base_url, e);
+                    history.record_requests(list_requests, &[error_reason(&e)]);
+                    history.up = false;
+                    history.warm_cycles = 0;
+                    return down(history);
+                }
+            }
+        }
+        Err(e) => {
+            warn!("Cannot reach Kafka Connect at {}: {}", base_url, e);
+            history.record_requests(list_requests, &[error_reason(&e)]);
+            history.up = false;
+            history.warm_cycles = 0;
+            return down(history);
+        }
+    };
+    let was_up = std::mem::replace(&mut history.up, true);
+    history.last_success = Some(SystemTime::now());
+    history.warm_cycles = history.warm_cycles.saturating_add(1);
+    history.down_cycles = 0;
+
+    // Filtered-out connectors are neither fetched nor emitted; the required
+    // and orphan checks still see the whole list
+    let listed_names = connector_names.clone();
+    if !inst.connector_filter.is_empty() {
+        connector_names.retain(|name| inst.connector_filter.matches(name));
+        debug!(
+            "{}: {} of {} connectors pass the name filter",
+            base_url,
+            connector_names.len(),
+            listed_names.len()
+        );
+    }
+    let total = connector_names.len();
+    if lite {
+        let mut lines = resolved;
+        for name in &connector_names {
+            lines.push(registry::gauge("kafka_connect_connector_info").label("connector", name).with(&base).set(true));
+        }
+        lines.push(registry::gauge("kafka_connect_up").with(&base).set(true));
+        history.last_summary = vec![registry::gauge("kafka_connect_connectors_total").with(&base).set(total)];
+        lines.extend(history.last_summary.iter().cloned());
+        if opts.marks_stale() {
+            lines.push(registry::gauge("kafka_connect_data_stale").with(&base).set(false));
+        }
+        lines.push(registry::gauge("kafka_connect_requests_per_scrape").with(&base).set(list_requests));
+        lines.push(registry::gauge("kafka_connect_scrape_error_ratio").with(&base).set(0.0));
+        history.record_requests(list_requests, &[]);
+        lines.extend(latency.lines(history, &base));
+        lines.extend(required_missing_lines(inst, &listed_names, &base));
+        if opts.keep_cycles > 0 {
+            history.last_series = kept_series(&lines);
+        }
+        return lines;
+    }
+
+    let mut running = 0usize;
+    let mut failed = 0usize;
+    // RUNNING connectors with at least one task that is not RUNNING
+    let mut degraded = 0usize;
+    // (running, failed) by connector type, for SPLIT_SUMMARY_BY_TYPE
+    let mut by_type: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    let mut lines = resolved.clone();
+    let mut task_workers = HashMap::new();
+    // Distinct worker_ids of connectors and tasks: the workers doing work
+    let mut workers: HashSet<String> = HashSet::new();
+    let mut orphaned = 0usize;
+    // (name, connector labels, type) of scraped connectors; their info series
+    // wait for the plugin inventory
+    let mut infos: Vec<(&str, registry::Labels, String)> = Vec::new();
+    // Connectors whose status was fetched successfully this cycle
+    let mut scraped = 0usize;
+    // HTTP requests made to this instance in this cycle, the list call included
+    let mut requests = list_requests;
+    // Of those, the reasons of requests that failed or returned an unusable
+    // body. A 404 status is an answer (an orphan), not an error.
+    let mut errors: Vec<&'static str> = Vec::new();
+
+    // A worker only changes version with a restart, which shows up as an
+    // outage; otherwise the cached version stands
+    if (!was_up || history.version.is_none()) && !opts.over_budget() {
+        let (resp, n) = opts.send(client, &format!("{}/", base_url)).await;
+        requests += n;
+        let root = match resp {
+            Ok(r) => r.json::<version::RootInfo>().await,
+            Err(e) => Err(e),
+        };
+        match root {
+            Ok(root) => {
+                history.version = version::ConnectVersion::parse(&root.version);
+                if history.version.is_none() {
+                    warn!("Unrecognised Kafka Connect version on {}: {}", base_url, root.version);
+                }
+                history.root = Some(root);
+            }
+            Err(e) => {
+                warn!("Failed to read the version of {}: {}", base_url, e);
+                errors.push(error_reason(&e));
+                history.version = None;
+                history.root = None;
+            }
+        }
+    }
+    let opts = ScrapeOptions {
+        fetch_topics: opts.fetch_topics && version::active_topics(history.version.as_ref()),
+        ..opts
+    };
+    let states: Vec<&str> = CONNECTOR_STATES
+        .iter()
+        .copied()
+        .filter(|s| !(suppress_outages && *s == "failed"))
+        .filter(|s| *s != "stopped" || version::stopped_state(history.version.as_ref()))
+        .filter(|s| *s != "restarting" || version::restarting_state(history.version.as_ref()))
+        .chain(["unknown"])
+        .collect();
+
+    // During an incident the connectors that were FAILED matter most: fetch
+    // them first so a tight cycle budget never cuts them off
+    if opts.prioritize_failed {
+        connector_names.sort_by_key(|name| {
+            history.connector_states.get(name).is_none_or(|state| state != "failed")
+        });
+    }
+
+    // Fetch status (and config) of up to `concurrency` connectors at a time.
+    // Unordered, so one slow connector does not hold back the next calls;
+    // results are put back in list order and processed sequentially.
+    // Indices instead of &String items keep the stream future Send
+    let expanded = listed.is_some();
+    let last_statuses = &history.last_statuses;
+    let known_classes = &history.connector_classes;
+    let mut fetches: Vec<(usize, ConnectorFetch)> = futures_util::stream::iter(0..connector_names.len())
+        .map(|i| {
+            let name = &connector_names[i];
+            let status = listed.as_mut().and_then(|statuses| statuses.remove(name));
+            let span = trace_span!("fetch_connector", connector = name.as_str());
+            let need_class = opts.describe && !known_classes.contains_key(name);
+            fetch_connector(client, opts, base_url, name, status, last_statuses.get(name), need_class)
+                .map(move |fetch| (i, fetch))
+                .instrument(span)
+        })
+        .buffer_unordered(opts.concurrency)
+        .collect()
+        .await;
+    fetches.sort_unstable_by_key(|(i, _)| *i);
+    let fetches: Vec<ConnectorFetch> = fetches.into_iter().map(|(_, fetch)| fetch).collect();
+    let skipped = fetches.iter().filter(|f| matches!(f.status, Fetched::Skipped)).count();
+    if skipped > 0 {
+        warn!(
+            "Cycle budget exhausted on {}: {} of {} connectors not fetched",
+            base_url, skipped, total
+        );
+    }
+
+    for (name, fetch) in connector_names.iter().zip(fetches) {
+        requests += fetch.requests;
+        errors.extend(fetch.errors);
+        if let Some(secs) = fetch.latency {
+            latency.record(secs);
+        }
+        history.status_not_modified += fetch.not_modified as u64;
+        let status = match fetch.status {
+            Fetched::Status(status) => *status,
+            Fetched::NotFound => {
+                orphaned += 1;
+                continue;
+            }
+            Fetched::Failed | Fetched::Skipped => continue,
+        };
+        history.last_statuses.insert(name.clone(), (fetch.etag, status.clone()));
+        if let Some(class) = fetch.class {
+            history.connector_classes.insert(name.clone(), class);
+        }
+        if let Some(topics) = fetch.topics {
+            history.connector_topics.insert(name.clone(), topics);
+        }
+        let first_line = lines.len();
+
+        // A failed config fetch keeps the last known tenant so series do not
+        // flap to "unknown"
+        let base = match opts.tenant_key {
+            Some(key) => {
+                if let Some(config) = &fetch.config {
+                    let tenant = config.get(key).and_then(|v| v.as_str()).unwrap_or("unknown");
+                    history.tenants.insert(name.clone(), tenant.to_owned());
+                }
+                let tenant = history.tenants.get(name).map_or("unknown", String::as_str);
+                let mut labels = registry::Labels::default().with("tenant", tenant);
+                labels.extend(&base);
+                labels
+            }
+            None => base.clone(),
+        };
+        infos.push((name, base.clone(), status.connector_type.as_deref().unwrap_or("unknown").to_lowercase()));
+        workers.extend(status.connector.worker_id.iter().cloned());
+        workers.extend(status.tasks.iter().filter_map(|t| t.worker_id.clone()));
+
+        scraped += 1;
+        orphaned += orphaned_tasks(base_url, name, &status, &listed_names);
+
+        let c_state = status.connector.state.to_lowercase();
+        if c_state == "running" { running += 1; }
+        if c_state == "failed"  { failed  += 1; }
+        let tally = by_type
+            .entry(status.connector_type.as_deref().unwrap_or("unknown").to_lowercase())
+            .or_default();
+        tally.0 += (c_state == "running") as usize;
+        tally.1 += (c_state == "failed") as usize;
+
+        let prev_state = history.connector_states.insert(name.clone(), c_state.clone());
+        let transitions = history.transitions.entry(name.clone()).or_default();
+        let now = Instant::now();
+        if let Some(prev) = prev_state.as_ref().filter(|prev| **prev != c_state) {
+            if *prev == "failed" || c_state == "failed" {
+                transitions.flaps.push_back(now);
+            }
+            *transitions.connector.entry((prev.clone(), c_state.clone())).or_default() += 1;
+        }
+        for task in &status.tasks {
+            let t_state = task.state.to_lowercase();
+            match transitions.task_states.insert(task.id, t_state.clone()) {
+                Some(prev) if prev != t_state => {
+                    if prev == "failed" || t_state == "failed" {
+                        transitions.flaps.push_back(now);
+                    }
+                    *transitions.tasks.entry((task.id, prev, t_state)).or_default() += 1;
+                }
+                _ => {}
+            }
+        }
+        // A task id that is gone starts over when it comes back
+        transitions.task_states.retain(|id, _| status.tasks.iter().any(|t| t.id == *id));
+        if c_state == "failed" {
+            history.failed_since.entry(name.clone()).or_insert_with(Instant::now);
+        } else {
+            history.failed_since.remove(name);
+        }
+        if let (Some(events), Some(prev)) = (opts.events, &prev_state) {
+            if *prev != c_state && (*prev == "failed" || c_state == "failed") {
+                events.emit(webhook::Event {
+                    cluster: inst.cluster.clone(),
+                    url: inst.url.clone(),
+                    ..webhook::Event::new(name, inst.label(), &inst.labels, prev, &c_state)
+                });
+            }
+        }
+        if opts.emit_edges {
+            // Edge-triggered: 1 only for the cycle the connector turned FAILED.
+            // A connector seen for the first time has no edge.
+            let became_failed =
+                c_state == "failed" && prev_state.as_deref().is_some_and(|p| p != "failed");
+            lines.push(
+                registry::gauge("kafka_connect_connector_became_failed")
+                    .label("connector", name)
+                    .with(&base)
+                    .set(became_failed),
+            );
+        }
+
+        // Local config version: +1 whenever the config hash changes
+        if let Some(config) = &fetch.config {
+            let hash = config_hash(config);
+            let entry = history.config_versions.entry(name.clone()).or_insert((hash, 1));
+            if entry.0 != hash {
+                info!("Config of connector {} on {} changed", name, base_url);
+                *entry = (hash, entry.1 + 1);
+            }
+        }
+        if opts.fetch_config {
+            if let Some((_, version)) = history.config_versions.get(name) {
+                lines.push(
+                    registry::gauge("kafka_connect_connector_config_version")
+                        .label("connector", name)
+                        .with(&base)
+                        .set(*version),
+                );
+            }
+        }
+
+        // Fewer tasks than last cycle without a lower tasks.max: tasks that
+        // were not recreated after a rebalance. Unknown tasks.max counts as unchanged.
+        let tasks_max = fetch.config.as_ref().and_then(|c| c.get("tasks.max")?.as_str()?.parse::<u64>().ok());
+        let count = status.tasks.len();
+        let dropped = history.task_counts.get(name).is_some_and(|&(prev, prev_max)| {
+            let max_lowered = matches!((prev_max, tasks_max), (Some(p), Some(m)) if m < p);
+            count < prev && !max_lowered
+        });
+        if dropped {
+            warn!("Connector {} on {} lost tasks without a tasks.max change, now {}", name, base_url, count);
+        }
+        history.task_counts.insert(name.clone(), (count, tasks_max));
+        lines.push(
+            registry::gauge("kafka_connect_connector_task_count_dropped")
+                .label("connector", name)
+                .with(&base)
+                .set(dropped),
+        );
+
+        // Emit state metrics as separate time series (one per state)
+        let extra = extra_labels(opts.extra_fields, &[&status.connector.extra, &status.extra]);
+        let c_series = state_series(&c_state, &states, suppress_outages);
+        for state in &states {
+            lines.push(
+                registry::gauge("kafka_connect_connector_state")
+                    .labels(&[("connector", name), ("state", state)])
+                    .with(&extra)
+                    .with(&base)
+                    .set(c_series == Some(*state)),
+            );
+        }
+        // A failed topics call keeps the last known topics
+        for topic in history.connector_topics.get(name).into_iter().flatten() {
+            lines.push(
+                registry::gauge("kafka_connect_connector_topic")
+                    .labels(&[("connector", name), ("topic", topic)])
+                    .with(&base)
+                    .set(true),
+            );
+        }
+        // Counted between scrapes, so a failure that recovered before the next
+        // one still shows up over long ranges
+        if let Some(transitions) = history.transitions.get_mut(name) {
+            lines.push(
+                registry::gauge("kafka_connect_connector_flapping")
+                    .label("connector", name)
+                    .with(&base)
+                    .set(transitions.flap_score(opts.flap_window)),
+            );
+            for ((from, to), n) in &transitions.connector {
+                lines.push(
+                    registry::counter("kafka_connect_connector_state_transitions_total")
+                        .labels(&[("connector", name), ("from", from), ("to", to)])
+                        .with(&base)
+                        .total(*n),
+                );
+            }
+            for ((task, from, to), n) in &transitions.tasks {
+                lines.push(
+                    registry::counter("kafka_connect_task_state_transitions_total")
+                        .labels(&[("connector", name), ("task", &task.to_string()), ("from", from), ("to", to)])
+                        .with(&base)
+                        .total(*n),
+                );
+            }
+        }
+        // The worker is a series of its own rather than a label on the state
+        // series, so a rebalance does not churn every state series
+        if let Some(worker) = &status.connector.worker_id {
+            lines.push(
+                registry::gauge("kafka_connect_connector_worker_info")
+                    .labels(&[("connector", name), ("worker_id", worker)])
+                    .with(&base)
+                    .set(true),
+            );
+        }
+
+        // A RUNNING connector with a task that is not RUNNING looks green on
+        // connector-level dashboards but is not fully processing
+        let is_degraded =
+            c_state == "running" && status.tasks.iter().any(|t| !t.state.eq_ignore_ascii_case("running"));
+        degraded += is_degraded as usize;
+        lines.push(
+            registry::gauge("kafka_connect_connector_degraded").label("connector", name).with(&base).set(is_degraded),
+        );
+
+        // All tasks of a multi-task connector on one worker lose fault
+        // tolerance: that worker's failure takes the whole connector down.
+        if status.tasks.len() > 1 {
+            let workers: Option<Vec<&String>> =
+                status.tasks.iter().map(|t| t.worker_id.as_ref()).collect();
+            if let Some(workers) = workers {
+                let single = workers.windows(2).all(|w| w[0] == w[1]);
+                lines.push(
+                    registry::gauge("kafka_connect_connector_single_worker")
+                        .label("connector", name)
+                        .with(&base)
+                        .set(single),
+                );
+            }
+        }
+
+        // Task counts by state, for alerts that would otherwise aggregate
+        // every task series
+        let task_series: Vec<Option<&str>> = status
+            .tasks
+            .iter()
+            .map(|t| state_series(&t.state.to_lowercase(), &states, suppress_outages))
+            .collect();
+        for state in &states {
+            let count = task_series.iter().filter(|s| **s == Some(*state)).count();
+            lines.push(
+                registry::gauge("kafka_connect_connector_tasks")
+                    .labels(&[("connector", name), ("state", state)])
+                    .with(&base)
+                    .set(count),
+            );
+        }
+
+        // Task-level metrics
+        for (task, t_series) in status.tasks.iter().zip(&task_series) {
+            // A task only counts as reassigned when it was seen in both cycles;
+            // tasks that appear or disappear are not movement.
+            if let Some(worker) = &task.worker_id {
+                let key = (name.clone(), task.id);
+                if history.task_workers.get(&key).is_some_and(|prev| prev != worker) {
+                    history.task_reassignments += 1;
+                }
+                task_workers.insert(key, worker.clone());
+            }
+
+            let extra = extra_labels(opts.extra_fields, &[&task.extra]);
+            let task_id = task.id.to_string();
+            for state in &states {
+                lines.push(
+                    registry::gauge("kafka_connect_connector_task_state")
+                        .labels(&[("connector", name), ("task", &task_id), ("state", state)])
+                        .with(&extra)
+                        .with(&base)
+                        .set(*t_series == Some(*state)),
+                );
+            }
+            if let Some(worker) = &task.worker_id {
+                lines.push(
+                    registry::gauge("kafka_connect_task_worker_info")
+                        .labels(&[("connector", name), ("task", &task_id), ("worker_id", worker)])
+                        .with(&base)
+                        .set(true),
+                );
+            }
+            // The class is enough to alert and group on; the whole trace is on
+            // /api/v1/connectors/:name/errors
+            if let Some(class) = task.trace.as_deref().filter(|_| !suppress_outages).and_then(exception_class) {
+                lines.push(
+                    registry::gauge("kafka_connect_task_error_info")
+                        .labels(&[("connector", name), ("task", &task_id), ("exception_class", &class)])
+                        .with(&base)
+                        .set(true),
+                );
+            }
+        }
+
+        // Changed since the previous cycle: this connector's series go to
+        // /metrics/delta. A connector seen for the first time is not a change.
+        let mut fingerprint = c_state.clone();
+        for task in &status.tasks {
+            fingerprint.push_str(&format!(",{}={}", task.id, task.state.to_lowercase()));
+        }
+        let prev = history.fingerprints.insert(name.clone(), fingerprint.clone());
+        if prev.is_some_and(|p| p != fingerprint) {
+            history.delta.extend(lines[first_line..].iter().cloned());
+        }
+    }
+
+    history.task_workers = task_workers;
+    // Keep the last known state of connectors whose status fetch failed this
+    // cycle, forget deleted ones
+    history.connector_states.retain(|name, _| connector_names.contains(name));
+    history.transitions.retain(|name, _| connector_names.contains(name));
+    history.config_versions.retain(|name, _| connector_names.contains(name));
+    history.fingerprints.retain(|name, _| connector_names.contains(name));
+    history.task_counts.retain(|name, _| connector_names.contains(name));
+    history.last_statuses.retain(|name, _| connector_names.contains(name));
+    history.tenants.retain(|name, _| connector_names.contains(name));
+    history.failed_since.retain(|name, _| connector_names.contains(name));
+    history.connector_classes.retain(|name, _| connector_names.contains(name));
+    history.connector_topics.retain(|name, _| connector_names.contains(name));
+
+    // Plugin inventory of the answering worker: exported as is, the source
+    // of connector versions, and compared across the workers of one cluster
+    // in render()
+    if (inst.cluster.is_some() || opts.describe) && !opts.over_budget() {
+        let (resp, n) = opts.send(client, &format!("{}/connector-plugins", base_url)).await;
+        requests += n;
+        match resp {
+            Ok(r) => match r.json::<Vec<PluginInfo>>().await {
+                Ok(plugins) => history.plugins = plugins,
+                Err(e) => {
+                    warn!("Failed to parse connector plugins from {}: {}", base_url, e);
+                    errors.push(error_reason(&e));
+                }
+            },
+            Err(e) => {
+                warn!("Failed to fetch connector plugins from {}: {}", base_url, e);
+                errors.push(error_reason(&e));
+            }
+        }
+    }
+
+    for plugin in &history.plugins {
+        lines.push(
+            registry::gauge("kafka_connect_plugin_info")
+                .labels(&[("class", &plugin.class), ("type", &plugin.plugin_type), ("version", &plugin.version)])
+                .with(&base)
+                .set(true),
+        );
+    }
+    for (name, base, connector_type) in infos {
+        let class = history.connector_classes.get(name).map_or("unknown", String::as_str);
+        let version = history
+            .plugins
+            .iter()
+            .find(|p| p.class == class)
+            .map_or("unknown", |p| p.version.as_str());
+        lines.push(
+            registry::gauge("kafka_connect_connector_info")
+                .labels(&[("connector", name), ("type", &connector_type), ("class", class), ("version", version)])
+                .with(&base)
+                .set(true),
+        );
+    }
+
+    // Summary metrics
+    lines.push(registry::gauge("kafka_connect_up").with(&base).set(true));
+    if let Some(version) = &history.version {
+        lines.push(registry::gauge("kafka_connect_version_info").label("version", &version.raw).with(&base).set(true));
+    }
+    if let Some(root) = &history.root {
+        lines.push(
+            registry::gauge("kafka_connect_cluster_info")
+                .labels(&[("version", &root.version), ("commit", &root.commit)])
+                .label("kafka_cluster_id", &root.kafka_cluster_id)
+                .with(&base)
+                .set(true),
+        );
+    }
+    lines.push(registry::gauge("kafka_connect_scrape_complete").with(&base).set(scraped == total));
+    // Gauges a down instance can repeat or zero, see DownPolicy
+    let mut summary = vec![
+        registry::gauge("kafka_connect_connectors_total").with(&base).set(total),
+        registry::gauge("kafka_connect_connectors_scraped").with(&base).set(scraped),
+    ];
+    if opts.split_by_type {
+        for (ty, (running, failed)) in &by_type {
+            let series = |name| registry::gauge(name).with(&base).label("type", ty);
+            summary.push(series("kafka_connect_connectors_running").set(*running));
+            if !suppress_outages {
+                summary.push(series("kafka_connect_connectors_failed").set(*failed));
+            }
+        }
+    } else {
+        summary.push(registry::gauge("kafka_connect_connectors_running").with(&base).set(running));
+        if !suppress_outages {
+            summary.push(registry::gauge("kafka_connect_connectors_failed").with(&base).set(failed));
+        }
+    }
+    summary.push(registry::gauge("kafka_connect_connectors_degraded").with(&base).set(degraded));
+    lines.extend(summary.iter().cloned());
+    history.last_summary = summary;
+    if opts.marks_stale() {
+        lines.push(registry::gauge("kafka_connect_data_stale").with(&base).set(false));
+    }
+    lines.push(
+        registry::counter("kafka_connect_task_reassignments_total").with(&base).total(history.task_reassignments),
+    );
+    lines.push(registry::gauge("kafka_connect_orphaned_tasks").with(&base).set(orphaned));
+    let oldest_failed = history.failed_since.values().map(|t| t.elapsed().as_secs_f64()).fold(0.0, f64::max);
+    lines.push(
+        registry::gauge("kafka_connect_oldest_failed_connector_age_seconds").with(&base).set(oldest_failed.round()),
+    );
+    lines.push(registry::gauge("kafka_connect_workers_total").with(&base).set(workers.len()));
+    lines.push(registry::gauge("kafka_connect_requests_per_scrape").with(&base).set(requests));
+    lines.push(
+        registry::counter("kafka_connect_status_not_modified_total").with(&base).total(history.status_not_modified),
+    );
+    lines.push(registry::gauge("kafka_connect_request_concurrency").with(&base).set(opts.concurrency));
+    lines.push(registry::gauge("kafka_connect_expand_status_supported").with(&base).set(expanded));
+    lines.push(
+        registry::gauge("kafka_connect_scrape_error_ratio")
+            .with(&base)
+            .set(errors.len() as f64 / requests.max(1) as f64),
+    );
+    history.record_requests(requests, &errors);
+    lines.extend(latency.lines(history, &base));
+    lines.extend(required_missing_lines(inst, &listed_names, &base));
+    if opts.keep_cycles > 0 {
+        history.last_series = kept_series(&lines);
+    }
+
+    lines
+}
+
+/// Per-connector families a down instance repeats for DOWN_INSTANCE_KEEP_CYCLES.
+/// The one-cycle edges are left out so they do not fire again.
+const KEPT_FAMILIES: &[&str] = &[
+    "kafka_connect_connector_state",
+    "kafka_connect_connector_task_state",
+    "kafka_connect_connector_tasks",
+    "kafka_connect_connector_info",
+    "kafka_connect_connector_single_worker",
+    "kafka_connect_connector_degraded",
+    "kafka_connect_connector_config_version",
+    "kafka_connect_required_connector_missing",
+    "kafka_connect_connector_worker_info",
+    "kafka_connect_task_worker_info",
+    "kafka_connect_connector_topic",
+    "kafka_connect_connector_state_transitions_total",
+    "kafka_connect_task_state_transitions_total",
+    "kafka_connect_connector_flapping",
+    "kafka_connect_task_error_info",
+];
+
+/// The samples of a successful scrape a down instance may repeat.
+fn kept_series(samples: &[registry::Sample]) -> Vec<registry::Sample> {
+    samples.iter().filter(|sample| KEPT_FAMILIES.contains(&sample.family)).cloned().collect()
+}
+
+/// Stable-within-process fingerprint of a connector config (keys sorted).
+fn config_hash(config: &BTreeMap<String, serde_json::Value>) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    for (key, value) in config {
+        key.hash(&mut hasher);
+        value.to_string().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Outcome of a connector's status call.
+enum Fetched {
+    Status(Box<ConnectorStatus>),
+    /// Listed but `404`: an orphan.
+    NotFound,
+    /// Network error or unusable body.
+    Failed,
+    /// Not attempted: the cycle budget ran out first.
+    Skipped,
+}
+
+/// Per-connector calls of one cycle: status, plus config with
+/// SCRAPE_CONNECTOR_CONFIG.
+struct ConnectorFetch {
+    status: Fetched,
+    config: Option<BTreeMap<String, serde_json::Value>>,
+    requests: usize,
+    /// Reasons of the calls that failed.
+    errors: Vec<&'static str>,
+    /// Seconds to the status response headers, when it answered.
+    latency: Option<f64>,
+    /// ETag of the status, to send as If-None-Match next cycle.
+    etag: Option<String>,
+    /// The status came from the cache after a `304 Not Modified`.
+    not_modified: bool,
+    /// `connector.class` from the config, when it was fetched.
+    class: Option<String>,
+    /// Active topics, when they were fetched.
+    topics: Option<Vec<String>>,
+}
+
+async fn fetch_connector(
+    client: &reqwest::Client,
+    opts: ScrapeOptions<'_>,
+    base_url: &str,
+    name: &str,
+    listed: Option<ConnectorStatus>,
+    cached: Option<&(Option<String>, ConnectorStatus)>,
+    need_class: bool,
+) -> ConnectorFetch {
+    let mut fetch = ConnectorFetch {
+        status: Fetched::Skipped,
+        config: None,
+        requests: 0,
+        errors: Vec::new(),
+        latency: None,
+        etag: None,
+        not_modified: false,
+        class: None,
+        topics: None,
+    };
+    // Statuses from the expanded connector list need no call of their own
+    if let Some(status) = listed {
+        fetch.status = Fetched::Status(Box::new(status));
+    } else {
+        if opts.over_budget() {
+            return fetch;
+        }
+
+        let started = Instant::now();
+        let etag = cached.and_then(|(etag, _)| etag.as_deref());
+        let (resp, requests) = opts
+            .send_with(client, &connector_url(base_url, name, "status"), |req| match etag {
+                Some(etag) => req.header(header::IF_NONE_MATCH, etag),
+                None => req,
+            })
+            .await;
+        fetch.requests += requests;
+        if resp.is_ok() {
+            fetch.latency = Some(started.elapsed().as_secs_f64());
+        }
+        fetch.status = match resp {
+            Ok(r) if r.status() == reqwest::StatusCode::NOT_FOUND => {
+                warn!("Orphaned connector on {}: {} is listed but has no status", base_url, name);
+                return ConnectorFetch { status: Fetched::NotFound, ..fetch };
+            }
+            // Only sent If-None-Match when there is a cached status to reuse
+            Ok(r) if r.status() == reqwest::StatusCode::NOT_MODIFIED && etag.is_some() => {
+                let (etag, status) = cached.unwrap();
+                fetch.etag = etag.clone();
+                fetch.not_modified = true;
+                Fetched::Status(Box::new(status.clone()))
+            }
+            // Still rebalancing after the retries: the last status stands
+            // rather than the connector dropping out of this cycle
+            Ok(r) if r.status() == reqwest::StatusCode::CONFLICT && cached.is_some() => {
+                debug!("Status of {} on {} blocked by a rebalance, reusing the last one", name, base_url);
+                let (etag, status) = cached.unwrap();
+                fetch.etag = etag.clone();
+                fetch.errors.push("http");
+                Fetched::Status(Box::new(status.clone()))
+            }
+            Ok(r) => {
+                fetch.etag = r
+                    .headers()
+                    .get(header::ETAG)
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::to_owned);
+                let reason = if r.status().is_success() { "decode" } else { "http" };
+                match r.json().await {
+                    Ok(s) => Fetched::Status(Box::new(s)),
+                    Err(e) => {
+                        warn!("Failed to parse status for {}: {}", name, e);
+                        fetch.errors.push(reason);
+                        Fetched::Failed
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Failed to fetch status for {}: {}", name, e);
+                fetch.errors.push(error_reason(&e));
+                Fetched::Failed
+            }
+        };
+    }
+
+    // Connect exposes no config epoch over REST, so changes are detected
+    // by hashing the config and counting hash changes. Without
+    // SCRAPE_CONNECTOR_CONFIG it is still fetched once for the class.
+    if (opts.fetch_config || need_class) && matches!(fetch.status, Fetched::Status(_)) && !opts.over_budget() {
+        let (resp, requests) = opts.send(client, &connector_url(base_url, name, "config")).await;
+        fetch.requests += requests;
+        match resp {
+            Ok(r) => {
+                let reason = if r.status().is_success() { "decode" } else { "http" };
+                match r.json::<BTreeMap<String, serde_json::Value>>().await {
+                    Ok(config) => {
+                        let class = config.get("connector.class").and_then(|v| v.as_str()).unwrap_or("unknown");
+                        fetch.class = Some(class.to_owned());
+                        fetch.config = opts.fetch_config.then_some(config);
+                    }
+                    Err(e) => {
+                        warn!("Failed to parse config for {}: {}", name, e);
+                        fetch.errors.push(reason);
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Failed to fetch config for {}: {}", name, e);
+                fetch.errors.push(error_reason(&e));
+            }
+        }
+    }
+
+    // Topics the connector has used since it was created or its topics were
+    // last reset (KIP-558)
+    if opts.fetch_topics && matches!(fetch.status, Fetched::Status(_)) && !opts.over_budget() {
+        let (resp, requests) = opts.send(client, &connector_url(base_url, name, "topics")).await;
+        fetch.requests += requests;
+        match resp {
+            // topic.tracking.enable=false on the worker
+            Ok(r) if r.status() == reqwest::StatusCode::FORBIDDEN => {
+                debug!("Topic tracking is disabled on {}", base_url);
+            }
+            Ok(r) => {
+                let reason = if r.status().is_success() { "decode" } else { "http" };
+                match r.json::<HashMap<String, ActiveTopics>>().await {
+                    Ok(mut active) => fetch.topics = Some(active.remove(name).map_or_else(Vec::new, |a| a.topics)),
+                    Err(e) => {
+                        warn!("Failed to parse topics for {}: {}", name, e);
+                        fetch.errors.push(reason);
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Failed to fetch topics for {}: {}", name, e);
+                fetch.errors.push(error_reason(&e));
+            }
+        }
+    }
+    fetch
+}
+
+/// Everything but RFC 3986 unreserved characters is percent-encoded in a
+/// path segment.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+/// `{base_url}/connectors/{name}/{endpoint}` with the name percent-encoded,
+/// since Connect allows `/`, spaces and other path-unsafe characters in names.
+fn connector_url(base_url: &str, name: &str, endpoint: &str) -> String {
+    format!("{}/connectors/{}/{}", base_url, utf8_percent_encode(name, PATH_SEGMENT), endpoint)
+}
+
+/// Parsed `GET /connectors`.
+struct ConnectorList {
+    names: Vec<String>,
+    /// Statuses by name when the body was the expanded form.
+    statuses: Option<HashMap<String, ConnectorStatus>>,
+}
+
+/// Connector names from `GET /connectors`, plus the statuses when the body
+/// was the expanded form: an object keyed by name with a `status` per entry
+/// (Connect 2.3+ with `?expand=status`) rather than a plain name array.
+/// Entries whose status does not parse are left for a status call.
+fn connector_list(body: serde_json::Value) -> Option<ConnectorList> {
+    match body {
+        serde_json::Value::Object(map) => {
+            let expanded = map.values().all(|v| v.get("status").is_some());
+            let mut names = Vec::with_capacity(map.len());
+            let mut statuses = HashMap::new();
+            for (name, mut entry) in map {
+                if let Some(status) = entry.get_mut("status").map(serde_json::Value::take) {
+                    match serde_json::from_value(status) {
+                        Ok(status) => {
+                            statuses.insert(name.clone(), status);
+                        }
+                        Err(e) => debug!("Expanded status of {} unusable: {}", name, e),
+                    }
+                }
+                names.push(name);
+            }
+            Some(ConnectorList { names, statuses: expanded.then_some(statuses) })
+        }
+        serde_json::Value::Array(items) => {
+            let names: Option<Vec<String>> =
+                items.into_iter().map(|v| v.as_str().map(str::to_owned)).collect();
+            names.map(|names| ConnectorList { names, statuses: None })
+        }
+        _ => None,
+    }
+}
+
+/// `kafka_connect_required_connector_missing` for each required connector.
+fn required_missing_lines(inst: &Instance, connector_names: &[String], base: &registry::Labels) -> Vec<registry::Sample> {
+    let mut lines = Vec::new();
+    for required in &inst.required_connectors {
+        let missing = !connector_names.contains(required);
+        if missing {
+            warn!("Required connector {} is missing on {}", required, inst.url);
+        }
+        lines.push(
+            registry::gauge("kafka_connect_required_connector_missing")
+                .label("connector", required)
+                .with(base)
+                .set(missing),
+        );
+    }
+    lines
+}
+
+/// Upper bound on `kafka_connect_resolved_addr_info` series per instance.
+const MAX_RESOLVED_ADDRS: usize = 4;
+
+/// `kafka_connect_resolved_addr_info` lines for the addresses the instance's
+/// host resolves to right now.
+async fn resolved_addr_lines(base_url: &str, base: &registry::Labels) -> Vec<registry::Sample> {
+    let Ok(url) = reqwest::Url::parse(base_url) else {
+        return Vec::new();
+    };
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return Vec::new();
+    };
+    let lookup = tokio::net::lookup_host((host, port)).await;
+    match lookup {
+        Ok(addrs) => {
+            let mut ips: Vec<_> = addrs.map(|a| a.ip()).collect();
+            ips.sort();
+            ips.dedup();
+            ips.truncate(MAX_RESOLVED_ADDRS);
+            ips.iter()
+                .map(|ip| {
+                    let addr = ip.to_string();
+                    registry::gauge("kafka_connect_resolved_addr_info").with(base).label("addr", &addr).set(true)
+                })
+                .collect()
+        }
+        Err(e) => {
+            warn!("Failed to resolve {} for {}: {}", host, base_url, e);
+            Vec::new()
+        }
+    }
+}
+
+/// Inconsistencies between one status payload and the connector list.
+///
+/// A task is orphaned when the status it is reported in belongs to a
+/// connector Connect no longer lists (the payload's `name` is not in the
+/// list), or when its id is reported more than once for the same connector.
+fn orphaned_tasks(
+    base_url: &str,
+    requested: &str,
+    status: &ConnectorStatus,
+    listed: &[String],
+) -> usize {
+    let mut orphaned = 0;
+    if let Some(owner) = status.name.as_deref() {
+        if owner != requested && !listed.iter().any(|n| n == owner) {
+            warn!(
+                "Orphaned tasks on {}: status for {} reports {} task(s) of unlisted connector {}",
+                base_url,
+                requested,
+                status.tasks.len(),
+                owner
+            );
+            orphaned += status.tasks.len();
+        }
+    }
+
+    let mut ids: Vec<u32> = status.tasks.iter().map(|t| t.id).collect();
+    ids.sort_unstable();
+    for dup in ids.windows(2).filter(|w| w[0] == w[1]) {
+        warn!("Orphaned task on {}: {} reports task {} more than once", base_url, requested, dup[0]);
+        orphaned += 1;
+    }
+    orphaned
+}
+
+/// Concurrency for an instance's `warm`-th cycle: rises linearly from
+/// max/(ramp+1) over the first `ramp` cycles, then stays at max.
+fn ramped_concurrency(max: usize, ramp: u32, warm: u32) -> usize {
+    if warm >= ramp {
+        return max;
+    }
+    (max * (warm as usize + 1)).div_ceil(ramp as usize + 1).max(1)
+}
+
+/// Scrape one instance against its own cross-scrape state.
+async fn scrape_history(
+    app: &AppState,
+    history: &mut InstanceState,
+    inst: &Instance,
+    deadline: Option<tokio::time::Instant>,
+) -> Vec<registry::Sample> {
+    let opts = ScrapeOptions {
+        suppress_outages: app.maintenance.load(Ordering::Relaxed) && app.config.maintenance_suppress,
+        emit_edges: app.config.emit_became_failed,
+        fetch_config: app.config.scrape_connector_config,
+        describe: true,
+        fetch_topics: app.config.scrape_connector_topics,
+        split_by_type: app.config.split_summary_by_type,
+        down_policy: app.config.down_policy,
+        keep_cycles: app.config.down_keep_cycles,
+        concurrency: ramped_concurrency(
+            app.config.fetch_concurrency,
+            app.config.concurrency_ramp_cycles,
+            history.warm_cycles,
+        ),
+        prioritize_failed: app.config.prioritize_failed,
+        deadline,
+        events: app.events.as_ref(),
+        auth: inst.auth.as_ref(),
+        timeout: inst.timeout(),
+        tenant_key: app.config.tenant_config_key.as_deref(),
+        extra_fields: &app.config.extra_status_fields,
+        retries: app.config.request_retries,
+        retry_backoff: app.config.retry_backoff,
+        flap_window: app.config.flap_window,
+    };
+    let span = trace_span!("scrape_instance", instance = inst.label());
+    let started = Instant::now();
+    let mut output = scrape_connect(inst.client(&app.client), inst, history, opts).instrument(span).await;
+    history.scrape_duration = started.elapsed();
+    let base = inst.base_labels();
+    output.push(registry::gauge("kafka_connect_instance_info").with(&base).label("url", &inst.url).set(true));
+    output.extend(history.self_lines(&base));
+    output
+}
+
+/// Scrape up to INSTANCE_CONCURRENCY instances at a time into `states`.
+/// Instance `i` starts `offsets[i]` after the call, and its cycle budget
+/// counts from then. Instances still waiting for their start on SIGTERM are
+/// left as they were.
+async fn scrape_all(app: &AppState, instances: &[Instance], states: &mut ScrapeState, offsets: &[Duration]) {
+    let start = tokio::time::Instant::now();
+    let limit = match app.config.instance_concurrency {
+        0 => instances.len().max(1),
+        n => n,
+    };
+    // Each scrape owns its instance's state while it runs
+    let mut histories: Vec<(usize, InstanceState)> =
+        instances.iter().map(|i| states.remove(&i.url).unwrap_or_default()).enumerate().collect();
+    // The earliest starts take the first concurrency slots
+    histories.sort_by_key(|(i, _)| offsets[*i]);
+    let span = trace_span!("scrape_cycle", instances = instances.len());
+    let mut results = futures_util::stream::iter(histories)
+        .map(|(i, mut history)| async move {
+            let begin = start + offsets[i];
+            let mut shutdown = app.shutdown.clone();
+            tokio::select! {
+                _ = tokio::time::sleep_until(begin) => {}
+                _ = shutdown.wait_for(|stop| *stop) => return (i, history),
+            }
+            let deadline = app.config.cycle_budget.map(|b| begin + b);
+            history.output = scrape_history(app, &mut history, &instances[i], deadline).await;
+            (i, history)
+        })
+        .buffer_unordered(limit);
+    while let Some((i, history)) = results.next().instrument(span.clone()).await {
+        states.insert(instances[i].url.clone(), history);
+    }
+}
+
+/// Metrics derived across instances.
+fn cross_instance_lines(instances: &[Instance], states: &ScrapeState) -> Vec<registry::Sample> {
+    let mut lines = plugin_skew_lines(instances, states);
+    lines.extend(name_collision_lines(instances, states));
+    lines
+}
+
+/// Full exposition from every instance's last scrape plus the metrics
+/// derived across instances.
+fn render(app: &AppState, instances: &[Instance], states: &ScrapeState) -> Vec<registry::Sample> {
+    let maintenance = app.maintenance.load(Ordering::Relaxed);
+    let mut all = vec![registry::gauge("kafka_connect_maintenance_mode").set(maintenance)];
+    for inst in instances {
+        if let Some(history) = states.get(&inst.url) {
+            all.extend(history.output.iter().cloned());
+        }
+    }
+    all.extend(cross_instance_lines(instances, states));
+    if app.remote_write.is_some() {
+        let failures = app.remote_write_failures.load(Ordering::Relaxed);
+        all.push(registry::counter("kafka_connect_remote_write_failures_total").total(failures));
+    }
+    if app.otlp_metrics.is_some() {
+        let failures = app.otlp_metrics_failures.load(Ordering::Relaxed);
+        all.push(registry::counter("kafka_connect_otlp_metrics_failures_total").total(failures));
+    }
+    if app.dogstatsd.is_some() {
+        let failures = app.dogstatsd_failures.load(Ordering::Relaxed);
+        all.push(registry::counter("kafka_connect_dogstatsd_failures_total").total(failures));
+    }
+    if app.pushgateway.is_some() {
+        let failures = app.pushgateway_failures.load(Ordering::Relaxed);
+        all.push(registry::counter("kafka_connect_pushgateway_failures_total").total(failures));
+    }
+    if let Some(events) = &app.events {
+        if let Some((_, deliveries)) = &events.webhook {
+            all.extend(deliveries.lines("kafka_connect_webhook_deliveries_total", &registry::Labels::default()));
+        }
+        if let Some(channels) = &events.channels {
+            all.extend(channels.lines());
+        }
+    }
+    if let Some(alerts) = &app.alerts {
+        all.extend(alerts.lines());
+    }
+    for ((connector, result), n) in app.heal_attempts.lock().unwrap().iter() {
+        all.push(
+            registry::counter("kafka_connect_heal_attempts_total")
+                .labels(&[("connector", connector), ("result", result)])
+                .total(*n),
+        );
+    }
+    for (((instance, connector, task), result), n) in app.task_restarts_total.lock().unwrap().iter() {
+        all.push(
+            registry::counter("kafka_connect_exporter_task_restarts_total")
+                .labels(&[("connector", connector), ("task", &task.to_string()), ("result", result)])
+                .label("instance", instance)
+                .total(*n),
+        );
+    }
+    all
+}
+
+/// Refresh the /metrics/delta body from every instance's last cycle.
+fn store_delta(app: &AppState, instances: &[Instance], states: &ScrapeState) {
+    let mut samples: Vec<registry::Sample> =
+        instances.iter().filter_map(|inst| states.get(&inst.url)).flat_map(|h| h.delta.iter().cloned()).collect();
+    add_extra_labels(&mut samples, &app.config.extra_labels);
+    *app.delta.write().unwrap() = samples.iter().map(|sample| format!("{}\n", sample)).collect();
+}
+
+/// Refresh the traces served by /api/v1/connectors/:name/errors from every
+/// instance's last known statuses.
+fn store_errors(app: &AppState, instances: &[Instance], states: &ScrapeState) {
+    let mut errors: BTreeMap<String, Vec<ConnectorErrors>> = BTreeMap::new();
+    for inst in instances {
+        let Some(history) = states.get(&inst.url) else { continue };
+        for (name, (_, status)) in &history.last_statuses {
+            let tasks: Vec<TaskError> = status
+                .tasks
+                .iter()
+                .filter_map(|task| {
+                    let trace = task.trace.clone()?;
+                    Some(TaskError {
+                        id: task.id,
+                        state: task.state.clone(),
+                        worker_id: task.worker_id.clone(),
+                        exception_class: exception_class(&trace),
+                        trace,
+                    })
+                })
+                .collect();
+            if tasks.is_empty() && status.connector.trace.is_none() {
+                continue;
+            }
+            errors.entry(name.clone()).or_default().push(ConnectorErrors {
+                instance: inst.label().to_owned(),
+                state: status.connector.state.clone(),
+                trace: status.connector.trace.clone(),
+                tasks,
+            });
+        }
+    }
+    *app.errors.write().unwrap() = errors;
+}
+
+/// Refresh the document served by /api/v1/connectors from every instance's
+/// last known statuses.
+fn store_connectors(app: &AppState, instances: &[Instance], states: &ScrapeState) {
+    let connectors = instances
+        .iter()
+        .map(|inst| {
+            let history = states.get(&inst.url);
+            let mut connectors: Vec<ConnectorSummary> = history
+                .map_or(&HashMap::new(), |h| &h.last_statuses)
+                .iter()
+                .map(|(name, (_, status))| ConnectorSummary {
+                    name: name.clone(),
+                    connector_type: status.connector_type.clone(),
+                    state: status.connector.state.clone(),
+                    worker_id: status.connector.worker_id.clone(),
+                    tasks: status
+                        .tasks
+                        .iter()
+                        .map(|task| TaskSummary {
+                            id: task.id,
+                            state: task.state.clone(),
+                            worker_id: task.worker_id.clone(),
+                        })
+                        .collect(),
+                })
+                .collect();
+            connectors.sort_by(|a, b| a.name.cmp(&b.name));
+            InstanceConnectors {
+                instance: inst.label().to_owned(),
+                cluster: inst.cluster.clone(),
+                up: history.is_some_and(|h| h.up),
+                last_scrape_time: history
+                    .and_then(|h| h.last_success)
+                    .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs()),
+                connectors,
+            }
+        })
+        .collect();
+    *app.connectors.write().unwrap() = connectors;
+}
+
+/// Record which instances the latest scrapes reached, for /health.
+fn store_health(app: &AppState, instances: &[Instance], states: &ScrapeState) {
+    let down = instances
+        .iter()
+        .filter(|inst| states.get(&inst.url).is_none_or(|h| !h.up))
+        .map(|inst| inst.label().to_owned())
+        .collect();
+    *app.health.write().unwrap() = ScrapeHealth { updated: Some(Instant::now()), instances: instances.len(), down };
+}
+
+/// Hand the samples of a fresh exposition to the cache and the push sinks.
+fn publish(app: &AppState, mut samples: Vec<registry::Sample>) {
+    add_extra_labels(&mut samples, &app.config.extra_labels);
+    // One copy, shared by the cache and every sink
+    let snapshot = registry::Snapshot::from(samples);
+    for tx in [&app.remote_write, &app.otlp_metrics, &app.dogstatsd, &app.pushgateway].into_iter().flatten() {
+        tx.send_replace(snapshot.clone());
+    }
+    // Rules wait out maintenance, so planned work neither fires nor resolves alerts
+    if let Some(alerts) = app.alerts.as_ref().filter(|_| !app.maintenance.load(Ordering::Relaxed)) {
+        alerts.evaluate(&snapshot);
+    }
+    store_metrics(&app.cache, &snapshot, app.config.cache_gzip, &app.config.extra_labels);
+    *app.samples.write().unwrap() = snapshot;
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    app.published.store(now.as_nanos() as u64, Ordering::Relaxed);
+}
+
+/// `kafka_connect_plugin_version_skew` for every plugin class of every
+/// cluster group: 1 when the workers of the group report different versions.
+fn plugin_skew_lines(instances: &[Instance], states: &ScrapeState) -> Vec<registry::Sample> {
+    // cluster -> class -> versions seen across its up workers
+    let mut groups: BTreeMap<&str, BTreeMap<&str, Vec<&str>>> = BTreeMap::new();
+    for inst in instances {
+        let (Some(cluster), Some(history)) = (&inst.cluster, states.get(&inst.url)) else {
+            continue;
+        };
+        if !history.up {
+            continue;
+        }
+        let classes = groups.entry(cluster).or_default();
+        for plugin in &history.plugins {
+            let versions = classes.entry(&plugin.class).or_default();
+            if !versions.contains(&plugin.version.as_str()) {
+                versions.push(&plugin.version);
+            }
+        }
+    }
+
+    let mut lines = Vec::new();
+    for (cluster, classes) in groups {
+        for (class, versions) in classes {
+            if versions.len() > 1 {
+                warn!("Plugin version skew in cluster {}: {} at {:?}", cluster, class, versions);
+            }
+            lines.push(
+                registry::gauge("kafka_connect_plugin_version_skew")
+                    .labels(&[("cluster", cluster), ("class", class)])
+                    .set(versions.len() > 1),
+            );
+        }
+    }
+    lines
+}
+
+/// `kafka_connect_connector_name_collision` for every connector name seen on
+/// more than one cluster. Instances sharing a `cluster` count once.
+fn name_collision_lines(instances: &[Instance], states: &ScrapeState) -> Vec<registry::Sample> {
+    // connector -> clusters (or instance URLs) it was listed on
+    let mut seen: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for inst in instances {
+        let Some(history) = states.get(&inst.url).filter(|h| h.up) else {
+            continue;
+        };
+        let group = inst.cluster.as_deref().unwrap_or(&inst.url);
+        for name in history.connector_states.keys() {
+            let groups = seen.entry(name).or_default();
+            if !groups.contains(&group) {
+                groups.push(group);
+            }
+        }
+    }
+
+    seen.into_iter()
+        .filter(|(_, groups)| groups.len() > 1)
+        .map(|(name, groups)| {
+            registry::gauge("kafka_connect_connector_name_collision").label("connector", name).set(groups.len())
+        })
+        .collect()
+}
+
+// ── Background scrape loop ────────────────────────────────────────────────────
+
+/// A random offset below `window` for each of `count` instances: the phase
+/// within the interval (SCRAPE_JITTER), or the startup splay
+/// (SCRAPE_INITIAL_SPLAY_SECS).
+fn scrape_phases(count: usize, window: Duration) -> Vec<Duration> {
+    let mut rng = rand::rng();
+    (0..count).map(|_| window.mul_f64(rng.random_range(0.0..1.0))).collect()
+}
+
+/// `interval`, longer or shorter by a random amount of up to `jitter` times
+/// itself (SCRAPE_INTERVAL_JITTER_PERCENT).
+fn jittered(interval: Duration, jitter: f64) -> Duration {
+    if jitter <= 0.0 {
+        return interval;
+    }
+    interval.mul_f64(1.0 + rand::rng().random_range(-jitter..=jitter))
+}
+
+/// Publish every instance's last scrape, and refresh what is derived from
+/// the whole set: /metrics/delta, /health, the JSON APIs and task restarts.
+fn publish_scraped(app: &AppState) {
+    let instances = app.instances();
+    let samples = {
+        let states = app.scraped.lock().unwrap();
+        store_delta(app, &instances, &states);
+        store_health(app, &instances, &states);
+        store_errors(app, &instances, &states);
+        store_connectors(app, &instances, &states);
+        restart_failed_tasks(app, &instances, &states);
+        render(app, &instances, &states)
+    };
+    publish(app, samples);
+}
+
+/// How long the scrape loop waits after a scrape lands before publishing,
+/// so scrapes that land together are rendered and encoded once.
+const PUBLISH_DEBOUNCE: Duration = Duration::from_secs(1);
+
+/// Keep one scrape task per instance, restarting the set as instances come
+/// and go, and publish whenever scrapes land. `cycle_done` is sent once
+/// every instance has been scraped for the first time. A scrape that is
+/// running when SIGTERM arrives is finished and published.
+async fn scrape_loop(app: AppState) {
+    if let Some(min) = app.config.scrape_on_demand {
+        return on_demand_loop(app, min).await;
+    }
+    let interval = app.config.scrape_interval;
+    let jitter = app.config.scrape_jitter;
+    let limit = match app.config.instance_concurrency {
+        0 => None,
+        n => Some(Arc::new(Semaphore::new(n))),
+    };
+    let mut tasks: HashMap<String, tokio::task::JoinHandle<()>> = HashMap::new();
+    let mut rescrape = app.rescrape.subscribe();
+    let mut shutdown = app.shutdown.clone();
+    let mut starting = true;
+    loop {
+        let instances = app.instances();
+        tasks.retain(|url, task| {
+            let keep = instances.iter().any(|i| i.url == *url);
+            if !keep {
+                task.abort();
+            }
+            keep && !task.is_finished()
+        });
+        app.scraped.lock().unwrap().retain(|url, _| instances.iter().any(|i| i.url == *url));
+        let now = tokio::time::Instant::now();
+        for inst in instances.iter() {
+            if tasks.contains_key(&inst.url) {
+                continue;
+            }
+            // A new instance, at startup or from a reload, is first scraped
+            // after its splay, then at its own random phase in every interval
+            // (SCRAPE_JITTER), so requests spread evenly over it
+            let first = now + app.config.initial_splay.map_or(Duration::ZERO, |splay| scrape_phases(1, splay)[0]);
+            let phase = if jitter { scrape_phases(1, interval)[0] } else { Duration::ZERO };
+            let task = instance_loop(app.clone(), inst.url.clone(), first, phase, limit.clone());
+            tasks.insert(inst.url.clone(), tokio::spawn(task));
+        }
+        if starting && instances.iter().all(|i| app.scraped.lock().unwrap().contains_key(&i.url)) {
+            starting = false;
+            app.cycle_done.send_replace(Instant::now());
+        }
+
+        let landed = tokio::select! {
+            _ = app.landed.notified() => true,
+            // A reload or maintenance toggle applies right away
+            _ = rescrape.changed() => false,
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        };
+        if landed {
+            tokio::time::sleep(PUBLISH_DEBOUNCE).await;
+        }
+        publish_scraped(&app);
+    }
+    join_all(tasks.into_values()).await;
+    publish_scraped(&app);
+}
+
+/// Scrape `url` at `first`, then every SCRAPE_INTERVAL_SECS after the start
+/// of its last scrape, the first gap longer by `phase`, or when a rescrape
+/// is asked for, and hand each result to the scrape loop. With FAILED_RECHECK_SECS, its FAILED connectors are
+/// polled in between and it is re-scraped as soon as one of them recovers.
+async fn instance_loop(
+    app: AppState,
+    url: String,
+    first: tokio::time::Instant,
+    mut phase: Duration,
+    limit: Option<Arc<Semaphore>>,
+) {
+    let current = || app.instances().iter().find(|i| i.url == url).cloned();
+    let mut history = app.scraped.lock().unwrap().get(&url).cloned().unwrap_or_default();
+    let mut rescrape = app.rescrape.subscribe();
+    let mut shutdown = app.shutdown.clone();
+    let recheck = app.config.failed_recheck;
+    let mut next = first;
+    loop {
+        let rechecking = tokio::select! {
+            _ = tokio::time::sleep_until(next) => false,
+            _ = rescrape.changed() => false,
+            _ = shutdown.wait_for(|stop| *stop) => return,
+            _ = tokio::time::sleep(recheck.unwrap_or_default()), if recheck.is_some() => true,
+        };
+        let Some(inst) = current() else { return };
+        if rechecking && !recheck_failed(&app, &inst, &history).await {
+            continue;
+        }
+        let permit = match &limit {
+            Some(limit) => Some(limit.clone().acquire_owned().await.expect("semaphore is never closed")),
+            None => None,
+        };
+        let start = tokio::time::Instant::now();
+        let deadline = app.config.cycle_budget.map(|b| start + b);
+        history.output = scrape_history(&app, &mut history, &inst, deadline).await;
+        drop(permit);
+        app.scraped.lock().unwrap().insert(url.clone(), history.clone());
+        app.landed.notify_one();
+        next = start + jittered(app.config.scrape_interval, app.config.interval_jitter) + std::mem::take(&mut phase);
+    }
+}
+
+/// SCRAPE_ON_DEMAND: a cycle over every instance at startup, then each time
+/// /metrics asks for one, at most once per `min`.
+async fn on_demand_loop(app: AppState, min: Duration) {
+    let mut rescrape = app.rescrape.subscribe();
+    let mut shutdown = app.shutdown.clone();
+    // The startup cycle runs right away
+    let mut starting = true;
+    loop {
+        if !std::mem::take(&mut starting) {
+            tokio::select! {
+                _ = async {
+                    tokio::time::sleep(min).await;
+                    let _ = rescrape.changed().await;
+                } => {}
+                _ = shutdown.wait_for(|stop| *stop) => return,
+            }
+        }
+        let instances = app.instances();
+        // The cycle owns the states while it runs; nothing else scrapes
+        let mut states = std::mem::take(&mut *app.scraped.lock().unwrap());
+        states.retain(|url, _| instances.iter().any(|i| i.url == *url));
+        // Instances not scraped before wait out their splay
+        let offsets: Vec<Duration> = instances
+            .iter()
+            .map(|i| match app.config.initial_splay.filter(|_| !states.contains_key(&i.url)) {
+                Some(splay) => scrape_phases(1, splay)[0],
+                None => Duration::ZERO,
+            })
+            .collect();
+        scrape_all(&app, &instances, &mut states, &offsets).await;
+        *app.scraped.lock().unwrap() = states;
+        publish_scraped(&app);
+        app.cycle_done.send_replace(Instant::now());
+    }
+}
+
+/// Poll the connectors that were FAILED in the last scrape of `inst`; true
+/// when one of them left FAILED.
+async fn recheck_failed(app: &AppState, inst: &Instance, history: &InstanceState) -> bool {
+    if !history.up {
+        return false;
+    }
+    let failed = history.connector_states.iter().filter(|(_, state)| *state == "failed");
+    for (name, _) in failed {
+        match fetch_status(inst.client(&app.client), inst, name).await {
+            Ok(status) if !status.connector.state.eq_ignore_ascii_case("failed") => {
+                info!("Connector {} on {} left FAILED, re-scraping", name, inst.url);
+                return true;
+            }
+            Ok(_) => {}
+            Err(e) => debug!("Recheck of {} on {} failed: {}", name, inst.url, e),
+        }
+    }
+    false
+}
+
+// ── HTTP handlers ─────────────────────────────────────────────────────────────
+
+/// Content coding of an exposition response.
+#[derive(Clone, Copy, PartialEq)]
+enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+/// Bodies smaller than this are sent uncompressed.
+const COMPRESS_MIN_BYTES: usize = 1024;
+
+/// The coding the request's Accept-Encoding ranks highest among gzip and
+/// deflate, gzip on a tie; none when it accepts neither.
+fn accepted_encoding(headers: &HeaderMap) -> Option<Encoding> {
+    let (mut gzip, mut deflate, mut any) = (None, None, None);
+    let values = headers.get_all(header::ACCEPT_ENCODING);
+    for enc in values.iter().filter_map(|v| v.to_str().ok()).flat_map(|v| v.split(',')) {
+        let mut parts = enc.split(';').map(str::trim);
+        let coding = parts.next().unwrap_or("").to_ascii_lowercase();
+        let q = parts
+            .filter_map(|p| p.replace(' ', "").strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()))
+            .next()
+            .unwrap_or(1.0);
+        match coding.as_str() {
+            "gzip" | "x-gzip" => gzip = Some(q),
+            "deflate" => deflate = Some(q),
+            "*" => any = Some(q),
+            _ => {}
+        }
+    }
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    let deflate = deflate.or(any).unwrap_or(0.0);
+    if gzip > 0.0 && gzip >= deflate {
+        Some(Encoding::Gzip)
+    } else if deflate > 0.0 {
+        Some(Encoding::Deflate)
+    } else {
+        None
+    }
+}
+
+/// Exposition format asked for by the request's Accept header.
+fn accept_format(headers: &HeaderMap) -> registry::Format {
+    let accept: Vec<&str> = headers.get_all(header::ACCEPT).iter().filter_map(|v| v.to_str().ok()).collect();
+    registry::Format::negotiate(&accept.join(","))
+}
+
+/// `text` (classic text format) as a response in `format`.
+fn exposition_response(format: registry::Format, text: Bytes, encoding: Option<Encoding>) -> Response {
+    let body = match format {
+        registry::Format::Text => text,
+        registry::Format::OpenMetrics => Bytes::from(registry::to_openmetrics(&String::from_utf8_lossy(&text))),
+    };
+    let headers = [(header::CONTENT_TYPE, format.content_type()), (header::VARY, "accept, accept-encoding")];
+    match encoding.filter(|_| body.len() >= COMPRESS_MIN_BYTES) {
+        Some(Encoding::Gzip) => {
+            (headers, [(header::CONTENT_ENCODING, "gzip")], gzip::compress(&body)).into_response()
+        }
+        Some(Encoding::Deflate) => {
+            (headers, [(header::CONTENT_ENCODING, "deflate")], gzip::zlib_compress(&body)).into_response()
+        }
+        None => (headers, body).into_response(),
+    }
+}
+
+/// With SCRAPE_ON_DEMAND, have the scrape loop run a cycle when the last
+/// one finished more than the minimum interval ago, and wait for it.
+/// Requests arriving meanwhile wait for the same cycle.
+async fn scrape_on_demand(state: &AppState) {
+    let Some(min) = state.config.scrape_on_demand else { return };
+    let mut done = state.cycle_done.subscribe();
+    let mut shutdown = state.shutdown.clone();
+    if done.borrow_and_update().elapsed() < min || *shutdown.borrow() {
+        return;
+    }
+    state.rescrape.send_replace(());
+    // The loop stops on shutdown, so the cache is served as it is
+    tokio::select! {
+        _ = done.changed() => {}
+        _ = shutdown.changed() => {}
+    }
+}
+
+async fn metrics_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    scrape_on_demand(&state).await;
+    let format = accept_format(&headers);
+    let encoding = accepted_encoding(&headers);
+    // Only a reference count is taken, so the lock is released right away
+    let cached = state.cache.read().unwrap().clone();
+    let text = match cached {
+        // The stored bytes are the text format; OpenMetrics is re-encoded
+        CachedMetrics::Gzip(gz) if format == registry::Format::Text && encoding == Some(Encoding::Gzip) => {
+            return (
+                [
+                    (header::CONTENT_TYPE, registry::TEXT_CONTENT_TYPE),
+                    (header::CONTENT_ENCODING, "gzip"),
+                    (header::VARY, "accept, accept-encoding"),
+                ],
+                gz,
+            )
+                .into_response();
+        }
+        cached => match cached.text() {
+            Ok(text) => text,
+            Err(e) => {
+                warn!("Failed to decompress metrics cache: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        },
+    };
+    exposition_response(format, text, encoding)
+}
+
+/// The cached samples in InfluxDB line protocol, stamped with the time of
+/// the cycle that filled the cache.
+async fn influx_handler(State(state): State<AppState>) -> Response {
+    let samples = state.samples.read().unwrap().clone();
+    let body = influx::render(&samples, state.published.load(Ordering::Relaxed));
+    ([(header::CONTENT_TYPE, influx::CONTENT_TYPE)], body).into_response()
+}
+
+#[derive(Deserialize)]
+struct ProbeParams {
+    target: String,
+    #[serde(default)]
+    module: Option<String>,
+}
+
+/// Check a /probe target against PROBE_ALLOWED_HOSTS; only plain http(s)
+/// base URLs to allowlisted hosts are scraped, so /probe cannot be used to
+/// reach arbitrary internal addresses.
+fn probe_target(target: &str, allowed: &[String]) -> Result<String, &'static str> {
+    let url = reqwest::Url::parse(target).map_err(|_| "target is not a valid URL")?;
+    if url.query().is_some() {
+        return Err("target must not carry credentials or a query");
+    }
+    probe_host_allowed(&url, allowed)?;
+    Ok(target.trim_end_matches('/').to_owned())
+}
+
+/// `url` is http or https, without credentials, on a host of `allowed`
+/// (PROBE_ALLOWED_HOSTS, as `host` or `host:port`).
+fn probe_host_allowed(url: &reqwest::Url, allowed: &[String]) -> Result<(), &'static str> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("target scheme must be http or https");
+    }
+    if !url.username().is_empty() || url.password().is_some() {
+        return Err("target must not carry credentials or a query");
+    }
+    let host = url.host_str().ok_or("target has no host")?;
+    let host_port = format!("{}:{}", host, url.port_or_known_default().unwrap_or_default());
+    if !allowed.iter().any(|a| a.eq_ignore_ascii_case(host) || a.eq_ignore_ascii_case(&host_port)) {
+        return Err("target host is not in PROBE_ALLOWED_HOSTS");
+    }
+    Ok(())
+}
+
+/// Redirects of /probe requests: each hop must pass the same allowlist as
+/// `?target=`, so an allowed host cannot send the exporter elsewhere.
+fn probe_redirects(allowed: Vec<String>) -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() >= 10 {
+            return attempt.error("too many redirects");
+        }
+        match probe_host_allowed(attempt.url(), &allowed) {
+            Ok(()) => attempt.follow(),
+            Err(e) => {
+                let error = format!("redirect to {} refused: {}", attempt.url(), e);
+                attempt.error(error)
+            }
+        }
+    })
+}
+
+/// Blackbox-style one-off scrape of `?target=` with the settings of
+/// `?module=`, independent of the configured instances and their
+/// cross-scrape state. Module `default`, unless the config file defines
+/// it, scrapes without credentials through the shared client.
+async fn probe_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<ProbeParams>,
+) -> Response {
+    let url = match probe_target(&params.target, &state.config.probe_allowed_hosts) {
+        Ok(url) => url,
+        Err(e) => {
+            warn!("probe of {} refused: {}", params.target, e);
+            return (StatusCode::FORBIDDEN, format!("{}\n", e)).into_response();
+        }
+    };
+    let module = params.module.as_deref().unwrap_or("default");
+    let mut inst = match state.config.probe_modules.get(module) {
+        Some(template) => template.clone(),
+        None if module == "default" => Instance::default(),
+        None => return (StatusCode::BAD_REQUEST, format!("unknown probe module {}\n", module)).into_response(),
+    };
+    inst.url = url;
+
+    // Finish within Prometheus' scrape timeout, when it sends one
+    let deadline = headers
+        .get("x-prometheus-scrape-timeout-seconds")
+        .and_then(|v| v.to_str().ok()?.parse::<f64>().ok())
+        .map(|secs| tokio::time::Instant::now() + Duration::from_secs_f64((secs - 0.5).max(0.1)));
+    let opts = ScrapeOptions {
+        suppress_outages: false,
+        emit_edges: false,
+        fetch_config: false,
+        describe: false,
+        fetch_topics: false,
+        split_by_type: state.config.split_summary_by_type,
+        down_policy: DownPolicy::Absent,
+        keep_cycles: 0,
+        concurrency: state.config.fetch_concurrency,
+        prioritize_failed: false,
+        deadline,
+        events: None,
+        auth: inst.auth.as_ref(),
+        timeout: inst.timeout(),
+        tenant_key: None,
+        extra_fields: &[],
+        retries: state.config.request_retries,
+        retry_backoff: state.config.retry_backoff,
+        flap_window: state.config.flap_window,
+    };
+    let mut history = InstanceState::default();
+    let mut samples = scrape_connect(inst.client(&state.probe_client), &inst, &mut history, opts).await;
+    add_extra_labels(&mut samples, &state.config.extra_labels);
+    let body = Bytes::from(registry::encode(&samples));
+    exposition_response(accept_format(&headers), body, accepted_encoding(&headers))
+}
+
+async fn delta_handler(State(state): State<AppState>) -> String {
+    state.delta.read().unwrap().clone()
+}
+
+/// Connector and task states of every instance as of its last scrape.
+async fn connectors_handler(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let instances = state.connectors.read().unwrap().clone();
+    Json(serde_json::json!({ "instances": instances }))
+}
+
+/// Failure traces of a connector as of the last scrape. Empty `errors` when
+/// no instance reports a trace for it, including unknown names.
+async fn errors_handler(State(state): State<AppState>, Path(name): Path<String>) -> Json<serde_json::Value> {
+    let errors = state.errors.read().unwrap().get(&name).cloned().unwrap_or_default();
+    Json(serde_json::json!({ "connector": name, "errors": errors }))
+}
+
+/// Why the scrapes count as stalled: none completed yet, or none refreshed
+/// the cache for HEALTH_STALE_INTERVALS scrape intervals.
+fn stall_reason(health: &ScrapeHealth, config: &Config) -> Option<String> {
+    // On demand, a quiet /metrics is no stall
+    let stale_after = match config.scrape_on_demand {
+        Some(_) => Duration::ZERO,
+        None => config.scrape_interval * config.health_stale_intervals,
+    };
+    match health.updated {
+        None => Some("no scrape has completed yet".to_owned()),
+        Some(at) if !stale_after.is_zero() && at.elapsed() > stale_after => {
+            Some(format!("no scrape completed in the last {}s", at.elapsed().as_secs()))
+        }
+        _ => None,
+    }
+}
+
+fn unhealthy(reason: String, failing: &[String]) -> Response {
+    let body = serde_json::json!({
+        "status": "fail",
+        "reason": reason,
+        "failing": failing,
+    });
+    (StatusCode::SERVICE_UNAVAILABLE, Json(body)).into_response()
+}
+
+/// `ok`, or `503` with the reason when the scrapes stalled or every
+/// instance was unreachable in its last scrape.
+async fn health_handler(State(state): State<AppState>) -> Response {
+    let health = state.health.read().unwrap();
+    let reason = stall_reason(&health, &state.config).or_else(|| {
+        (health.instances > 0 && health.down.len() == health.instances)
+            .then(|| "no instance was reachable in its last scrape".to_owned())
+    });
+    match reason {
+        Some(reason) => unhealthy(reason, &health.down),
+        None => "ok".into_response(),
+    }
+}
+
+/// Liveness: the scrape loop keeps refreshing the cache. Connect being
+/// down does not fail it, since a restart would not help.
+async fn livez_handler(State(state): State<AppState>) -> Response {
+    let health = state.health.read().unwrap();
+    match stall_reason(&health, &state.config) {
+        Some(reason) => unhealthy(reason, &[]),
+        None => "ok".into_response(),
+    }
+}
+
+/// Readiness: the cache holds a completed scrape and no shutdown is under
+/// way.
+async fn readyz_handler(State(state): State<AppState>) -> Response {
+    if *state.shutdown.borrow() {
+        return unhealthy("shutting down".to_owned(), &[]);
+    }
+    match state.health.read().unwrap().updated {
+        Some(_) => "ok".into_response(),
+        None => unhealthy("no scrape has completed yet".to_owned(), &[]),
+    }
+}
+
+/// The health endpoints, added after the web auth layer so probes need no
+/// credentials.
+fn with_probes(router: Router<AppState>) -> Router<AppState> {
+    router
+        .route("/health", get(health_handler))
+        .route("/livez", get(livez_handler))
+        .route("/readyz", get(readyz_handler))
+}
+
+#[derive(Serialize)]
+struct SelftestInstance {
+    instance: String,
+    reachable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    latency_ms: u128,
+}
+
+/// Fresh connectivity check against the connector list endpoint of one instance.
+async fn selftest_instance(
+    client: &reqwest::Client,
+    inst: &Instance,
+    timeout: Duration,
+) -> SelftestInstance {
+    let started = Instant::now();
+    let result = inst
+        .authorize(client.get(format!("{}/connectors", inst.url)))
+        .timeout(timeout)
+        .send()
+        .await;
+    let latency_ms = started.elapsed().as_millis();
+    let instance = inst.label().to_owned();
+
+    match result {
+        Ok(r) => SelftestInstance {
+            instance,
+            reachable: r.status().is_success(),
+            status: Some(r.status().as_u16()),
+            error: None,
+            latency_ms,
+        },
+        Err(e) => SelftestInstance {
+            instance,
+            reachable: false,
+            status: None,
+            error: Some(e.to_string()),
+            latency_ms,
+        },
+    }
+}
+
+async fn selftest_handler(State(state): State<AppState>) -> Response {
+    // Rate-limit: at most one active probe round per SELFTEST_MIN_INTERVAL_SECS
+    {
+        let mut last = state.last_selftest.lock().unwrap();
+        if let Some(at) = *last {
+            let since = at.elapsed();
+            if since < state.config.selftest_min_interval {
+                let retry_after = (state.config.selftest_min_interval - since).as_secs() + 1;
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, retry_after.to_string())],
+                    "selftest rate-limited\n",
+                )
+                    .into_response();
+            }
+        }
+        *last = Some(Instant::now());
+    }
+
+    let instances = state.instances();
+    let results = join_all(instances.iter().map(|inst| {
+        selftest_instance(inst.client(&state.client), inst, state.config.selftest_timeout)
+    }))
+    .await;
+
+    let ok = results.iter().all(|r| r.reachable);
+    for r in results.iter().filter(|r| !r.reachable) {
+        warn!("selftest: {} unreachable ({:?} {:?})", r.instance, r.status, r.error);
+    }
+    let code = if ok { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    let body = serde_json::json!({
+        "status": if ok { "ok" } else { "fail" },
+        "instances": results,
+    });
+    (code, Json(body)).into_response()
+}
+
+#[derive(Deserialize)]
+struct MaintenanceParams {
+    enabled: bool,
+}
+
+async fn maintenance_get_handler(State(state): State<AppState>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "maintenance": state.maintenance.load(Ordering::Relaxed) }))
+}
+
+async fn maintenance_put_handler(
+    State(state): State<AppState>,
+    Query(params): Query<MaintenanceParams>,
+) -> Json<serde_json::Value> {
+    let was = state.maintenance.swap(params.enabled, Ordering::Relaxed);
+    if was != params.enabled {
+        if params.enabled {
+            warn!("MAINTENANCE MODE ENABLED via admin API");
+        } else {
+            warn!("MAINTENANCE MODE DISABLED via admin API");
+        }
+        state.rescrape.send_replace(());
+    }
+    Json(serde_json::json!({ "maintenance": params.enabled }))
+}
+
+// ── Heal ──────────────────────────────────────────────────────────────────────
+
+#[derive(Deserialize)]
+struct HealParams {
+    /// Instance label or URL; optional when only one instance is configured.
+    #[serde(default)]
+    instance: Option<String>,
+}
+
+#[derive(Serialize)]
+struct HealOutcome {
+    connector: String,
+    instance: String,
+    /// healed | already_running | failed | timeout | error
+    result: &'static str,
+    restarts: u32,
+    /// Connector state at the last poll.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+enum Health {
+    Running,
+    Failed,
+    Pending,
+}
+
+/// RUNNING only when the connector and every task run; any FAILED task
+/// counts as failed.
+fn connector_health(status: &ConnectorStatus) -> Health {
+    let states = std::iter::once(&status.connector.state).chain(status.tasks.iter().map(|t| &t.state));
+    let mut all_running = true;
+    for state in states {
+        match state.as_str() {
+            "FAILED" => return Health::Failed,
+            "RUNNING" => {}
+            _ => all_running = false,
+        }
+    }
+    if all_running { Health::Running } else { Health::Pending }
+}
+
+async fn fetch_status(client: &reqwest::Client, inst: &Instance, name: &str) -> Result<ConnectorStatus, String> {
+    let resp = inst
+        .authorize(client.get(connector_url(&inst.url, name, "status")).timeout(inst.timeout()))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !resp.status().is_success() {
+        return Err(format!("status returned HTTP {}", resp.status()));
+    }
+    resp.json().await.map_err(|e| e.to_string())
+}
+
+/// Restart the connector and its failed tasks (Connect 3.0+; older workers
+/// ignore the parameters and restart only the connector).
+async fn restart_connector(client: &reqwest::Client, inst: &Instance, name: &str) -> Result<(), String> {
+    let url = format!("{}?includeTasks=true&onlyFailed=true", connector_url(&inst.url, name, "restart"));
+    let resp = inst
+        .authorize(client.post(url).timeout(inst.timeout()))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !resp.status().is_success() {
+        return Err(format!("restart returned HTTP {}", resp.status()));
+    }
+    Ok(())
+}
+
+/// The manual runbook: restart, watch the status, restart again if it
+/// re-fails, until RUNNING, HEAL_MAX_RESTARTS or HEAL_TIMEOUT_SECS.
+async fn heal(state: &AppState, inst: &Instance, name: &str) -> HealOutcome {
+    let config = &state.config;
+    let deadline = Instant::now() + config.heal_timeout;
+    let mut outcome = HealOutcome {
+        connector: name.to_owned(),
+        instance: inst.label().to_owned(),
+        result: "error",
+        restarts: 0,
+        state: None,
+        error: None,
+    };
+
+    let mut health = match fetch_status(inst.client(&state.client), inst, name).await {
+        Ok(status) => {
+            outcome.state = Some(status.connector.state.clone());
+            connector_health(&status)
+        }
+        Err(e) => {
+            outcome.error = Some(e);
+            return outcome;
+        }
+    };
+    if matches!(health, Health::Running) {
+        outcome.result = "already_running";
+        return outcome;
+    }
+
+    loop {
+        if matches!(health, Health::Failed) {
+            if outcome.restarts == config.heal_max_restarts {
+                outcome.result = "failed";
+                return outcome;
+            }
+            info!("heal: restarting {} on {} (attempt {})", name, inst.url, outcome.restarts + 1);
+            if let Err(e) = restart_connector(inst.client(&state.client), inst, name).await {
+                outcome.error = Some(e);
+                return outcome;
+            }
+            outcome.restarts += 1;
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            outcome.result = "timeout";
+            return outcome;
+        }
+        tokio::time::sleep(config.heal_poll_interval.min(deadline - now)).await;
+
+        match fetch_status(inst.client(&state.client), inst, name).await {
+            Ok(status) => {
+                outcome.state = Some(status.connector.state.clone());
+                health = connector_health(&status);
+                if matches!(health, Health::Running) {
+                    outcome.result = "healed";
+                    return outcome;
+                }
+            }
+            // A worker mid-rebalance may not answer; keep polling
+            Err(e) => {
+                warn!("heal: status of {} on {} unavailable: {}", name, inst.url, e);
+                health = Health::Pending;
+            }
+        }
+    }
+}
+
+/// Marks a connector as being healed until dropped, so a client that goes
+/// away mid-heal, which drops the handler future, still releases it.
+struct HealGuard {
+    healing: Arc<Mutex<HashSet<(String, String)>>>,
+    key: (String, String),
+}
+
+impl HealGuard {
+    /// `None` when `key` is already being healed.
+    fn claim(healing: &Arc<Mutex<HashSet<(String, String)>>>, key: (String, String)) -> Option<Self> {
+        healing.lock().unwrap().insert(key.clone()).then(|| Self { healing: healing.clone(), key })
+    }
+}
+
+impl Drop for HealGuard {
+    fn drop(&mut self) {
+        self.healing.lock().unwrap().remove(&self.key);
+    }
+}
+
+async fn heal_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(params): Query<HealParams>,
+) -> Response {
+    if !state.config.heal_allowlist.contains(&name) {
+        return (StatusCode::FORBIDDEN, "connector is not in HEAL_ALLOWLIST\n").into_response();
+    }
+    let instances = state.instances();
+    let inst = match &params.instance {
+        Some(want) => instances.iter().find(|i| i.label() == want || i.url == *want),
+        None if instances.len() == 1 => instances.first(),
+        None => {
+            return (StatusCode::BAD_REQUEST, "instance is required with several instances\n")
+                .into_response()
+        }
+    };
+    let Some(inst) = inst else {
+        return (StatusCode::NOT_FOUND, "unknown instance\n").into_response();
+    };
+
+    let Some(guard) = HealGuard::claim(&state.healing, (inst.url.clone(), name.clone())) else {
+        return (StatusCode::CONFLICT, "heal already in progress\n").into_response();
+    };
+    warn!("heal of {} on {} requested via admin API", name, inst.url);
+    let outcome = heal(&state, inst, &name).await;
+    drop(guard);
+
+    info!("heal of {} on {}: {} after {} restart(s)", name, inst.url, outcome.result, outcome.restarts);
+    *state
+        .heal_attempts
+        .lock()
+        .unwrap()
+        .entry((name, outcome.result))
+        .or_default() += 1;
+
+    let code = match outcome.result {
+        "healed" | "already_running" => StatusCode::OK,
+        "timeout" => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::BAD_GATEWAY,
+    };
+    (code, Json(outcome)).into_response()
+}
+
+// ── Connector actions ─────────────────────────────────────────────────────────
+
+/// Instances of `cluster`: those with that `cluster`, or else the one
+/// labelled so. Instances up in their last scrape come first.
+fn cluster_instances(state: &AppState, cluster: &str) -> Vec<Instance> {
+    let instances = state.instances();
+    let mut members: Vec<Instance> =
+        instances.iter().filter(|i| i.cluster.as_deref() == Some(cluster)).cloned().collect();
+    if members.is_empty() {
+        members = instances.iter().filter(|i| i.cluster.is_none() && i.label() == cluster).cloned().collect();
+    }
+    let down = state.health.read().unwrap().down.clone();
+    members.sort_by_key(|i| down.iter().any(|d| d == i.label()));
+    members
+}
+
+/// `POST /api/v1/clusters/:cluster/connectors/:name/:action`: pause, resume
+/// or restart the connector through a worker of the cluster, passing the
+/// worker's answer on. A worker that cannot be reached is skipped for the
+/// next one. The query string of a restart (`includeTasks`, `onlyFailed`)
+/// is forwarded.
+async fn connector_action_handler(
+    State(state): State<AppState>,
+    Path((cluster, name, action)): Path<(String, String, String)>,
+    RawQuery(query): RawQuery,
+) -> Response {
+    let (method, query) = match action.as_str() {
+        "pause" | "resume" => (reqwest::Method::PUT, None),
+        "restart" => (reqwest::Method::POST, query),
+        _ => return (StatusCode::NOT_FOUND, "unknown action: expected pause, resume or restart\n").into_response(),
+    };
+    let members = cluster_instances(&state, &cluster);
+    if members.is_empty() {
+        return (StatusCode::NOT_FOUND, "unknown cluster\n").into_response();
+    }
+    warn!("{} of {} in cluster {} requested via admin API", action, name, cluster);
+
+    let mut errors = Vec::new();
+    for inst in &members {
+        let mut url = connector_url(&inst.url, &name, &action);
+        if let Some(query) = &query {
+            url = format!("{}?{}", url, query);
+        }
+        let req = inst.authorize(inst.client(&state.client).request(method.clone(), url).timeout(inst.timeout()));
+        let resp = match req.send().await {
+            Ok(resp) => resp,
+            Err(e) => {
+                warn!("{} of {}: {} unreachable, trying the next worker: {}", action, name, inst.url, e);
+                errors.push(format!("{}: {}", inst.label(), e));
+                continue;
+            }
+        };
+        let code = StatusCode::from_u16(resp.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+        let content_type = resp.headers().get(reqwest::header::CONTENT_TYPE).cloned();
+        let body = match resp.bytes().await {
+            Ok(body) => body,
+            Err(e) => return (StatusCode::BAD_GATEWAY, format!("{}: {}\n", inst.label(), e)).into_response(),
+        };
+        info!("{} of {} on {}: HTTP {}", action, name, inst.url, code.as_u16());
+        if code.is_success() {
+            // Show the new state without waiting for the next cycle
+            state.rescrape.send_replace(());
+        }
+        let mut response = (code, body).into_response();
+        if let Some(content_type) = content_type {
+            response.headers_mut().insert(header::CONTENT_TYPE, content_type);
+        }
+        return response;
+    }
+    (StatusCode::BAD_GATEWAY, format!("no worker of {} reachable: {}\n", cluster, errors.join("; "))).into_response()
+}
+
+// ── Task restarts ─────────────────────────────────────────────────────────────
+
+/// Automatic restarts of one FAILED task.
+struct TaskRestarts {
+    attempts: u32,
+    /// Earliest time of the next restart; a task that is not FAILED by then
+    /// counts as recovered.
+    next: Instant,
+    /// TASK_RESTART_MAX_ATTEMPTS was reached and counted.
+    gave_up: bool,
+}
+
+/// Wait after the `attempts`-th automatic restart of a task.
+fn task_restart_backoff(config: &Config, attempts: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+    config.task_restart_backoff.saturating_mul(factor).min(config.task_restart_max_backoff)
+}
+
+async fn restart_task(client: &reqwest::Client, inst: &Instance, name: &str, task: u32) -> Result<(), String> {
+    let url = connector_url(&inst.url, name, &format!("tasks/{}/restart", task));
+    let resp = inst
+        .authorize(client.post(url).timeout(inst.timeout()))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !resp.status().is_success() {
+        return Err(format!("restart returned HTTP {}", resp.status()));
+    }
+    Ok(())
+}
+
+/// With TASK_AUTO_RESTART, restart the FAILED tasks of the latest statuses
+/// whose backoff has passed, in the background. Nothing is restarted in
+/// maintenance mode or while the connector is being healed. A task that
+/// is not FAILED once its backoff has passed starts over.
+fn restart_failed_tasks(app: &AppState, instances: &[Instance], states: &ScrapeState) {
+    let config = &app.config;
+    if !config.task_auto_restart || app.maintenance.load(Ordering::Relaxed) {
+        return;
+    }
+    let now = Instant::now();
+    let healing = app.healing.lock().unwrap().clone();
+    let mut restarts = app.task_restarts.lock().unwrap();
+    let mut seen = HashSet::new();
+    let mut due = Vec::new();
+    for inst in instances {
+        let Some(history) = states.get(&inst.url).filter(|h| h.up) else { continue };
+        for (name, (_, status)) in &history.last_statuses {
+            for task in &status.tasks {
+                let key = (inst.url.clone(), name.clone(), task.id);
+                seen.insert(key.clone());
+                if task.state != "FAILED" {
+                    if restarts.get(&key).is_some_and(|r| now >= r.next) {
+                        restarts.remove(&key);
+                    }
+                    continue;
+                }
+                let entry = restarts.entry(key).or_insert(TaskRestarts { attempts: 0, next: now, gave_up: false });
+                if now < entry.next || healing.contains(&(inst.url.clone(), name.clone())) {
+                    continue;
+                }
+                if entry.attempts >= config.task_restart_max_attempts {
+                    if !entry.gave_up {
+                        entry.gave_up = true;
+                        warn!(
+                            "Task {} of {} on {} still FAILED after {} restarts, leaving it",
+                            task.id, name, inst.url, entry.attempts
+                        );
+                        *app.task_restarts_total
+                            .lock()
+                            .unwrap()
+                            .entry(((inst.label().to_owned(), name.clone(), task.id), "gave_up"))
+                            .or_default() += 1;
+                    }
+                    continue;
+                }
+                entry.attempts += 1;
+                entry.next = now + task_restart_backoff(config, entry.attempts);
+                due.push((inst.clone(), name.clone(), task.id, entry.attempts));
+            }
+        }
+    }
+    // Instances that are down keep their records until they answer again
+    restarts.retain(|key, _| {
+        seen.contains(key) || !instances.iter().any(|i| i.url == key.0 && states.get(&i.url).is_some_and(|h| h.up))
+    });
+    drop(restarts);
+    if due.is_empty() {
+        return;
+    }
+
+    let app = app.clone();
+    tokio::spawn(async move {
+        for (inst, name, task, attempt) in due {
+            let result = match restart_task(inst.client(&app.client), &inst, &name, task).await {
+                Ok(()) => {
+                    info!("Restarted FAILED task {} of {} on {} (attempt {})", task, name, inst.url, attempt);
+                    "restarted"
+                }
+                Err(e) => {
+                    warn!("Failed to restart task {} of {} on {}: {}", task, name, inst.url, e);
+                    "error"
+                }
+            };
+            *app.task_restarts_total
+                .lock()
+                .unwrap()
+                .entry(((inst.label().to_owned(), name, task), result))
+                .or_default() += 1;
+        }
+    });
+}
+
+// ── Main ──────────────────────────────────────────────────────────────────────
+
+#[tokio::main]
+async fn main() {
+    let args = cli::args();
+
+    // RUST_LOG (or --log-level) only filters the log output; span export has
+    // its own filter so the trace-level scrape spans never reach the logs
+    let log_filter = match &args.log_level {
+        Some(level) => tracing_subscriber::EnvFilter::try_new(level).unwrap_or_else(|e| {
+            eprintln!("error: invalid value '{}' for '--log-level': {}", level, e);
+            std::process::exit(2);
+        }),
+        None => tracing_subscriber::EnvFilter::from_default_env()
+            .add_directive("kafka_connect_exporter=info".parse().unwrap()),
+    };
+    let otlp_endpoint = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok().filter(|e| !e.is_empty());
+    let (otlp_layer, otlp_queue) = match &otlp_endpoint {
+        Some(_) => {
+            let (layer, rx, dropped) = otlp::layer();
+            let filter = tracing_subscriber::filter::filter_fn(|meta| {
+                meta.target().starts_with("kafka_connect_exporter")
+                    && (meta.is_span() || *meta.level() <= tracing::Level::INFO)
+            });
+            (Some(layer.with_filter(filter)), Some((rx, dropped)))
+        }
+        None => (None, None),
+    };
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(log_filter))
+        .with(otlp_layer)
+        .init();
+
+    let config = Arc::new(Config::load(&args));
+    // Shared by instances without TLS settings and the token and push
+    // endpoints, and by /probe with its redirects checked; both trust
+    // KAFKA_CONNECT_CA_FILE too
+    let shared_tls = tls::ClientTls { ca_file: config.ca_file.clone(), ..Default::default() };
+    let client = shared_tls
+        .client(REQUEST_TIMEOUT, None, reqwest::redirect::Policy::default())
+        .unwrap_or_else(|e| panic!("Failed to build HTTP client: {}", e));
+    let probe_client = shared_tls
+        .client(REQUEST_TIMEOUT, None, probe_redirects(config.probe_allowed_hosts.clone()))
+        .unwrap_or_else(|e| panic!("Failed to build HTTP client: {}", e));
+
+    // HTTPS on every listener when a certificate is configured; rotated
+    // files are picked up by the watcher
+    let acceptor = config.server_tls.clone().map(|(cert, key)| {
+        let cert = tls::ServerCert::load(cert, key)
+            .unwrap_or_else(|e| panic!("Invalid TLS_CERT_FILE / TLS_KEY_FILE: {}", e));
+        let cert = Arc::new(cert);
+        tokio::spawn(cert.clone().watch());
+        cert.acceptor()
+    });
+    let scheme = if acceptor.is_some() { "https" } else { "http" };
+
+    let cache: MetricsCache = Arc::new(RwLock::new(CachedMetrics::Plain(Bytes::new())));
+
+    if config.maintenance_mode {
+        warn!("MAINTENANCE MODE ENABLED at startup (MAINTENANCE_MODE=true)");
+    }
+
+    if let (Some(endpoint), Some((rx, dropped))) = (otlp_endpoint, otlp_queue) {
+        info!("OTLP trace export enabled: sending scrape spans to {}", endpoint);
+        let service_name = std::env::var("OTEL_SERVICE_NAME")
+            .unwrap_or_else(|_| "kafka-connect-exporter".into());
+        tokio::spawn(otlp::run(
+            client.clone(),
+            otlp::OtlpConfig { endpoint, service_name },
+            rx,
+            dropped,
+        ));
+    }
+
+    let instances = match &config.discovery {
+        Some(discovery) => {
+            info!("{}", discovery.describe());
+            config.discover_instances(discovery, &[]).await.unwrap_or_else(|e| {
+                warn!("Discovery failed, starting without instances: {}", e);
+                Vec::new()
+            })
+        }
+        None => config.load_instances(&[]).unwrap_or_else(|e| panic!("{}", e)),
+    };
+    start_oauth(&client, &instances, &[]).await;
+    let probe_modules: Vec<Instance> = config.probe_modules.values().cloned().collect();
+    start_oauth(&client, &probe_modules, &[]).await;
+
+    let remote_write_failures = Arc::new(AtomicU64::new(0));
+    let remote_write = config.remote_write_url.clone().map(|url| {
+        info!("remote-write enabled: pushing each cycle to {}", url);
+        let (tx, rx) = watch::channel(registry::Snapshot::default());
+        tokio::spawn(remote_write::run(
+            client.clone(),
+            remote_write::RemoteWriteConfig {
+                url,
+                max_retries: config.remote_write_max_retries,
+                auth: config.remote_write_auth.clone(),
+            },
+            rx,
+            remote_write_failures.clone(),
+        ));
+        Arc::new(tx)
+    });
+
+    let otlp_metrics_failures = Arc::new(AtomicU64::new(0));
+    let otlp_metrics = config.otlp_metrics_url.clone().map(|url| {
+        info!("OTLP metrics export enabled: sending each cycle to {}", url);
+        let transport = match config.otlp_metrics_protocol {
+            otlp::Protocol::HttpJson => otlp::Transport::Http { protobuf: false },
+            otlp::Protocol::HttpProtobuf => otlp::Transport::Http { protobuf: true },
+            // The push endpoints trust KAFKA_CONNECT_CA_FILE like the shared client
+            otlp::Protocol::Grpc => otlp::Transport::Grpc(
+                grpc::Channel::new(&url, config.ca_file.as_deref(), REQUEST_TIMEOUT)
+                    .unwrap_or_else(|e| panic!("Invalid OTEL_EXPORTER_OTLP_METRICS_ENDPOINT {}", e)),
+            ),
+        };
+        let (tx, rx) = watch::channel(registry::Snapshot::default());
+        tokio::spawn(otlp::run_metrics(
+            client.clone(),
+            otlp::MetricsConfig { url, resource: config.otel_resource.clone(), transport },
+            rx,
+            otlp_metrics_failures.clone(),
+        ));
+        Arc::new(tx)
+    });
+
+    let dogstatsd_failures = Arc::new(AtomicU64::new(0));
+    let dogstatsd = config.dogstatsd.clone().map(|target| {
+        info!("DogStatsD enabled: sending each cycle's gauges to {:?}", target);
+        let (tx, rx) = watch::channel(registry::Snapshot::default());
+        let statsd = statsd::StatsdConfig { target, tags: config.dogstatsd_tags.clone() };
+        tokio::spawn(statsd::run(statsd, rx, dogstatsd_failures.clone()));
+        Arc::new(tx)
+    });
+
+    let pushgateway_failures = Arc::new(AtomicU64::new(0));
+    let pushgateway = config.pushgateway_url.clone().map(|url| {
+        let push = pushgateway::PushgatewayConfig {
+            url,
+            job: config.pushgateway_job.clone(),
+            grouping: config.pushgateway_grouping.clone(),
+            max_retries: config.pushgateway_max_retries,
+        };
+        info!("Pushgateway enabled: pushing each cycle to {}", push.group_url());
+        let (tx, rx) = watch::channel(registry::Snapshot::default());
+        tokio::spawn(pushgateway::run(client.clone(), push, rx, pushgateway_failures.clone()));
+        Arc::new(tx)
+    });
+
+    let webhook = config.event_webhook_url.clone().map(|url| {
+        info!("event webhook enabled: posting connector FAILED transitions to {}", url);
+        let (tx, rx) = tokio::sync::mpsc::channel(webhook::QUEUE_CAPACITY);
+        let deliveries = Arc::new(webhook::Deliveries::default());
+        tokio::spawn(webhook::run(
+            client.clone(),
+            webhook::WebhookConfig {
+                url,
+                max_retries: config.event_webhook_max_retries,
+            },
+            rx,
+            deliveries.clone(),
+        ));
+        (tx, deliveries)
+    });
+    let channels = (!config.channels.is_empty()).then(|| {
+        let sink = channels::Sink::new(client.clone(), config.channels.clone());
+        info!("notification channels enabled: {:?}", sink.names());
+        sink
+    });
+    let events = (webhook.is_some() || channels.is_some()).then_some(webhook::EventSink { webhook, channels });
+
+    let alerts = config.alerts.as_ref().map(|alerts| {
+        info!("alert rules enabled: {:?}, notifying {}", alerts.names(), alerts.webhook.url);
+        let (tx, rx) = tokio::sync::mpsc::channel(webhook::QUEUE_CAPACITY);
+        let deliveries = Arc::new(webhook::Deliveries::default());
+        tokio::spawn(webhook::run(client.clone(), alerts.webhook.clone(), rx, deliveries.clone()));
+        Arc::new(alerts::Engine::new(alerts, tx, deliveries))
+    });
+
+    let (shutdown_tx, shutdown) = watch::channel(false);
+    let state = AppState {
+        cache,
+        published: Arc::default(),
+        samples: Arc::default(),
+        config: config.clone(),
+        instances: Arc::new(RwLock::new(Arc::new(instances))),
+        client,
+        probe_client,
+        last_selftest: Arc::new(Mutex::new(None)),
+        maintenance: Arc::new(AtomicBool::new(config.maintenance_mode)),
+        rescrape: Arc::new(watch::channel(()).0),
+        scraped: Arc::default(),
+        landed: Arc::new(Notify::new()),
+        cycle_done: Arc::new(watch::channel(Instant::now()).0),
+        remote_write,
+        remote_write_failures,
+        otlp_metrics,
+        otlp_metrics_failures,
+        dogstatsd,
+        dogstatsd_failures,
+        pushgateway,
+        pushgateway_failures,
+        events,
+        alerts,
+        heal_attempts: Arc::default(),
+        healing: Arc::default(),
+        task_restarts: Arc::default(),
+        task_restarts_total: Arc::default(),
+        delta: Arc::default(),
+        health: Arc::default(),
+        errors: Arc::default(),
+        connectors: Arc::default(),
+        shutdown: shutdown.clone(),
+    };
+
+    // With web auth configured, every route but the health endpoints needs credentials;
+    // route_layer only covers the routes added before it
+    let guard = config.web_auth.as_ref().map(|auth| Arc::new(web_auth::Guard::from(auth)));
+    let protect = |router: Router<AppState>| match &guard {
+        Some(guard) => router.route_layer(axum::middleware::from_fn_with_state(guard.clone(), web_auth::require)),
+        None => router,
+    };
+
+    let mut app = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .route("/metrics/delta", get(delta_handler))
+        .route("/metrics/influx", get(influx_handler))
+        .route("/api/v1/connectors", get(connectors_handler))
+        .route("/api/v1/connectors/:name/errors", get(errors_handler))
+        .route("/probe", get(probe_handler))
+        .route("/selftest", get(selftest_handler));
+    let mut servers = Vec::new();
+    if config.admin_enabled {
+        let admin = Router::new()
+            .route(
+                "/admin/maintenance",
+                get(maintenance_get_handler).put(maintenance_put_handler),
+            )
+            .route("/connectors/:name/heal", post(heal_handler));
+        // Changing connectors is only allowed behind credentials
+        let admin = match (config.connector_actions, &config.web_auth) {
+            (true, Some(_)) => {
+                admin.route("/api/v1/clusters/:cluster/connectors/:name/:action", post(connector_action_handler))
+            }
+            (true, None) => {
+                warn!("CONNECTOR_ACTIONS_ENABLED is set but web auth is not; not serving connector actions");
+                admin
+            }
+            (false, _) => admin,
+        };
+        // Admin routes get their own listener when ADMIN_BIND_ADDR is set,
+        // so network policy can restrict them apart from /metrics
+        match &config.admin_bind_addr {
+            Some(addr) => {
+                let admin = with_probes(protect(admin)).with_state(state.clone());
+                let listener = bind(addr).await;
+                info!("admin endpoints listening on {}://{}", scheme, addr);
+                servers.push(tokio::spawn(serve(listener, admin, acceptor.clone(), shutdown.clone())));
+            }
+            None => app = app.merge(admin),
+        }
+    } else if config.admin_bind_addr.is_some() {
+        warn!("ADMIN_BIND_ADDR is set but ADMIN_ENABLED is not; not serving admin endpoints");
+    }
+    if config.tenant_config_key.is_some() && !config.scrape_connector_config {
+        warn!("TENANT_CONFIG_KEY is set but SCRAPE_CONNECTOR_CONFIG is not; every tenant is \"unknown\"");
+    }
+    let app = with_probes(protect(app)).with_state(state.clone());
+
+    let instances = state.instances();
+    let scraping = instances.iter().map(|i| i.url.as_str()).collect::<Vec<_>>();
+    if config.push_only {
+        if config.admin_enabled && config.admin_bind_addr.is_none() {
+            warn!("PUSH_ONLY is set: the admin endpoints need ADMIN_BIND_ADDR to be served");
+        }
+        info!("kafka-connect-exporter pushing only, without a metrics listener, scraping: {:?}", scraping);
+    } else {
+        info!("kafka-connect-exporter listening on {}://{} scraping: {:?}", scheme, config.bind_addr, scraping);
+        let listener = bind(&config.bind_addr).await;
+        servers.push(tokio::spawn(serve(listener, app, acceptor, shutdown)));
+    }
+
+    // Scraping starts once the listeners are up, each instance after its
+    // own splay
+    let mut started = state.cycle_done.subscribe();
+    let scraper = tokio::spawn(scrape_loop(state.clone()));
+    if config.discovery.is_some() {
+        tokio::spawn(discover_loop(state.clone()));
+    } else {
+        #[cfg(unix)]
+        tokio::spawn(reload_on_hangup(state.clone()));
+    }
+    if config.fail_if_all_down {
+        let _ = started.changed().await;
+        if !state.scraped.lock().unwrap().values().any(|s| s.up) {
+            error!("No Kafka Connect instance reachable on startup and FAIL_IF_ALL_DOWN is set; exiting");
+            std::process::exit(1);
+        }
+    }
+
+    shutdown_signal().await;
+    info!("Shutting down, waiting up to {}s for open requests and the current scrape", config.shutdown_grace.as_secs());
+    shutdown_tx.send_replace(true);
+    let drained = tokio::time::timeout(config.shutdown_grace, async {
+        join_all(servers).await;
+        let _ = scraper.await;
+    })
+    .await;
+    match drained {
+        Ok(()) => info!("Shutdown complete"),
+        Err(_) => warn!("Shutdown grace period elapsed, exiting with requests or a scrape still open"),
+    }
+}
+
+/// Resolves on SIGTERM or Ctrl-C.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                warn!("Cannot listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = terminate => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
+
+/// Fetch a first token for every OAuth client of `instances` that is not
+/// among `running`, then keep it fresh in the background. The global
+/// client is shared by many instances and started once.
+async fn start_oauth(client: &reqwest::Client, instances: &[Instance], running: &[Instance]) {
+    let mut started: Vec<&oauth::OAuthClient> = running.iter().filter_map(Instance::oauth).collect();
+    for c in instances.iter().filter_map(Instance::oauth) {
+        if started.iter().any(|known| known.same_token(c)) {
+            continue;
+        }
+        let delay = c.refresh(client).await;
+        tokio::spawn(c.clone().run(client.clone(), delay));
+        started.push(c);
+    }
+}
+
+/// Re-read the instance list on SIGHUP and switch the scrape loop over to
+/// it; the next cycle starts right away. A list that fails to load is
+/// logged and the current one kept.
+#[cfg(unix)]
+async fn reload_on_hangup(app: AppState) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => return warn!("Cannot listen for SIGHUP, config reload disabled: {}", e),
+    };
+    while hangup.recv().await.is_some() {
+        let current = app.instances();
+        let instances = match app.config.load_instances(&current) {
+            Ok(instances) => instances,
+            Err(e) => {
+                warn!("Config reload failed, keeping the current instances: {}", e);
+                continue;
+            }
+        };
+        switch_instances(&app, instances, &current, "Config reloaded").await;
+    }
+}
+
+/// Hand `instances` to the scrape loop in place of `current`, which starts
+/// a cycle right away.
+async fn switch_instances(app: &AppState, instances: Vec<Instance>, current: &[Instance], what: &str) {
+    start_oauth(&app.client, &instances, current).await;
+    let added: Vec<&str> = instances
+        .iter()
+        .filter(|i| !current.iter().any(|c| c.url == i.url))
+        .map(|i| i.url.as_str())
+        .collect();
+    let removed: Vec<&str> = current
+        .iter()
+        .filter(|c| !instances.iter().any(|i| i.url == c.url))
+        .map(|c| c.url.as_str())
+        .collect();
+    info!("{}: {} instances, added {:?}, removed {:?}", what, instances.len(), added, removed);
+    *app.instances.write().unwrap() = Arc::new(instances);
+    app.rescrape.send_replace(());
+}
+
+/// Keep the instances in step with the discovered workers. A failed
+/// lookup is logged and the current instances kept.
+async fn discover_loop(app: AppState) {
+    let Some(discovery) = &app.config.discovery else { return };
+    let mut tick = discovery.interval().map(|interval| {
+        let mut tick = tokio::time::interval(interval);
+        tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        tick
+    });
+    if let Some(tick) = &mut tick {
+        tick.tick().await;
+    }
+    loop {
+        if let Some(tick) = &mut tick {
+            tick.tick().await;
+        }
+        let current = app.instances();
+        let instances = match app.config.discover_instances(discovery, &current).await {
+            Ok(instances) => instances,
+            Err(e) => {
+                warn!("Discovery failed, keeping the current instances: {}", e);
+                if tick.is_none() {
+                    tokio::time::sleep(discovery.retry_delay()).await;
+                }
+                continue;
+            }
+        };
+        let unchanged = instances.len() == current.len()
+            && instances.iter().zip(current.iter()).all(|(i, c)| i.same_settings(c));
+        if !unchanged {
+            switch_instances(&app, instances, &current, "Discovered workers changed").await;
+        }
+    }
+}
+
+/// Serve `app` until `shutdown` turns true, then drain the open requests.
+async fn serve(
+    listener: tokio::net::TcpListener,
+    app: Router,
+    acceptor: Option<tokio_rustls::TlsAcceptor>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let stop = async move {
+        let _ = shutdown.wait_for(|stop| *stop).await;
+    };
+    match acceptor {
+        Some(acceptor) => tls::serve(listener, app, acceptor, stop).await,
+        None => axum::serve(listener, app).with_graceful_shutdown(stop).await.unwrap(),
+    }
+}
+
+async fn bind(addr: &str) -> tokio::net::TcpListener {
+    tokio::net::TcpListener::bind(addr)
+        .await
+        .unwrap_or_else(|e| panic!("Failed to bind {}: {}", addr, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Exposition of `connectors` connectors with four tasks each, as a
+    /// large fleet produces it.
+    fn synthetic_snapshot(connectors: usize) -> Vec<registry::Sample> {
+        const STATES: [&str; 5] = ["running", "failed", "paused", "unassigned", "restarting"];
+        let mut out = Vec::new();
+        for c in 0..connectors {
+            let labels = registry::Labels::default()
+                .with("connector", &format!("sink-{c}"))
+                .with("instance", &format!("connect-{}:8083", c % 6))
+                .with("cluster", "prod");
+            for state in STATES {
+                let series = registry::gauge("kafka_connect_connector_state").with(&labels).label("state", state);
+                out.push(series.set(state == "running"));
+            }
+            for task in 0..4 {
+                let task_labels = labels.clone().with("task", &task.to_string());
+                for state in STATES {
+                    let series = registry::gauge("kafka_connect_connector_task_state").with(&task_labels);
+                    out.push(series.label("state", state).set(state == "running"));
+                }
+                let worker = format!("10.0.{}.{task}:8083", c % 7);
+                let series = registry::gauge("kafka_connect_task_worker_info").with(&task_labels);
+                out.push(series.label("worker_id", &worker).set(true));
+            }
+        }
+        out
+    }
+
+    /// Paths a mock worker was asked for, as sent: still percent-encoded.
+    type Requests = Arc<Mutex<Vec<String>>>;
+
+    /// A Connect worker on a free local port listing `names`, all RUNNING
+    /// with one task. The list is never expanded, so each status is fetched
+    /// on its own URL.
+    async fn mock_connect(names: &[&str]) -> (String, Requests) {
+        let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        let requests = Requests::default();
+        let seen = requests.clone();
+        let app = Router::new().fallback(move |req: axum::extract::Request| {
+            let (names, seen) = (names.clone(), seen.clone());
+            async move {
+                let path = req.uri().path().to_owned();
+                seen.lock().unwrap().push(path.clone());
+                let status = |name: &str| {
+                    serde_json::json!({
+                        "name": name,
+                        "type": "sink",
+                        "connector": { "state": "RUNNING", "worker_id": "w1:8083" },
+                        "tasks": [{ "id": 0, "state": "RUNNING", "worker_id": "w1:8083" }],
+                    })
+                };
+                let found = path.strip_prefix("/connectors/").and_then(|rest| {
+                    let name = rest.strip_suffix("/status")?;
+                    let name = percent_encoding::percent_decode_str(name).decode_utf8().ok()?;
+                    names.iter().find(|n| **n == name)
+                });
+                match (path.as_str(), found) {
+                    ("/", _) => Json(serde_json::json!({ "version": "3.7.0", "commit": "c", "kafka_cluster_id": "k" }))
+                        .into_response(),
+                    ("/connectors", _) => Json(serde_json::json!(names)).into_response(),
+                    ("/connector-plugins", _) => Json(serde_json::json!([])).into_response(),
+                    (_, Some(name)) => Json(status(name)).into_response(),
+                    _ => StatusCode::NOT_FOUND.into_response(),
+                }
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, requests)
+    }
+
+    /// One scrape of `url` with the /probe options, as encoded text.
+    async fn scrape(url: &str) -> String {
+        scrape_as(&Instance { url: url.to_owned(), ..Instance::default() }).await
+    }
+
+    /// One scrape of `inst` with the /probe options, as encoded text.
+    async fn scrape_as(inst: &Instance) -> String {
+        let opts = ScrapeOptions {
+            suppress_outages: false,
+            emit_edges: false,
+            fetch_config: false,
+            describe: false,
+            fetch_topics: false,
+            split_by_type: false,
+            down_policy: DownPolicy::Absent,
+            keep_cycles: 0,
+            concurrency: 4,
+            prioritize_failed: false,
+            deadline: None,
+            events: None,
+            auth: None,
+            timeout: Duration::from_secs(5),
+            tenant_key: None,
+            extra_fields: &[],
+            retries: 0,
+            retry_backoff: Duration::from_millis(10),
+            flap_window: Duration::from_secs(3600),
+        };
+        let mut history = InstanceState::default();
+        registry::encode(&scrape_connect(&reqwest::Client::new(), inst, &mut history, opts).await)
+    }
+
+    /// An env lookup over `vars` only.
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |key| vars.get(key).cloned()
+    }
+
+    #[test]
+    fn indexed_instances_stop_at_the_first_gap() {
+        let instances = indexed_instances(env(&[
+            ("KAFKA_CONNECT_URL_0", "http://connect-0:8083"),
+            ("KAFKA_CONNECT_URL_1", "http://connect-1:8083"),
+            ("KAFKA_CONNECT_URL_3", "http://connect-3:8083"),
+        ]));
+        let urls: Vec<&str> = instances.iter().map(|i| i.url.as_str()).collect();
+        assert_eq!(urls, ["http://connect-0:8083", "http://connect-1:8083"]);
+        assert!(indexed_instances(env(&[("KAFKA_CONNECT_URL_1", "http://connect-1:8083")])).is_empty());
+    }
+
+    #[test]
+    fn indexed_instances_read_basic_auth_per_index() {
+        let instances = indexed_instances(env(&[
+            ("KAFKA_CONNECT_URL_0", "http://connect-0:8083"),
+            ("KAFKA_CONNECT_URL_1", "http://connect-1:8083"),
+            ("KAFKA_CONNECT_USERNAME_1", "exporter"),
+            ("KAFKA_CONNECT_PASSWORD_1", "secret"),
+        ]));
+        assert_eq!(instances[0].auth, None);
+        assert_eq!(
+            instances[1].auth,
+            Some(Credentials::Basic(BasicAuth { username: "exporter".into(), password: Some("secret".into()) }))
+        );
+    }
+
+    #[test]
+    fn indexed_instances_trim_trailing_slashes() {
+        let instances = indexed_instances(env(&[("KAFKA_CONNECT_URL_0", " https://connect-0:8083/ ")]));
+        assert_eq!(instances[0].url, "https://connect-0:8083");
+        let instances = indexed_instances(env(&[("KAFKA_CONNECT_URL_0", "http://proxy/connect//")]));
+        assert_eq!(instances[0].url, "http://proxy/connect");
+    }
+
+    #[test]
+    #[should_panic(expected = "KAFKA_CONNECT_PASSWORD_0 is set without KAFKA_CONNECT_USERNAME_0")]
+    fn indexed_instances_refuse_a_password_without_username() {
+        indexed_instances(env(&[
+            ("KAFKA_CONNECT_URL_0", "http://connect-0:8083"),
+            ("KAFKA_CONNECT_PASSWORD_0", "secret"),
+        ]));
+    }
+
+    #[test]
+    fn instances_come_from_flags_then_env_then_the_file() {
+        let urls = |instances: Vec<Instance>| instances.into_iter().map(|i| i.url).collect::<Vec<_>>();
+        let file = || vec![Instance { url: "http://from-file:8083".into(), ..Default::default() }];
+        let vars = [("KAFKA_CONNECT_URLS", "http://from-env:8083"), ("KAFKA_CONNECT_URL_0", "http://indexed:8083")];
+        let flags = ["http://from-flag:8083".to_owned()];
+
+        assert_eq!(urls(pick_instances(&flags, env(&vars), file())), ["http://from-flag:8083"]);
+        assert_eq!(urls(pick_instances(&[], env(&vars), file())), ["http://from-env:8083", "http://indexed:8083"]);
+        assert_eq!(urls(pick_instances(&[], env(&vars[1..]), file())), ["http://indexed:8083"]);
+        assert_eq!(urls(pick_instances(&[], env(&[]), file())), ["http://from-file:8083"]);
+        assert_eq!(urls(pick_instances(&[], env(&[]), Vec::new())), ["http://localhost:8083"]);
+    }
+
+    #[test]
+    fn web_auth_comes_from_env_then_the_file() {
+        let file = || Some(web_auth::WebAuth { bearer_token: Some("file-token".into()), ..Default::default() });
+
+        let auth = web_auth_settings(env(&[("WEB_AUTH_USERNAME", "prometheus"), ("WEB_AUTH_PASSWORD", "pw")]), file());
+        assert_eq!(auth.username.as_deref(), Some("prometheus"));
+        assert_eq!(auth.bearer_token, None);
+
+        let auth = web_auth_settings(env(&[]), file());
+        assert_eq!(auth.bearer_token.as_deref(), Some("file-token"));
+        assert!(web_auth_settings(env(&[]), None).is_empty());
+    }
+
+    #[test]
+    fn extra_labels_go_after_the_labels_a_sample_lacks() {
+        let extra = [("cluster".to_string(), "prod".to_string()), ("env".to_string(), "eu".to_string())];
+        let mut samples = vec![
+            // A label value that reads like a label set must not be touched
+            registry::gauge("kafka_connect_connector_state").label("connector", r#"a",cluster="x"#).set(true),
+            registry::gauge("kafka_connect_up").label("cluster", "own").set(true),
+        ];
+        add_extra_labels(&mut samples, &extra);
+        assert_eq!(
+            samples.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                r#"kafka_connect_connector_state{connector="a\",cluster=\"x",cluster="prod",env="eu"} 1"#,
+                r#"kafka_connect_up{cluster="own",env="eu"} 1"#,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn instance_labels_go_on_every_series_of_that_instance_only() {
+        let labels = |pairs: &[(&str, &str)]| pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let (prod_url, _) = mock_connect(&["pg-source", "s3-sink"]).await;
+        let (staging_url, _) = mock_connect(&["pg-source"]).await;
+        let prod = Instance {
+            url: prod_url,
+            labels: labels(&[("env", "prod"), ("region", "eu-west-1"), ("team", "payments")]),
+            ..Instance::default()
+        };
+        let staging = Instance { url: staging_url, labels: labels(&[("env", "staging")]), ..Instance::default() };
+
+        for (inst, own, other) in [
+            (&prod, r#"instance="{}",env="prod",region="eu-west-1",team="payments""#, "staging"),
+            (&staging, r#"instance="{}",env="staging""#, "prod"),
+        ] {
+            let out = scrape_as(inst).await;
+            let own = own.replace("{}", inst.label());
+            let samples: Vec<&str> = out.lines().filter(|l| !l.starts_with('#')).collect();
+            assert!(samples.len() > 20, "{}", out);
+            for sample in samples {
+                assert!(sample.contains(&own), "{} lacks {}", sample, own);
+                assert!(!sample.contains(other), "{}", sample);
+            }
+        }
+    }
+
+    #[test]
+    fn connector_url_encodes_the_name_as_one_segment() {
+        assert_eq!(
+            connector_url("http://h:8083", "group/name", "status"),
+            "http://h:8083/connectors/group%2Fname/status"
+        );
+    }
+
+    #[tokio::test]
+    async fn scrapes_connector_with_slash_in_its_name() {
+        let (url, requests) = mock_connect(&["group/name"]).await;
+        let out = scrape(&url).await;
+        assert!(requests.lock().unwrap().contains(&"/connectors/group%2Fname/status".to_owned()));
+        assert!(out.contains("kafka_connect_connector_state{connector=\"group/name\",state=\"running\""), "{}", out);
+        let scraped = out.lines().find(|l| l.starts_with("kafka_connect_connectors_scraped{"));
+        assert!(scraped.is_some_and(|l| l.ends_with(" 1")), "{}", out);
+    }
+
+    #[tokio::test]
+    async fn scrapes_connectors_with_spaces_unicode_and_quotes_in_their_names() {
+        // Name, its path segment, its label value in the exposition
+        let cases = [
+            ("a b/c", "a%20b%2Fc", "a b/c"),
+            ("ü-x", "%C3%BC-x", "ü-x"),
+            (r#"q"uo\te"#, "q%22uo%5Cte", r#"q\"uo\\te"#),
+        ];
+        let names: Vec<&str> = cases.iter().map(|(name, _, _)| *name).collect();
+        let (url, requests) = mock_connect(&names).await;
+        let out = scrape(&url).await;
+        let requests = requests.lock().unwrap().clone();
+        for (name, segment, label) in cases {
+            let path = format!("/connectors/{}/status", segment);
+            assert!(requests.contains(&path), "no request for {:?} at {}: {:?}", name, path, requests);
+            let state = format!("kafka_connect_connector_state{{connector=\"{}\",state=\"running\"", label);
+            assert!(out.contains(&state), "{}", out);
+        }
+        // Every request went to the encoded name, never the raw one
+        for path in &requests {
+            assert!(path.is_ascii() && !path.contains(' ') && !path.contains('"') && !path.contains('\\'), "{}", path);
+            assert!(path.matches('/').count() <= 3, "{}", path);
+        }
+        let scraped = out.lines().find(|l| l.starts_with("kafka_connect_connectors_scraped{"));
+        assert!(scraped.is_some_and(|l| l.ends_with(" 3")), "{}", out);
+    }
+
+    /// A server answering every request with a 302 to `location` and
+    /// counting them.
+    async fn mock_redirect(location: String) -> (String, Arc<AtomicU64>) {
+        let hits = Arc::new(AtomicU64::new(0));
+        let counted = hits.clone();
+        let app = Router::new().fallback(move || {
+            counted.fetch_add(1, Ordering::Relaxed);
+            let location = location.clone();
+            async move { (StatusCode::FOUND, [(header::LOCATION, location)]).into_response() }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, hits)
+    }
+
+    /// The messages of `e` and its sources.
+    fn error_chain(e: &dyn std::error::Error) -> String {
+        let mut out = e.to_string();
+        let mut source = e.source();
+        while let Some(e) = source {
+            out.push_str(&format!(": {}", e));
+            source = e.source();
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn probe_client_refuses_redirects_off_the_allowlist() {
+        let (internal, internal_hits) = mock_connect(&["sink"]).await;
+        let (allowed, _) = mock_redirect(format!("{}/connectors", internal)).await;
+        let hosts = vec![allowed.trim_start_matches("http://").to_owned()];
+        assert!(probe_target(&allowed, &hosts).is_ok());
+
+        let client = tls::ClientTls::default()
+            .client(Duration::from_secs(5), None, probe_redirects(hosts))
+            .unwrap();
+        let e = client.get(format!("{}/connectors", allowed)).send().await.unwrap_err();
+        assert!(e.is_redirect(), "{}", error_chain(&e));
+        assert!(error_chain(&e).contains("not in PROBE_ALLOWED_HOSTS"), "{}", error_chain(&e));
+        assert_eq!(internal_hits.lock().unwrap().len(), 0, "the disallowed host was fetched");
+    }
+
+    #[tokio::test]
+    async fn probe_module_client_refuses_redirects_off_the_allowlist() {
+        let (internal, internal_hits) = mock_connect(&["sink"]).await;
+        let (allowed, _) = mock_redirect(format!("{}/connectors", internal)).await;
+        let hosts = vec![allowed.trim_start_matches("http://").to_owned()];
+        // A connect timeout gives the module a client of its own
+        let module: ProbeModule = serde_json::from_str(r#"{ "connect_timeout_secs": 2 }"#).unwrap();
+        let inst = module.instance("slow", &hosts).unwrap();
+        assert!(inst.client.is_some());
+
+        let shared = reqwest::Client::new();
+        let e = inst.client(&shared).get(format!("{}/connectors", allowed)).send().await.unwrap_err();
+        assert!(error_chain(&e).contains("not in PROBE_ALLOWED_HOSTS"), "{}", error_chain(&e));
+        assert_eq!(internal_hits.lock().unwrap().len(), 0, "the disallowed host was fetched");
+    }
+
+    #[tokio::test]
+    async fn probe_client_follows_redirects_within_the_allowlist() {
+        let (worker, _) = mock_connect(&["sink"]).await;
+        let (front, _) = mock_redirect(format!("{}/connectors", worker)).await;
+        let hosts: Vec<String> = [&front, &worker].iter().map(|u| u.trim_start_matches("http://").to_owned()).collect();
+        let client = tls::ClientTls::default()
+            .client(Duration::from_secs(5), None, probe_redirects(hosts))
+            .unwrap();
+        let names: Vec<String> = client.get(&front).send().await.unwrap().json().await.unwrap();
+        assert_eq!(names, ["sink"]);
+    }
+
+    #[tokio::test]
+    async fn heal_guard_is_released_when_the_heal_is_cancelled() {
+        let healing: Arc<Mutex<HashSet<(String, String)>>> = Arc::default();
+        let key = ("http://connect:8083".to_owned(), "sink".to_owned());
+        let guard = HealGuard::claim(&healing, key.clone()).unwrap();
+        assert!(HealGuard::claim(&healing, key.clone()).is_none(), "claimed twice");
+
+        // A client that disconnects drops the handler future mid-heal
+        let heal = tokio::spawn(async move {
+            let _guard = guard;
+            std::future::pending::<()>().await;
+        });
+        heal.abort();
+        assert!(heal.await.unwrap_err().is_cancelled());
+        assert!(healing.lock().unwrap().is_empty(), "cancelled heal kept its key");
+        assert!(HealGuard::claim(&healing, key).is_some());
+    }
+
+    fn cache() -> MetricsCache {
+        Arc::new(RwLock::new(CachedMetrics::Plain(Bytes::new())))
+    }
+
+    #[test]
+    fn gzip_cache_shrinks_large_snapshot() {
+        let snapshot = synthetic_snapshot(3000);
+        let (plain, gzipped) = (cache(), cache());
+        store_metrics(&plain, &snapshot, false, &[]);
+        store_metrics(&gzipped, &snapshot, true, &[]);
+        assert!(plain.read().unwrap().len() > 5_000_000, "{} bytes", plain.read().unwrap().len());
+        let (plain, gzipped) = (plain.read().unwrap().clone(), gzipped.read().unwrap().clone());
+        assert!(matches!(gzipped, CachedMetrics::Gzip(_)));
+        assert!(
+            gzipped.len() * 8 < plain.len(),
+            "gzip holds {} bytes of {}, less than 8x smaller",
+            gzipped.len(),
+            plain.len()
+        );
+        // Both hold the same exposition, apart from the label of the cache size
+        let samples = |cached: &CachedMetrics| {
+            let text = String::from_utf8(cached.text().unwrap().to_vec()).unwrap();
+            let samples = text.lines().filter(|l| !l.contains("kafka_connect_exporter_cache_bytes"));
+            samples.map(str::to_owned).collect::<Vec<_>>()
+        };
+        assert!(samples(&gzipped) == samples(&plain), "gzip cache decodes to another exposition");
+    }
+
+    #[test]
+    fn reads_toml_config() {
+        let file = FileConfig::from_toml(
+            r#"
+[web_auth]
+bearer_token = "secret"
+
+[[instances]]
+url = "http://connect-blue:8083/"
+name = "payments-prod"
+timeout_secs = 5
+labels = { color = "blue" }
+
+[[instances]]
+url = "http://connect-green:8083"
+lite = true
+"#,
+        )
+        .unwrap();
+        assert_eq!(file.instances.len(), 2);
+        assert_eq!(file.instances[0].name.as_deref(), Some("payments-prod"));
+        assert_eq!(file.instances[0].timeout_secs, Some(5));
+        assert_eq!(file.instances[0].labels.get("color").map(String::as_str), Some("blue"));
+        assert_eq!(file.instances[1].lite, Some(true));
+        assert!(file.web_auth.is_some());
+    }
+
+    #[test]
+    fn toml_config_errors_name_the_key_and_line() {
+        let err = |text: &str| FileConfig::from_toml(text).err().unwrap();
+        let unknown = err(concat!(
+            "[[instances]]\nurl = \"http://a:8083\"\n\n",
+            "[[instances]]\nurl = \"http://b:8083\"\n[instances.tls]\ncafile = \"/ca.pem\"\n",
+        ));
+        assert!(unknown.starts_with("instances[1].tls.cafile: unknown field `cafile`"), "{}", unknown);
+        assert!(unknown.ends_with("(line 7)"), "{}", unknown);
+        let wrong_type = err("[[instances]]\nurl = \"http://a:8083\"\ntimeout_secs = \"5\"\n");
+        assert!(wrong_type.starts_with("instances[0].timeout_secs: invalid type: string \"5\""), "{}", wrong_type);
+        assert!(wrong_type.ends_with("(line 3)"), "{}", wrong_type);
+        assert_eq!(err("[[instances]]\nurl = 'http://a:8083\n"), "line 2: unterminated string");
+    }
+
+    #[test]
+    fn reads_yaml_config() {
+        let file = FileConfig::from_yaml(
+            r#"
+web_auth:
+  bearer_token: secret
+
+instances:
+  - url: http://connect-blue:8083/
+    name: payments-prod
+    timeout_secs: 5
+    labels: { color: blue }
+  - url: http://connect-green:8083
+    lite: true
+"#,
+        )
+        .unwrap();
+        assert_eq!(file.instances.len(), 2);
+        assert_eq!(file.instances[0].name.as_deref(), Some("payments-prod"));
+        assert_eq!(file.instances[0].timeout_secs, Some(5));
+        assert_eq!(file.instances[0].labels.get("color").map(String::as_str), Some("blue"));
+        assert_eq!(file.instances[1].lite, Some(true));
+        assert!(file.web_auth.is_some());
+
+        let err = FileConfig::from_yaml("instances:\n  - url: http://a:8083\n    timeout_secs: '5'\n").err().unwrap();
+        assert!(err.starts_with("instances[0].timeout_secs: invalid type: string \"5\""), "{}", err);
+        assert!(err.ends_with("(line 3)"), "{}", err);
+    }
+
+    #[test]
+    fn config_file_format_follows_the_extension() {
+        let dir = std::env::temp_dir().join(format!("kafka-connect-exporter-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let toml = dir.join("config.toml");
+        std::fs::write(&toml, "[[instances]]\nurl = \"http://a:8083\"\n").unwrap();
+        let json = dir.join("config.json");
+        std::fs::write(&json, r#"{ "instances": [{ "url": "http://a:8083" }] }"#).unwrap();
+        let yaml = dir.join("config.yaml");
+        std::fs::write(&yaml, "instances:\n  - url: http://a:8083\n").unwrap();
+
+        let read = |path: &std::path::Path| FileConfig::read(path.to_str().unwrap());
+        assert_eq!(read(&toml).unwrap().instances[0].url, "http://a:8083");
+        assert_eq!(read(&json).unwrap().instances[0].url, "http://a:8083");
+        assert_eq!(read(&yaml).unwrap().instances[0].url, "http://a:8083");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -0,0 +1,128 @@
+//! OAuth 2.0 client-credentials tokens for Kafka Connect requests.
+//!
+//! The token is fetched once at startup and then refreshed in the
+//! background shortly before it expires, so building a request only reads
+//! the current token and never waits on the token endpoint.
+
+use serde::Deserialize;
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tracing::{debug, warn};
+
+/// Refresh this long before the token expires.
+const REFRESH_MARGIN: Duration = Duration::from_secs(30);
+/// Lifetime assumed when the token response has no `expires_in`.
+const DEFAULT_LIFETIME: Duration = Duration::from_secs(300);
+/// Wait after a failed refresh before trying again.
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Client registration plus the current access token. Clones share the token.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OAuthClient {
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    #[serde(default)]
+    pub scope: Option<String>,
+    #[serde(skip)]
+    token: Arc<RwLock<Option<String>>>,
+}
+
+// Keep the secret and the token out of logs
+impl std::fmt::Debug for OAuthClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OAuthClient")
+            .field("token_url", &self.token_url)
+            .field("client_id", &self.client_id)
+            .finish_non_exhaustive()
+    }
+}
+
+// Same registration; the token is not compared
+impl PartialEq for OAuthClient {
+    fn eq(&self, other: &Self) -> bool {
+        self.token_url == other.token_url
+            && self.client_id == other.client_id
+            && self.client_secret == other.client_secret
+            && self.scope == other.scope
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+impl OAuthClient {
+    pub fn new(token_url: String, client_id: String, client_secret: String, scope: Option<String>) -> Self {
+        Self { token_url, client_id, client_secret, scope, token: Arc::default() }
+    }
+
+    /// Whether both handles refer to the same token.
+    pub fn same_token(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.token, &other.token)
+    }
+
+    /// `req` with the current token; unchanged while there is none yet.
+    pub fn apply(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self.token.read().unwrap().as_deref() {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        }
+    }
+
+    async fn fetch(&self, client: &reqwest::Client) -> Result<Duration, String> {
+        let mut form = vec![("grant_type", "client_credentials")];
+        if let Some(scope) = &self.scope {
+            form.push(("scope", scope));
+        }
+        let resp = client
+            .post(&self.token_url)
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("HTTP {}", resp.status()));
+        }
+        let body: TokenResponse = resp.json().await.map_err(|e| e.to_string())?;
+        *self.token.write().unwrap() = Some(body.access_token);
+        Ok(body.expires_in.map_or(DEFAULT_LIFETIME, Duration::from_secs))
+    }
+
+    /// Fetch a token now; returns how long to wait before the next refresh.
+    /// On failure the previous token, if any, stays in use.
+    pub async fn refresh(&self, client: &reqwest::Client) -> Duration {
+        match self.fetch(client).await {
+            Ok(lifetime) => {
+                debug!("oauth: got a token from {} valid for {:?}", self.token_url, lifetime);
+                lifetime.saturating_sub(REFRESH_MARGIN).max(RETRY_DELAY)
+            }
+            Err(e) => {
+                warn!("OAuth token request to {} failed: {}", self.token_url, e);
+                RETRY_DELAY
+            }
+        }
+    }
+
+    /// Keep the token fresh, starting after `first_delay`. Ends once this
+    /// is the last handle to the token, i.e. a config reload removed every
+    /// instance using it.
+    pub async fn run(self, client: reqwest::Client, first_delay: Duration) {
+        let mut delay = first_delay;
+        loop {
+            tokio::time::sleep(delay).await;
+            if Arc::strong_count(&self.token) == 1 {
+                debug!("oauth: no instance uses {} any more, stopping refresh", self.token_url);
+                return;
+            }
+            delay = self.refresh(&client).await;
+        }
+    }
+}
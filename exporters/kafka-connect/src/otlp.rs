@@ -0,0 +1,709 @@
+//! OpenTelemetry export: trace spans of the scrape operations over OTLP/HTTP
+//! with the JSON encoding (OTEL_EXPORTER_OTLP_ENDPOINT), and the metrics
+//! snapshot of every cycle over OTLP/HTTP, JSON or protobuf, or OTLP/gRPC
+//! (OTEL_EXPORTER_OTLP_METRICS_ENDPOINT).
+//!
+//! A `tracing` layer turns the exporter's spans into OTLP spans, with the
+//! events logged inside them as span events. Like remote-write, the wire
+//! format is written by hand instead of pulling in the OpenTelemetry SDK.
+//! Finished spans are queued without blocking and sent in batches by a
+//! background task; when the queue is full they are dropped.
+
+use crate::{
+    grpc,
+    registry::{self, Sample},
+    remote_write::{put_bytes, put_varint},
+};
+use serde_json::{json, Value};
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{mpsc, watch};
+use tracing::{
+    debug,
+    field::{Field, Visit},
+    span, warn, Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// Finished spans waiting for export before new ones are dropped.
+const QUEUE_CAPACITY: usize = 8192;
+/// Spans per export request.
+const BATCH_SIZE: usize = 512;
+/// Longest a finished span waits for its batch.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(out, "{b:02x}");
+    }
+    out
+}
+
+// ── Span collection ───────────────────────────────────────────────────────────
+
+/// Kept in the span's extensions while it is open.
+struct SpanData {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_span_id: Option<[u8; 8]>,
+    start: u64,
+    attributes: Vec<(String, String)>,
+    events: Vec<SpanEvent>,
+}
+
+struct SpanEvent {
+    time: u64,
+    name: String,
+    attributes: Vec<(String, String)>,
+}
+
+pub struct FinishedSpan {
+    name: &'static str,
+    data: SpanData,
+    end: u64,
+}
+
+/// Collects span fields as string attributes; the `message` of an event
+/// becomes its name.
+struct Fields<'a> {
+    attributes: &'a mut Vec<(String, String)>,
+    message: Option<&'a mut String>,
+}
+
+impl Visit for Fields<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.attributes.push((field.name().to_owned(), value.to_owned()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        match &mut self.message {
+            Some(message) if field.name() == "message" => **message = format!("{value:?}"),
+            _ => self.attributes.push((field.name().to_owned(), format!("{value:?}"))),
+        }
+    }
+}
+
+pub struct OtlpLayer {
+    tx: mpsc::Sender<FinishedSpan>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let parent = span
+            .parent()
+            .and_then(|p| p.extensions().get::<SpanData>().map(|d| (d.trace_id, d.span_id)));
+        let (trace_id, parent_span_id) = match parent {
+            Some((trace_id, span_id)) => (trace_id, Some(span_id)),
+            None => (rand::random(), None),
+        };
+        let mut attributes = Vec::new();
+        attrs.record(&mut Fields { attributes: &mut attributes, message: None });
+        span.extensions_mut().insert(SpanData {
+            trace_id,
+            span_id: rand::random(),
+            parent_span_id,
+            start: now_nanos(),
+            attributes,
+            events: Vec::new(),
+        });
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut extensions = span.extensions_mut();
+        if let Some(data) = extensions.get_mut::<SpanData>() {
+            values.record(&mut Fields { attributes: &mut data.attributes, message: None });
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.event_span(event) else { return };
+        let mut name = String::new();
+        let mut attributes = vec![("level".to_owned(), event.metadata().level().to_string())];
+        event.record(&mut Fields { attributes: &mut attributes, message: Some(&mut name) });
+        let mut extensions = span.extensions_mut();
+        if let Some(data) = extensions.get_mut::<SpanData>() {
+            data.events.push(SpanEvent { time: now_nanos(), name, attributes });
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else { return };
+        let Some(data) = span.extensions_mut().remove::<SpanData>() else { return };
+        let finished = FinishedSpan { name: span.name(), data, end: now_nanos() };
+        if self.tx.try_send(finished).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// ── Export ────────────────────────────────────────────────────────────────────
+
+pub struct OtlpConfig {
+    /// OTEL_EXPORTER_OTLP_ENDPOINT; spans go to `{endpoint}/v1/traces`.
+    pub endpoint: String,
+    /// OTEL_SERVICE_NAME.
+    pub service_name: String,
+}
+
+/// Layer plus the receiving end for [`run`].
+pub fn layer() -> (OtlpLayer, mpsc::Receiver<FinishedSpan>, Arc<AtomicU64>) {
+    let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+    let dropped = Arc::new(AtomicU64::new(0));
+    (OtlpLayer { tx, dropped: dropped.clone() }, rx, dropped)
+}
+
+fn attributes(attrs: &[(String, String)]) -> Vec<Value> {
+    attrs
+        .iter()
+        .map(|(k, v)| json!({ "key": k, "value": { "stringValue": v } }))
+        .collect()
+}
+
+/// `ExportTraceServiceRequest` in the OTLP JSON encoding.
+fn export_request(service_name: &str, spans: &[FinishedSpan]) -> Value {
+    let spans: Vec<Value> = spans
+        .iter()
+        .map(|s| {
+            let mut span = json!({
+                "traceId": hex(&s.data.trace_id),
+                "spanId": hex(&s.data.span_id),
+                "name": s.name,
+                "kind": 1,
+                "startTimeUnixNano": s.data.start.to_string(),
+                "endTimeUnixNano": s.end.to_string(),
+                "attributes": attributes(&s.data.attributes),
+                "events": s.data.events.iter().map(|e| json!({
+                    "timeUnixNano": e.time.to_string(),
+                    "name": e.name,
+                    "attributes": attributes(&e.attributes),
+                })).collect::<Vec<_>>(),
+            });
+            if let Some(parent) = &s.data.parent_span_id {
+                span["parentSpanId"] = json!(hex(parent));
+            }
+            span
+        })
+        .collect();
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": attributes(&[("service.name".to_owned(), service_name.to_owned())]),
+            },
+            "scopeSpans": [{
+                "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+/// Send finished spans in batches of up to BATCH_SIZE, at least every
+/// FLUSH_INTERVAL. A failed batch is logged and dropped.
+pub async fn run(
+    client: reqwest::Client,
+    config: OtlpConfig,
+    mut rx: mpsc::Receiver<FinishedSpan>,
+    dropped: Arc<AtomicU64>,
+) {
+    let url = format!("{}/v1/traces", config.endpoint.trim_end_matches('/'));
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+    loop {
+        tokio::select! {
+            span = rx.recv() => match span {
+                Some(span) => {
+                    batch.push(span);
+                    if batch.len() < BATCH_SIZE {
+                        continue;
+                    }
+                }
+                None => return,
+            },
+            _ = flush.tick() => {}
+        }
+
+        let lost = dropped.swap(0, Ordering::Relaxed);
+        if lost > 0 {
+            warn!("OTLP span queue full, dropped {} spans", lost);
+        }
+        if batch.is_empty() {
+            continue;
+        }
+        let body = export_request(&config.service_name, &batch);
+        match client.post(&url).json(&body).send().await {
+            Ok(r) if r.status().is_success() => debug!("otlp: exported {} spans", batch.len()),
+            Ok(r) => warn!("OTLP export to {} failed: HTTP {}", url, r.status()),
+            Err(e) => warn!("OTLP export to {} failed: {}", url, e),
+        }
+        batch.clear();
+    }
+}
+
+// ── Metrics ───────────────────────────────────────────────────────────────────
+
+/// The metrics service method of OTLP/gRPC.
+const EXPORT_METHOD: &str = "/opentelemetry.proto.collector.metrics.v1.MetricsService/Export";
+
+/// OTEL_EXPORTER_OTLP_METRICS_PROTOCOL.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Protocol {
+    #[default]
+    HttpJson,
+    HttpProtobuf,
+    Grpc,
+}
+
+impl Protocol {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "http/json" => Some(Self::HttpJson),
+            "http/protobuf" => Some(Self::HttpProtobuf),
+            "grpc" => Some(Self::Grpc),
+            _ => None,
+        }
+    }
+}
+
+/// How snapshots reach the collector.
+pub enum Transport {
+    /// Posted to the URL as is, in the JSON or the protobuf encoding.
+    Http { protobuf: bool },
+    /// The Export call of the metrics service on the endpoint.
+    Grpc(grpc::Channel),
+}
+
+pub struct MetricsConfig {
+    /// OTEL_EXPORTER_OTLP_METRICS_ENDPOINT.
+    pub url: String,
+    /// `service.name` followed by OTEL_RESOURCE_ATTRIBUTES.
+    pub resource: Vec<(String, String)>,
+    pub transport: Transport,
+}
+
+/// `key=value` pairs of OTEL_RESOURCE_ATTRIBUTES, percent-decoded. Entries
+/// without a `=` are skipped.
+pub fn resource_attributes(raw: &str) -> Vec<(String, String)> {
+    let decode = |s: &str| percent_encoding::percent_decode_str(s.trim()).decode_utf8_lossy().into_owned();
+    raw.split(',')
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(k, v)| (decode(k), decode(v)))
+        .filter(|(k, _)| !k.is_empty())
+        .collect()
+}
+
+/// Samples by family, in the order the families first appear. Summaries
+/// are left out, their quantiles have no OTLP gauge equivalent worth
+/// keeping.
+fn families(samples: &[Sample]) -> Vec<(&'static str, Vec<&Sample>)> {
+    let mut out: Vec<(&'static str, Vec<&Sample>)> = Vec::new();
+    let mut index = std::collections::HashMap::new();
+    for sample in samples.iter().filter(|s| s.kind() != Some(registry::Kind::Summary)) {
+        let i = *index.entry(sample.family).or_insert_with(|| {
+            out.push((sample.family, Vec::new()));
+            out.len() - 1
+        });
+        out[i].1.push(sample);
+    }
+    out
+}
+
+fn is_counter(family: &str) -> bool {
+    registry::family(family).is_some_and(|f| f.kind == registry::Kind::Counter)
+}
+
+/// `ExportMetricsServiceRequest` in the OTLP JSON encoding. Gauge families
+/// become gauges and counters cumulative monotonic sums counted from
+/// `start`.
+fn export_metrics_request(resource: &[(String, String)], samples: &[Sample], start: u64, now: u64) -> Value {
+    let metrics: Vec<Value> = families(samples)
+        .into_iter()
+        .map(|(name, samples)| {
+            let counter = is_counter(name);
+            let data_points: Vec<Value> = samples
+                .iter()
+                .map(|sample| {
+                    let mut point = json!({
+                        "attributes": attributes(sample.labels.pairs()),
+                        "timeUnixNano": now.to_string(),
+                        "asDouble": sample.value,
+                    });
+                    if counter {
+                        point["startTimeUnixNano"] = json!(start.to_string());
+                    }
+                    point
+                })
+                .collect();
+            let help = registry::family(name).map_or("", |f| f.help);
+            let mut metric = json!({ "name": name, "description": help });
+            if counter {
+                // Cumulative
+                metric["sum"] = json!({
+                    "dataPoints": data_points,
+                    "aggregationTemporality": 2,
+                    "isMonotonic": true,
+                });
+            } else {
+                metric["gauge"] = json!({ "dataPoints": data_points });
+            }
+            metric
+        })
+        .collect();
+    json!({
+        "resourceMetrics": [{
+            "resource": { "attributes": attributes(resource) },
+            "scopeMetrics": [{
+                "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                "metrics": metrics,
+            }],
+        }],
+    })
+}
+
+fn put_fixed64(out: &mut Vec<u8>, field: u32, value: u64) {
+    put_varint(out, ((field << 3) | 1) as u64);
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Repeated `KeyValue { key = 1; AnyValue value = 2 }` with string values.
+fn put_attributes(out: &mut Vec<u8>, field: u32, attrs: &[(String, String)]) {
+    let (mut kv, mut value) = (Vec::new(), Vec::new());
+    for (k, v) in attrs {
+        kv.clear();
+        value.clear();
+        put_bytes(&mut kv, 1, k.as_bytes());
+        // AnyValue { string_value = 1 }
+        put_bytes(&mut value, 1, v.as_bytes());
+        put_bytes(&mut kv, 2, &value);
+        put_bytes(out, field, &kv);
+    }
+}
+
+/// The same request as [`export_metrics_request`], in the protobuf
+/// encoding.
+fn encode_metrics_request(resource: &[(String, String)], samples: &[Sample], start: u64, now: u64) -> Vec<u8> {
+    let mut scope_metrics = Vec::new();
+    let mut scope = Vec::new();
+    put_bytes(&mut scope, 1, env!("CARGO_PKG_NAME").as_bytes());
+    put_bytes(&mut scope, 2, env!("CARGO_PKG_VERSION").as_bytes());
+    put_bytes(&mut scope_metrics, 1, &scope);
+
+    let (mut metric, mut data, mut point) = (Vec::new(), Vec::new(), Vec::new());
+    for (name, samples) in families(samples) {
+        let counter = is_counter(name);
+        data.clear();
+        for sample in samples {
+            // NumberDataPoint { start_time_unix_nano = 2; time_unix_nano = 3;
+            // as_double = 4; attributes = 7 }
+            point.clear();
+            if counter {
+                put_fixed64(&mut point, 2, start);
+            }
+            put_fixed64(&mut point, 3, now);
+            put_fixed64(&mut point, 4, sample.value.to_bits());
+            put_attributes(&mut point, 7, sample.labels.pairs());
+            put_bytes(&mut data, 1, &point);
+        }
+        // Metric { name = 1; description = 2; gauge = 5; sum = 7 }
+        metric.clear();
+        put_bytes(&mut metric, 1, name.as_bytes());
+        put_bytes(&mut metric, 2, registry::family(name).map_or("", |f| f.help).as_bytes());
+        if counter {
+            // Sum { aggregation_temporality = 2 (cumulative); is_monotonic = 3 }
+            data.extend_from_slice(&[2 << 3, 2, 3 << 3, 1]);
+            put_bytes(&mut metric, 7, &data);
+        } else {
+            put_bytes(&mut metric, 5, &data);
+        }
+        put_bytes(&mut scope_metrics, 2, &metric);
+    }
+
+    // ResourceMetrics { Resource resource = 1 { attributes = 1 }; scope_metrics = 2 }
+    let mut resource_metrics = Vec::new();
+    let mut res = Vec::new();
+    put_attributes(&mut res, 1, resource);
+    put_bytes(&mut resource_metrics, 1, &res);
+    put_bytes(&mut resource_metrics, 2, &scope_metrics);
+    let mut request = Vec::new();
+    put_bytes(&mut request, 1, &resource_metrics);
+    request
+}
+
+async fn post(request: reqwest::RequestBuilder) -> Result<(), String> {
+    match request.send().await {
+        Ok(r) if r.status().is_success() => Ok(()),
+        Ok(r) => Err(format!("HTTP {}", r.status())),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Export every snapshot published on `rx`. A failed export is logged,
+/// counted in `failures` and not retried; the next cycle brings a newer
+/// snapshot.
+pub async fn run_metrics(
+    client: reqwest::Client,
+    config: MetricsConfig,
+    mut rx: watch::Receiver<registry::Snapshot>,
+    failures: Arc<AtomicU64>,
+) {
+    let start = now_nanos();
+    while rx.changed().await.is_ok() {
+        let samples = rx.borrow_and_update().clone();
+        let (resource, now) = (&config.resource, now_nanos());
+        let sent = match &config.transport {
+            Transport::Http { protobuf: false } => {
+                post(client.post(&config.url).json(&export_metrics_request(resource, &samples, start, now))).await
+            }
+            Transport::Http { protobuf: true } => {
+                let body = encode_metrics_request(resource, &samples, start, now);
+                let request = client.post(&config.url).header(reqwest::header::CONTENT_TYPE, "application/x-protobuf");
+                post(request.body(body)).await
+            }
+            Transport::Grpc(channel) => {
+                channel.call(EXPORT_METHOD, &encode_metrics_request(resource, &samples, start, now)).await.map(drop)
+            }
+        };
+        match sent {
+            Ok(()) => debug!("otlp: exported the metrics snapshot to {}", config.url),
+            Err(e) => {
+                warn!("OTLP metrics export to {} failed: {}", config.url, e);
+                failures.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Bytes, extract::Request, http::StatusCode};
+    use std::sync::Mutex;
+
+    /// A connector state gauge, a transitions counter and a latency summary.
+    fn samples() -> Vec<Sample> {
+        let labels = registry::Labels::default().with("instance", "a").with("connector", "sink");
+        let summary = registry::summary("kafka_connect_instance_request_latency_seconds").with(&labels);
+        vec![
+            registry::gauge("kafka_connect_connector_state").with(&labels).label("state", "RUNNING").set(true),
+            registry::gauge("kafka_connect_connector_state").with(&labels).label("state", "FAILED").set(false),
+            summary.quantile(0.5, 0.01),
+            summary.count(3),
+            registry::counter("kafka_connect_connector_state_transitions_total").with(&labels).total(2),
+        ]
+    }
+
+    fn resource() -> Vec<(String, String)> {
+        vec![("service.name".into(), "kafka-connect-exporter".into()), ("env".into(), "prod".into())]
+    }
+
+    #[test]
+    fn resource_attributes_are_percent_decoded() {
+        assert_eq!(
+            resource_attributes("env=prod, team=data%20eng,broken,=x,k8s.ns=a%3Db"),
+            [("env", "prod"), ("team", "data eng"), ("k8s.ns", "a=b")]
+                .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                .to_vec()
+        );
+        assert!(resource_attributes("").is_empty());
+    }
+
+    #[test]
+    fn json_request_has_gauges_and_cumulative_sums() {
+        let body = export_metrics_request(&resource(), &samples(), 100, 200);
+        let resource = &body["resourceMetrics"][0];
+        assert_eq!(resource["resource"]["attributes"][1], json!({ "key": "env", "value": { "stringValue": "prod" } }));
+        let metrics = resource["scopeMetrics"][0]["metrics"].as_array().unwrap();
+        // The summary is left out
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0]["name"], "kafka_connect_connector_state");
+        let points = metrics[0]["gauge"]["dataPoints"].as_array().unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[1]["asDouble"], 0.0);
+        assert_eq!(points[1]["timeUnixNano"], "200");
+        assert_eq!(points[1]["attributes"][2], json!({ "key": "state", "value": { "stringValue": "FAILED" } }));
+        assert!(points[1].get("startTimeUnixNano").is_none());
+        let sum = &metrics[1]["sum"];
+        assert_eq!((&sum["aggregationTemporality"], &sum["isMonotonic"]), (&json!(2), &json!(true)));
+        assert_eq!(sum["dataPoints"][0]["asDouble"], 2.0);
+        assert_eq!(sum["dataPoints"][0]["startTimeUnixNano"], "100");
+    }
+
+    /// A protobuf field: varints and fixed64 as numbers, the rest as bytes.
+    #[derive(Debug, PartialEq)]
+    enum Wire {
+        Int(u64),
+        Bytes(Vec<u8>),
+    }
+
+    /// The fields of a message, in order.
+    fn walk(mut data: &[u8]) -> Vec<(u64, Wire)> {
+        fn varint(data: &mut &[u8]) -> u64 {
+            let (mut v, mut shift) = (0, 0);
+            loop {
+                let b = data[0];
+                *data = &data[1..];
+                v |= ((b & 0x7f) as u64) << shift;
+                shift += 7;
+                if b < 0x80 {
+                    return v;
+                }
+            }
+        }
+        let mut fields = Vec::new();
+        while !data.is_empty() {
+            let key = varint(&mut data);
+            let value = match key & 7 {
+                0 => Wire::Int(varint(&mut data)),
+                1 => {
+                    let (v, rest) = data.split_at(8);
+                    data = rest;
+                    Wire::Int(u64::from_le_bytes(v.try_into().unwrap()))
+                }
+                2 => {
+                    let len = varint(&mut data) as usize;
+                    let (v, rest) = data.split_at(len);
+                    data = rest;
+                    Wire::Bytes(v.to_vec())
+                }
+                wire => panic!("wire type {}", wire),
+            };
+            fields.push((key >> 3, value));
+        }
+        fields
+    }
+
+    /// Field `number` of `fields`, a message, walked.
+    fn message(fields: &[(u64, Wire)], number: u64) -> Vec<Vec<(u64, Wire)>> {
+        fields
+            .iter()
+            .filter(|(n, _)| *n == number)
+            .map(|(_, v)| match v {
+                Wire::Bytes(b) => walk(b),
+                Wire::Int(_) => panic!("field {} is not a message", number),
+            })
+            .collect()
+    }
+
+    fn bytes(s: &str) -> Wire {
+        Wire::Bytes(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn protobuf_request_matches_the_json_one() {
+        let request = walk(&encode_metrics_request(&resource(), &samples(), 100, 200));
+        let resource_metrics = message(&request, 1);
+        assert_eq!(resource_metrics.len(), 1);
+        let resource = &message(&resource_metrics[0], 1)[0];
+        let env = &message(resource, 1)[1];
+        assert_eq!(env[0], (1, bytes("env")));
+        assert_eq!(message(env, 2)[0], [(1, bytes("prod"))]);
+
+        let scope_metrics = &message(&resource_metrics[0], 2)[0];
+        let scope = &message(scope_metrics, 1)[0];
+        assert_eq!(scope[0], (1, bytes(env!("CARGO_PKG_NAME"))));
+        let metrics = message(scope_metrics, 2);
+        assert_eq!(metrics.len(), 2);
+
+        let state = &metrics[0];
+        assert_eq!(state[0], (1, bytes("kafka_connect_connector_state")));
+        assert_eq!(state[1], (2, bytes("1 if the connector is in this state.")));
+        let points = message(&message(state, 5)[0], 1);
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0][..2], [(3, Wire::Int(200)), (4, Wire::Int(1f64.to_bits()))]);
+        let attributes = message(&points[0], 7);
+        assert_eq!(attributes.len(), 3);
+        assert_eq!(attributes[2][0], (1, bytes("state")));
+        assert_eq!(message(&attributes[2], 2)[0], [(1, bytes("RUNNING"))]);
+
+        let transitions = &metrics[1];
+        assert_eq!(transitions[0], (1, bytes("kafka_connect_connector_state_transitions_total")));
+        let sum = &message(transitions, 7)[0];
+        assert_eq!(sum[1..], [(2, Wire::Int(2)), (3, Wire::Int(1))]);
+        let point = &message(sum, 1)[0];
+        assert_eq!(point[..3], [(2, Wire::Int(100)), (3, Wire::Int(200)), (4, Wire::Int(2f64.to_bits()))]);
+    }
+
+    #[test]
+    fn protocols_parse_by_their_otel_names() {
+        assert_eq!(Protocol::parse("http/json"), Some(Protocol::HttpJson));
+        assert_eq!(Protocol::parse("http/protobuf"), Some(Protocol::HttpProtobuf));
+        assert_eq!(Protocol::parse("grpc"), Some(Protocol::Grpc));
+        assert_eq!(Protocol::parse("GRPC"), None);
+    }
+
+    /// Content type and body of a request the collector got.
+    type Exports = Arc<Mutex<Vec<(String, Vec<u8>)>>>;
+
+    /// A collector answering `status`, and the requests it got.
+    async fn collector(status: StatusCode) -> (String, Exports) {
+        let exports: Exports = Arc::default();
+        let seen = exports.clone();
+        let app = axum::Router::new().fallback(move |req: Request| {
+            let seen = seen.clone();
+            async move {
+                let (parts, body) = req.into_parts();
+                let body: Bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+                let content_type = parts.headers.get("content-type").map(|v| v.to_str().unwrap().to_owned());
+                seen.lock().unwrap().push((content_type.unwrap_or_default(), body.to_vec()));
+                status
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1/metrics", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, exports)
+    }
+
+    /// Publish one snapshot to run_metrics and wait for its export.
+    async fn export(status: StatusCode, protobuf: bool) -> ((String, Vec<u8>), u64) {
+        let (url, exports) = collector(status).await;
+        let (tx, rx) = watch::channel(registry::Snapshot::default());
+        let failures = Arc::new(AtomicU64::new(0));
+        let config = MetricsConfig { url, resource: resource(), transport: Transport::Http { protobuf } };
+        tokio::spawn(run_metrics(reqwest::Client::new(), config, rx, failures.clone()));
+        tx.send_replace(registry::Snapshot::from(samples()));
+        for _ in 0..50 {
+            if !exports.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let export = exports.lock().unwrap().pop().expect("nothing exported");
+        // The failure is counted once the response is in
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        (export, failures.load(Ordering::Relaxed))
+    }
+
+    #[tokio::test]
+    async fn run_metrics_posts_each_snapshot() {
+        let ((content_type, body), failures) = export(StatusCode::OK, true).await;
+        assert_eq!(content_type, "application/x-protobuf");
+        assert_eq!(message(&walk(&body), 1).len(), 1);
+        assert_eq!(failures, 0);
+
+        let ((content_type, body), failures) = export(StatusCode::INTERNAL_SERVER_ERROR, false).await;
+        assert_eq!(content_type, "application/json");
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["resourceMetrics"][0]["scopeMetrics"][0]["metrics"].as_array().unwrap().len(), 2);
+        assert_eq!(failures, 1);
+    }
+}
@@ -0,0 +1,223 @@
+//! Prometheus Pushgateway sink for sites that cannot be scraped inbound.
+//!
+//! Each snapshot replaces the exporter's group on the gateway with a PUT of
+//! its text exposition, so connectors that disappear are dropped there too.
+
+use crate::{registry, web_auth};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+pub struct PushgatewayConfig {
+    pub url: String,
+    pub job: String,
+    /// Grouping labels after `job`, in the order they were given.
+    pub grouping: Vec<(String, String)>,
+    pub max_retries: u32,
+}
+
+/// `name/value` of a grouping key path. Values that are empty or hold a
+/// `/` use the gateway's base64url form, all others are percent-encoded.
+fn path_segment(name: &str, value: &str) -> String {
+    if value.is_empty() || value.contains('/') {
+        let encoded = web_auth::base64(value.as_bytes()).replace('+', "-").replace('/', "_");
+        return format!("{}@base64/{}", name, if encoded.is_empty() { "=" } else { &encoded });
+    }
+    format!("{}/{}", name, utf8_percent_encode(value, PATH_SEGMENT))
+}
+
+impl PushgatewayConfig {
+    /// `/metrics/job/<job>/<label>/<value>…` under the gateway URL.
+    pub fn group_url(&self) -> String {
+        let mut url = format!("{}/metrics/{}", self.url.trim_end_matches('/'), path_segment("job", &self.job));
+        for (name, value) in &self.grouping {
+            url.push('/');
+            url.push_str(&path_segment(name, value));
+        }
+        url
+    }
+}
+
+async fn send_once(client: &reqwest::Client, url: &str, body: String) -> Result<(), (bool, String)> {
+    let resp = client
+        .put(url)
+        .header("Content-Type", registry::TEXT_CONTENT_TYPE)
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| (true, e.to_string()))?;
+
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    // A 400 means the gateway rejected the content; sending it again will not help
+    let retry = status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+    // The gateway explains a rejection on the first line of the body
+    let body = resp.text().await.unwrap_or_default();
+    match body.lines().next().map(str::trim).filter(|l| !l.is_empty()) {
+        Some(reason) => Err((retry, format!("HTTP {}: {}", status, reason.chars().take(200).collect::<String>()))),
+        None => Err((retry, format!("HTTP {}", status))),
+    }
+}
+
+/// Push every snapshot published on `rx`. When a newer snapshot arrives
+/// while an older one is still being retried, the older one is dropped and
+/// counted as a failure.
+pub async fn run(
+    client: reqwest::Client,
+    config: PushgatewayConfig,
+    mut rx: watch::Receiver<registry::Snapshot>,
+    failures: Arc<AtomicU64>,
+) {
+    let url = config.group_url();
+    let mut superseded = false;
+    loop {
+        if !superseded && rx.changed().await.is_err() {
+            return;
+        }
+        superseded = false;
+
+        let body = registry::encode(&rx.borrow_and_update());
+
+        let mut backoff = Duration::from_secs(1);
+        for attempt in 0..=config.max_retries {
+            let Err((retry, e)) = send_once(&client, &url, body.clone()).await else {
+                debug!("pushgateway: pushed {} bytes to {}", body.len(), url);
+                break;
+            };
+            if !retry || attempt == config.max_retries {
+                warn!("Pushgateway push to {} failed: {}", url, e);
+                failures.fetch_add(1, Ordering::Relaxed);
+                break;
+            }
+            warn!("Pushgateway push to {} failed ({}), retrying in {:?}", url, e, backoff);
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                _ = rx.changed() => {
+                    failures.fetch_add(1, Ordering::Relaxed);
+                    superseded = true;
+                    break;
+                }
+            }
+            backoff *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Bytes, extract::Request, http::StatusCode};
+    use std::sync::Mutex;
+
+    /// Method, path, content type and body of a request the gateway got.
+    type Pushes = Arc<Mutex<Vec<(String, String, String, String)>>>;
+
+    /// A gateway answering with `replies` in turn, then 200, and the
+    /// requests it got.
+    async fn gateway(replies: &[(StatusCode, &'static str)]) -> (String, Pushes) {
+        let pushes: Pushes = Arc::default();
+        let replies = Arc::new(Mutex::new(replies.to_vec()));
+        let seen = pushes.clone();
+        let app = axum::Router::new().fallback(move |req: Request| {
+            let (seen, replies) = (seen.clone(), replies.clone());
+            async move {
+                let (parts, body) = req.into_parts();
+                let body: Bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+                let content_type = parts.headers.get("content-type").map(|v| v.to_str().unwrap().to_owned());
+                seen.lock().unwrap().push((
+                    parts.method.to_string(),
+                    parts.uri.path().to_owned(),
+                    content_type.unwrap_or_default(),
+                    String::from_utf8(body.to_vec()).unwrap(),
+                ));
+                let mut replies = replies.lock().unwrap();
+                if replies.is_empty() {
+                    (StatusCode::OK, "")
+                } else {
+                    replies.remove(0)
+                }
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, pushes)
+    }
+
+    fn config(url: &str, grouping: &[(&str, &str)]) -> PushgatewayConfig {
+        PushgatewayConfig {
+            url: url.to_owned(),
+            job: "kafka-connect".into(),
+            grouping: grouping.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            max_retries: 2,
+        }
+    }
+
+    #[test]
+    fn group_url_encodes_each_label_as_one_segment() {
+        let config = config("http://gw:9091/", &[("env", "prod eu"), ("path", "/var/x"), ("empty", "")]);
+        assert_eq!(
+            config.group_url(),
+            "http://gw:9091/metrics/job/kafka-connect/env/prod%20eu/path@base64/L3Zhci94/empty@base64/="
+        );
+        assert_eq!(path_segment("job", "a.b_c-d~e"), "job/a.b_c-d~e");
+        assert_eq!(path_segment("x", "n?é"), "x/n%3F%C3%A9");
+    }
+
+    #[tokio::test]
+    async fn send_once_tells_retryable_failures_apart() {
+        let client = reqwest::Client::new();
+        let (url, pushes) = gateway(&[
+            (StatusCode::BAD_REQUEST, "pushed metrics are invalid\nsecond line"),
+            (StatusCode::SERVICE_UNAVAILABLE, ""),
+            (StatusCode::TOO_MANY_REQUESTS, ""),
+        ])
+        .await;
+        let send = || send_once(&client, &url, "x 1\n".into());
+        assert_eq!(send().await, Err((false, "HTTP 400 Bad Request: pushed metrics are invalid".into())));
+        assert_eq!(send().await, Err((true, "HTTP 503 Service Unavailable".into())));
+        assert_eq!(send().await, Err((true, "HTTP 429 Too Many Requests".into())));
+        assert_eq!(send().await, Ok(()));
+        let pushes = pushes.lock().unwrap();
+        assert_eq!(pushes.len(), 4);
+        assert!(pushes.iter().all(|(method, _, content_type, _)| method == "PUT"
+            && content_type == registry::TEXT_CONTENT_TYPE));
+    }
+
+    #[tokio::test]
+    async fn run_puts_each_snapshot_to_the_group() {
+        let (url, pushes) = gateway(&[(StatusCode::SERVICE_UNAVAILABLE, "")]).await;
+        let (tx, rx) = watch::channel(registry::Snapshot::default());
+        let failures = Arc::new(AtomicU64::new(0));
+        tokio::spawn(run(reqwest::Client::new(), config(&url, &[("env", "prod")]), rx, failures.clone()));
+
+        let up = registry::gauge("kafka_connect_up").label("instance", "a").set(true);
+        let snapshot = registry::Snapshot::from(vec![up]);
+        tx.send_replace(snapshot.clone());
+        // The first push fails and is retried after a second
+        for _ in 0..50 {
+            if pushes.lock().unwrap().len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let pushes = pushes.lock().unwrap().clone();
+        assert_eq!(pushes.len(), 2, "{:?}", pushes);
+        for (_, path, _, body) in &pushes {
+            assert_eq!(path, "/metrics/job/kafka-connect/env/prod");
+            assert_eq!(body, &registry::encode(&snapshot));
+        }
+        assert_eq!(failures.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,567 @@
+//! Type and help text of every metric the exporter emits, the typed
+//! samples the scrape collects, and the encode step that turns them into
+//! the served exposition.
+//!
+//! The scrape code produces [`Sample`]s in whatever order it meets them.
+//! [`encode`] groups them by family, drops repeated series and puts the
+//! `# HELP` / `# TYPE` header in front of each family, so the output is
+//! valid text format however the samples were collected.
+//! [`to_openmetrics`] turns that into OpenMetrics for scrapers that ask
+//! for it. The push sinks write out the same samples in their own formats.
+//!
+//! Samples are built with [`gauge`], [`counter`] and [`summary`], e.g.
+//! `registry::gauge("kafka_connect_up").with(&base).set(true)`; label values
+//! are escaped when the sample is written out.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write as _};
+use std::marker::PhantomData;
+use tracing::debug;
+
+/// Content type of the classic text format.
+pub const TEXT_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+/// Content type of OpenMetrics 1.0.
+pub const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Exposition format of a response.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    OpenMetrics,
+}
+
+impl Format {
+    /// The format an `Accept` header prefers: OpenMetrics when it ranks
+    /// `application/openmetrics-text` above the classic text format (or
+    /// lists only OpenMetrics), text otherwise.
+    pub fn negotiate(accept: &str) -> Self {
+        let (mut openmetrics, mut text) = (0.0_f32, 0.0_f32);
+        for range in accept.split(',') {
+            let mut parts = range.split(';').map(str::trim);
+            let media = parts.next().unwrap_or("").to_ascii_lowercase();
+            let q = parts
+                .filter_map(|p| p.strip_prefix("q="))
+                .find_map(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            match media.as_str() {
+                "application/openmetrics-text" => openmetrics = openmetrics.max(q),
+                "text/plain" | "text/*" | "*/*" => text = text.max(q),
+                _ => {}
+            }
+        }
+        if openmetrics > 0.0 && openmetrics > text {
+            Format::OpenMetrics
+        } else {
+            Format::Text
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Text => TEXT_CONTENT_TYPE,
+            Format::OpenMetrics => OPENMETRICS_CONTENT_TYPE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Gauge,
+    Counter,
+    Summary,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Gauge => "gauge",
+            Kind::Counter => "counter",
+            Kind::Summary => "summary",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Family {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub help: &'static str,
+}
+
+impl Family {
+    const fn gauge(name: &'static str, help: &'static str) -> Family {
+        Family { name, kind: Kind::Gauge, help }
+    }
+
+    const fn counter(name: &'static str, help: &'static str) -> Family {
+        Family { name, kind: Kind::Counter, help }
+    }
+}
+
+/// Every metric family, in no particular order. A sample whose family is
+/// missing here is still served, just without a header.
+pub const FAMILIES: &[Family] = &[
+    // Per connector
+    Family::gauge("kafka_connect_connector_state", "1 if the connector is in this state."),
+    Family::gauge("kafka_connect_connector_task_state", "1 if the task is in this state."),
+    Family::gauge("kafka_connect_connector_tasks", "Tasks of the connector in this state."),
+    Family::gauge("kafka_connect_connector_info", "1 per connector, with its type, class and plugin version."),
+    Family::gauge("kafka_connect_connector_single_worker", "1 if all tasks of the connector run on one worker."),
+    Family::gauge("kafka_connect_connector_degraded", "1 if the connector is RUNNING with a task that is not."),
+    Family::gauge("kafka_connect_connector_config_version", "Bumps on each observed change of the connector config."),
+    Family::gauge("kafka_connect_connector_became_failed", "1 for the cycle the connector turned FAILED."),
+    Family::gauge("kafka_connect_connector_task_count_dropped", "1 for the cycle the connector lost tasks."),
+    Family::gauge("kafka_connect_required_connector_missing", "1 if a required connector is absent."),
+    Family::gauge("kafka_connect_connector_worker_info", "1, with the worker the connector runs on."),
+    Family::gauge("kafka_connect_task_worker_info", "1, with the worker the task runs on."),
+    Family::gauge("kafka_connect_connector_topic", "1 per topic the connector has used."),
+    Family::counter("kafka_connect_connector_state_transitions_total", "Observed connector state changes."),
+    Family::counter("kafka_connect_task_state_transitions_total", "Observed task state changes."),
+    Family::gauge("kafka_connect_connector_flapping", "Connector and task FAILED entries and exits per hour."),
+    Family::gauge("kafka_connect_task_error_info", "1 per task with a failure trace, by exception class."),
+    // Per instance
+    Family::gauge("kafka_connect_up", "1 if the Connect REST API was reachable."),
+    Family::gauge("kafka_connect_instance_info", "1, with the URL of the instance."),
+    Family::gauge("kafka_connect_version_info", "1, with the version reported by GET /."),
+    Family::gauge("kafka_connect_cluster_info", "1, with the version, commit and Kafka cluster id reported by GET /."),
+    Family::gauge("kafka_connect_connectors_total", "Connectors listed by the instance."),
+    Family::gauge("kafka_connect_connectors_scraped", "Connectors whose status was fetched."),
+    Family::gauge("kafka_connect_scrape_complete", "1 if every listed connector was scraped."),
+    Family::gauge("kafka_connect_connectors_running", "RUNNING connectors."),
+    Family::gauge("kafka_connect_connectors_failed", "FAILED connectors."),
+    Family::gauge("kafka_connect_connectors_degraded", "RUNNING connectors with a task that is not RUNNING."),
+    Family::counter("kafka_connect_task_reassignments_total", "Tasks that moved to another worker."),
+    Family::gauge("kafka_connect_workers_total", "Distinct worker ids seen on connectors and tasks."),
+    Family::gauge("kafka_connect_orphaned_tasks", "Connector and task inconsistencies."),
+    Family::gauge("kafka_connect_oldest_failed_connector_age_seconds", "Longest time a connector has been FAILED."),
+    Family::gauge("kafka_connect_data_stale", "1 if series are repeated from the last successful scrape."),
+    Family::gauge("kafka_connect_requests_per_scrape", "HTTP requests made in the last cycle."),
+    Family::counter("kafka_connect_status_not_modified_total", "Status requests answered with 304 Not Modified."),
+    Family {
+        name: "kafka_connect_instance_request_latency_seconds",
+        kind: Kind::Summary,
+        help: "Latency of the list and status requests.",
+    },
+    Family::gauge("kafka_connect_expand_status_supported", "1 if the list request returned expanded statuses."),
+    Family::gauge("kafka_connect_request_concurrency", "Per-connector requests allowed in flight."),
+    Family::gauge("kafka_connect_scrape_error_ratio", "Failed over total requests in the last cycle."),
+    Family::gauge("kafka_connect_resolved_addr_info", "1 per address the instance host resolves to."),
+    Family::gauge("kafka_connect_plugin_info", "1 per connector plugin installed on the worker."),
+    Family::gauge(
+        "kafka_connect_last_successful_scrape_timestamp_seconds",
+        "Unix time the instance was last reached, 0 if never.",
+    ),
+    // Across instances
+    Family::gauge(
+        "kafka_connect_plugin_version_skew",
+        "1 if the workers of a cluster report different plugin versions.",
+    ),
+    Family::gauge("kafka_connect_connector_name_collision", "Clusters listing the same connector name."),
+    // Exporter
+    Family::gauge("kafka_connect_maintenance_mode", "1 while in maintenance mode."),
+    Family::counter("kafka_connect_remote_write_failures_total", "Snapshots remote-write failed to deliver."),
+    Family::counter(
+        "kafka_connect_otlp_metrics_failures_total",
+        "Snapshots the OTLP metrics export failed to deliver.",
+    ),
+    Family::counter("kafka_connect_dogstatsd_failures_total", "Snapshots the DogStatsD sink failed to send."),
+    Family::counter("kafka_connect_pushgateway_failures_total", "Snapshots the Pushgateway push failed to deliver."),
+    Family::counter("kafka_connect_webhook_deliveries_total", "Event webhook deliveries by outcome."),
+    Family::counter("kafka_connect_channel_notifications_total", "Slack and Teams channel deliveries by outcome."),
+    Family::gauge("kafka_connect_alerts_firing", "Series of the alert rule that are firing."),
+    Family::counter("kafka_connect_alert_notifications_total", "Alert notification deliveries by outcome."),
+    Family::counter("kafka_connect_heal_attempts_total", "Finished heal requests by outcome."),
+    Family::gauge("kafka_connect_exporter_cache_bytes", "Size of the metrics cache."),
+    Family::gauge("kafka_connect_exporter_scrape_duration_seconds", "Duration of the last scrape of the instance."),
+    Family::counter("kafka_connect_exporter_http_requests_made_total", "HTTP requests made to the instance."),
+    Family::counter("kafka_connect_exporter_scrape_errors_total", "Failed requests to the instance by reason."),
+    Family::counter("kafka_connect_exporter_task_restarts_total", "Automatic restarts of FAILED tasks by outcome."),
+];
+
+/// Family of the sample named `name`, including the `_sum` and `_count`
+/// series of a summary.
+pub fn family(name: &str) -> Option<&'static Family> {
+    FAMILIES.iter().find(|f| f.name == name).or_else(|| {
+        let base = name.strip_suffix("_sum").or_else(|| name.strip_suffix("_count"))?;
+        FAMILIES.iter().find(|f| f.name == base && f.kind == Kind::Summary)
+    })
+}
+
+/// Label pairs of a series in the order they were added. Values are kept
+/// as given and escaped when the sample is written out.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Labels(Vec<(String, String)>);
+
+impl Labels {
+    /// `self` with `name="value"` after the labels already there.
+    pub fn with(mut self, name: &str, value: &str) -> Self {
+        self.push(name, value);
+        self
+    }
+
+    pub fn push(&mut self, name: &str, value: &str) {
+        self.0.push((name.to_owned(), value.to_owned()));
+    }
+
+    /// `self` followed by the labels of `other`.
+    pub fn extend(&mut self, other: &Labels) {
+        self.0.extend(other.0.iter().cloned());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The value of the label `name`, if the series has it.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn pairs(&self) -> &[(String, String)] {
+        &self.0
+    }
+}
+
+/// `name="value",...`, with `\`, `"` and line breaks in values escaped.
+impl fmt::Display for Labels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}=\"", name)?;
+            for c in value.chars() {
+                match c {
+                    '\\' => f.write_str("\\\\")?,
+                    '"' => f.write_str("\\\"")?,
+                    '\n' => f.write_str("\\n")?,
+                    c => f.write_char(c)?,
+                }
+            }
+            f.write_str("\"")?;
+        }
+        Ok(())
+    }
+}
+
+/// A sample value: a number, or a flag as 1 or 0.
+pub trait Value {
+    fn value(self) -> f64;
+}
+
+impl Value for bool {
+    fn value(self) -> f64 {
+        self as u8 as f64
+    }
+}
+
+impl Value for u32 {
+    fn value(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Value for u64 {
+    fn value(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Value for usize {
+    fn value(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Value for f64 {
+    fn value(self) -> f64 {
+        self
+    }
+}
+
+/// One sample of a series, kept typed until [`encode`] or a push sink
+/// writes it out in its own format.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    /// Name of the family in [`FAMILIES`].
+    pub family: &'static str,
+    /// `_sum` or `_count` for those series of a summary, else empty.
+    pub suffix: &'static str,
+    pub labels: Labels,
+    pub value: f64,
+}
+
+impl Sample {
+    /// Name of the series, the family name with its suffix.
+    pub fn name(&self) -> String {
+        format!("{}{}", self.family, self.suffix)
+    }
+
+    /// Kind of the family; `None` for a name missing from [`FAMILIES`].
+    pub fn kind(&self) -> Option<Kind> {
+        FAMILIES.iter().find(|f| f.name == self.family).map(|f| f.kind)
+    }
+
+    /// `self` with its value set to `value`.
+    pub fn with_value(self, value: f64) -> Self {
+        Sample { value, ..self }
+    }
+}
+
+/// The sample line of the text format.
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.family, self.suffix)?;
+        if !self.labels.is_empty() {
+            write!(f, "{{{}}}", self.labels)?;
+        }
+        match self.value {
+            v if v.is_nan() => f.write_str(" NaN"),
+            f64::INFINITY => f.write_str(" +Inf"),
+            f64::NEG_INFINITY => f.write_str(" -Inf"),
+            v => write!(f, " {}", v),
+        }
+    }
+}
+
+/// The samples of one exposition, shared by the cache and the push sinks.
+pub type Snapshot = std::sync::Arc<[Sample]>;
+
+/// Kinds of [`Series`]; each takes its value its own way.
+pub struct Gauge;
+pub struct Counter;
+pub struct Summary;
+
+/// A series of one family being labelled, until its value makes it a
+/// [`Sample`].
+pub struct Series<K> {
+    name: &'static str,
+    labels: Labels,
+    kind: PhantomData<K>,
+}
+
+fn series<K>(name: &'static str, kind: Kind) -> Series<K> {
+    debug_assert!(
+        FAMILIES.iter().any(|f| f.name == name && f.kind == kind),
+        "{} is not a {} in FAMILIES",
+        name,
+        kind.as_str()
+    );
+    Series { name, labels: Labels::default(), kind: PhantomData }
+}
+
+/// A series of the gauge family `name`.
+pub fn gauge(name: &'static str) -> Series<Gauge> {
+    series(name, Kind::Gauge)
+}
+
+/// A series of the counter family `name`.
+pub fn counter(name: &'static str) -> Series<Counter> {
+    series(name, Kind::Counter)
+}
+
+/// A series of the summary family `name`.
+pub fn summary(name: &'static str) -> Series<Summary> {
+    series(name, Kind::Summary)
+}
+
+impl<K> Series<K> {
+    pub fn label(mut self, name: &str, value: &str) -> Self {
+        self.labels.push(name, value);
+        self
+    }
+
+    pub fn labels(mut self, pairs: &[(&str, &str)]) -> Self {
+        for (name, value) in pairs {
+            self.labels.push(name, value);
+        }
+        self
+    }
+
+    /// `labels` after the ones already set.
+    pub fn with(mut self, labels: &Labels) -> Self {
+        self.labels.extend(labels);
+        self
+    }
+
+    fn sample(&self, suffix: &'static str, labels: Labels, value: f64) -> Sample {
+        Sample { family: self.name, suffix, labels, value }
+    }
+}
+
+impl Series<Gauge> {
+    pub fn set(self, value: impl Value) -> Sample {
+        Sample { family: self.name, suffix: "", labels: self.labels, value: value.value() }
+    }
+}
+
+impl Series<Counter> {
+    /// The sample of the count so far.
+    pub fn total(self, value: u64) -> Sample {
+        Sample { family: self.name, suffix: "", labels: self.labels, value: value as f64 }
+    }
+}
+
+impl Series<Summary> {
+    pub fn quantile(&self, q: f64, value: f64) -> Sample {
+        self.sample("", self.labels.clone().with("quantile", &q.to_string()), value)
+    }
+
+    pub fn sum(&self, value: f64) -> Sample {
+        self.sample("_sum", self.labels.clone(), value)
+    }
+
+    pub fn count(&self, value: u64) -> Sample {
+        self.sample("_count", self.labels.clone(), value as f64)
+    }
+}
+
+/// The text format of `samples`. Families keep the order their first
+/// sample came in, samples keep their order within a family. A series
+/// seen again is dropped.
+pub fn encode(samples: &[Sample]) -> String {
+    let mut order: Vec<&str> = Vec::new();
+    let mut groups: HashMap<&str, Vec<&Sample>> = HashMap::new();
+    let mut seen: HashSet<(&str, &str, &Labels)> = HashSet::new();
+    let mut repeated = 0;
+    for sample in samples {
+        if !seen.insert((sample.family, sample.suffix, &sample.labels)) {
+            repeated += 1;
+            continue;
+        }
+        groups
+            .entry(sample.family)
+            .or_insert_with(|| {
+                order.push(sample.family);
+                Vec::new()
+            })
+            .push(sample);
+    }
+    if repeated > 0 {
+        debug!("exposition: dropped {} repeated series", repeated);
+    }
+
+    let mut out = String::with_capacity(samples.len() * 96 + order.len() * 96);
+    for key in order {
+        if let Some(f) = family(key) {
+            let _ = write!(out, "# HELP {} {}\n# TYPE {} {}\n", f.name, f.help, f.name, f.kind.as_str());
+        }
+        for sample in &groups[key] {
+            let _ = writeln!(out, "{}", sample);
+        }
+    }
+    out
+}
+
+/// `text`, as produced by [`encode`], in OpenMetrics: counter families are
+/// named without their `_total` suffix in the metadata, and the exposition
+/// ends with `# EOF`. The samples themselves are the same in both formats.
+pub fn to_openmetrics(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 8);
+    for line in text.lines() {
+        match line.strip_prefix("# HELP ").or_else(|| line.strip_prefix("# TYPE ")) {
+            Some(rest) => {
+                let (name, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+                let name = match family(name) {
+                    Some(f) if f.kind == Kind::Counter => name.strip_suffix("_total").unwrap_or(name),
+                    _ => name,
+                };
+                out.push_str(&format!("{}{} {}", &line[..7], name, tail));
+            }
+            None => out.push_str(line),
+        }
+        out.push('\n');
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn series_escape_label_values_in_order() {
+        let base = Labels::default().with("instance", "connect-1:8083").with("color", "blue");
+        let line = gauge("kafka_connect_connector_state")
+            .labels(&[("connector", "q\"uo\\te\nx"), ("state", "running")])
+            .with(&base)
+            .set(true);
+        let labels = r#"connector="q\"uo\\te\nx",state="running",instance="connect-1:8083",color="blue""#;
+        assert_eq!(line.to_string(), format!("kafka_connect_connector_state{{{}}} 1", labels));
+        assert_eq!(line.labels.get("connector"), Some("q\"uo\\te\nx"));
+        assert_eq!(gauge("kafka_connect_maintenance_mode").set(false).to_string(), "kafka_connect_maintenance_mode 0");
+        assert_eq!(gauge("kafka_connect_up").with(&Labels::default()).set(true).to_string(), "kafka_connect_up 1");
+    }
+
+    #[test]
+    fn values_render_for_the_text_format() {
+        let up = |v: f64| gauge("kafka_connect_scrape_error_ratio").set(v).to_string();
+        assert_eq!(up(0.25), "kafka_connect_scrape_error_ratio 0.25");
+        assert_eq!(up(1.0), "kafka_connect_scrape_error_ratio 1");
+        assert_eq!(up(f64::NAN), "kafka_connect_scrape_error_ratio NaN");
+        assert_eq!(up(f64::INFINITY), "kafka_connect_scrape_error_ratio +Inf");
+        assert_eq!(gauge("kafka_connect_workers_total").set(3_usize).to_string(), "kafka_connect_workers_total 3");
+        let total = counter("kafka_connect_heal_attempts_total").labels(&[("connector", "s"), ("result", "healed")]);
+        assert_eq!(total.total(7).to_string(), r#"kafka_connect_heal_attempts_total{connector="s",result="healed"} 7"#);
+    }
+
+    #[test]
+    fn summary_series_share_the_labels() {
+        let base = Labels::default().with("instance", "a");
+        let summary = summary("kafka_connect_instance_request_latency_seconds").with(&base);
+        assert_eq!(
+            [summary.quantile(0.5, 0.125), summary.sum(1.5), summary.count(3)].map(|s| s.to_string()),
+            [
+                r#"kafka_connect_instance_request_latency_seconds{instance="a",quantile="0.5"} 0.125"#,
+                r#"kafka_connect_instance_request_latency_seconds_sum{instance="a"} 1.5"#,
+                r#"kafka_connect_instance_request_latency_seconds_count{instance="a"} 3"#,
+            ]
+        );
+        // The suffixed series group under the summary's header
+        let text = encode(&[summary.sum(1.5), summary.quantile(0.5, 0.125)]);
+        assert!(text.starts_with("# HELP kafka_connect_instance_request_latency_seconds "), "{}", text);
+        assert_eq!(text.matches("# TYPE").count(), 1, "{}", text);
+    }
+
+    #[test]
+    fn encode_groups_families_and_drops_repeats() {
+        let up = |instance: &str| gauge("kafka_connect_up").label("instance", instance).set(true);
+        let workers = gauge("kafka_connect_workers_total").label("instance", "a").set(2_usize);
+        let text = encode(&[up("a"), workers, up("b"), up("a")]);
+        let lines: Vec<&str> = text.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(
+            lines,
+            [
+                r#"kafka_connect_up{instance="a"} 1"#,
+                r#"kafka_connect_up{instance="b"} 1"#,
+                r#"kafka_connect_workers_total{instance="a"} 2"#,
+            ]
+        );
+        assert_eq!(text.matches("# TYPE kafka_connect_up gauge").count(), 1, "{}", text);
+        assert!(encode(&[]).is_empty());
+    }
+
+    #[test]
+    fn labels_extend() {
+        let mut labels = Labels::default().with("tenant", "t");
+        labels.extend(&Labels::default());
+        labels.extend(&Labels::default().with("instance", "a"));
+        assert_eq!(labels.to_string(), r#"tenant="t",instance="a""#);
+        let mut empty = Labels::default();
+        empty.extend(&labels);
+        assert_eq!(empty, labels);
+    }
+
+    #[test]
+    #[should_panic(expected = "kafka_connect_up is not a counter in FAMILIES")]
+    fn series_must_match_the_family_kind() {
+        counter("kafka_connect_up");
+    }
+
+    #[test]
+    #[should_panic(expected = "kafka_connect_unknown is not a gauge in FAMILIES")]
+    fn series_must_have_a_family() {
+        gauge("kafka_connect_unknown");
+    }
+}
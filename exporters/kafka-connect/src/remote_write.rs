@@ -0,0 +1,525 @@
+//! Prometheus remote-write sink (protocol 1.0: snappy-compressed protobuf
+//! `WriteRequest`).
+//!
+//! The protobuf and snappy encodings are written by hand; both are small
+//! and stable, and this keeps the build free of codegen and native deps.
+
+use crate::registry::{self, Sample};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+// ── Protobuf encoding ─────────────────────────────────────────────────────────
+
+pub fn put_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+pub fn put_bytes(out: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    put_varint(out, ((field << 3) | 2) as u64);
+    put_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// `prometheus.WriteRequest` with one single-sample series per input sample.
+pub fn encode_write_request(samples: &[Sample], timestamp_ms: i64) -> Vec<u8> {
+    let mut req = Vec::new();
+    let mut series = Vec::new();
+    let mut buf = Vec::new();
+
+    for s in samples {
+        series.clear();
+
+        // Labels must be sorted by name, with __name__ among them
+        let name = s.name();
+        let mut labels: Vec<(&str, &str)> = s.labels.pairs().iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        labels.push(("__name__", &name));
+        labels.sort();
+        for (name, value) in labels {
+            buf.clear();
+            put_bytes(&mut buf, 1, name.as_bytes());
+            put_bytes(&mut buf, 2, value.as_bytes());
+            put_bytes(&mut series, 1, &buf);
+        }
+
+        // Sample { double value = 1; int64 timestamp = 2; }
+        buf.clear();
+        buf.push((1 << 3) | 1);
+        buf.extend_from_slice(&s.value.to_le_bytes());
+        buf.push(2 << 3);
+        put_varint(&mut buf, timestamp_ms as u64);
+        put_bytes(&mut series, 2, &buf);
+
+        put_bytes(&mut req, 1, &series);
+    }
+    req
+}
+
+// ── Snappy block compression ──────────────────────────────────────────────────
+
+fn snappy_literal(out: &mut Vec<u8>, lit: &[u8]) {
+    let n = lit.len() - 1;
+    if n < 60 {
+        out.push((n as u8) << 2);
+    } else {
+        let bytes = (n as u32).to_le_bytes();
+        let width = 4 - (n as u32).leading_zeros() as usize / 8;
+        out.push(((59 + width) as u8) << 2);
+        out.extend_from_slice(&bytes[..width]);
+    }
+    out.extend_from_slice(lit);
+}
+
+fn snappy_copy(out: &mut Vec<u8>, offset: usize, mut len: usize) {
+    // Copy with 2-byte offset; 1..=64 bytes per element
+    while len > 0 {
+        // Keep the tail at least 4 bytes long so it stays worth a copy
+        let n = if len > 64 && len < 68 { 60 } else { len.min(64) };
+        out.push((((n - 1) as u8) << 2) | 2);
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        len -= n;
+    }
+}
+
+/// Snappy block format (no framing), as remote-write expects.
+pub fn snappy_compress(data: &[u8]) -> Vec<u8> {
+    const MIN_MATCH: usize = 4;
+    const MAX_OFFSET: usize = 65535;
+    const HASH_BITS: u32 = 14;
+
+    let mut out = Vec::with_capacity(data.len() / 2 + 16);
+    put_varint(&mut out, data.len() as u64);
+
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let hash = |i: usize| {
+        let v = u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        (v.wrapping_mul(0x1E35_A7BD) >> (32 - HASH_BITS)) as usize
+    };
+
+    let mut lit_start = 0;
+    let mut i = 0;
+    while i + MIN_MATCH <= data.len() {
+        let h = hash(i);
+        let cand = table[h];
+        table[h] = i;
+        if cand != usize::MAX && i - cand <= MAX_OFFSET && data[cand..cand + 4] == data[i..i + 4] {
+            let len = MIN_MATCH
+                + data[cand + MIN_MATCH..]
+                    .iter()
+                    .zip(&data[i + MIN_MATCH..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+            if lit_start < i {
+                snappy_literal(&mut out, &data[lit_start..i]);
+            }
+            snappy_copy(&mut out, i - cand, len);
+            i += len;
+            lit_start = i;
+        } else {
+            i += 1;
+        }
+    }
+    if lit_start < data.len() {
+        snappy_literal(&mut out, &data[lit_start..]);
+    }
+    out
+}
+
+// ── Writer ────────────────────────────────────────────────────────────────────
+
+pub struct RemoteWriteConfig {
+    pub url: String,
+    pub max_retries: u32,
+    pub auth: Option<Auth>,
+}
+
+/// Credentials sent with every push (REMOTE_WRITE_USERNAME and
+/// REMOTE_WRITE_PASSWORD, or REMOTE_WRITE_BEARER_TOKEN).
+#[derive(Clone)]
+pub enum Auth {
+    Basic { username: String, password: Option<String> },
+    Bearer(String),
+}
+
+impl Auth {
+    /// Credentials from the REMOTE_WRITE_* variables `var` returns, if any.
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, &'static str> {
+        match (var("REMOTE_WRITE_USERNAME"), var("REMOTE_WRITE_PASSWORD"), var("REMOTE_WRITE_BEARER_TOKEN")) {
+            (Some(_), _, Some(_)) => Err("set REMOTE_WRITE_USERNAME or REMOTE_WRITE_BEARER_TOKEN, not both"),
+            (None, Some(_), _) => Err("REMOTE_WRITE_PASSWORD is set without REMOTE_WRITE_USERNAME"),
+            (Some(username), password, None) => Ok(Some(Auth::Basic { username, password })),
+            (None, None, Some(token)) => Ok(Some(Auth::Bearer(token))),
+            (None, None, None) => Ok(None),
+        }
+    }
+
+    fn apply(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self {
+            Auth::Basic { username, password } => req.basic_auth(username, password.as_ref()),
+            Auth::Bearer(token) => req.bearer_auth(token),
+        }
+    }
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+async fn send_once(
+    client: &reqwest::Client,
+    config: &RemoteWriteConfig,
+    body: Vec<u8>,
+) -> Result<(), (bool, String)> {
+    let mut req = client.post(&config.url);
+    if let Some(auth) = &config.auth {
+        req = auth.apply(req);
+    }
+    let resp = req
+        .header("Content-Encoding", "snappy")
+        .header("Content-Type", "application/x-protobuf")
+        .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| (true, e.to_string()))?;
+
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    // Per the spec 5xx and 429 are retryable, other 4xx are not
+    let retry = status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+    Err((retry, format!("HTTP {}", status)))
+}
+
+/// Push every snapshot published on `rx`. When a newer snapshot arrives
+/// while an older one is still being retried, the older one is dropped and
+/// counted as a failure.
+pub async fn run(
+    client: reqwest::Client,
+    config: RemoteWriteConfig,
+    mut rx: watch::Receiver<registry::Snapshot>,
+    failures: Arc<AtomicU64>,
+) {
+    let mut superseded = false;
+    loop {
+        if !superseded && rx.changed().await.is_err() {
+            return;
+        }
+        superseded = false;
+
+        let samples = rx.borrow_and_update().clone();
+        let body = snappy_compress(&encode_write_request(&samples, now_ms()));
+
+        let mut backoff = Duration::from_secs(1);
+        for attempt in 0..=config.max_retries {
+            let Err((retry, e)) = send_once(&client, &config, body.clone()).await else {
+                debug!("remote-write: pushed {} samples", samples.len());
+                break;
+            };
+            if !retry || attempt == config.max_retries {
+                warn!("remote-write to {} failed: {}", config.url, e);
+                failures.fetch_add(1, Ordering::Relaxed);
+                break;
+            }
+            warn!("remote-write to {} failed ({}), retrying in {:?}", config.url, e, backoff);
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                _ = rx.changed() => {
+                    failures.fetch_add(1, Ordering::Relaxed);
+                    superseded = true;
+                    break;
+                }
+            }
+            backoff *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Snappy block decoder after the format description, with every
+    /// element type, so the encoder is checked against the spec rather
+    /// than against itself.
+    fn snappy_decode(data: &[u8]) -> Vec<u8> {
+        let (mut len, mut shift, mut pos) = (0usize, 0, 0);
+        loop {
+            let b = data[pos];
+            pos += 1;
+            len |= ((b & 0x7f) as usize) << shift;
+            shift += 7;
+            if b < 0x80 {
+                break;
+            }
+        }
+        let mut out = Vec::with_capacity(len);
+        let le = |bytes: &[u8]| bytes.iter().rev().fold(0usize, |v, &b| v << 8 | b as usize);
+        while pos < data.len() {
+            let tag = data[pos];
+            pos += 1;
+            let (copy_len, offset) = match tag & 3 {
+                0 => {
+                    let mut n = (tag >> 2) as usize;
+                    if n >= 60 {
+                        let width = n - 59;
+                        n = le(&data[pos..pos + width]);
+                        pos += width;
+                    }
+                    out.extend_from_slice(&data[pos..pos + n + 1]);
+                    pos += n + 1;
+                    continue;
+                }
+                1 => {
+                    let offset = ((tag as usize >> 5) << 8) | data[pos] as usize;
+                    pos += 1;
+                    (4 + ((tag >> 2) & 7) as usize, offset)
+                }
+                2 => {
+                    pos += 2;
+                    (1 + (tag >> 2) as usize, le(&data[pos - 2..pos]))
+                }
+                _ => {
+                    pos += 4;
+                    (1 + (tag >> 2) as usize, le(&data[pos - 4..pos]))
+                }
+            };
+            assert!(offset > 0 && offset <= out.len(), "copy offset {} at output {}", offset, out.len());
+            let start = out.len() - offset;
+            for k in 0..copy_len {
+                out.push(out[start + k]);
+            }
+        }
+        assert_eq!(out.len(), len, "decoded length differs from the preamble");
+        out
+    }
+
+    /// Bytes that do not repeat within 4, from a linear congruential generator.
+    fn noise(n: usize, seed: u32) -> Vec<u8> {
+        let mut x = seed;
+        (0..n)
+            .map(|_| {
+                x = x.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (x >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn write_request_matches_protobuf_encoding() {
+        let labels = registry::Labels::default().with("job", "x");
+        let sample = Sample { family: "up", suffix: "", labels, value: 1.0 };
+        let mut expected = vec![0x0a, 40]; // WriteRequest.timeseries, 40 bytes
+        expected.extend([0x0a, 14, 0x0a, 8]); // TimeSeries.labels: Label.name
+        expected.extend(b"__name__");
+        expected.extend([0x12, 2]); // Label.value
+        expected.extend(b"up");
+        expected.extend([0x0a, 8, 0x0a, 3]);
+        expected.extend(b"job");
+        expected.extend([0x12, 1]);
+        expected.extend(b"x");
+        expected.extend([0x12, 12, 0x09]); // TimeSeries.samples: Sample.value, fixed64
+        expected.extend(1.0f64.to_le_bytes());
+        expected.extend([0x10, 0xe8, 0x07]); // Sample.timestamp, varint 1000
+        assert_eq!(encode_write_request(&[sample], 1000), expected);
+    }
+
+    #[test]
+    fn varints_use_seven_bits_per_byte() {
+        for (v, bytes) in [(0u64, &[0x00][..]), (127, &[0x7f]), (128, &[0x80, 0x01]), (300, &[0xac, 0x02])] {
+            let mut out = Vec::new();
+            put_varint(&mut out, v);
+            assert_eq!(out, bytes);
+        }
+        let mut out = Vec::new();
+        put_varint(&mut out, 1_700_000_000_000);
+        assert_eq!(out, [0x80, 0xd0, 0x95, 0xff, 0xbc, 0x31]);
+    }
+
+    #[test]
+    fn snappy_emits_short_and_long_literals() {
+        assert_eq!(snappy_compress(b"abc"), [3, 2 << 2, b'a', b'b', b'c']);
+
+        // 100 bytes: one literal with a 1-byte length after the tag
+        let data = noise(100, 1);
+        let out = snappy_compress(&data);
+        assert_eq!(out[..3], [100, 60 << 2, 99]);
+        assert_eq!(snappy_decode(&out), data);
+
+        // 1000 bytes: a 2-byte length
+        let data = noise(1000, 2);
+        let out = snappy_compress(&data);
+        assert_eq!(out[..5], [0xe8, 0x07, 61 << 2, 0xe7, 0x03]);
+        assert_eq!(snappy_decode(&out), data);
+    }
+
+    #[test]
+    fn snappy_emits_copies_for_repeats() {
+        // A run: one literal, then copies of at most 64 bytes at offset 1
+        let data = vec![b'a'; 200];
+        let out = snappy_compress(&data);
+        assert_eq!(out[..4], [0xc8, 0x01, 0, b'a'], "preamble, then a 1-byte literal");
+        let copies: Vec<&[u8]> = out[4..].chunks(3).collect();
+        assert_eq!(copies, [&[63 << 2 | 2, 1, 0][..], &[63 << 2 | 2, 1, 0], &[63 << 2 | 2, 1, 0], &[6 << 2 | 2, 1, 0]]);
+        assert_eq!(snappy_decode(&out), data);
+
+        let line = b"kafka_connect_connector_state{connector=\"sink\",state=\"running\",instance=\"a:8083\"} 1\n";
+        let data = line.repeat(50);
+        let out = snappy_compress(&data);
+        assert!(out.len() < data.len() / 10, "{} bytes from {}", out.len(), data.len());
+        assert_eq!(snappy_decode(&out), data);
+    }
+
+    #[test]
+    fn snappy_round_trips_beyond_the_copy_window() {
+        // Over 64 KiB, with repeats both within and beyond the 65535 offset limit
+        let chunk = noise(40_000, 3);
+        let mut data = chunk.clone();
+        data.extend(noise(30_000, 4));
+        data.extend(&chunk);
+        data.extend(&chunk[..20_000]);
+        data.extend(b"kafka_connect_up{instance=\"a\"} 1\n".repeat(3000));
+        let out = snappy_compress(&data);
+        assert!(data.len() > 200_000);
+        assert_eq!(snappy_decode(&out), data);
+    }
+
+    #[test]
+    fn snappy_round_trips_empty_input() {
+        assert_eq!(snappy_compress(b""), [0]);
+        assert_eq!(snappy_decode(&[0]), b"");
+    }
+
+    /// Positions of `needles` in `haystack`.
+    fn positions(haystack: &[u8], needles: &[&[u8]]) -> Vec<usize> {
+        needles.iter().map(|n| haystack.windows(n.len()).position(|w| w == *n).unwrap()).collect()
+    }
+
+    #[test]
+    fn write_request_sorts_labels_around_the_name() {
+        let labels = registry::Labels::default().with("zone", "z1").with("Region", "eu").with("app", "a1");
+        let summary = registry::summary("kafka_connect_instance_request_latency_seconds").with(&labels);
+        let out = encode_write_request(&[summary.sum(2.5), summary.count(4)], 1);
+        // Byte order: upper case, then the underscore of __name__, then lower case
+        let at = positions(&out, &[b"Region", b"__name__", b"app", b"zone"]);
+        assert!(at.windows(2).all(|w| w[0] < w[1]), "labels at {:?}", at);
+        assert_eq!(out.windows(8).filter(|w| w == b"__name__").count(), 2);
+        for suffix in [&b"latency_seconds_sum"[..], b"latency_seconds_count"] {
+            assert!(out.windows(suffix.len()).any(|w| w == suffix), "{:?}", out);
+        }
+        assert!(out.windows(8).any(|w| w == 2.5f64.to_le_bytes()));
+        assert!(out.windows(8).any(|w| w == 4.0f64.to_le_bytes()));
+    }
+
+    #[test]
+    fn auth_comes_from_one_set_of_variables() {
+        let env = |pairs: &'static [(&str, &str)]| {
+            move |key: &str| pairs.iter().find(|(k, _)| *k == key).map(|(_, v)| v.to_string())
+        };
+        assert!(Auth::from_env(env(&[])).unwrap().is_none());
+        let basic = Auth::from_env(env(&[("REMOTE_WRITE_USERNAME", "u"), ("REMOTE_WRITE_PASSWORD", "p")])).unwrap();
+        let Some(Auth::Basic { username, password }) = basic else { panic!("expected basic auth") };
+        assert_eq!((username.as_str(), password.as_deref()), ("u", Some("p")));
+        let bearer = Auth::from_env(env(&[("REMOTE_WRITE_BEARER_TOKEN", "t")])).unwrap();
+        assert!(matches!(bearer, Some(Auth::Bearer(token)) if token == "t"));
+        assert!(Auth::from_env(env(&[("REMOTE_WRITE_PASSWORD", "p")])).is_err());
+        assert!(Auth::from_env(env(&[("REMOTE_WRITE_USERNAME", "u"), ("REMOTE_WRITE_BEARER_TOKEN", "t")])).is_err());
+    }
+
+    /// Headers and body of a push the receiver got.
+    type Pushes = Arc<std::sync::Mutex<Vec<(axum::http::HeaderMap, Vec<u8>)>>>;
+
+    /// A receiver answering with `replies` in turn, then 204, and the
+    /// pushes it got.
+    async fn receiver(replies: &[axum::http::StatusCode]) -> (String, Pushes) {
+        let pushes: Pushes = Arc::default();
+        let replies = Arc::new(std::sync::Mutex::new(replies.to_vec()));
+        let seen = pushes.clone();
+        let app = axum::Router::new().fallback(move |req: axum::extract::Request| {
+            let (seen, replies) = (seen.clone(), replies.clone());
+            async move {
+                let (parts, body) = req.into_parts();
+                let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+                seen.lock().unwrap().push((parts.headers, body.to_vec()));
+                let mut replies = replies.lock().unwrap();
+                if replies.is_empty() {
+                    axum::http::StatusCode::NO_CONTENT
+                } else {
+                    replies.remove(0)
+                }
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api/v1/write", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, pushes)
+    }
+
+    #[tokio::test]
+    async fn send_once_sets_the_protocol_headers_and_classifies_failures() {
+        use axum::http::StatusCode;
+
+        let (url, pushes) =
+            receiver(&[StatusCode::BAD_REQUEST, StatusCode::TOO_MANY_REQUESTS, StatusCode::BAD_GATEWAY]).await;
+        let config = RemoteWriteConfig { url, max_retries: 0, auth: Some(Auth::Bearer("secret".into())) };
+        let client = reqwest::Client::new();
+        let send = || send_once(&client, &config, vec![0]);
+        assert_eq!(send().await, Err((false, "HTTP 400 Bad Request".into())));
+        assert_eq!(send().await, Err((true, "HTTP 429 Too Many Requests".into())));
+        assert_eq!(send().await, Err((true, "HTTP 502 Bad Gateway".into())));
+        assert_eq!(send().await, Ok(()));
+
+        let (headers, _) = pushes.lock().unwrap()[0].clone();
+        assert_eq!(headers["content-encoding"], "snappy");
+        assert_eq!(headers["content-type"], "application/x-protobuf");
+        assert_eq!(headers["x-prometheus-remote-write-version"], "0.1.0");
+        assert_eq!(headers["authorization"], "Bearer secret");
+    }
+
+    #[tokio::test]
+    async fn run_retries_a_push_until_it_lands() {
+        let (url, pushes) = receiver(&[axum::http::StatusCode::SERVICE_UNAVAILABLE]).await;
+        let config = RemoteWriteConfig { url, max_retries: 3, auth: None };
+        let (tx, rx) = watch::channel(registry::Snapshot::default());
+        let failures = Arc::new(AtomicU64::new(0));
+        tokio::spawn(run(reqwest::Client::new(), config, rx, failures.clone()));
+
+        let up = registry::gauge("kafka_connect_up").label("instance", "a").set(true);
+        tx.send_replace(registry::Snapshot::from(vec![up.clone()]));
+        for _ in 0..50 {
+            if pushes.lock().unwrap().len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let pushes = pushes.lock().unwrap().clone();
+        assert_eq!(pushes.len(), 2);
+        // The retry sends the same request, stamped when it was first encoded
+        assert_eq!(pushes[0].1, pushes[1].1);
+        let request = snappy_decode(&pushes[1].1);
+        // Sample.timestamp is the last field: a varint after its 0x10 tag
+        let mut start = request.len() - 1;
+        while request[start - 1] >= 0x80 {
+            start -= 1;
+        }
+        assert_eq!(request[start - 1], 0x10);
+        let stamp = request[start..].iter().rev().fold(0i64, |v, &b| v << 7 | (b & 0x7f) as i64);
+        assert!((now_ms() - stamp).abs() < 60_000, "stamped {}", stamp);
+        assert_eq!(request, encode_write_request(&[up], stamp));
+        assert_eq!(failures.load(Ordering::Relaxed), 0);
+    }
+}
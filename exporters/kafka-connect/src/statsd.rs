@@ -0,0 +1,275 @@
+//! DogStatsD sink for hosts that run a Datadog agent instead of Prometheus.
+//!
+//! Every gauge of a snapshot is sent as a DogStatsD gauge, its labels as
+//! tags, over UDP or a Unix datagram socket. Lines are packed into
+//! datagrams up to the agent's default buffer size; DogStatsD has no
+//! acknowledgement, so only local send errors are seen.
+
+use crate::registry::{self, Sample};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+/// Largest datagram over UDP: stays under a 1500 byte MTU.
+const UDP_PAYLOAD: usize = 1432;
+/// Largest datagram over a Unix socket; the agent's buffer default.
+const UNIX_PAYLOAD: usize = 8192;
+
+/// Where DOGSTATSD_URL points.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    /// `udp://host:port`
+    Udp(String),
+    /// `unix:///path/to/dsd.socket`
+    Unix(String),
+}
+
+impl Target {
+    /// DOGSTATSD_URL: `udp://host:port` (port 8125 when left out) or
+    /// `unix:///path`.
+    pub fn parse(url: &str) -> Result<Self, String> {
+        if let Some(path) = url.strip_prefix("unix://") {
+            if !path.starts_with('/') {
+                return Err(format!("{}: the socket path must be absolute", url));
+            }
+            return Ok(Target::Unix(path.to_owned()));
+        }
+        let Some(addr) = url.strip_prefix("udp://") else {
+            return Err(format!("{}: expected udp://host:port or unix:///path", url));
+        };
+        let addr = addr.trim_end_matches('/');
+        if addr.is_empty() {
+            return Err(format!("{}: no host", url));
+        }
+        // A bare IPv6 address needs brackets to tell it from a port
+        let has_port = addr.rsplit_once(':').is_some_and(|(host, port)| {
+            port.parse::<u16>().is_ok() && (!host.contains(':') || host.ends_with(']'))
+        });
+        Ok(Target::Udp(if has_port { addr.to_owned() } else { format!("{}:8125", addr) }))
+    }
+
+    fn payload(&self) -> usize {
+        match self {
+            Target::Udp(_) => UDP_PAYLOAD,
+            Target::Unix(_) => UNIX_PAYLOAD,
+        }
+    }
+}
+
+pub struct StatsdConfig {
+    pub target: Target,
+    /// DOGSTATSD_TAGS, added to every metric.
+    pub tags: Vec<String>,
+}
+
+enum Socket {
+    Udp(tokio::net::UdpSocket),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixDatagram),
+}
+
+impl Socket {
+    async fn open(target: &Target) -> std::io::Result<Self> {
+        match target {
+            Target::Udp(addr) => {
+                let addr = tokio::net::lookup_host(addr.as_str())
+                    .await?
+                    .next()
+                    .ok_or_else(|| std::io::Error::other("no address"))?;
+                let local = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                let socket = tokio::net::UdpSocket::bind(local).await?;
+                socket.connect(addr).await?;
+                Ok(Socket::Udp(socket))
+            }
+            #[cfg(unix)]
+            Target::Unix(path) => {
+                let socket = tokio::net::UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Ok(Socket::Unix(socket))
+            }
+            #[cfg(not(unix))]
+            Target::Unix(_) => Err(std::io::Error::other("Unix sockets are not supported on this platform")),
+        }
+    }
+
+    async fn send(&self, datagram: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Socket::Udp(socket) => socket.send(datagram).await,
+            #[cfg(unix)]
+            Socket::Unix(socket) => socket.send(datagram).await,
+        }
+    }
+}
+
+/// `value` with the characters DogStatsD gives a meaning in tags replaced.
+fn tag_value(value: &str) -> String {
+    value.replace([',', '|', '#', '\n', '\r'], "_")
+}
+
+/// One `name:value|g|#tags` line per gauge of `samples`. Counters and
+/// summaries are left out: DogStatsD counts are deltas per flush, not
+/// running totals.
+fn lines(samples: &[Sample], tags: &[String]) -> Vec<String> {
+    samples
+        .iter()
+        .filter(|s| s.kind().is_none_or(|kind| kind == registry::Kind::Gauge))
+        .filter(|s| s.value.is_finite())
+        .map(|s| {
+            let mut all: Vec<String> = tags.to_vec();
+            all.extend(s.labels.pairs().iter().map(|(k, v)| format!("{}:{}", k, tag_value(v))));
+            let mut line = format!("{}:{}|g", s.name(), s.value);
+            if !all.is_empty() {
+                line.push_str("|#");
+                line.push_str(&all.join(","));
+            }
+            line
+        })
+        .collect()
+}
+
+/// `lines` joined by newlines into datagrams of at most `max` bytes. A
+/// line longer than that goes alone and may be cut by the agent.
+fn datagrams(lines: &[String], max: usize) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + 1 + line.len() > max {
+            out.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        out.push(current);
+    }
+    out
+}
+
+/// Send every snapshot published on `rx`. A snapshot that could not be
+/// sent in full counts as one failure; the socket is opened again for the
+/// next one.
+pub async fn run(config: StatsdConfig, mut rx: watch::Receiver<registry::Snapshot>, failures: Arc<AtomicU64>) {
+    let mut socket: Option<Socket> = None;
+    while rx.changed().await.is_ok() {
+        let samples = rx.borrow_and_update().clone();
+        let lines = lines(&samples, &config.tags);
+        let datagrams = datagrams(&lines, config.target.payload());
+        let result = async {
+            if socket.is_none() {
+                socket = Some(Socket::open(&config.target).await?);
+            }
+            let socket = socket.as_ref().expect("opened above");
+            for datagram in &datagrams {
+                socket.send(datagram.as_bytes()).await?;
+            }
+            Ok::<_, std::io::Error>(())
+        }
+        .await;
+        match result {
+            Ok(()) => debug!("dogstatsd: sent {} gauges in {} datagrams", lines.len(), datagrams.len()),
+            Err(e) => {
+                warn!("DogStatsD send to {:?} failed: {}", config.target, e);
+                failures.fetch_add(1, Ordering::Relaxed);
+                socket = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_parses_udp_and_unix_urls() {
+        assert_eq!(Target::parse("udp://agent:9125"), Ok(Target::Udp("agent:9125".into())));
+        assert_eq!(Target::parse("udp://agent/"), Ok(Target::Udp("agent:8125".into())));
+        assert_eq!(Target::parse("udp://[::1]:9125"), Ok(Target::Udp("[::1]:9125".into())));
+        assert_eq!(Target::parse("udp://[::1]"), Ok(Target::Udp("[::1]:8125".into())));
+        assert_eq!(Target::parse("unix:///var/run/dsd.socket"), Ok(Target::Unix("/var/run/dsd.socket".into())));
+        assert!(Target::parse("unix://dsd.socket").unwrap_err().contains("absolute"));
+        assert!(Target::parse("udp://").unwrap_err().contains("no host"));
+        assert!(Target::parse("tcp://agent:8125").unwrap_err().contains("expected udp://"));
+    }
+
+    #[test]
+    fn lines_carry_gauges_only() {
+        let latency = registry::summary("kafka_connect_instance_request_latency_seconds");
+        let state = registry::gauge("kafka_connect_connector_state");
+        let samples = [
+            registry::gauge("kafka_connect_up").label("instance", "a:8083").set(true),
+            registry::counter("kafka_connect_heal_attempts_total").label("connector", "s").total(3),
+            latency.quantile(0.5, 0.1),
+            latency.sum(1.0),
+            registry::gauge("kafka_connect_scrape_error_ratio").set(f64::NAN),
+            state.labels(&[("connector", "a,b|c#d"), ("state", "running")]).set(true),
+        ];
+        assert_eq!(
+            lines(&samples, &["env:prod".into()]),
+            [
+                "kafka_connect_up:1|g|#env:prod,instance:a:8083",
+                "kafka_connect_connector_state:1|g|#env:prod,connector:a_b_c_d,state:running",
+            ]
+        );
+        assert_eq!(lines(&samples[..1], &[]), ["kafka_connect_up:1|g|#instance:a:8083"]);
+        let workers = registry::gauge("kafka_connect_workers_total").set(2_usize);
+        assert_eq!(lines(&[workers], &[]), ["kafka_connect_workers_total:2|g"]);
+    }
+
+    #[test]
+    fn datagrams_pack_lines_up_to_the_limit() {
+        let lines: Vec<String> = ["aaaa", "bbbb", "cccc", "dddddddddddd"].map(String::from).into();
+        assert_eq!(datagrams(&lines, 9), ["aaaa\nbbbb", "cccc", "dddddddddddd"]);
+        assert_eq!(datagrams(&lines, 100), ["aaaa\nbbbb\ncccc\ndddddddddddd"]);
+        assert!(datagrams(&[], 100).is_empty());
+    }
+
+    #[tokio::test]
+    async fn run_sends_each_snapshot_over_udp() {
+        let agent = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = Target::Udp(agent.local_addr().unwrap().to_string());
+        let (tx, rx) = watch::channel(registry::Snapshot::default());
+        let failures = Arc::new(AtomicU64::new(0));
+        tokio::spawn(run(StatsdConfig { target, tags: Vec::new() }, rx, failures.clone()));
+
+        let ups: Vec<_> = (0..100)
+            .map(|i| registry::gauge("kafka_connect_up").label("instance", &format!("connect-{}:8083", i)).set(true))
+            .collect();
+        tx.send_replace(registry::Snapshot::from(ups));
+        let mut received = Vec::new();
+        let mut buf = [0u8; 2048];
+        while received.len() < 100 {
+            let recv = tokio::time::timeout(std::time::Duration::from_secs(5), agent.recv(&mut buf));
+            let n = recv.await.expect("datagram").unwrap();
+            assert!(n <= UDP_PAYLOAD, "{} byte datagram", n);
+            received.extend(String::from_utf8(buf[..n].to_vec()).unwrap().lines().map(str::to_owned));
+        }
+        assert_eq!(received[0], "kafka_connect_up:1|g|#instance:connect-0:8083");
+        assert_eq!(received[99], "kafka_connect_up:1|g|#instance:connect-99:8083");
+        assert_eq!(failures.load(Ordering::Relaxed), 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn run_counts_a_snapshot_it_cannot_send() {
+        let dir = std::env::temp_dir().join(format!("statsd-test-{}", std::process::id()));
+        let target = Target::Unix(dir.join("missing.socket").to_string_lossy().into_owned());
+        let (tx, rx) = watch::channel(registry::Snapshot::default());
+        let failures = Arc::new(AtomicU64::new(0));
+        tokio::spawn(run(StatsdConfig { target, tags: Vec::new() }, rx, failures.clone()));
+
+        tx.send_replace(registry::Snapshot::from(vec![registry::gauge("kafka_connect_up").set(true)]));
+        for _ in 0..50 {
+            if failures.load(Ordering::Relaxed) == 1 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(failures.load(Ordering::Relaxed), 1);
+    }
+}
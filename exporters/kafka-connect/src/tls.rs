@@ -0,0 +1,291 @@
+//! TLS for the connections to Kafka Connect and for the exporter's own
+//! listeners.
+//!
+//! Instances with TLS settings or a connect timeout get their own HTTP
+//! client, built once at startup; all others share the default one. Connections reqwest does not
+//! make, such as OTLP/gRPC, use [`client_config`]. The listeners serve HTTPS
+//! when TLS_CERT_FILE and TLS_KEY_FILE are set, and pick up a rotated
+//! certificate without a restart.
+
+use axum::Router;
+use hyper_util::{rt::TokioIo, service::TowerToHyperService};
+use serde::Deserialize;
+use std::{
+    future::Future,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio::{sync::watch, task::JoinSet};
+use tokio_rustls::{
+    rustls::{
+        self,
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        RootCertStore,
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+    },
+    TlsAcceptor,
+};
+use tracing::{debug, info, warn};
+
+/// How often the listener certificate files are checked for changes.
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// Longest a client may take to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// ── Client ────────────────────────────────────────────────────────────────────
+
+/// `tls` of an instance in the config file, or the global
+/// KAFKA_CONNECT_CLIENT_* / KAFKA_CONNECT_CA_FILE settings.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientTls {
+    /// PEM client certificate (chain) for mutual TLS.
+    #[serde(default)]
+    pub client_cert: Option<String>,
+    /// PEM private key of `client_cert`.
+    #[serde(default)]
+    pub client_key: Option<String>,
+    /// PEM bundle of CA certificates trusted in addition to the built-in roots.
+    #[serde(default)]
+    pub ca_file: Option<String>,
+    /// Accept any server certificate. For tests only.
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+}
+
+fn read(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Root certificates from a PEM bundle.
+pub fn ca_certificates(path: &str) -> Result<Vec<reqwest::Certificate>, String> {
+    let certs = reqwest::Certificate::from_pem_bundle(&read(path)?).map_err(|e| format!("{}: {}", path, e))?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificates found", path));
+    }
+    Ok(certs)
+}
+
+/// `e` with its cause; reqwest's own message is only "builder error".
+fn builder_error(e: reqwest::Error) -> String {
+    std::error::Error::source(&e).map_or(e.to_string(), |s| s.to_string())
+}
+
+impl ClientTls {
+    /// These settings, with the certificate pair and the CA file taken from
+    /// `global` where they are not set.
+    pub fn or(self, global: &ClientTls) -> Self {
+        let (client_cert, client_key) = match (self.client_cert, self.client_key) {
+            (None, None) => (global.client_cert.clone(), global.client_key.clone()),
+            pair => pair,
+        };
+        Self {
+            client_cert,
+            client_key,
+            ca_file: self.ca_file.or_else(|| global.ca_file.clone()),
+            insecure_skip_verify: self.insecure_skip_verify,
+        }
+    }
+
+    /// Nothing to configure: the shared client will do.
+    pub fn is_empty(&self) -> bool {
+        self.client_cert.is_none() && self.client_key.is_none() && self.ca_file.is_none() && !self.insecure_skip_verify
+    }
+
+    /// HTTP client with these settings and timeouts, following the
+    /// redirects `redirect` allows. Fails when a file cannot be read or does
+    /// not hold a usable certificate or key.
+    pub fn client(
+        &self,
+        timeout: Duration,
+        connect_timeout: Option<Duration>,
+        redirect: reqwest::redirect::Policy,
+    ) -> Result<reqwest::Client, String> {
+        let mut builder = reqwest::Client::builder().timeout(timeout).redirect(redirect);
+        if let Some(connect_timeout) = connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => {
+                let mut pem = read(cert)?;
+                pem.push(b'\n');
+                pem.extend(read(key)?);
+                let identity =
+                    reqwest::Identity::from_pem(&pem).map_err(|e| format!("{} / {}: {}", cert, key, e))?;
+                builder = builder.identity(identity);
+            }
+            (None, None) => {}
+            _ => return Err("client_cert and client_key must be set together".into()),
+        }
+        if let Some(path) = &self.ca_file {
+            for cert in ca_certificates(path)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if self.insecure_skip_verify {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        builder.build().map_err(builder_error)
+    }
+}
+
+/// rustls settings trusting the built-in roots and those in `ca_file`, and
+/// offering the `alpn` protocols.
+pub fn client_config(ca_file: Option<&str>, alpn: &[&[u8]]) -> Result<rustls::ClientConfig, String> {
+    let mut roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+    if let Some(path) = ca_file {
+        let pem = read(path)?;
+        let certs = CertificateDer::pem_slice_iter(&pem)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("{}: {}", path, e))?;
+        if certs.is_empty() {
+            return Err(format!("{}: no certificates found", path));
+        }
+        for cert in certs {
+            roots.add(cert).map_err(|e| format!("{}: {}", path, e))?;
+        }
+    }
+    let mut config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .expect("TLS protocol versions")
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+    Ok(config)
+}
+
+// ── Listeners ─────────────────────────────────────────────────────────────────
+
+/// Certificate and key of the exporter's listeners (TLS_CERT_FILE,
+/// TLS_KEY_FILE), reloaded when either file changes.
+#[derive(Debug)]
+pub struct ServerCert {
+    cert_file: String,
+    key_file: String,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn load_certified_key(cert_file: &str, key_file: &str) -> Result<CertifiedKey, String> {
+    let certs = CertificateDer::pem_slice_iter(&read(cert_file)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("{}: {}", cert_file, e))?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificates found", cert_file));
+    }
+    let key = PrivateKeyDer::from_pem_slice(&read(key_file)?).map_err(|e| format!("{}: {}", key_file, e))?;
+    let key = rustls::crypto::ring::sign::any_supported_type(&key).map_err(|e| format!("{}: {}", key_file, e))?;
+    let certified = CertifiedKey::new(certs, key);
+    certified
+        .keys_match()
+        .map_err(|_| format!("{} does not match {}", key_file, cert_file))?;
+    Ok(certified)
+}
+
+impl ServerCert {
+    pub fn load(cert_file: String, key_file: String) -> Result<Self, String> {
+        let current = load_certified_key(&cert_file, &key_file)?;
+        Ok(Self { cert_file, key_file, current: RwLock::new(Arc::new(current)) })
+    }
+
+    /// Poll the files and swap in the new certificate when they change.
+    /// A pair that fails to load is logged and retried on the next check
+    /// while the previous certificate stays in use, so a rotation that
+    /// writes the two files one after the other is picked up once both
+    /// are in place.
+    pub async fn watch(self: Arc<Self>) {
+        let mtimes = || (modified(&self.cert_file), modified(&self.key_file));
+        let mut loaded = mtimes();
+        let mut tick = tokio::time::interval(RELOAD_CHECK_INTERVAL);
+        tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tick.tick().await;
+            let now = mtimes();
+            if now == loaded {
+                continue;
+            }
+            match load_certified_key(&self.cert_file, &self.key_file) {
+                Ok(certified) => {
+                    *self.current.write().unwrap() = Arc::new(certified);
+                    loaded = now;
+                    info!("Reloaded TLS certificate from {}", self.cert_file);
+                }
+                Err(e) => warn!("Failed to reload TLS certificate, keeping the previous one: {}", e),
+            }
+        }
+    }
+
+    pub fn acceptor(self: &Arc<Self>) -> TlsAcceptor {
+        let mut config = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("TLS protocol versions")
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone());
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        TlsAcceptor::from(Arc::new(config))
+    }
+}
+
+impl ResolvesServerCert for ServerCert {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+/// Serve `app` over HTTPS (HTTP/1.1) on `listener` until `stop` resolves,
+/// then finish the requests in flight and close the connections. Those
+/// whose handshake fails or times out are dropped.
+pub async fn serve(
+    listener: tokio::net::TcpListener,
+    app: Router,
+    acceptor: TlsAcceptor,
+    stop: impl Future<Output = ()>,
+) {
+    // Dropped on stop, which tells every connection to wind down
+    let (close_tx, close_rx) = watch::channel(());
+    let mut connections = JoinSet::new();
+    tokio::pin!(stop);
+    loop {
+        let (tcp, peer) = tokio::select! {
+            conn = listener.accept() => match conn {
+                Ok(conn) => conn,
+                Err(e) => {
+                    warn!("Failed to accept a connection: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            _ = &mut stop => break,
+        };
+        let acceptor = acceptor.clone();
+        let service = TowerToHyperService::new(app.clone());
+        let mut close = close_rx.clone();
+        connections.spawn(async move {
+            let stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(tcp)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => return debug!("tls: handshake with {} failed: {}", peer, e),
+                Err(_) => return debug!("tls: handshake with {} timed out", peer),
+            };
+            let conn = hyper::server::conn::http1::Builder::new().serve_connection(TokioIo::new(stream), service);
+            tokio::pin!(conn);
+            let result = tokio::select! {
+                result = conn.as_mut() => result,
+                _ = close.changed() => {
+                    conn.as_mut().graceful_shutdown();
+                    conn.await
+                }
+            };
+            if let Err(e) = result {
+                debug!("tls: connection from {} ended: {}", peer, e);
+            }
+        });
+        while connections.try_join_next().is_some() {}
+    }
+    drop(listener);
+    drop(close_tx);
+    while connections.join_next().await.is_some() {}
+}
@@ -0,0 +1,596 @@
+//! Reader for TOML config files: the part of TOML the config file needs,
+//! turned into the JSON value the JSON reader would produce, so both go
+//! through the same deserialization and key-path errors.
+//!
+//! Supported are `key = value` lines with bare, quoted and dotted keys,
+//! `[tables]` and `[[arrays of tables]]`, basic and literal strings,
+//! integers, floats, booleans, arrays and inline tables. Multi-line strings
+//! and dates are rejected, since no config key takes them.
+
+use serde_json::{Map, Number, Value};
+use std::collections::{HashMap, HashSet};
+
+/// A parsed file, with the line each key path was set on.
+pub struct Document {
+    pub value: Value,
+    /// By path as serde_path_to_error prints it, e.g. `instances[1].tls.ca_file`.
+    pub lines: HashMap<String, usize>,
+}
+
+impl Document {
+    /// The line `path` or its closest enclosing key was set on.
+    pub fn line_of(&self, path: &str) -> Option<usize> {
+        let mut path = path;
+        loop {
+            if let Some(line) = self.lines.get(path) {
+                return Some(*line);
+            }
+            path = &path[..path.rfind(['.', '['])?];
+        }
+    }
+}
+
+/// One step from a table to the table a header or dotted key names.
+enum Step {
+    Key(String),
+    /// The last table of an array of tables.
+    Last,
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    line: usize,
+    lines: HashMap<String, usize>,
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Parse `text` into the JSON value of the document.
+pub fn parse(text: &str) -> Result<Document, String> {
+    let mut p = Parser { src: text, pos: 0, line: 1, lines: HashMap::new() };
+    let mut root = Map::new();
+    // Headers seen, so a table is not defined twice
+    let mut defined: HashSet<String> = HashSet::new();
+    let mut current: Vec<Step> = Vec::new();
+    let mut current_path = String::new();
+    loop {
+        p.skip_blank();
+        let Some(c) = p.peek() else { break };
+        if c == '[' {
+            let line = p.line;
+            p.bump();
+            let array = p.eat('[');
+            p.skip_ws();
+            let keys = p.key()?;
+            p.skip_ws();
+            if !p.eat(']') || (array && !p.eat(']')) {
+                return Err(p.error(if array { "expected `]]` after the table name" } else { "expected `]`" }));
+            }
+            p.end_of_line()?;
+            let (steps, path) = Parser::header(&mut root, &keys, array).map_err(|e| format!("line {}: {}", line, e))?;
+            if !array && !defined.insert(path.clone()) {
+                return Err(format!("line {}: {}: table defined twice", line, path));
+            }
+            p.lines.insert(path.clone(), line);
+            current = steps;
+            current_path = path;
+        } else {
+            let line = p.line;
+            let keys = p.key()?;
+            p.skip_ws();
+            if !p.eat('=') {
+                return Err(p.error(&format!("expected `=` after `{}`", keys.join("."))));
+            }
+            p.skip_ws();
+            let path = keys.iter().fold(current_path.clone(), |path, k| join(&path, k));
+            let value = p.value(&path)?;
+            p.end_of_line()?;
+            let table = walk(&mut root, &current).expect("current table exists");
+            insert(table, &keys, value).map_err(|e| format!("line {}: {}: {}", line, path, e))?;
+            p.lines.insert(path, line);
+        }
+    }
+    Ok(Document { value: Value::Object(root), lines: p.lines })
+}
+
+/// The table `steps` lead to from `root`.
+fn walk<'m>(root: &'m mut Map<String, Value>, steps: &[Step]) -> Option<&'m mut Map<String, Value>> {
+    let mut value = None::<&mut Value>;
+    let mut table = Some(root);
+    for step in steps {
+        let next = match step {
+            Step::Key(key) => table.take()?.get_mut(key)?,
+            Step::Last => value.take()?.as_array_mut()?.last_mut()?,
+        };
+        match next {
+            Value::Object(map) => table = Some(map),
+            other => value = Some(other),
+        }
+    }
+    table
+}
+
+/// Set `keys` (a dotted key) in `table`, creating the tables before the
+/// last key.
+fn insert(table: &mut Map<String, Value>, keys: &[String], value: Value) -> Result<(), &'static str> {
+    let (last, parents) = keys.split_last().expect("a key");
+    let mut table = table;
+    for key in parents {
+        table = match table.entry(key.clone()).or_insert_with(|| Value::Object(Map::new())) {
+            Value::Object(map) => map,
+            _ => return Err("already set to a value that is not a table"),
+        };
+    }
+    if table.contains_key(last) {
+        return Err("set twice");
+    }
+    table.insert(last.clone(), value);
+    Ok(())
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.bump();
+        }
+        found
+    }
+
+    fn error(&self, msg: &str) -> String {
+        format!("line {}: {}", self.line, msg)
+    }
+
+    fn error_at(&self, path: &str, msg: &str) -> String {
+        format!("line {}: {}: {}", self.line, path, msg)
+    }
+
+    fn skip_ws(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.bump();
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.bump();
+            }
+        }
+    }
+
+    /// Whitespace, comments and line breaks, as between lines and between
+    /// the elements of an array.
+    fn skip_blank(&mut self) {
+        loop {
+            self.skip_ws();
+            self.skip_comment();
+            if !(self.eat('\n') || self.src[self.pos..].starts_with("\r\n") && self.eat('\r')) {
+                return;
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), String> {
+        self.skip_ws();
+        self.skip_comment();
+        self.eat('\r');
+        match self.peek() {
+            None => Ok(()),
+            Some('\n') => {
+                self.bump();
+                Ok(())
+            }
+            Some(c) => Err(self.error(&format!("unexpected `{}` after the value", c))),
+        }
+    }
+
+    /// A key, dotted or not, as its parts.
+    fn key(&mut self) -> Result<Vec<String>, String> {
+        let mut keys = Vec::new();
+        loop {
+            let key = match self.peek() {
+                Some('"') => self.basic_string()?,
+                Some('\'') => self.literal_string()?,
+                _ => {
+                    let start = self.pos;
+                    while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                        self.bump();
+                    }
+                    if start == self.pos {
+                        return Err(self.error("expected a key"));
+                    }
+                    self.src[start..self.pos].to_owned()
+                }
+            };
+            keys.push(key);
+            self.skip_ws();
+            if !self.eat('.') {
+                return Ok(keys);
+            }
+            self.skip_ws();
+        }
+    }
+
+    /// The table a `[keys]` or `[[keys]]` header names, created as needed,
+    /// and its path.
+    fn header(
+        root: &mut Map<String, Value>,
+        keys: &[String],
+        array: bool,
+    ) -> Result<(Vec<Step>, String), String> {
+        let mut steps = Vec::new();
+        let mut path = String::new();
+        let mut table = root;
+        for (i, key) in keys.iter().enumerate() {
+            path = join(&path, key);
+            steps.push(Step::Key(key.clone()));
+            let last = i == keys.len() - 1;
+            let slot = table.entry(key.clone()).or_insert_with(|| {
+                if last && array {
+                    Value::Array(Vec::new())
+                } else {
+                    Value::Object(Map::new())
+                }
+            });
+            let wanted = last && array;
+            table = match (slot, wanted) {
+                (Value::Array(items), true) => {
+                    items.push(Value::Object(Map::new()));
+                    path.push_str(&format!("[{}]", items.len() - 1));
+                    steps.push(Step::Last);
+                    items.last_mut().and_then(Value::as_object_mut).expect("just pushed")
+                }
+                // Tables under an array of tables go into its last element
+                (Value::Array(items), false) if !last => {
+                    path.push_str(&format!("[{}]", items.len().saturating_sub(1)));
+                    steps.push(Step::Last);
+                    match items.last_mut() {
+                        Some(Value::Object(map)) => map,
+                        _ => return Err(format!("{}: not an array of tables", path)),
+                    }
+                }
+                (Value::Object(map), false) => map,
+                (_, true) => return Err(format!("{}: already set to something else than an array of tables", path)),
+                _ => return Err(format!("{}: already set to a value that is not a table", path)),
+            };
+        }
+        Ok((steps, path))
+    }
+
+    fn value(&mut self, path: &str) -> Result<Value, String> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => {
+                self.bump();
+                let mut items = Vec::new();
+                loop {
+                    self.skip_blank();
+                    if self.eat(']') {
+                        return Ok(Value::Array(items));
+                    }
+                    let item_path = format!("{}[{}]", path, items.len());
+                    let line = self.line;
+                    items.push(self.value(&item_path)?);
+                    self.lines.entry(item_path).or_insert(line);
+                    self.skip_blank();
+                    if !self.eat(',') {
+                        self.skip_blank();
+                        if self.eat(']') {
+                            return Ok(Value::Array(items));
+                        }
+                        return Err(self.error_at(path, "expected `,` or `]` in the array"));
+                    }
+                }
+            }
+            Some('{') => {
+                self.bump();
+                let mut table = Map::new();
+                self.skip_ws();
+                if self.eat('}') {
+                    return Ok(Value::Object(table));
+                }
+                loop {
+                    self.skip_ws();
+                    let keys = self.key()?;
+                    self.skip_ws();
+                    if !self.eat('=') {
+                        return Err(self.error(&format!("expected `=` after `{}`", keys.join("."))));
+                    }
+                    self.skip_ws();
+                    let key_path = keys.iter().fold(path.to_owned(), |path, k| join(&path, k));
+                    let line = self.line;
+                    let value = self.value(&key_path)?;
+                    insert(&mut table, &keys, value).map_err(|e| self.error_at(&key_path, e))?;
+                    self.lines.insert(key_path, line);
+                    self.skip_ws();
+                    if self.eat('}') {
+                        return Ok(Value::Object(table));
+                    }
+                    if !self.eat(',') {
+                        return Err(self.error_at(path, "expected `,` or `}` in the inline table"));
+                    }
+                }
+            }
+            Some(_) => self.scalar(path),
+            None => Err(self.error_at(path, "expected a value")),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, String> {
+        if self.src[self.pos..].starts_with("\"\"\"") {
+            return Err(self.error("multi-line strings are not supported"));
+        }
+        self.bump();
+        let mut out = String::new();
+        loop {
+            let c = match self.peek() {
+                None | Some('\n') => return Err(self.error("unterminated string")),
+                Some(c) => c,
+            };
+            self.bump();
+            match c {
+                '"' => return Ok(out),
+                '\\' => match self.bump() {
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some('r') => out.push('\r'),
+                    Some('b') => out.push('\u{8}'),
+                    Some('f') => out.push('\u{c}'),
+                    Some(u @ ('u' | 'U')) => {
+                        let len = if u == 'u' { 4 } else { 8 };
+                        let hex = self.src.get(self.pos..self.pos + len).unwrap_or_default();
+                        let c = u32::from_str_radix(hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| self.error("invalid unicode escape"))?;
+                        self.pos += len;
+                        out.push(c);
+                    }
+                    _ => return Err(self.error("invalid escape in string")),
+                },
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, String> {
+        if self.src[self.pos..].starts_with("'''") {
+            return Err(self.error("multi-line strings are not supported"));
+        }
+        self.bump();
+        let start = self.pos;
+        loop {
+            match self.peek() {
+                None | Some('\n') => return Err(self.error("unterminated string")),
+                Some('\'') => {
+                    let s = self.src[start..self.pos].to_owned();
+                    self.bump();
+                    return Ok(s);
+                }
+                Some(_) => self.bump(),
+            };
+        }
+    }
+
+    /// A boolean or a number.
+    fn scalar(&mut self, path: &str) -> Result<Value, String> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-' | '.' | ':')) {
+            self.bump();
+        }
+        let raw = &self.src[start..self.pos];
+        let invalid = || self.error_at(path, &format!("invalid value `{}`", raw));
+        match raw {
+            "true" => return Ok(Value::Bool(true)),
+            "false" => return Ok(Value::Bool(false)),
+            "" => return Err(self.error_at(path, "expected a value")),
+            _ => {}
+        }
+        let date = raw.len() > 4 && raw[4..].starts_with('-') && raw[..4].bytes().all(|b| b.is_ascii_digit());
+        if date || raw.contains(':') {
+            return Err(self.error_at(path, "dates and times are not supported"));
+        }
+        let digits = raw.replace('_', "");
+        let (sign, unsigned) = match digits.strip_prefix('-') {
+            Some(rest) => (-1, rest),
+            None => (1, digits.strip_prefix('+').unwrap_or(&digits)),
+        };
+        let radix = match unsigned.get(..2) {
+            Some("0x") => Some(16),
+            Some("0o") => Some(8),
+            Some("0b") => Some(2),
+            _ => None,
+        };
+        if let Some(radix) = radix {
+            let n = i64::from_str_radix(&unsigned[2..], radix).map_err(|_| invalid())?;
+            return Ok(Value::Number(Number::from(sign * n)));
+        }
+        if unsigned.contains(['.', 'e', 'E']) || matches!(unsigned, "inf" | "nan") {
+            let f: f64 = digits.parse().map_err(|_| invalid())?;
+            return Number::from_f64(f).map(Value::Number).ok_or_else(|| self.error_at(path, "not a finite number"));
+        }
+        digits.parse::<i64>().map(|n| Value::Number(Number::from(n))).map_err(|_| invalid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_the_config_subset() {
+        let doc = parse(
+            r#"
+# Blue/green workers
+[web_auth]
+username = "prometheus"
+password = 'p@ss\word'
+
+[[instances]]
+url = "http://connect-blue:8083"
+timeout_secs = 5
+lite = false
+labels = { color = "blue", "dc" = "fra1" }
+required_connectors = [
+  "pg-source",   # trailing comma and comments are fine
+  "s3-sink",
+]
+
+[instances.tls]
+ca_file = "/etc/ca.pem"
+
+[[instances]]
+url = "http://connect-green:8083"
+labels.color = "green"
+
+[alerts]
+webhook_url = "https://hooks.example.com/a"
+rules = [{ name = "Down", expr = "kafka_connect_up == 0", for_secs = 1_200 }]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            doc.value,
+            json!({
+                "web_auth": { "username": "prometheus", "password": "p@ss\\word" },
+                "instances": [
+                    {
+                        "url": "http://connect-blue:8083",
+                        "timeout_secs": 5,
+                        "lite": false,
+                        "labels": { "color": "blue", "dc": "fra1" },
+                        "required_connectors": ["pg-source", "s3-sink"],
+                        "tls": { "ca_file": "/etc/ca.pem" },
+                    },
+                    { "url": "http://connect-green:8083", "labels": { "color": "green" } },
+                ],
+                "alerts": {
+                    "webhook_url": "https://hooks.example.com/a",
+                    "rules": [{ "name": "Down", "expr": "kafka_connect_up == 0", "for_secs": 1200 }],
+                },
+            })
+        );
+        assert_eq!(doc.line_of("instances[0].tls.ca_file"), Some(18));
+        assert_eq!(doc.line_of("instances[1].labels.color"), Some(22));
+        assert_eq!(doc.line_of("alerts.rules[0].for_secs"), Some(26));
+        assert_eq!(doc.line_of("instances[1].labels.color.unknown"), Some(22));
+    }
+
+    #[test]
+    fn parses_scalars() {
+        let doc = parse("a = -12\nb = 0x1F\nc = 1.5e3\nd = \"tab\\there \\u00fc\"\ne = true\n").unwrap();
+        assert_eq!(doc.value, json!({ "a": -12, "b": 31, "c": 1500.0, "d": "tab\there ü", "e": true }));
+    }
+
+    #[test]
+    fn parses_arrays_of_tables_with_sub_tables() {
+        let doc = parse(concat!(
+            "[[instances]]\nurl = \"http://a:8083\"\n[instances.auth]\nusername = \"a\"\n",
+            "[[instances]]\nurl = \"http://b:8083\"\n[instances.tls]\nca_file = \"/b.pem\"\n",
+            "[[instances]]\n",
+        ))
+        .unwrap();
+        assert_eq!(
+            doc.value,
+            json!({ "instances": [
+                { "url": "http://a:8083", "auth": { "username": "a" } },
+                { "url": "http://b:8083", "tls": { "ca_file": "/b.pem" } },
+                {},
+            ] })
+        );
+        assert_eq!(doc.line_of("instances[1].tls.ca_file"), Some(8));
+        assert_eq!(doc.line_of("instances[2]"), Some(9));
+    }
+
+    #[test]
+    fn parses_inline_tables() {
+        let doc = parse(concat!(
+            "empty = {}\n",
+            "auth = { username = \"u\", \"pass word\" = 'p', tls.ca_file = \"/ca.pem\" }\n",
+            "rules = [{ name = \"A\", labels = { team = \"x\" } }, { name = \"B\" }]\n",
+        ))
+        .unwrap();
+        assert_eq!(
+            doc.value,
+            json!({
+                "empty": {},
+                "auth": { "username": "u", "pass word": "p", "tls": { "ca_file": "/ca.pem" } },
+                "rules": [{ "name": "A", "labels": { "team": "x" } }, { "name": "B" }],
+            })
+        );
+        assert_eq!(doc.line_of("rules[0].labels.team"), Some(3));
+    }
+
+    #[test]
+    fn unescapes_basic_strings_only() {
+        let doc = parse(
+            r#"
+basic = "q\" b\\ n\n t\t r\r b\b f\f \u00e9 \U0001F600"
+literal = 'C:\path\n'
+"quoted key\t" = 1
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            doc.value,
+            json!({
+                "basic": "q\" b\\ n\n t\t r\r b\u{8} f\u{c} é 😀",
+                "literal": "C:\\path\\n",
+                "quoted key\t": 1,
+            })
+        );
+        let err = |text: &str| parse(text).err().unwrap();
+        assert_eq!(err("s = \"\\q\"\n"), "line 1: invalid escape in string");
+        assert_eq!(err("s = \"\\uD800\"\n"), "line 1: invalid unicode escape");
+        assert_eq!(err("s = \"\\u12\"\n"), "line 1: invalid unicode escape");
+    }
+
+    #[test]
+    fn refuses_duplicate_keys() {
+        let err = |text: &str| parse(text).err().unwrap();
+        assert_eq!(err("x = 1\nx = 2\n"), "line 2: x: set twice");
+        assert_eq!(err("\"x\" = 1\nx = 2\n"), "line 2: x: set twice");
+        assert_eq!(err("a.b = 1\na.b = 2\n"), "line 2: a.b: set twice");
+        assert_eq!(err("t = { k = 1, k = 2 }\n"), "line 1: t.k: set twice");
+        assert_eq!(err("[[a]]\nx = 1\nx = 2\n"), "line 3: a[0].x: set twice");
+        assert_eq!(err("a = 1\n[[a]]\n"), "line 2: a: already set to something else than an array of tables");
+    }
+
+    #[test]
+    fn errors_carry_the_line_and_key() {
+        let err = |text: &str| parse(text).err().unwrap();
+        assert_eq!(err("[a]\nx = 1\nx = 2\n"), "line 3: a.x: set twice");
+        assert_eq!(err("[a]\n[a]\n"), "line 2: a: table defined twice");
+        assert_eq!(err("a = \"\"\"\nmulti\n\"\"\"\n"), "line 1: multi-line strings are not supported");
+        assert_eq!(err("when = 1979-05-27\n"), "line 1: when: dates and times are not supported");
+        assert_eq!(err("x = 1 2\n"), "line 1: unexpected `2` after the value");
+        assert_eq!(err("\n\nkey \"v\"\n"), "line 3: expected `=` after `key`");
+        assert_eq!(err("a = 1\n[a.b]\n"), "line 2: a: already set to a value that is not a table");
+        assert_eq!(err("s = \"open\n"), "line 1: unterminated string");
+    }
+}
@@ -0,0 +1,73 @@
+//! Kafka Connect version detection and the features that depend on it.
+//!
+//! The version comes from the worker's root endpoint (`GET /`). Every
+//! check answers "unknown" in the way the exporter behaved before version
+//! detection, so an instance whose version cannot be read scrapes as it
+//! always has.
+
+use serde::Deserialize;
+
+/// Body of `GET /`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RootInfo {
+    pub version: String,
+    #[serde(default)]
+    pub commit: String,
+    /// ID of the Kafka cluster the worker stores its state in.
+    #[serde(default)]
+    pub kafka_cluster_id: String,
+}
+
+/// Detected version of one instance, as Apache Kafka major.minor.
+#[derive(Clone, Debug)]
+pub struct ConnectVersion {
+    /// As reported, e.g. `3.6.1` or `7.5.0-ccs`.
+    pub raw: String,
+    major: u32,
+    minor: u32,
+}
+
+impl ConnectVersion {
+    /// `None` when the version does not start with `major.minor`.
+    /// Confluent Platform versions (5.x to 8.x) are mapped to the Apache
+    /// Kafka release they ship.
+    pub fn parse(raw: &str) -> Option<Self> {
+        let mut parts = raw.split(|c: char| !c.is_ascii_digit());
+        let major: u32 = parts.next()?.parse().ok()?;
+        let minor: u32 = parts.next()?.parse().ok()?;
+        let (major, minor) = match major {
+            8 => (4, minor),
+            7 => (3, minor),
+            6 => (2, minor + 6),
+            5 => (2, minor),
+            _ => (major, minor),
+        };
+        Some(Self { raw: raw.to_owned(), major, minor })
+    }
+
+    fn at_least(&self, major: u32, minor: u32) -> bool {
+        (self.major, self.minor) >= (major, minor)
+    }
+}
+
+/// `?expand=status` on the connector list (KIP-465, 2.3+).
+pub fn expand_status(version: Option<&ConnectVersion>) -> bool {
+    version.is_none_or(|v| v.at_least(2, 3))
+}
+
+/// The STOPPED connector state (KIP-875, 3.5+). Unknown versions keep the
+/// four classic states.
+pub fn stopped_state(version: Option<&ConnectVersion>) -> bool {
+    version.is_some_and(|v| v.at_least(3, 5))
+}
+
+/// The RESTARTING state of a restart with `includeTasks` (KIP-745, 3.0+).
+/// Unknown versions leave it out.
+pub fn restarting_state(version: Option<&ConnectVersion>) -> bool {
+    version.is_some_and(|v| v.at_least(3, 0))
+}
+
+/// `GET /connectors/{name}/topics` (KIP-558, 2.5+).
+pub fn active_topics(version: Option<&ConnectVersion>) -> bool {
+    version.is_none_or(|v| v.at_least(2, 5))
+}
@@ -0,0 +1,132 @@
+//! Authentication on the exporter's own endpoints.
+//!
+//! Requests must carry either the configured basic auth credentials or the
+//! static bearer token. The expected `Authorization` values are built once
+//! at startup and compared in constant time.
+
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// `web_auth` in the config file, or WEB_AUTH_USERNAME / WEB_AUTH_PASSWORD
+/// and WEB_AUTH_BEARER_TOKEN. Either kind or both may be set.
+#[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebAuth {
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub bearer_token: Option<String>,
+}
+
+// Keep the password and the token out of logs
+impl std::fmt::Debug for WebAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebAuth").field("username", &self.username).finish_non_exhaustive()
+    }
+}
+
+impl WebAuth {
+    pub fn is_empty(&self) -> bool {
+        self.username.is_none() && self.password.is_none() && self.bearer_token.is_none()
+    }
+
+    /// Fails on a password without a username or an empty secret.
+    pub fn validate(&self) -> Result<(), String> {
+        match (&self.username, &self.password) {
+            (Some(_), None) | (None, Some(_)) => return Err("username and password must be set together".into()),
+            (Some(u), Some(p)) if u.is_empty() || p.is_empty() => return Err("empty username or password".into()),
+            _ => {}
+        }
+        if self.bearer_token.as_deref() == Some("") {
+            return Err("empty bearer_token".into());
+        }
+        Ok(())
+    }
+}
+
+/// Accepted credentials, as they appear after the scheme in `Authorization`.
+pub struct Guard {
+    basic: Option<Vec<u8>>,
+    bearer: Option<Vec<u8>>,
+}
+
+impl From<&WebAuth> for Guard {
+    fn from(auth: &WebAuth) -> Self {
+        let basic = match (&auth.username, &auth.password) {
+            (Some(u), Some(p)) => Some(base64(format!("{u}:{p}").as_bytes()).into_bytes()),
+            _ => None,
+        };
+        Self { basic, bearer: auth.bearer_token.as_ref().map(|t| t.as_bytes().to_vec()) }
+    }
+}
+
+pub fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl Guard {
+    fn accepts(&self, authorization: &str) -> bool {
+        let Some((scheme, credentials)) = authorization.split_once(' ') else { return false };
+        let expected = if scheme.eq_ignore_ascii_case("basic") {
+            &self.basic
+        } else if scheme.eq_ignore_ascii_case("bearer") {
+            &self.bearer
+        } else {
+            return false;
+        };
+        expected
+            .as_ref()
+            .is_some_and(|e| constant_time_eq(e, credentials.trim().as_bytes()))
+    }
+
+    fn challenge(&self) -> &'static str {
+        if self.basic.is_some() {
+            "Basic realm=\"kafka-connect-exporter\""
+        } else {
+            "Bearer"
+        }
+    }
+}
+
+/// Middleware: pass authorized requests on, answer the rest with `401`.
+pub async fn require(State(guard): State<Arc<Guard>>, req: Request, next: Next) -> Response {
+    let authorized = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| guard.accepts(v));
+    if authorized {
+        return next.run(req).await;
+    }
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, guard.challenge())],
+        "unauthorized\n",
+    )
+        .into_response()
+}
@@ -0,0 +1,298 @@
+//! Connector state transition events POSTed to EVENT_WEBHOOK_URL and sent
+//! to the chat channels, and the delivery the alert notifications share.
+//!
+//! Scrapes only enqueue; a single background task delivers events in order.
+//! When the queue is full new events are dropped, so a slow or dead webhook
+//! never holds up scraping.
+
+use crate::registry::{self, Labels};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+/// Events waiting for delivery before new ones are dropped.
+pub const QUEUE_CAPACITY: usize = 1024;
+
+/// JSON body of one webhook call.
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub connector: String,
+    pub instance: String,
+    /// Extra labels of the instance from the config file.
+    pub labels: BTreeMap<String, String>,
+    /// `cluster` of the instance, when set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<String>,
+    /// Base URL of the instance, for links.
+    #[serde(skip)]
+    pub url: String,
+    pub old_state: String,
+    pub new_state: String,
+    /// Unix time in milliseconds at which the transition was observed.
+    pub timestamp: i64,
+}
+
+impl Event {
+    pub fn new(
+        connector: &str,
+        instance: &str,
+        labels: &BTreeMap<String, String>,
+        old_state: &str,
+        new_state: &str,
+    ) -> Self {
+        Self {
+            connector: connector.to_owned(),
+            instance: instance.to_owned(),
+            labels: labels.clone(),
+            cluster: None,
+            url: String::new(),
+            old_state: old_state.to_uppercase(),
+            new_state: new_state.to_uppercase(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as i64)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Outcome counters for kafka_connect_webhook_deliveries_total{result}, or
+/// the metric [`Deliveries::lines`] is given, after its `labels`.
+#[derive(Default)]
+pub struct Deliveries {
+    pub success: AtomicU64,
+    pub failure: AtomicU64,
+    pub dropped: AtomicU64,
+}
+
+impl Deliveries {
+    pub fn lines(&self, metric: &'static str, labels: &Labels) -> Vec<registry::Sample> {
+        [
+            ("success", &self.success),
+            ("failure", &self.failure),
+            ("dropped", &self.dropped),
+        ]
+        .into_iter()
+        .map(|(result, n)| {
+            registry::counter(metric).with(labels).label("result", result).total(n.load(Ordering::Relaxed))
+        })
+        .collect()
+    }
+}
+
+/// Sending half handed to the scrapers.
+#[derive(Clone)]
+pub struct EventSink {
+    /// Queue of the EVENT_WEBHOOK_URL task, when set.
+    pub webhook: Option<(mpsc::Sender<Event>, Arc<Deliveries>)>,
+    /// Chat channels of the config file, when any.
+    pub channels: Option<crate::channels::Sink>,
+}
+
+impl EventSink {
+    /// Queue `event` without waiting; counts it as dropped when the queue is full.
+    pub fn emit(&self, event: Event) {
+        if let Some(channels) = &self.channels {
+            channels.emit(&event);
+        }
+        if let Some((tx, deliveries)) = &self.webhook {
+            if tx.try_send(event).is_err() {
+                warn!("Webhook queue full, dropping connector event");
+                deliveries.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    pub max_retries: u32,
+}
+
+async fn post_once(client: &reqwest::Client, url: &str, event: &impl Serialize) -> Result<(), (bool, String)> {
+    let resp = client
+        .post(url)
+        .json(event)
+        .send()
+        .await
+        // The URL of a channel is its secret, so errors leave it out
+        .map_err(|e| (true, e.without_url().to_string()))?;
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let retry = status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+    Err((retry, format!("HTTP {}", status)))
+}
+
+/// Longest wait between two attempts at one delivery.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Retries stop once they would go on past this, whatever `max_retries`
+/// says, so a dead endpoint holds up the queue behind it for at most this
+/// long.
+const MAX_RETRY_TIME: Duration = Duration::from_secs(120);
+
+/// The wait before retry `attempt` (from 0): 1s, doubled per retry up to
+/// MAX_BACKOFF, or `None` when it would end past MAX_RETRY_TIME after
+/// `elapsed`.
+fn retry_delay(attempt: u32, elapsed: Duration) -> Option<Duration> {
+    let delay = Duration::from_secs(1).saturating_mul(2u32.saturating_pow(attempt)).min(MAX_BACKOFF);
+    (elapsed + delay <= MAX_RETRY_TIME).then_some(delay)
+}
+
+/// POST `body` to `url`, retrying network errors, 5xx and 429 with
+/// exponential backoff up to `max_retries` times, within MAX_RETRY_TIME.
+/// `target` names the destination in the logs.
+pub async fn deliver(
+    client: &reqwest::Client,
+    url: &str,
+    max_retries: u32,
+    body: &impl Serialize,
+    target: &str,
+) -> Result<(), String> {
+    let started = Instant::now();
+    let mut attempt = 0;
+    loop {
+        let Err((retry, e)) = post_once(client, url, body).await else {
+            return Ok(());
+        };
+        if !retry || attempt == max_retries {
+            return Err(e);
+        }
+        let Some(backoff) = retry_delay(attempt, started.elapsed()) else {
+            return Err(format!("{}; retries stopped after {:?}", e, MAX_RETRY_TIME));
+        };
+        warn!("Webhook delivery to {} failed ({}), retrying in {:?}", target, e, backoff);
+        tokio::time::sleep(backoff).await;
+        attempt += 1;
+    }
+}
+
+/// Deliver queued events one at a time, retrying network errors, 5xx and
+/// 429 with exponential backoff.
+pub async fn run<T: Serialize>(
+    client: reqwest::Client,
+    config: WebhookConfig,
+    mut rx: mpsc::Receiver<T>,
+    deliveries: Arc<Deliveries>,
+) {
+    while let Some(event) = rx.recv().await {
+        match deliver(&client, &config.url, config.max_retries, &event, &config.url).await {
+            Ok(()) => {
+                debug!("webhook: delivered to {}", config.url);
+                deliveries.success.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                warn!("Webhook delivery to {} failed: {}", config.url, e);
+                deliveries.failure.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct LogWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Logs written on this thread while the guard is held, which covers
+    /// the tasks of a current-thread test runtime.
+    pub(crate) fn capture_logs() -> (Arc<Mutex<Vec<u8>>>, tracing::subscriber::DefaultGuard) {
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let sink = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(move || LogWriter(sink.clone()))
+            .finish();
+        (logs, tracing::subscriber::set_default(subscriber))
+    }
+
+    /// A URL nothing listens on.
+    pub(crate) async fn refused_url(path: &str) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        format!("http://{}{}", addr, path)
+    }
+
+    #[test]
+    fn retry_delays_double_up_to_the_cap() {
+        let delays: Vec<u64> = (0..8).map(|a| retry_delay(a, Duration::ZERO).unwrap().as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30, 30]);
+        assert_eq!(retry_delay(u32::MAX, Duration::ZERO), Some(MAX_BACKOFF));
+        // However many retries are left, none starts past the time limit
+        assert_eq!(retry_delay(12, MAX_RETRY_TIME - Duration::from_secs(31)), Some(MAX_BACKOFF));
+        assert_eq!(retry_delay(12, MAX_RETRY_TIME - Duration::from_secs(29)), None);
+        assert_eq!(retry_delay(0, MAX_RETRY_TIME), None);
+    }
+
+    /// A server answering 503 to the first `failures` requests and 200 after,
+    /// and the count of requests.
+    async fn flaky_server(failures: usize) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        use axum::{http::StatusCode, Router};
+
+        let hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counted = hits.clone();
+        let app = Router::new().fallback(move || {
+            let failing = counted.fetch_add(1, Ordering::Relaxed) < failures;
+            async move {
+                if failing {
+                    StatusCode::SERVICE_UNAVAILABLE
+                } else {
+                    StatusCode::OK
+                }
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, hits)
+    }
+
+    #[tokio::test]
+    async fn retries_a_server_error_until_it_succeeds() {
+        let client = reqwest::Client::new();
+        let body = serde_json::json!({ "connector": "sink" });
+        let (url, hits) = flaky_server(1).await;
+        deliver(&client, &url, 3, &body, "webhook").await.unwrap();
+        assert_eq!(hits.load(Ordering::Relaxed), 2);
+
+        let (url, hits) = flaky_server(1).await;
+        let err = deliver(&client, &url, 0, &body, "webhook").await.unwrap_err();
+        assert_eq!(err, "HTTP 503 Service Unavailable");
+        assert_eq!(hits.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_leaves_the_url_out() {
+        let (logs, _guard) = capture_logs();
+        let url = refused_url("/services/T000/B000/secret-token").await;
+        let client = reqwest::Client::new();
+        let err = deliver(&client, &url, 1, &serde_json::json!({}), "channel ops").await.unwrap_err();
+        assert!(!err.contains("secret-token"), "{}", err);
+        let logs = String::from_utf8(logs.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("Webhook delivery to channel ops failed"), "{}", logs);
+        assert!(!logs.contains("secret-token"), "{}", logs);
+    }
+}
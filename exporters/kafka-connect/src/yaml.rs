@@ -0,0 +1,563 @@
+//! Reader for YAML config files: the block style config files are written
+//! in, turned into the JSON value the JSON reader would produce, like
+//! [`crate::toml`] does for TOML.
+//!
+//! Supported are block mappings and sequences (a sequence may sit at the
+//! indent of its key), plain, single- and double-quoted scalars, flow
+//! collections on one line (`[a, b]`, `{ k: v }`), comments and a leading
+//! `---`. Plain scalars are typed as in the YAML core schema: `null` and `~`,
+//! `true` and `false`, integers and floats; everything else is a string.
+//! Anchors, aliases, tags, block scalars (`|`, `>`), scalars spanning lines
+//! and further documents are rejected, since no config key needs them.
+
+use crate::toml::Document;
+use serde_json::{Map, Number, Value};
+use std::collections::HashMap;
+
+/// A line with content, comments left in.
+#[derive(Clone, Copy)]
+struct Line<'a> {
+    number: usize,
+    indent: usize,
+    text: &'a str,
+}
+
+struct Parser<'a> {
+    lines: Vec<Line<'a>>,
+    next: usize,
+    paths: HashMap<String, usize>,
+}
+
+/// A position within one line, for scalars, keys and flow collections.
+struct Cursor<'a, 'p> {
+    text: &'a str,
+    pos: usize,
+    line: usize,
+    paths: &'p mut HashMap<String, usize>,
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Whether `text` starts a block sequence item.
+fn is_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+/// Parse `text` into the JSON value of the document.
+pub fn parse(text: &str) -> Result<Document, String> {
+    let mut lines = Vec::new();
+    let mut started = false;
+    for (i, raw) in text.lines().enumerate() {
+        let number = i + 1;
+        let content = raw.trim_start_matches(' ').trim_end();
+        if content.trim_start().is_empty() || content.starts_with('#') {
+            continue;
+        }
+        if content.starts_with('\t') {
+            return Err(format!("line {}: tabs are not allowed in indentation", number));
+        }
+        let indent = raw.len() - raw.trim_start_matches(' ').len();
+        if indent == 0 && (content == "---" || content.starts_with("--- #")) {
+            if started || !lines.is_empty() {
+                return Err(format!("line {}: multiple documents are not supported", number));
+            }
+            started = true;
+            continue;
+        }
+        if indent == 0 && (content == "..." || content.starts_with('%')) {
+            return Err(format!("line {}: directives and document end markers are not supported", number));
+        }
+        lines.push(Line { number, indent, text: content });
+    }
+    let mut p = Parser { lines, next: 0, paths: HashMap::new() };
+    let value = match p.lines.first() {
+        None => Value::Object(Map::new()),
+        Some(first) => p.block(first.indent, "")?,
+    };
+    if let Some(line) = p.lines.get(p.next) {
+        return Err(format!("line {}: unexpected indentation", line.number));
+    }
+    Ok(Document { value, lines: p.paths })
+}
+
+impl<'a> Parser<'a> {
+    fn cursor(&mut self, text: &'a str, line: usize) -> Cursor<'a, '_> {
+        Cursor { text, pos: 0, line, paths: &mut self.paths }
+    }
+
+    /// The mapping or sequence whose first line is the next one.
+    fn block(&mut self, indent: usize, path: &str) -> Result<Value, String> {
+        if is_item(self.lines[self.next].text) {
+            self.sequence(indent, path)
+        } else {
+            self.mapping(indent, path)
+        }
+    }
+
+    /// The value of a key or item with nothing after it on its line: the
+    /// block indented below it, a sequence at the indent of the key when
+    /// `key`, else null.
+    fn nested(&mut self, indent: usize, path: &str, key: bool) -> Result<Value, String> {
+        match self.lines.get(self.next) {
+            Some(line) if line.indent > indent => self.block(line.indent, path),
+            Some(line) if key && line.indent == indent && is_item(line.text) => self.sequence(indent, path),
+            _ => Ok(Value::Null),
+        }
+    }
+
+    fn mapping(&mut self, indent: usize, path: &str) -> Result<Value, String> {
+        let mut map = Map::new();
+        while let Some(line) = self.lines.get(self.next) {
+            let Line { number, indent: line_indent, text } = *line;
+            if line_indent < indent {
+                break;
+            }
+            if line_indent > indent {
+                return Err(format!("line {}: unexpected indentation", number));
+            }
+            if is_item(text) {
+                return Err(format!("line {}: expected `key: value`, not a sequence item", number));
+            }
+            let mut cur = self.cursor(text, number);
+            let key = cur.key()?;
+            let key_path = join(path, &key);
+            if map.contains_key(&key) {
+                return Err(format!("line {}: {}: set twice", number, key_path));
+            }
+            let value = if cur.at_end() {
+                self.next += 1;
+                self.nested(indent, &key_path, true)?
+            } else {
+                let value = cur.value(&key_path, false)?;
+                cur.end()?;
+                self.next += 1;
+                value
+            };
+            self.paths.insert(key_path, number);
+            map.insert(key, value);
+        }
+        Ok(Value::Object(map))
+    }
+
+    fn sequence(&mut self, indent: usize, path: &str) -> Result<Value, String> {
+        let mut items = Vec::new();
+        while let Some(line) = self.lines.get(self.next) {
+            let Line { number, indent: line_indent, text } = *line;
+            if line_indent > indent {
+                return Err(format!("line {}: unexpected indentation", number));
+            }
+            if line_indent < indent || !is_item(text) {
+                break;
+            }
+            let item_path = format!("{}[{}]", path, items.len());
+            self.paths.insert(item_path.clone(), number);
+            let rest = &text[1..];
+            let content = rest.trim_start_matches(' ');
+            let item = if content.is_empty() || content.starts_with('#') {
+                self.next += 1;
+                self.nested(indent, &item_path, false)?
+            } else if is_item(content) || self.cursor(content, number).key().is_ok() {
+                // The item is a block of its own, indented to where its content starts
+                let inner = indent + 1 + rest.len() - content.len();
+                self.lines[self.next] = Line { number, indent: inner, text: content };
+                self.block(inner, &item_path)?
+            } else {
+                let mut cur = self.cursor(content, number);
+                let value = cur.value(&item_path, false)?;
+                cur.end()?;
+                self.next += 1;
+                value
+            };
+            items.push(item);
+        }
+        Ok(Value::Array(items))
+    }
+}
+
+impl<'a> Cursor<'a, '_> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.bump();
+            return true;
+        }
+        false
+    }
+
+    fn error(&self, msg: &str) -> String {
+        format!("line {}: {}", self.line, msg)
+    }
+
+    fn error_at(&self, path: &str, msg: &str) -> String {
+        format!("line {}: {}: {}", self.line, path, msg)
+    }
+
+    fn skip_ws(&mut self) {
+        while self.eat(' ') {}
+    }
+
+    /// Whether only whitespace and a comment are left.
+    fn at_end(&mut self) -> bool {
+        self.skip_ws();
+        match self.peek() {
+            None => true,
+            Some('#') => self.pos == 0 || self.text[..self.pos].ends_with(' '),
+            Some(_) => false,
+        }
+    }
+
+    fn end(&mut self) -> Result<(), String> {
+        if self.at_end() {
+            return Ok(());
+        }
+        Err(self.error(&format!("unexpected `{}` after the value", self.peek().unwrap_or_default())))
+    }
+
+    /// A block mapping key and its `:`.
+    fn key(&mut self) -> Result<String, String> {
+        let key = match self.peek() {
+            Some('"') => self.double_quoted()?,
+            Some('\'') => self.single_quoted()?,
+            Some('?') if matches!(self.text[self.pos + 1..].chars().next(), None | Some(' ')) => {
+                return Err(self.error("complex keys are not supported"));
+            }
+            Some(c) if "[]{},&*!|>%@`#".contains(c) => return Err(self.error("expected `key: value`")),
+            _ => self.plain(false).to_owned(),
+        };
+        self.skip_ws();
+        if !self.eat(':') || !matches!(self.peek(), None | Some(' ')) {
+            return Err(self.error("expected `key: value`"));
+        }
+        Ok(key)
+    }
+
+    /// A scalar or flow collection; `flow` inside a flow collection.
+    fn value(&mut self, path: &str, flow: bool) -> Result<Value, String> {
+        self.skip_ws();
+        match self.peek() {
+            Some('"') => self.double_quoted().map(Value::String),
+            Some('\'') => self.single_quoted().map(Value::String),
+            Some('[') => self.flow_sequence(path),
+            Some('{') => self.flow_mapping(path),
+            Some('&') => Err(self.error_at(path, "anchors are not supported")),
+            Some('*') => Err(self.error_at(path, "aliases are not supported")),
+            Some('!') => Err(self.error_at(path, "tags are not supported")),
+            Some('|' | '>') => Err(self.error_at(path, "block scalars are not supported")),
+            None | Some('#') => Err(self.error_at(path, "expected a value")),
+            Some(c @ (']' | '}' | ',' | '%' | '@' | '`')) => Err(self.error_at(path, &format!("unexpected `{}`", c))),
+            Some(c @ ('-' | '?' | ':')) if matches!(self.text[self.pos + 1..].chars().next(), None | Some(' ')) => {
+                Err(self.error_at(path, &format!("unexpected `{}`", c)))
+            }
+            Some(_) => {
+                let raw = self.plain(flow);
+                typed(raw).map_err(|e| self.error_at(path, &format!("{} `{}`", e, raw)))
+            }
+        }
+    }
+
+    /// A plain scalar, up to a `: `, a comment or, when `flow`, a flow
+    /// indicator.
+    fn plain(&mut self, flow: bool) -> &'a str {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            let after = self.text[self.pos + c.len_utf8()..].chars().next();
+            let ends = match c {
+                ':' => after.is_none() || after == Some(' ') || flow && after.is_some_and(|a| ",[]{}".contains(a)),
+                '#' => self.text[..self.pos].ends_with(' '),
+                ',' | '[' | ']' | '{' | '}' => flow,
+                _ => false,
+            };
+            if ends {
+                break;
+            }
+            self.bump();
+        }
+        self.text[start..self.pos].trim_end()
+    }
+
+    fn double_quoted(&mut self) -> Result<String, String> {
+        self.bump();
+        let mut out = String::new();
+        loop {
+            let Some(c) = self.bump() else { return Err(self.error("unterminated string")) };
+            match c {
+                '"' => return Ok(out),
+                '\\' => match self.bump() {
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    Some('/') => out.push('/'),
+                    Some(' ') => out.push(' '),
+                    Some('0') => out.push('\0'),
+                    Some('a') => out.push('\u{7}'),
+                    Some('b') => out.push('\u{8}'),
+                    Some('t') => out.push('\t'),
+                    Some('n') => out.push('\n'),
+                    Some('v') => out.push('\u{b}'),
+                    Some('f') => out.push('\u{c}'),
+                    Some('r') => out.push('\r'),
+                    Some('e') => out.push('\u{1b}'),
+                    Some(x @ ('x' | 'u' | 'U')) => {
+                        let len = match x {
+                            'x' => 2,
+                            'u' => 4,
+                            _ => 8,
+                        };
+                        let hex = self.text.get(self.pos..self.pos + len).unwrap_or_default();
+                        let c = u32::from_str_radix(hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| self.error("invalid unicode escape"))?;
+                        self.pos += len;
+                        out.push(c);
+                    }
+                    _ => return Err(self.error("invalid escape in string")),
+                },
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn single_quoted(&mut self) -> Result<String, String> {
+        self.bump();
+        let mut out = String::new();
+        loop {
+            match self.bump() {
+                None => return Err(self.error("unterminated string")),
+                Some('\'') if self.eat('\'') => out.push('\''),
+                Some('\'') => return Ok(out),
+                Some(c) => out.push(c),
+            }
+        }
+    }
+
+    /// Fails when the line ends before the collection is closed.
+    fn check_open(&mut self, path: &str) -> Result<(), String> {
+        if self.at_end() {
+            return Err(self.error_at(path, "flow collections must close on the line they open"));
+        }
+        Ok(())
+    }
+
+    fn flow_sequence(&mut self, path: &str) -> Result<Value, String> {
+        self.bump();
+        let mut items = Vec::new();
+        loop {
+            self.check_open(path)?;
+            if self.eat(']') {
+                return Ok(Value::Array(items));
+            }
+            let item_path = format!("{}[{}]", path, items.len());
+            items.push(self.value(&item_path, true)?);
+            self.paths.insert(item_path, self.line);
+            self.check_open(path)?;
+            if self.eat(']') {
+                return Ok(Value::Array(items));
+            }
+            if !self.eat(',') {
+                return Err(self.error_at(path, "expected `,` or `]` in the flow sequence"));
+            }
+        }
+    }
+
+    fn flow_mapping(&mut self, path: &str) -> Result<Value, String> {
+        self.bump();
+        let mut map = Map::new();
+        loop {
+            self.check_open(path)?;
+            if self.eat('}') {
+                return Ok(Value::Object(map));
+            }
+            let key = match self.peek() {
+                Some('"') => self.double_quoted()?,
+                Some('\'') => self.single_quoted()?,
+                _ => self.plain(true).to_owned(),
+            };
+            if key.is_empty() {
+                return Err(self.error_at(path, "expected a key in the flow mapping"));
+            }
+            self.skip_ws();
+            if !self.eat(':') {
+                return Err(self.error(&format!("expected `:` after `{}`", key)));
+            }
+            let key_path = join(path, &key);
+            if map.contains_key(&key) {
+                return Err(self.error_at(&key_path, "set twice"));
+            }
+            self.check_open(path)?;
+            let value = match self.peek() {
+                Some(',' | '}') => Value::Null,
+                _ => self.value(&key_path, true)?,
+            };
+            self.paths.insert(key_path, self.line);
+            map.insert(key, value);
+            self.check_open(path)?;
+            if self.eat('}') {
+                return Ok(Value::Object(map));
+            }
+            if !self.eat(',') {
+                return Err(self.error_at(path, "expected `,` or `}` in the flow mapping"));
+            }
+        }
+    }
+}
+
+/// A plain scalar typed as in the core schema.
+fn typed(raw: &str) -> Result<Value, &'static str> {
+    match raw {
+        "" | "~" | "null" | "Null" | "NULL" => return Ok(Value::Null),
+        "true" | "True" | "TRUE" => return Ok(Value::Bool(true)),
+        "false" | "False" | "FALSE" => return Ok(Value::Bool(false)),
+        _ => {}
+    }
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let unsigned = raw.strip_prefix(['-', '+']).unwrap_or(raw);
+    let radix = match raw.get(..2) {
+        Some("0x") => Some((16, &raw[2..])),
+        Some("0o") => Some((8, &raw[2..])),
+        _ => None,
+    };
+    if let Some((radix, n)) = radix.filter(|(radix, n)| !n.is_empty() && n.chars().all(|c| c.is_digit(*radix))) {
+        return i64::from_str_radix(n, radix).map(|n| Value::Number(n.into())).map_err(|_| "integer out of range");
+    }
+    if digits(unsigned) {
+        return raw.parse::<i64>().map(|n| Value::Number(n.into())).map_err(|_| "integer out of range");
+    }
+    if matches!(unsigned, ".inf" | ".Inf" | ".INF") || matches!(raw, ".nan" | ".NaN" | ".NAN") {
+        return Err("not a finite number");
+    }
+    let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent.strip_prefix(['-', '+']).unwrap_or(exponent))),
+        None => (unsigned, None),
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let float = (digits(int) || digits(frac))
+        && (int.is_empty() || digits(int))
+        && (frac.is_empty() || digits(frac))
+        && (mantissa.contains('.') || exponent.is_some())
+        && exponent.is_none_or(digits);
+    if float {
+        let f: f64 = raw.parse().map_err(|_| "invalid number")?;
+        return Number::from_f64(f).map(Value::Number).ok_or("not a finite number");
+    }
+    Ok(Value::String(raw.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_the_config_subset() {
+        let doc = parse(
+            r#"---
+# Blue/green workers
+web_auth:
+  username: prometheus
+  password: 'p@ss''word'   # quotes keep it a string
+
+instances:
+- url: http://connect-blue:8083
+  timeout_secs: 5
+  lite: false
+  labels: { color: blue, "dc": fra1 }
+  required_connectors:
+    - pg-source
+    - s3-sink
+  tls:
+    ca_file: /etc/ca.pem
+-   url: "http://connect-green:8083"
+    labels:
+      color: green
+
+alerts:
+  webhook_url: https://hooks.example.com/a
+  rules: [{ name: Down, expr: "kafka_connect_up == 0", for_secs: 1200 }]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            doc.value,
+            json!({
+                "web_auth": { "username": "prometheus", "password": "p@ss'word" },
+                "instances": [
+                    {
+                        "url": "http://connect-blue:8083",
+                        "timeout_secs": 5,
+                        "lite": false,
+                        "labels": { "color": "blue", "dc": "fra1" },
+                        "required_connectors": ["pg-source", "s3-sink"],
+                        "tls": { "ca_file": "/etc/ca.pem" },
+                    },
+                    { "url": "http://connect-green:8083", "labels": { "color": "green" } },
+                ],
+                "alerts": {
+                    "webhook_url": "https://hooks.example.com/a",
+                    "rules": [{ "name": "Down", "expr": "kafka_connect_up == 0", "for_secs": 1200 }],
+                },
+            })
+        );
+        assert_eq!(doc.line_of("instances[0].tls.ca_file"), Some(16));
+        assert_eq!(doc.line_of("instances[1].labels.color"), Some(19));
+        assert_eq!(doc.line_of("alerts.rules[0].for_secs"), Some(23));
+        assert_eq!(doc.line_of("instances[1].labels.color.unknown"), Some(19));
+    }
+
+    #[test]
+    fn types_scalars_as_the_core_schema() {
+        let doc = parse(concat!(
+            "a: -12\nb: 0x1F\nc: 1.5e3\nd: .5\ne: TRUE\nf: ~\ng:\nh: \"tab\\there \\u00fc\"\n",
+            "i: '42'\nj: 1.2.3\nk: yes\nl: 10:30\n",
+        ))
+        .unwrap();
+        assert_eq!(
+            doc.value,
+            json!({
+                "a": -12, "b": 31, "c": 1500.0, "d": 0.5, "e": true, "f": null, "g": null, "h": "tab\there ü",
+                "i": "42", "j": "1.2.3", "k": "yes", "l": "10:30",
+            })
+        );
+    }
+
+    #[test]
+    fn parses_nested_and_compact_sequences() {
+        let doc = parse("matrix:\n  - - 1\n    - 2\n  - []\n  -\n  - - a: x\n      b: y\n").unwrap();
+        assert_eq!(doc.value, json!({ "matrix": [[1, 2], [], null, [{ "a": "x", "b": "y" }]] }));
+        assert_eq!(parse("").unwrap().value, json!({}));
+        assert_eq!(parse("- a\n- b\n").unwrap().value, json!(["a", "b"]));
+    }
+
+    #[test]
+    fn errors_carry_the_line_and_key() {
+        let err = |text: &str| parse(text).err().unwrap();
+        assert_eq!(err("a:\n  x: 1\n  x: 2\n"), "line 3: a.x: set twice");
+        assert_eq!(err("t: { k: 1, k: 2 }\n"), "line 1: t.k: set twice");
+        assert_eq!(err("base: &base\n  x: 1\n"), "line 1: base: anchors are not supported");
+        assert_eq!(err("copy: *base\n"), "line 1: copy: aliases are not supported");
+        assert_eq!(err("n: !!str 1\n"), "line 1: n: tags are not supported");
+        assert_eq!(err("text: |\n  line\n"), "line 1: text: block scalars are not supported");
+        assert_eq!(err("a: one\n  two\n"), "line 2: unexpected indentation");
+        assert_eq!(err("a: [1,\n  2]\n"), "line 1: a: flow collections must close on the line they open");
+        assert_eq!(err("a: 1\n---\nb: 2\n"), "line 2: multiple documents are not supported");
+        assert_eq!(err("a:\n\t- 1\n"), "line 2: tabs are not allowed in indentation");
+        assert_eq!(err("a: 1\njust text\n"), "line 2: expected `key: value`");
+        assert_eq!(err("s: \"open\n"), "line 1: unterminated string");
+        assert_eq!(err("x: .inf\n"), "line 1: x: not a finite number `.inf`");
+        assert_eq!(err("m: - not a list\n"), "line 1: m: unexpected `-`");
+    }
+}